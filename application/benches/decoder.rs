@@ -0,0 +1,182 @@
+//! Сравнение ленивого декодера с прежним рекурсивным парсером на nom.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use application::{
+    io::{
+        deserialization::parse_node,
+        serialization::{BencodeDictBuilder, Serialize},
+    },
+    repository::types::TorrentMetadata,
+};
+
+/// Прежняя реализация, сохранённая только для сравнения.
+mod legacy {
+    use std::collections::HashMap;
+
+    use nom::{
+        branch::alt,
+        bytes::complete::{take, take_while},
+        character::{complete::char, is_digit},
+        combinator::map_res,
+        error::{Error as Err, ErrorKind},
+        multi::many0,
+        sequence::{delimited, pair, preceded, tuple},
+        Err::Error,
+        IResult,
+    };
+
+    #[derive(Debug, PartialEq, Clone)]
+    pub enum Node<'a> {
+        UnsignedNum(u64),
+        String(&'a [u8]),
+        List(Vec<Node<'a>>),
+        Dict(HashMap<&'a [u8], Node<'a>>, &'a [u8]),
+    }
+
+    pub fn parse_node(inp: &[u8]) -> IResult<&[u8], Node<'_>> {
+        alt((parse_string, parse_number, parse_list, parse_dict))(inp)
+    }
+
+    fn parse_digits(inp: &[u8]) -> IResult<&[u8], u64> {
+        let (inp, r) = take_while(is_digit)(inp)?;
+
+        if !r.is_empty() {
+            let mut digits = 0;
+            for b in r {
+                digits *= 10;
+                digits += (b - b'0') as u64;
+            }
+
+            Ok((inp, digits))
+        } else {
+            Err(Error(Err {
+                input: inp,
+                code: ErrorKind::TakeWhile1,
+            }))
+        }
+    }
+
+    fn parse_number(inp: &[u8]) -> IResult<&[u8], Node<'_>> {
+        let (inp, (_, number, _)) = tuple((char('i'), parse_digits, char('e')))(inp)?;
+
+        Ok((inp, Node::UnsignedNum(number)))
+    }
+
+    fn parse_string(inp: &[u8]) -> IResult<&[u8], Node<'_>> {
+        let (inp, length) = parse_digits(inp)?;
+
+        let (inp, s) = preceded(char(':'), take(length))(inp)?;
+
+        Ok((inp, Node::String(s)))
+    }
+
+    fn parse_list(inp: &[u8]) -> IResult<&[u8], Node<'_>> {
+        map_res(delimited(char('l'), many0(parse_node), char('e')), |list| {
+            Result::<Node, ()>::Ok(Node::List(list))
+        })(inp)
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn parse_dict<'a>(inp: &'a [u8]) -> IResult<&'a [u8], Node<'a>> {
+        let pairs_to_dict =
+            |pairs: Vec<(Node<'a>, Node<'a>)>| -> Result<HashMap<&'a [u8], Node<'a>>, ()> {
+                let mut dict = HashMap::new();
+                for (key, value) in pairs {
+                    if let Node::String(s) = key {
+                        dict.insert(s, value);
+                    } else {
+                        return Result::Err(());
+                    }
+                }
+                Result::Ok(dict)
+            };
+
+        let parse_pair = pair(parse_string, parse_node);
+
+        let (new_inp, dict) = map_res(
+            delimited(char('d'), many0(parse_pair), char('e')),
+            pairs_to_dict,
+        )(inp)?;
+
+        Ok((
+            new_inp,
+            Node::Dict(dict, &inp[0..(inp.len() - new_inp.len())]),
+        ))
+    }
+
+    /// Так выглядел доступ к полю: каждый раз копия поддерева.
+    pub fn lookup<'a>(node: &Node<'a>, key: &[u8]) -> Option<Node<'a>> {
+        if let Node::Dict(dict, _) = node {
+            dict.get(key).cloned()
+        } else {
+            None
+        }
+    }
+}
+
+fn generate_torrent(files: usize) -> Vec<u8> {
+    let files: Vec<Vec<u8>> = (0..files)
+        .map(|i| {
+            BencodeDictBuilder::new()
+                .required(b"length", (i as u64) * 1024 + 1)
+                .required(
+                    b"path",
+                    vec![format!("dir{}", i % 10), format!("file{i}.bin")],
+                )
+                .fin()
+        })
+        .collect();
+
+    let mut list = vec![b'l'];
+    files.iter().for_each(|f| list.extend(f));
+    list.push(b'e');
+
+    let mut info = b"d5:files".to_vec();
+    info.extend(list);
+    info.extend(b"4:name4:data12:piece lengthi262144e".iter());
+    info.extend(b"6:pieces".iter());
+    info.extend(vec![7u8; 20 * 4096].serialize());
+    info.push(b'e');
+
+    let mut torrent = b"d8:announce".to_vec();
+    torrent.extend("http://tracker.local/announce".to_string().serialize());
+    torrent.extend(b"4:info".iter());
+    torrent.extend(info);
+    torrent.push(b'e');
+    torrent
+}
+
+fn decoder(c: &mut Criterion) {
+    let torrent = generate_torrent(10_000);
+
+    let mut group = c.benchmark_group("parse multi-file torrent");
+    group.bench_function("legacy", |b| {
+        b.iter(|| legacy::parse_node(black_box(&torrent)).unwrap())
+    });
+    group.bench_function("lazy", |b| {
+        b.iter(|| parse_node(black_box(&torrent)).unwrap())
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("lookup info dict");
+    let (_, legacy_node) = legacy::parse_node(&torrent).unwrap();
+    let (_, node) = parse_node(&torrent).unwrap();
+    group.bench_function("legacy", |b| {
+        b.iter(|| legacy::lookup(black_box(&legacy_node), b"info").unwrap())
+    });
+    group.bench_function("lazy", |b| {
+        b.iter(|| match black_box(node) {
+            application::io::deserialization::Node::Dict(dict) => dict.get(b"info").unwrap(),
+            _ => unreachable!(),
+        })
+    });
+    group.finish();
+
+    c.bench_function("TorrentMetadata::new", |b| {
+        b.iter(|| TorrentMetadata::new(black_box(&torrent)).unwrap())
+    });
+}
+
+criterion_group!(benches, decoder);
+criterion_main!(benches);
@@ -0,0 +1,39 @@
+#![no_main]
+
+use application::io::{
+    deserialization::{parse_node, Node},
+    json::{node_to_json, BinaryEncoding},
+    pretty::{pretty, PrettyOptions},
+};
+use libfuzzer_sys::fuzz_target;
+
+/// Обходит всё дерево, чтобы задействовать ленивое чтение списков и словарей.
+fn walk(node: Node) -> usize {
+    let mut stack = vec![node];
+    let mut count = 0;
+
+    while let Some(node) = stack.pop() {
+        count += 1;
+        match node {
+            Node::List(list) => stack.extend(list.iter()),
+            Node::Dict(dict) => {
+                let _ = dict.raw();
+                for (key, value) in dict.iter() {
+                    assert!(dict.get(key).is_some());
+                    stack.push(value);
+                }
+            }
+            _ => {}
+        }
+    }
+    count
+}
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((rest, node)) = parse_node(data) {
+        assert!(rest.len() < data.len());
+        walk(node);
+        let _ = pretty(node, PrettyOptions::default());
+        let _ = node_to_json(node, BinaryEncoding::Hex);
+    }
+});
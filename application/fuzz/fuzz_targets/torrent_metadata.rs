@@ -0,0 +1,15 @@
+#![no_main]
+
+use application::{
+    io::{deserialization::TryDeserialize, serialization::Serialize},
+    repository::types::TorrentMetadata,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((metadata, _)) = TorrentMetadata::new(data) {
+        let bytes = metadata.serialize();
+        let new = TorrentMetadata::try_deserialize(&bytes).expect("serialized metadata must parse");
+        assert_eq!(metadata, new);
+    }
+});
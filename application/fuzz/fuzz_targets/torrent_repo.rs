@@ -0,0 +1,15 @@
+#![no_main]
+
+use application::{
+    io::{deserialization::TryDeserialize, serialization::Serialize},
+    repository::TorrentRepo,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(repo) = TorrentRepo::try_deserialize(data) {
+        let bytes = repo.serialize();
+        let new = TorrentRepo::try_deserialize(&bytes).expect("serialized repo must parse");
+        assert_eq!(repo, new);
+    }
+});
@@ -0,0 +1,572 @@
+pub mod output;
+
+/// Модуль с интерфейсом командной строки.
+///
+/// Команды над торрентами превращаются в вызовы `rpc`. Если демон
+/// запущен, вызовы уходят ему, иначе выполняются прямо над репозиторием
+/// в каталоге состояния. Вывод пишется в переданный `Write`, чтобы его
+/// можно было проверить в тестах; с `--json` он машиночитаемый.
+use std::{
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    creation::TorrentBuilder,
+    daemon::{repo_backend, socket_path, Daemon, DaemonConfig},
+    error::AsyncErr,
+    io::{
+        deserialization::parse_node,
+        json::{from_json_str, to_json_string, BinaryEncoding},
+        pretty::{pretty, PrettyOptions},
+        serialization::Serialize,
+    },
+    repository::{
+        backend::{migrate, Directory, RepoBackend, SingleFile},
+        TorrentRepo,
+    },
+    rpc::{client::RpcClient, methods, Endpoint},
+    settings::{
+        format_ratio, parse_minutes, parse_rate, parse_ratio, SettingsError, SettingsSource,
+    },
+    tools::to_hex,
+};
+
+use self::output::{format_size, write_details, write_settings, write_table};
+
+#[derive(Debug, Parser)]
+#[command(name = "bittorrent", version, about = "A BitTorrent client")]
+pub struct Cli {
+    /// Directory with the torrent repository and the daemon socket
+    #[arg(long, global = true, env = "BITTORRENT_STATE_DIR")]
+    pub state_dir: Option<PathBuf>,
+
+    /// Daemon to control: unix:<path> or <ip>:<port>. By default the
+    /// daemon of the state directory is used if it is running
+    #[arg(long, global = true, env = "BITTORRENT_CONNECT")]
+    pub connect: Option<Endpoint>,
+
+    /// Token the daemon requires from its clients
+    #[arg(long, global = true, env = "BITTORRENT_TOKEN")]
+    pub token: Option<String>,
+
+    /// Settings file, by default config.toml in $XDG_CONFIG_HOME/bittorrent_client
+    #[arg(long, global = true, env = "BITTORRENT_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Override a setting as <key>=<value>, may be repeated
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    pub settings: Vec<String>,
+
+    /// Print machine-readable JSON
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Add a torrent file
+    Add {
+        /// Path to a .torrent file
+        source: String,
+        /// Directory to download into, the current one by default
+        #[arg(long)]
+        save_path: Option<PathBuf>,
+        /// Add without starting
+        #[arg(long)]
+        paused: bool,
+        #[arg(long = "label")]
+        labels: Vec<String>,
+    },
+    /// List torrents
+    List {
+        #[arg(long)]
+        status: Option<StatusArg>,
+        #[arg(long)]
+        label: Option<String>,
+    },
+    /// Show everything known about a torrent
+    Info { id: String },
+    /// Change labels, file priorities, rate limits and seeding goals of a torrent
+    Set {
+        id: String,
+        /// Replace the labels, may be repeated
+        #[arg(long = "label")]
+        labels: Option<Vec<String>>,
+        /// Set a file priority as <file>=<skip|low|normal|high>
+        #[arg(long = "priority")]
+        priorities: Vec<String>,
+        /// Limit the download rate, e.g. 512KiB; 0 or none removes the limit
+        #[arg(long, value_name = "RATE")]
+        download_limit: Option<String>,
+        /// Limit the upload rate, e.g. 1MiB; 0 or none removes the limit
+        #[arg(long, value_name = "RATE")]
+        upload_limit: Option<String>,
+        /// Stop seeding at this upload ratio, e.g. 2.0, or global or unlimited
+        #[arg(long, value_name = "GOAL")]
+        ratio_goal: Option<String>,
+        /// Stop seeding after this many minutes, or global or unlimited
+        #[arg(long, value_name = "GOAL")]
+        seed_time_goal: Option<String>,
+        /// Stop seeding after this many idle minutes, or global or unlimited
+        #[arg(long, value_name = "GOAL")]
+        idle_goal: Option<String>,
+    },
+    /// Move a torrent in the queue
+    Queue {
+        id: String,
+        /// top, up, down, bottom or a position counted from 0
+        to: String,
+    },
+    /// Remove a torrent from the repository
+    Remove {
+        id: String,
+        /// Also delete downloaded files
+        #[arg(long)]
+        delete_data: bool,
+    },
+    /// Resume a paused torrent
+    Start { id: String },
+    /// Pause a torrent
+    Stop { id: String },
+    /// Verify downloaded data against piece hashes
+    Recheck { id: String },
+    /// Browse and control torrents interactively
+    Tui,
+    /// Show the settings in effect, the daemon's ones if it is running
+    Settings {
+        /// Make the running daemon re-read its settings file
+        #[arg(long)]
+        reload: bool,
+    },
+    /// Run the daemon in the foreground
+    Daemon {
+        /// Also accept control connections on this loopback address
+        #[arg(long)]
+        listen: Option<SocketAddr>,
+        /// Serve the Transmission RPC protocol on this address
+        #[arg(long)]
+        transmission: Option<SocketAddr>,
+        /// Directory for torrents added without a save path
+        #[arg(long)]
+        download_dir: Option<PathBuf>,
+    },
+    /// Create a torrent file
+    Create {
+        path: PathBuf,
+        output: PathBuf,
+        #[arg(long)]
+        announce: Option<String>,
+        /// Add a backup tracker tier, may be repeated
+        #[arg(long = "tier")]
+        tiers: Vec<String>,
+        #[arg(long)]
+        comment: Option<String>,
+        #[arg(long = "web-seed")]
+        web_seeds: Vec<String>,
+        #[arg(long)]
+        piece_length: Option<u64>,
+        #[arg(long)]
+        private: bool,
+    },
+    /// Print any bencoded file
+    Dump {
+        file: PathBuf,
+        /// Show binary strings in JSON as base64 instead of hex
+        #[arg(long)]
+        base64: bool,
+    },
+    /// Build a bencoded file from edited `dump --json` output
+    FromJson { input: PathBuf, output: PathBuf },
+    /// Move the repository between a single file and a directory
+    MigrateRepo { from: PathBuf, to: PathBuf },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StatusArg {
+    Paused,
+    Queued,
+    Downloading,
+    Seeding,
+}
+
+/// Файл настроек по умолчанию: `$XDG_CONFIG_HOME/bittorrent_client/config.toml`
+/// или `~/.config/bittorrent_client/config.toml`.
+pub fn default_config_file() -> PathBuf {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .unwrap_or_default();
+    base.join("bittorrent_client/config.toml")
+}
+
+/// Источник настроек: файл, окружение и `--set`, в таком порядке.
+fn settings_source(cli: &Cli) -> Result<SettingsSource, AsyncErr> {
+    let mut source = match &cli.config {
+        Some(file) => SettingsSource::new().file(file),
+        None => SettingsSource::new().default_file(default_config_file()),
+    }
+    .env(std::env::vars());
+    for setting in &cli.settings {
+        let (key, value) = setting
+            .split_once('=')
+            .ok_or_else(|| format!("expected <key>=<value>, got {setting}"))?;
+        source = source.set(key.trim(), value);
+    }
+    Ok(source)
+}
+
+/// Каталог состояния по умолчанию: `$XDG_DATA_HOME/bittorrent_client`
+/// или `~/.local/share/bittorrent_client`.
+pub fn default_state_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")))
+        .unwrap_or_default();
+    base.join("bittorrent_client")
+}
+
+pub async fn run(cli: Cli, out: &mut dyn Write) -> Result<(), AsyncErr> {
+    let state_dir = cli.state_dir.clone().unwrap_or_else(default_state_dir);
+    let json = cli.json;
+
+    match cli.command {
+        Command::Daemon {
+            listen,
+            transmission,
+            ref download_dir,
+        } => {
+            let mut settings = settings_source(&cli)?;
+            if let Some(dir) = download_dir {
+                settings = settings.set("download_dir", dir.display().to_string());
+            }
+            let mut config = DaemonConfig::new(&state_dir).settings(settings);
+            if let Some(addr) = listen {
+                config = config.listen(addr);
+            }
+            if let Some(addr) = transmission {
+                config = config.transmission(addr);
+            }
+            if let Some(token) = cli.token {
+                config = config.token(token);
+            }
+            let daemon = Daemon::bind(config).await?;
+            for endpoint in daemon.endpoints() {
+                eprintln!("listening on {endpoint}");
+            }
+            if let Some(addr) = daemon.transmission_addr() {
+                eprintln!("serving Transmission RPC on http://{addr}/transmission/rpc");
+            }
+            daemon
+                .run(async {
+                    let _ = tokio::signal::ctrl_c().await;
+                })
+                .await
+        }
+        Command::Create {
+            path,
+            output,
+            announce,
+            tiers,
+            comment,
+            web_seeds,
+            piece_length,
+            private,
+        } => {
+            let announce = announce.ok_or("--announce is required")?;
+            let mut builder = TorrentBuilder::new(path)
+                .private(private)
+                .announce(announce);
+            for tier in tiers {
+                builder = builder.announce_tier(vec![tier]);
+            }
+            for seed in web_seeds {
+                builder = builder.web_seed(seed);
+            }
+            if let Some(comment) = comment {
+                builder = builder.comment(comment);
+            }
+            if let Some(piece_length) = piece_length {
+                builder = builder.piece_length(piece_length);
+            }
+            let builder = builder.on_progress(|p| {
+                eprint!("\rhashed {}/{} pieces", p.hashed_pieces, p.total_pieces);
+            });
+
+            let (metadata, hash) = tokio::task::spawn_blocking(move || builder.build()).await??;
+            eprintln!();
+            tokio::fs::write(&output, metadata.serialize()).await?;
+            if json {
+                writeln!(out, "{}", json!({ "hash": to_hex(&hash.short()) }))?;
+            } else {
+                writeln!(out, "{}", to_hex(&hash.short()))?;
+            }
+            Ok(())
+        }
+        Command::Dump { file, base64 } => {
+            let bytes = tokio::fs::read(file).await?;
+            if json {
+                let encoding = if base64 {
+                    BinaryEncoding::Base64
+                } else {
+                    BinaryEncoding::Hex
+                };
+                writeln!(out, "{}", to_json_string(&bytes, encoding, true)?)?;
+            } else {
+                let (_, node) = parse_node(&bytes)?;
+                write!(out, "{}", pretty(node, PrettyOptions::default()))?;
+            }
+            Ok(())
+        }
+        Command::FromJson { input, output } => {
+            let json = tokio::fs::read_to_string(input).await?;
+            tokio::fs::write(output, from_json_str(&json)?).await?;
+            Ok(())
+        }
+        Command::MigrateRepo { from, to } => {
+            let count = if tokio::fs::metadata(&from).await?.is_dir() {
+                migrate(&mut Directory::new(from), &mut SingleFile::new(to)).await?
+            } else {
+                migrate(&mut SingleFile::new(from), &mut Directory::new(to)).await?
+            };
+            if json {
+                writeln!(out, "{}", json!({ "migrated": count }))?;
+            } else {
+                writeln!(out, "migrated {count} torrents")?;
+            }
+            Ok(())
+        }
+        Command::Settings { reload } => {
+            let target = Target::connect(&cli.connect, cli.token.as_deref(), &state_dir).await?;
+            let settings = match target {
+                Target::Daemon(mut client) => {
+                    let method = if reload {
+                        "settings.reload"
+                    } else {
+                        "settings.get"
+                    };
+                    client.call(method, json!({})).await?
+                }
+                Target::Local { .. } if reload => return Err("no daemon is running".into()),
+                Target::Local { .. } => settings_source(&cli)?.load()?.to_json(),
+            };
+            if json {
+                writeln!(out, "{settings}")?;
+            } else {
+                write_settings(out, &settings)?;
+            }
+            Ok(())
+        }
+        Command::Tui => {
+            let target = Target::connect(&cli.connect, cli.token.as_deref(), &state_dir).await?;
+            crate::tui::run(target).await
+        }
+        ref command => {
+            let (method, params) = request(command, &cli).await?;
+            let mut target =
+                Target::connect(&cli.connect, cli.token.as_deref(), &state_dir).await?;
+            let result = target.call(method, params).await?;
+            if json {
+                writeln!(out, "{result}")?;
+            } else {
+                print(command, &result, out)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// С чем работают команды: с запущенным демоном или, если его нет, прямо
+/// с репозиторием в каталоге состояния.
+pub enum Target {
+    Daemon(RpcClient),
+    Local {
+        backend: Directory,
+        repo: Box<TorrentRepo>,
+    },
+}
+
+impl Target {
+    /// Без явного адреса ищет сокет демона в каталоге состояния.
+    pub async fn connect(
+        endpoint: &Option<Endpoint>,
+        token: Option<&str>,
+        state_dir: &Path,
+    ) -> Result<Target, AsyncErr> {
+        if let Some(endpoint) = endpoint {
+            return Ok(Target::Daemon(RpcClient::connect(endpoint, token).await?));
+        }
+        #[cfg(unix)]
+        {
+            let socket = Endpoint::Unix(socket_path(state_dir));
+            match RpcClient::connect(&socket, token).await {
+                Ok(client) => return Ok(Target::Daemon(client)),
+                // Сокета нет или он остался от упавшего демона
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+                    ) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let mut backend = repo_backend(state_dir);
+        let repo = Box::new(backend.load().await?);
+        Ok(Target::Local { backend, repo })
+    }
+
+    pub async fn call(&mut self, method: &str, params: Value) -> Result<Value, AsyncErr> {
+        match self {
+            Target::Daemon(client) => client.call(method, params).await,
+            Target::Local { backend, repo } => {
+                let (result, changed) = methods::call(repo, method, &params).await?;
+                if changed {
+                    backend.save(repo).await?;
+                }
+                Ok(result)
+            }
+        }
+    }
+}
+
+/// Вызов, которому соответствует команда над торрентами.
+async fn request(command: &Command, cli: &Cli) -> Result<(&'static str, Value), AsyncErr> {
+    Ok(match command {
+        Command::Add {
+            source,
+            save_path,
+            paused,
+            labels,
+        } => {
+            if source.starts_with("magnet:") {
+                return Err("magnet links are not supported yet: \
+                            fetching metadata from peers is not implemented"
+                    .into());
+            }
+            let metainfo = tokio::fs::read(source).await?;
+            // Демон может работать в другом каталоге, поэтому путь абсолютный
+            let save_path = match save_path {
+                Some(path) => std::path::absolute(path)?,
+                None => settings_source(cli)?.load()?.download_dir,
+            };
+            let params = json!({
+                "metainfo": STANDARD.encode(metainfo),
+                "save_path": save_path,
+                "paused": paused,
+                "labels": labels,
+            });
+            ("torrent.add", params)
+        }
+        Command::List { status, label } => {
+            let status = status.map(|s| match s {
+                StatusArg::Paused => "paused",
+                StatusArg::Queued => "queued",
+                StatusArg::Downloading => "downloading",
+                StatusArg::Seeding => "seeding",
+            });
+            ("torrent.list", json!({ "status": status, "label": label }))
+        }
+        Command::Info { id } => ("torrent.get", json!({ "id": id })),
+        Command::Set {
+            id,
+            labels,
+            priorities,
+            download_limit,
+            upload_limit,
+            ratio_goal,
+            seed_time_goal,
+            idle_goal,
+        } => {
+            let mut files = Map::new();
+            for priority in priorities {
+                let (file, priority) = priority
+                    .split_once('=')
+                    .ok_or_else(|| format!("expected <file>=<priority>, got {priority}"))?;
+                files.insert(file.to_string(), priority.into());
+            }
+            // Ноль в запросе снимает ограничение
+            let limit = |key, value: &Option<String>| {
+                value
+                    .as_deref()
+                    .map(|v| parse_rate(key, v).map(|rate| rate.unwrap_or(0)))
+                    .transpose()
+            };
+            let params = json!({
+                "id": id,
+                "labels": labels,
+                "priorities": files,
+                "download_limit": limit("download_limit", download_limit)?,
+                "upload_limit": limit("upload_limit", upload_limit)?,
+                "ratio_goal": goal("ratio_goal", ratio_goal, true)?,
+                "seed_time_goal": goal("seed_time_goal", seed_time_goal, false)?,
+                "idle_goal": goal("idle_goal", idle_goal, false)?,
+            });
+            ("torrent.set", params)
+        }
+        Command::Queue { id, to } => {
+            let params = match to.parse::<u64>() {
+                Ok(position) => json!({ "id": id, "position": position }),
+                Err(_) => json!({ "id": id, "move": to }),
+            };
+            ("torrent.queue", params)
+        }
+        Command::Remove { id, delete_data } => (
+            "torrent.remove",
+            json!({ "id": id, "delete_data": delete_data }),
+        ),
+        Command::Start { id } => ("torrent.start", json!({ "id": id })),
+        Command::Stop { id } => ("torrent.stop", json!({ "id": id })),
+        Command::Recheck { id } => ("torrent.recheck", json!({ "id": id })),
+        _ => unreachable!("{command:?} does not work with torrents"),
+    })
+}
+
+/// Цель раздачи для `torrent.set`: `global`, `unlimited` или число.
+fn goal(key: &str, value: &Option<String>, is_ratio: bool) -> Result<Value, SettingsError> {
+    let Some(value) = value.as_deref() else {
+        return Ok(Value::Null);
+    };
+    Ok(match value {
+        "global" | "unlimited" => value.into(),
+        _ if is_ratio => parse_ratio(key, value)?.map_or(0.0, format_ratio).into(),
+        _ => parse_minutes(key, value)?.unwrap_or(0).into(),
+    })
+}
+
+fn print(command: &Command, result: &Value, out: &mut dyn Write) -> io::Result<()> {
+    let text = |key: &str| result[key].as_str().unwrap_or_default();
+    match command {
+        Command::Add { .. } if result["merged"] == true => writeln!(
+            out,
+            "{} is already added as {}, trackers merged",
+            text("name"),
+            text("id")
+        ),
+        Command::Add { .. } => writeln!(out, "added {} as {}", text("name"), text("id")),
+        Command::List { .. } => write_table(out, result.as_array().map_or(&[], Vec::as_slice)),
+        Command::Info { .. } | Command::Set { .. } => write_details(out, result),
+        Command::Remove { .. } => writeln!(out, "removed {}", text("name")),
+        Command::Queue { .. } => {
+            let queue = result["queue"].as_array().map_or(&[][..], Vec::as_slice);
+            let position = queue.iter().position(|id| *id == result["id"]);
+            writeln!(out, "queue position {}", position.unwrap_or_default())
+        }
+        Command::Start { .. } => writeln!(out, "started {}", text("name")),
+        Command::Stop { .. } => writeln!(out, "paused {}", text("name")),
+        Command::Recheck { .. } => writeln!(
+            out,
+            "{} of {} verified",
+            format_size(result["downloaded"].as_u64().unwrap_or_default()),
+            format_size(result["size"].as_u64().unwrap_or_default())
+        ),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,152 @@
+/// Модуль с форматированием вывода командной строки.
+use std::io::{self, Write};
+
+use serde_json::Value;
+
+use crate::settings::KEYS;
+
+/// Размер в двоичных единицах: `1.5 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// Таблица из строк `torrent.list`.
+pub fn write_table(out: &mut dyn Write, torrents: &[Value]) -> io::Result<()> {
+    writeln!(
+        out,
+        "{:<8}  {:<11}  {:>8}  {:>10}  {:>12}  {:>12}  NAME",
+        "ID", "STATUS", "PROGRESS", "SIZE", "DOWN", "UP"
+    )?;
+    for t in torrents {
+        let size = |key: &str| format_size(t[key].as_u64().unwrap_or_default());
+        let id = t["id"].as_str().unwrap_or_default();
+        writeln!(
+            out,
+            "{:<8}  {:<11}  {:>7.1}%  {:>10}  {:>10}/s  {:>10}/s  {}",
+            &id[..id.len().min(8)],
+            t["status"].as_str().unwrap_or_default(),
+            t["progress"].as_f64().unwrap_or_default() * 100.0,
+            size("size"),
+            size("download_rate"),
+            size("upload_rate"),
+            t["name"].as_str().unwrap_or_default()
+        )?;
+    }
+    Ok(())
+}
+
+/// Текстовый вид `torrent.get`.
+pub fn write_details(out: &mut dyn Write, details: &Value) -> io::Result<()> {
+    let text = |key: &str| match &details[key] {
+        Value::Null => "-".to_string(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    let list = |key: &str| -> Vec<String> {
+        details[key]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect()
+    };
+    let size = |key: &str| format_size(details[key].as_u64().unwrap_or_default());
+
+    writeln!(out, "id:\t\t{}", text("id"))?;
+    writeln!(out, "name:\t\t{}", text("name"))?;
+    writeln!(out, "hash v1:\t{}", text("hash_v1"))?;
+    writeln!(out, "hash v2:\t{}", text("hash_v2"))?;
+    writeln!(out, "status:\t\t{}", text("status"))?;
+    writeln!(
+        out,
+        "progress:\t{:.1}% ({} of {})",
+        details["progress"].as_f64().unwrap_or_default() * 100.0,
+        size("downloaded"),
+        size("size")
+    )?;
+    writeln!(out, "save path:\t{}", text("save_path"))?;
+    writeln!(out, "labels:\t\t{}", list("labels").join(", "))?;
+    let limit = |key: &str| match details[key].as_u64() {
+        Some(rate) => format!("{}/s", format_size(rate)),
+        None => "unlimited".to_string(),
+    };
+    writeln!(
+        out,
+        "limits:\t\tdown {}, up {}",
+        limit("download_limit"),
+        limit("upload_limit")
+    )?;
+    writeln!(out, "queue:\t\t{}", text("queue_position"))?;
+    writeln!(
+        out,
+        "seeding:\tratio {}, {} min, idle {} min",
+        text("ratio"),
+        details["seeding_time"].as_u64().unwrap_or_default() / 60,
+        details["idle_time"].as_u64().unwrap_or_default() / 60
+    )?;
+    let minutes = |key: &str| match details[key].as_u64() {
+        Some(minutes) => format!("{minutes} min"),
+        None => text(key),
+    };
+    writeln!(
+        out,
+        "seeding goals:\tratio {}, time {}, idle {}",
+        text("ratio_goal"),
+        minutes("seed_time_goal"),
+        minutes("idle_goal")
+    )?;
+    writeln!(
+        out,
+        "pieces:\t\t{} x {}",
+        text("pieces"),
+        size("piece_length")
+    )?;
+    writeln!(out, "private:\t{}", text("private"))?;
+    writeln!(out, "comment:\t{}", text("comment"))?;
+    writeln!(out, "created by:\t{}", text("created_by"))?;
+    writeln!(out, "creation date:\t{}", text("creation_date"))?;
+    for tracker in list("trackers") {
+        writeln!(out, "tracker:\t{tracker}")?;
+    }
+    for seed in list("web_seeds") {
+        writeln!(out, "web seed:\t{seed}")?;
+    }
+    writeln!(out, "files:")?;
+    for file in details["files"].as_array().into_iter().flatten() {
+        writeln!(
+            out,
+            "  {:>10}  {:<6}  {}",
+            format_size(file["size"].as_u64().unwrap_or_default()),
+            file["priority"].as_str().unwrap_or_default(),
+            file["path"].as_str().unwrap_or_default()
+        )?;
+    }
+    Ok(())
+}
+
+/// Настройки строками `ключ = значение`, как их принимает `--set`.
+pub fn write_settings(out: &mut dyn Write, settings: &Value) -> io::Result<()> {
+    for key in KEYS {
+        let value = match settings.pointer(&format!("/{}", key.replace('.', "/"))) {
+            None | Some(Value::Null) => "none".to_string(),
+            Some(Value::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+        };
+        writeln!(out, "{key} = {value}")?;
+    }
+    if let Some(peer_id) = settings["peer_id"].as_str() {
+        writeln!(out, "peer_id = {peer_id}")?;
+    }
+    Ok(())
+}
@@ -1,4 +1,107 @@
-pub const PEER_ID: [u8; 20] = *b"-RC0000-156716088174"; // generate time based
+/// Модуль с тем, как клиент представляется трекерам и пирам.
+use sha1::{Digest, Sha1};
+
+use crate::repository::types::InfoHash;
+
+/// Код клиента в peer ID.
+pub const CLIENT_CODE: [u8; 2] = *b"RC";
 pub const COMPACT: u8 = 0;
-pub const PORT: i16 = 6881;
-pub const KEY: i32 = 0x34659034;
\ No newline at end of file
+
+/// Символы случайной части peer ID: печатные, чтобы ID читался в логах.
+const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Peer ID и секрет для ключей трекера. Случайная часть peer ID создаётся
+/// заново на каждый запуск, а секрет демон хранит в каталоге состояния,
+/// чтобы ключи трекеров переживали перезапуск.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Identity {
+    pub peer_id: [u8; 20],
+    secret: [u8; 16],
+    /// Случайные символы, которыми peer ID добирается до 20 байт после
+    /// префикса.
+    random: [u8; 20],
+    prefix_len: usize,
+    /// Ключ из настроек вместо выводимого из секрета.
+    key: Option<u32>,
+}
+
+impl Identity {
+    /// Паникует, только если в системе нет генератора случайных чисел.
+    pub fn generate() -> Identity {
+        let mut bytes = [0; 36];
+        getrandom::getrandom(&mut bytes).expect("the system random number generator failed");
+        let mut random = [0; 20];
+        for (byte, random) in random.iter_mut().zip(&bytes[..20]) {
+            *byte = ALPHABET[*random as usize % ALPHABET.len()];
+        }
+        let mut identity = Identity {
+            peer_id: [0; 20],
+            secret: bytes[20..].try_into().unwrap(),
+            random,
+            prefix_len: 0,
+            key: None,
+        };
+        identity.set_prefix(&peer_id_prefix());
+        identity
+    }
+
+    /// Заменяет начало peer ID, остаток остаётся случайным. Префикс
+    /// короче 20 байт, это проверяют настройки.
+    pub fn set_prefix(&mut self, prefix: &[u8]) {
+        let len = prefix.len();
+        self.peer_id[..len].copy_from_slice(prefix);
+        self.peer_id[len..].copy_from_slice(&self.random[..20 - len]);
+        self.prefix_len = len;
+    }
+
+    pub fn prefix(&self) -> &[u8] {
+        &self.peer_id[..self.prefix_len]
+    }
+
+    /// Один ключ для всех торрентов, `None` — свой у каждого.
+    pub fn set_key(&mut self, key: Option<u32>) {
+        self.key = key;
+    }
+
+    pub fn key(&self) -> Option<u32> {
+        self.key
+    }
+
+    pub fn secret(&self) -> [u8; 16] {
+        self.secret
+    }
+
+    pub fn set_secret(&mut self, secret: [u8; 16]) {
+        self.secret = secret;
+    }
+
+    /// Параметр `key` для трекеров. У каждого торрента свой, но от анонса к
+    /// анонсу он не меняется: выводится из секрета и хеша.
+    pub fn tracker_key(&self, hash: &InfoHash) -> u32 {
+        if let Some(key) = self.key {
+            return key;
+        }
+        let digest = Sha1::new()
+            .chain_update(self.secret)
+            .chain_update(hash.short())
+            .finalize();
+        u32::from_be_bytes(digest[..4].try_into().unwrap())
+    }
+}
+
+/// Начало peer ID в стиле Azureus: `-RC0100-` для версии 0.1.0.
+pub fn peer_id_prefix() -> [u8; 8] {
+    let version = [
+        env!("CARGO_PKG_VERSION_MAJOR"),
+        env!("CARGO_PKG_VERSION_MINOR"),
+        env!("CARGO_PKG_VERSION_PATCH"),
+    ]
+    .map(|part| version_char(part.parse().unwrap_or(0)));
+    let [a, b] = CLIENT_CODE;
+    [b'-', a, b, version[0], version[1], version[2], b'0', b'-']
+}
+
+/// Одна часть версии одним символом: цифра, после девяти — буква.
+fn version_char(part: u32) -> u8 {
+    ALPHABET[(part as usize).min(35)]
+}
@@ -0,0 +1,430 @@
+/// Модуль для создания торрентов из файлов и каталогов.
+///
+/// Куски хешируются параллельно: потоки по очереди забирают номера кусков
+/// и читают нужные байты сами, поэтому кусок может захватывать несколько файлов.
+use std::{
+    fmt::Display,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    io::serialization::Serialize,
+    repository::types::{
+        FileMetadata, FilesMetadata, Info, InfoHash, MetaVersion, TorrentMetadata,
+    },
+};
+
+pub const MIN_PIECE_LENGTH: u64 = 16 * 1024;
+pub const MAX_PIECE_LENGTH: u64 = 16 * 1024 * 1024;
+/// Сколько кусков примерно получается при автоматическом выборе длины.
+const TARGET_PIECES: u64 = 1500;
+
+/// Выбирает степень двойки так, чтобы кусков было около `TARGET_PIECES`.
+pub fn auto_piece_length(total_length: u64) -> u64 {
+    (total_length / TARGET_PIECES)
+        .next_power_of_two()
+        .clamp(MIN_PIECE_LENGTH, MAX_PIECE_LENGTH)
+}
+
+#[derive(Debug)]
+pub enum CreateError {
+    Io(io::Error),
+    NothingToShare(PathBuf),
+    InvalidName(PathBuf),
+    InvalidPieceLength(u64),
+    NoTracker,
+    Cancelled,
+}
+
+impl Display for CreateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CreateError::Io(e) => write!(f, "I/O error: {e}"),
+            CreateError::NothingToShare(p) => write!(f, "no files found in {}", p.display()),
+            CreateError::InvalidName(p) => write!(f, "{} is not a valid UTF-8 name", p.display()),
+            CreateError::InvalidPieceLength(l) => {
+                write!(
+                    f,
+                    "piece length {l} must be a power of two not less than 16 KiB"
+                )
+            }
+            CreateError::NoTracker => write!(f, "no tracker given"),
+            CreateError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for CreateError {}
+
+impl From<io::Error> for CreateError {
+    fn from(e: io::Error) -> Self {
+        CreateError::Io(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub hashed_pieces: usize,
+    pub total_pieces: usize,
+}
+
+type ProgressCallback = Box<dyn Fn(Progress) + Send + Sync>;
+
+pub struct TorrentBuilder {
+    root: PathBuf,
+    piece_length: Option<u64>,
+    announce: Option<String>,
+    announce_list: Vec<Vec<String>>,
+    comment: Option<String>,
+    created_by: Option<String>,
+    creation_date: Option<u64>,
+    private: bool,
+    web_seeds: Vec<String>,
+    threads: usize,
+    progress: Option<ProgressCallback>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl TorrentBuilder {
+    pub fn new(root: impl Into<PathBuf>) -> TorrentBuilder {
+        TorrentBuilder {
+            root: root.into(),
+            piece_length: None,
+            announce: None,
+            announce_list: vec![],
+            comment: None,
+            created_by: Some(concat!("application/", env!("CARGO_PKG_VERSION")).to_string()),
+            creation_date: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_secs()),
+            private: false,
+            web_seeds: vec![],
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            progress: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Задаёт длину куска вместо автоматического выбора.
+    pub fn piece_length(self, piece_length: u64) -> TorrentBuilder {
+        TorrentBuilder {
+            piece_length: Some(piece_length),
+            ..self
+        }
+    }
+
+    pub fn announce(self, url: impl Into<String>) -> TorrentBuilder {
+        TorrentBuilder {
+            announce: Some(url.into()),
+            ..self
+        }
+    }
+
+    /// Добавляет уровень в announce-list.
+    pub fn announce_tier(mut self, tier: Vec<String>) -> TorrentBuilder {
+        self.announce_list.push(tier);
+        self
+    }
+
+    pub fn comment(self, comment: impl Into<String>) -> TorrentBuilder {
+        TorrentBuilder {
+            comment: Some(comment.into()),
+            ..self
+        }
+    }
+
+    pub fn created_by(self, created_by: Option<String>) -> TorrentBuilder {
+        TorrentBuilder { created_by, ..self }
+    }
+
+    /// `None` убирает дату, чтобы один и тот же каталог давал одинаковый файл.
+    pub fn creation_date(self, creation_date: Option<u64>) -> TorrentBuilder {
+        TorrentBuilder {
+            creation_date,
+            ..self
+        }
+    }
+
+    pub fn private(self, private: bool) -> TorrentBuilder {
+        TorrentBuilder { private, ..self }
+    }
+
+    pub fn web_seed(mut self, url: impl Into<String>) -> TorrentBuilder {
+        self.web_seeds.push(url.into());
+        self
+    }
+
+    pub fn threads(self, threads: usize) -> TorrentBuilder {
+        TorrentBuilder {
+            threads: threads.max(1),
+            ..self
+        }
+    }
+
+    /// Колбэк вызывается из рабочих потоков после каждого куска.
+    pub fn on_progress(self, f: impl Fn(Progress) + Send + Sync + 'static) -> TorrentBuilder {
+        TorrentBuilder {
+            progress: Some(Box::new(f)),
+            ..self
+        }
+    }
+
+    /// Флаг, установка которого прерывает хеширование.
+    pub fn cancel_handle(&self) -> Arc<AtomicBool> {
+        self.cancel.clone()
+    }
+
+    /// Хеширует данные и возвращает метаданные вместе с инфо-хешем.
+    pub fn build(self) -> Result<(TorrentMetadata, InfoHash), CreateError> {
+        let announce = self
+            .announce
+            .clone()
+            .or_else(|| self.announce_list.iter().flatten().next().cloned())
+            .ok_or(CreateError::NoTracker)?;
+
+        let root = self.root.canonicalize()?;
+        let name = file_name(&root)?;
+        let (files, single) = collect_files(&root)?;
+        let total_length = files.iter().map(|f| f.length).sum();
+
+        let piece_length = match self.piece_length {
+            Some(l) if l < MIN_PIECE_LENGTH || !l.is_power_of_two() => {
+                return Err(CreateError::InvalidPieceLength(l))
+            }
+            Some(l) => l,
+            None => auto_piece_length(total_length),
+        };
+
+        let pieces = self.hash_pieces(&files, total_length, piece_length)?;
+
+        let files = if single {
+            FilesMetadata::Single {
+                name,
+                length: total_length,
+                md5sum: None,
+            }
+        } else {
+            FilesMetadata::Multiple {
+                base_name: name,
+                files: files
+                    .into_iter()
+                    .map(|f| FileMetadata {
+                        path: f.path,
+                        path_raw: None,
+                        path_utf8: None,
+                        length: f.length,
+                        md5sum: None,
+                        attr: None,
+                        symlink_path: None,
+                        sha1: None,
+                    })
+                    .collect(),
+            }
+        };
+
+        let info = Info {
+            piece_length,
+            pieces: Some(pieces),
+            private: self.private.then_some(1),
+            files,
+            meta_version: None,
+            file_tree: None,
+            name_raw: None,
+            name_utf8: None,
+        };
+        let hash = InfoHash::from_info_bytes(&info.serialize(), MetaVersion::V1);
+
+        let metadata = TorrentMetadata {
+            info,
+            announce,
+            encoding: None,
+            httpseeds: None,
+            announce_list: (!self.announce_list.is_empty()).then_some(self.announce_list),
+            creation_date: self.creation_date,
+            comment: self.comment,
+            created_by: self.created_by,
+            url_list: (!self.web_seeds.is_empty()).then_some(self.web_seeds),
+            piece_layers: None,
+        };
+
+        Ok((metadata, hash))
+    }
+
+    fn hash_pieces(
+        &self,
+        files: &[InputFile],
+        total_length: u64,
+        piece_length: u64,
+    ) -> Result<Vec<u8>, CreateError> {
+        let total_pieces = total_length.div_ceil(piece_length) as usize;
+        let next = AtomicUsize::new(0);
+        let hashed = AtomicUsize::new(0);
+        // Ошибка в одном потоке останавливает остальные
+        let failed = AtomicBool::new(false);
+
+        let results = thread::scope(|s| {
+            let workers: Vec<_> = (0..self.threads.min(total_pieces.max(1)))
+                .map(|_| {
+                    s.spawn(|| -> Result<Vec<(usize, [u8; 20])>, CreateError> {
+                        let mut reader = PieceReader::new(files);
+                        let mut buf = vec![0u8; piece_length as usize];
+                        let mut out = vec![];
+
+                        loop {
+                            if self.cancel.load(Ordering::Relaxed) || failed.load(Ordering::Relaxed)
+                            {
+                                return Ok(out);
+                            }
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            if index >= total_pieces {
+                                return Ok(out);
+                            }
+
+                            let start = index as u64 * piece_length;
+                            let len = piece_length.min(total_length - start) as usize;
+                            if let Err(e) = reader.read_at(start, &mut buf[..len]) {
+                                failed.store(true, Ordering::Relaxed);
+                                return Err(e.into());
+                            }
+                            out.push((index, Sha1::digest(&buf[..len]).into()));
+
+                            let done = hashed.fetch_add(1, Ordering::Relaxed) + 1;
+                            if let Some(progress) = &self.progress {
+                                progress(Progress {
+                                    hashed_pieces: done,
+                                    total_pieces,
+                                });
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .map(|w| w.join().expect("hashing thread panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        let mut pieces = vec![0u8; total_pieces * 20];
+        for result in results {
+            for (index, hash) in result? {
+                pieces[index * 20..(index + 1) * 20].copy_from_slice(&hash);
+            }
+        }
+        if self.cancel.load(Ordering::Relaxed) {
+            return Err(CreateError::Cancelled);
+        }
+
+        Ok(pieces)
+    }
+}
+
+struct InputFile {
+    source: PathBuf,
+    path: Vec<String>,
+    length: u64,
+    offset: u64,
+}
+
+fn file_name(path: &Path) -> Result<String, CreateError> {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .map(str::to_string)
+        .ok_or_else(|| CreateError::InvalidName(path.to_path_buf()))
+}
+
+/// Собирает обычные файлы в порядке путей. Символические ссылки пропускаются.
+fn collect_files(root: &Path) -> Result<(Vec<InputFile>, bool), CreateError> {
+    let meta = fs::metadata(root)?;
+    if meta.is_file() {
+        let file = InputFile {
+            source: root.to_path_buf(),
+            path: vec![],
+            length: meta.len(),
+            offset: 0,
+        };
+        return Ok((vec![file], true));
+    }
+
+    let mut files = vec![];
+    let mut dirs = vec![(root.to_path_buf(), vec![])];
+    while let Some((dir, prefix)) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let kind = entry.file_type()?;
+            let mut path: Vec<String> = prefix.clone();
+            path.push(file_name(&entry.path())?);
+
+            if kind.is_dir() {
+                dirs.push((entry.path(), path));
+            } else if kind.is_file() {
+                files.push(InputFile {
+                    source: entry.path(),
+                    path,
+                    length: entry.metadata()?.len(),
+                    offset: 0,
+                });
+            }
+        }
+    }
+    if files.is_empty() {
+        return Err(CreateError::NothingToShare(root.to_path_buf()));
+    }
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut offset = 0;
+    for file in &mut files {
+        file.offset = offset;
+        offset += file.length;
+    }
+
+    Ok((files, false))
+}
+
+/// Читает байты по смещению в общем потоке всех файлов торрента.
+struct PieceReader<'f> {
+    files: &'f [InputFile],
+    open: Option<(usize, File)>,
+}
+
+impl<'f> PieceReader<'f> {
+    fn new(files: &'f [InputFile]) -> PieceReader<'f> {
+        PieceReader { files, open: None }
+    }
+
+    fn read_at(&mut self, mut offset: u64, mut buf: &mut [u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            // Пустые файлы пропускаются сами собой
+            let index = self
+                .files
+                .partition_point(|f| f.offset + f.length <= offset);
+            let file = self.files.get(index).ok_or(io::ErrorKind::UnexpectedEof)?;
+
+            let within = offset - file.offset;
+            let len = (buf.len() as u64).min(file.length - within) as usize;
+
+            let handle = match &mut self.open {
+                Some((i, handle)) if *i == index => handle,
+                open => &mut open.insert((index, File::open(&file.source)?)).1,
+            };
+            handle.seek(SeekFrom::Start(within))?;
+            handle.read_exact(&mut buf[..len])?;
+
+            offset += len as u64;
+            buf = &mut buf[len..];
+        }
+        Ok(())
+    }
+}
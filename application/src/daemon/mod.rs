@@ -0,0 +1,737 @@
+pub mod session;
+pub mod transmission;
+
+/// Модуль с демоном, который владеет репозиторием и сессиями торрентов.
+///
+/// Управление идёт по протоколу из `rpc` через Unix-сокет в каталоге
+/// состояния и, по желанию, через TCP на localhost. Каждое изменение
+/// репозитория сразу сохраняется, а настройки перечитываются по `SIGHUP`.
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{watch, Mutex},
+};
+
+use crate::{
+    error::AsyncErr,
+    network::bandwidth::{Limiter, Transfer, LIMITED_TRAFFIC},
+    repository::{
+        backend::{Directory, RepoBackend},
+        queue::QueueManager,
+        resume::Recheck,
+        Id, TorrentRepo,
+    },
+    rpc::{
+        methods, read_message, response, write_message, Endpoint, Request, RpcError, UNAUTHORIZED,
+    },
+    settings::{Settings, SettingsError, SettingsSource},
+    storage::disk::DiskIo,
+    tools::{from_hex, to_hex},
+};
+
+use self::session::Session;
+
+/// Как часто демон сверяет расписание скоростей и обновляет счётчики.
+const TICK: Duration = Duration::from_secs(1);
+/// Время раздачи копится в памяти и сохраняется раз в столько тиков.
+const SAVE_TICKS: u64 = 60;
+/// Потоки дисковой подсистемы.
+const DISK_THREADS: usize = 4;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DaemonConfig {
+    /// Здесь лежат репозиторий и сокет `run/daemon.sock`.
+    pub state_dir: PathBuf,
+    /// Адрес для TCP, только петлевой.
+    pub listen: Option<SocketAddr>,
+    /// Если задан, без него вызовы не принимаются.
+    pub token: Option<String>,
+    /// Откуда брать настройки. Источник хранится, чтобы перечитывать
+    /// настройки по `SIGHUP` и `settings.reload`.
+    pub settings: SettingsSource,
+    /// Адрес для совместимого с Transmission RPC. Адрес не на localhost
+    /// допускается только вместе с токеном.
+    pub transmission: Option<SocketAddr>,
+}
+
+impl DaemonConfig {
+    pub fn new(state_dir: impl Into<PathBuf>) -> DaemonConfig {
+        DaemonConfig {
+            state_dir: state_dir.into(),
+            listen: None,
+            token: None,
+            settings: SettingsSource::new(),
+            transmission: None,
+        }
+    }
+
+    pub fn listen(self, addr: SocketAddr) -> DaemonConfig {
+        DaemonConfig {
+            listen: Some(addr),
+            ..self
+        }
+    }
+
+    pub fn token(self, token: impl Into<String>) -> DaemonConfig {
+        DaemonConfig {
+            token: Some(token.into()),
+            ..self
+        }
+    }
+
+    pub fn settings(self, settings: SettingsSource) -> DaemonConfig {
+        DaemonConfig { settings, ..self }
+    }
+
+    pub fn transmission(self, addr: SocketAddr) -> DaemonConfig {
+        DaemonConfig {
+            transmission: Some(addr),
+            ..self
+        }
+    }
+}
+
+/// Путь к сокету демона в каталоге состояния. Сокет лежит в отдельном
+/// закрытом каталоге, чтобы к нему нельзя было подключиться, пока у него
+/// права по umask.
+pub fn socket_path(state_dir: &Path) -> PathBuf {
+    state_dir.join("run").join("daemon.sock")
+}
+
+/// Репозиторий в каталоге состояния.
+pub fn repo_backend(state_dir: &Path) -> Directory {
+    Directory::new(state_dir.join("torrents"))
+}
+
+struct State {
+    repo: TorrentRepo,
+    backend: Directory,
+    sessions: HashMap<Id, Session>,
+    settings: Settings,
+    /// Изменения настроек на ходу добавляются к переопределениям источника,
+    /// чтобы пережить перечитывание файла.
+    source: SettingsSource,
+    transmission: transmission::Ids,
+    /// Ограничитель скорости, общий для всех соединений демона.
+    limiter: Arc<Limiter>,
+    /// Кеши и потоки для работы с диском, общие для всех торрентов.
+    disk: Arc<DiskIo>,
+}
+
+impl State {
+    /// Выполняет метод над репозиторием, сохраняет изменения и обновляет
+    /// сессии.
+    async fn call(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        let mut params = params.clone();
+        if method == "torrent.add" && params["save_path"].is_null() {
+            params["save_path"] = json!(self.settings.download_dir);
+        }
+        if method == "torrent.start" {
+            let id = methods::find(&self.repo, params["id"].as_str().unwrap_or_default())?;
+            self.queue()
+                .release(&mut self.repo.get_by_id_mut(id).unwrap());
+        }
+        let (mut result, changed) = methods::call(&mut self.repo, method, &params).await?;
+        if changed {
+            self.commit().await?;
+        }
+        match &mut result {
+            Value::Array(list) if method == "torrent.list" => {
+                list.iter_mut().for_each(|t| self.fill_rates(t))
+            }
+            details if method == "torrent.get" => {
+                self.fill_rates(details);
+                self.fill_tracker_key(details);
+            }
+            _ => {}
+        }
+        Ok(result)
+    }
+
+    /// Раздаёт места в очереди, сохраняет изменённый репозиторий и
+    /// обновляет сессии.
+    async fn commit(&mut self) -> Result<(), RpcError> {
+        self.queue().schedule(&mut self.repo);
+        self.save()
+            .await
+            .map_err(|e| RpcError::failed(format!("saving the repository failed: {e}")))?;
+        self.sync_sessions();
+        Ok(())
+    }
+
+    /// Запоминает состояние файлов торрентов и сохраняет репозиторий.
+    async fn save(&mut self) -> Result<(), AsyncErr> {
+        self.repo.capture_all().await?;
+        self.backend.save(&self.repo).await
+    }
+
+    fn queue(&self) -> QueueManager {
+        QueueManager::new(&self.settings)
+    }
+
+    /// Учитывает прошедшее время раздачи и останавливает торренты, которые
+    /// достигли целей. Возвращает `true`, если репозиторий нужно сохранить.
+    fn tick(&mut self, elapsed: u64) -> bool {
+        self.refresh_rates();
+        let sessions = &self.sessions;
+        let transferring = |id| {
+            sessions
+                .get(&id)
+                .is_some_and(|s| s.download_rate > 0 || s.upload_rate > 0)
+        };
+        let changed = self.queue().tick(&mut self.repo, elapsed, transferring);
+        if changed {
+            self.sync_sessions();
+        }
+        changed
+    }
+
+    /// Запускает сессии активных торрентов и убирает лишние.
+    fn sync_sessions(&mut self) {
+        let repo = &self.repo;
+        let (limiter, disk) = (&self.limiter, &self.disk);
+        self.sessions.retain(|id, _| {
+            let active = repo
+                .get_by_id(*id)
+                .is_some_and(|t| t.value.status().is_active());
+            if !active {
+                limiter.remove_torrent(*id);
+                disk.remove_torrent(*id);
+            }
+            active
+        });
+        for torrent in repo.iter() {
+            if !torrent.value.status().is_active() {
+                continue;
+            }
+            let value = &torrent.value;
+            limiter.set_torrent(torrent.id, value.download_limit, value.upload_limit);
+            let changed = self
+                .sessions
+                .get(&torrent.id)
+                .is_none_or(|s| s.state.torrent != *value);
+            if changed {
+                disk.add_torrent(torrent.id, value.storage(), value.metadata.clone());
+            }
+            self.sessions
+                .entry(torrent.id)
+                .or_insert_with(|| Session::new(torrent))
+                .update(value);
+        }
+    }
+
+    /// Применяет новые настройки к очереди, скоростям и кешам диска.
+    fn apply_settings(&mut self) {
+        self.queue().schedule(&mut self.repo);
+        self.sync_sessions();
+        self.apply_limits();
+        self.disk.set_cache(&self.settings.disk);
+    }
+
+    /// Применяет общие ограничения скорости с учётом расписания запасных.
+    fn apply_limits(&self) {
+        let (download, upload) = self.settings.rate_limits(SystemTime::now());
+        self.limiter.set_global(download, upload);
+    }
+
+    /// Обновляет скорости сессий по учёту ограничителя и добавляет
+    /// переданное к счётчикам торрентов. Счётчики сохраняются вместе со
+    /// временем раздачи.
+    fn refresh_rates(&mut self) {
+        for session in self.sessions.values_mut() {
+            let (downloaded, uploaded) = session.refresh(&self.limiter);
+            if downloaded == 0 && uploaded == 0 {
+                continue;
+            }
+            let mut torrent = self.repo.get_by_id_mut(session.id);
+            if let Some(resume) = torrent.as_mut().and_then(|t| t.resume.as_mut()) {
+                resume.total_downloaded += downloaded;
+                resume.total_uploaded += uploaded;
+            }
+        }
+    }
+
+    /// Меняет настройки на ходу. Изменения применяются все вместе или ни
+    /// одно.
+    fn configure(&mut self, changes: Vec<(String, String)>) -> Result<(), SettingsError> {
+        let mut settings = self.settings.clone();
+        for (key, value) in &changes {
+            // Пиры и трекеры не должны видеть, как клиент меняет имя на ходу
+            if key.starts_with("identity.") {
+                return Err(SettingsError::InvalidValue {
+                    key: key.clone(),
+                    message: "can only be changed with a restart".to_string(),
+                });
+            }
+            settings.set(key, value)?;
+        }
+        settings.validate()?;
+        self.settings = settings;
+        self.source.overrides.extend(changes);
+        self.apply_settings();
+        Ok(())
+    }
+
+    /// Перечитывает файл настроек. При ошибке остаются прежние настройки.
+    /// Peer ID и ключи трекеров не меняются до перезапуска.
+    fn reload(&mut self) -> Result<(), SettingsError> {
+        self.settings = Settings {
+            identity: self.settings.identity,
+            ..self.source.load()?
+        };
+        self.apply_settings();
+        Ok(())
+    }
+
+    /// Ключ, с которым торрент анонсируется трекерам в этой сессии.
+    fn fill_tracker_key(&self, details: &mut Value) {
+        let torrent = details["id"]
+            .as_str()
+            .and_then(|id| id.parse().ok())
+            .and_then(|id| self.repo.get_by_id(id));
+        if let Some(torrent) = torrent {
+            let key = self.settings.identity.tracker_key(&torrent.value.hash);
+            details["tracker_key"] = format!("{key:08X}").into();
+        }
+    }
+
+    /// Подставляет скорости и число пиров из сессий в описание торрента.
+    fn fill_rates(&self, summary: &mut Value) {
+        let session = summary["id"]
+            .as_str()
+            .and_then(|id| id.parse().ok())
+            .and_then(|id| self.sessions.get(&id));
+        if let Some(session) = session {
+            summary["download_rate"] = session.download_rate.into();
+            summary["upload_rate"] = session.upload_rate.into();
+            summary["connected_peers"] = session.connected_peers.into();
+        }
+    }
+}
+
+/// Счётчики дисковой подсистемы для `daemon.stats`.
+fn disk_stats(disk: &DiskIo) -> Value {
+    let stats = disk.stats();
+    json!({
+        "write_cache": stats.write_cache,
+        "read_cache": stats.read_cache,
+        "pending_pieces": stats.pending_pieces,
+        "queue_depth": stats.queue_depth,
+        "read_hits": stats.read_hits,
+        "read_misses": stats.read_misses,
+        "read_ahead": stats.read_ahead,
+        "pieces_written": stats.pieces_written,
+        "hash_failures": stats.hash_failures,
+        "spilled": stats.spilled,
+        "congested": stats.congested,
+    })
+}
+
+/// Параметры `settings.set`: объект из ключа настройки в значение.
+fn settings_changes(params: &Value) -> Result<Vec<(String, String)>, RpcError> {
+    let params = params.as_object().into_iter().flatten();
+    params
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                Value::Number(_) | Value::Bool(_) => value.to_string(),
+                Value::Null => String::new(),
+                _ => {
+                    return Err(RpcError::invalid_params(format!(
+                        "{key} must be a string, a number or a boolean"
+                    )))
+                }
+            };
+            Ok((key.clone(), value))
+        })
+        .collect()
+}
+
+struct Shared {
+    state: Mutex<State>,
+    token: Option<String>,
+    shutdown: watch::Sender<bool>,
+    started: Instant,
+    /// Значение `X-Transmission-Session-Id` на время работы демона.
+    transmission_session: String,
+}
+
+impl Shared {
+    async fn call(&self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        let mut state = self.state.lock().await;
+        match method {
+            "daemon.stats" => {
+                let stats = state.limiter.stats(None);
+                let transfer = |t: Transfer| {
+                    json!({
+                        "payload": t.payload,
+                        "overhead": t.overhead,
+                        "payload_rate": t.payload_rate,
+                        "overhead_rate": t.overhead_rate,
+                    })
+                };
+                Ok(json!({
+                    "torrents": state.repo.len(),
+                    "active": state.sessions.len(),
+                    "download_rate": stats.download.payload_rate,
+                    "upload_rate": stats.upload.payload_rate,
+                    // Демон сам не анонсирует и не качает, а пиров
+                    // ограничитель не касается
+                    "limits_enforced": false,
+                    "limited_traffic": LIMITED_TRAFFIC,
+                    "alt_speed": state.settings.alt_speed.active(SystemTime::now()),
+                    "download": transfer(stats.download),
+                    "upload": transfer(stats.upload),
+                    "disk": disk_stats(&state.disk),
+                    "uptime": self.started.elapsed().as_secs(),
+                }))
+            }
+            "daemon.shutdown" => {
+                self.shutdown.send_replace(true);
+                Ok(json!({}))
+            }
+            "settings.get" => Ok(state.settings.to_json()),
+            "settings.set" => {
+                let changes = settings_changes(params)?;
+                state
+                    .configure(changes)
+                    .map_err(|e| RpcError::invalid_params(e.to_string()))?;
+                Ok(state.settings.to_json())
+            }
+            "settings.reload" => {
+                state
+                    .reload()
+                    .map_err(|e| RpcError::failed(e.to_string()))?;
+                Ok(state.settings.to_json())
+            }
+            "torrent.recheck" => {
+                // Проверка читает все данные торрента, поэтому остальные
+                // вызовы не должны её ждать
+                let (id, metadata, storage) = methods::recheck_target(&state.repo, params)?;
+                drop(state);
+                let check = Recheck::run(&metadata, &storage).await?;
+                let mut state = self.state.lock().await;
+                let (result, _) = methods::finish_recheck(&mut state.repo, id, check, &storage)?;
+                state.commit().await?;
+                Ok(result)
+            }
+            method => state.call(method, params).await,
+        }
+    }
+}
+
+pub struct Daemon {
+    shared: Arc<Shared>,
+    #[cfg(unix)]
+    unix: tokio::net::UnixListener,
+    socket: PathBuf,
+    tcp: Option<TcpListener>,
+    transmission: Option<TcpListener>,
+}
+
+impl Daemon {
+    /// Загружает репозиторий и занимает сокеты.
+    pub async fn bind(config: DaemonConfig) -> Result<Daemon, AsyncErr> {
+        if let Some(addr) = config.listen.filter(|a| !a.ip().is_loopback()) {
+            return Err(format!(
+                "refusing to listen on {addr}: only loopback addresses are allowed"
+            )
+            .into());
+        }
+        if let Some(addr) = config.transmission {
+            if !addr.ip().is_loopback() && config.token.is_none() {
+                return Err(format!(
+                    "refusing to serve Transmission RPC on {addr} without a token"
+                )
+                .into());
+            }
+        }
+        let mut settings = config.settings.load()?;
+        tokio::fs::create_dir_all(&config.state_dir).await?;
+        let secret = tracker_secret(&config.state_dir, settings.identity.secret()).await?;
+        settings.identity.set_secret(secret);
+        let mut backend = repo_backend(&config.state_dir);
+        let mut repo = backend.load().await?;
+        for (path, e) in backend.corrupt() {
+            eprintln!("skipping a broken torrent entry {}: {e}", path.display());
+        }
+        // Торренты, чьи файлы менялись без демона, перепроверяются
+        for (id, outcome) in repo.resume_all().await {
+            if let Err(e) = outcome {
+                eprintln!("resuming torrent {id} failed: {e}");
+            }
+        }
+
+        let socket = socket_path(&config.state_dir);
+        #[cfg(unix)]
+        let unix = bind_unix(&socket).await?;
+        let tcp = match config.listen {
+            Some(addr) => Some(TcpListener::bind(addr).await?),
+            None => None,
+        };
+        let transmission = match config.transmission {
+            Some(addr) => Some(TcpListener::bind(addr).await?),
+            None => None,
+        };
+
+        let mut state = State {
+            repo,
+            backend,
+            sessions: HashMap::new(),
+            disk: Arc::new(DiskIo::new(DISK_THREADS, &settings.disk)?),
+            settings,
+            source: config.settings,
+            transmission: transmission::Ids::default(),
+            limiter: Arc::new(Limiter::new()),
+        };
+        state.apply_settings();
+        Ok(Daemon {
+            shared: Arc::new(Shared {
+                state: Mutex::new(state),
+                token: config.token,
+                shutdown: watch::channel(false).0,
+                started: Instant::now(),
+                transmission_session: transmission::session_id()?,
+            }),
+            #[cfg(unix)]
+            unix,
+            socket,
+            tcp,
+            transmission,
+        })
+    }
+
+    /// Адреса, по которым к демону можно подключиться.
+    pub fn endpoints(&self) -> Vec<Endpoint> {
+        let mut endpoints = vec![];
+        #[cfg(unix)]
+        endpoints.push(Endpoint::Unix(self.socket.clone()));
+        if let Some(addr) = self.tcp.as_ref().and_then(|l| l.local_addr().ok()) {
+            endpoints.push(Endpoint::Tcp(addr));
+        }
+        endpoints
+    }
+
+    /// Адрес совместимого с Transmission RPC, если он включён.
+    pub fn transmission_addr(&self) -> Option<SocketAddr> {
+        self.transmission.as_ref().and_then(|l| l.local_addr().ok())
+    }
+
+    /// Принимает вызовы, пока не придёт `daemon.shutdown` или не завершится
+    /// `stop`. Перед выходом сохраняет репозиторий и убирает сокет.
+    pub async fn run(self, stop: impl Future<Output = ()>) -> Result<(), AsyncErr> {
+        let mut shutdown = self.shared.shutdown.subscribe();
+        let mut hangup = Hangup::new()?;
+        let mut tick = tokio::time::interval(TICK);
+        let mut ticks: u64 = 0;
+        tokio::pin!(stop);
+        loop {
+            tokio::select! {
+                // Ошибка приёма касается одного соединения, а не демона
+                conn = accept_unix(&self) => match conn {
+                    Ok(conn) => drop(tokio::spawn(serve(conn, self.shared.clone()))),
+                    Err(e) => eprintln!("accepting a connection failed: {e}"),
+                },
+                conn = accept_tcp(&self.tcp) => match conn {
+                    Ok(conn) => drop(tokio::spawn(serve(conn, self.shared.clone()))),
+                    Err(e) => eprintln!("accepting a connection failed: {e}"),
+                },
+                conn = accept_tcp(&self.transmission) => match conn {
+                    Ok(conn) => drop(tokio::spawn(transmission::serve(conn, self.shared.clone()))),
+                    Err(e) => eprintln!("accepting a connection failed: {e}"),
+                },
+                // Расписание запасных скоростей, счётчики и цели раздачи
+                _ = tick.tick() => {
+                    let mut state = self.shared.state.lock().await;
+                    state.apply_limits();
+                    ticks += 1;
+                    if state.tick(TICK.as_secs()) || ticks.is_multiple_of(SAVE_TICKS) {
+                        if let Err(e) = state.save().await {
+                            eprintln!("saving the repository failed: {e}");
+                        }
+                    }
+                }
+                _ = hangup.recv() => {
+                    if let Err(e) = self.shared.state.lock().await.reload() {
+                        eprintln!("reloading settings failed, keeping the old ones: {e}");
+                    }
+                }
+                _ = shutdown.changed() => break,
+                _ = &mut stop => break,
+            }
+        }
+
+        self.shared.shutdown.send_replace(true);
+        let saved = self.shared.state.lock().await.save().await;
+        let _ = tokio::fs::remove_file(&self.socket).await;
+        saved
+    }
+}
+
+/// `SIGHUP`, по которому демон перечитывает настройки.
+#[cfg(unix)]
+struct Hangup(tokio::signal::unix::Signal);
+
+#[cfg(unix)]
+impl Hangup {
+    fn new() -> io::Result<Hangup> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        signal(SignalKind::hangup()).map(Hangup)
+    }
+
+    async fn recv(&mut self) {
+        if self.0.recv().await.is_none() {
+            std::future::pending().await
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct Hangup;
+
+#[cfg(not(unix))]
+impl Hangup {
+    fn new() -> io::Result<Hangup> {
+        Ok(Hangup)
+    }
+
+    async fn recv(&mut self) {
+        std::future::pending().await
+    }
+}
+
+/// Секрет для ключей трекеров из каталога состояния, чтобы трекеры узнавали
+/// торренты клиента и после перезапуска. Если файла нет или он испорчен,
+/// туда записывается `fresh`.
+async fn tracker_secret(state_dir: &Path, fresh: [u8; 16]) -> io::Result<[u8; 16]> {
+    let path = state_dir.join("secret");
+    match tokio::fs::read_to_string(&path).await {
+        Ok(text) => {
+            if let Some(secret) = from_hex(text.trim()).and_then(|b| b.try_into().ok()) {
+                return Ok(secret);
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&path).await?;
+    file.write_all(to_hex(&fresh).as_bytes()).await?;
+    file.sync_all().await?;
+    Ok(fresh)
+}
+
+/// Занимает сокет. Оставшийся от упавшего демона файл удаляется, а живой
+/// демон не даёт запустить второй.
+#[cfg(unix)]
+async fn bind_unix(path: &Path) -> Result<tokio::net::UnixListener, AsyncErr> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    if let Some(dir) = path.parent() {
+        match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                tokio::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700)).await?
+            }
+            result => result?,
+        }
+    }
+    if tokio::net::UnixStream::connect(path).await.is_ok() {
+        return Err(format!("a daemon is already running at {}", path.display()).into());
+    }
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    // Управлять демоном может только его владелец
+    tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).await?;
+    Ok(listener)
+}
+
+#[cfg(unix)]
+async fn accept_unix(daemon: &Daemon) -> io::Result<tokio::net::UnixStream> {
+    daemon.unix.accept().await.map(|(stream, _)| stream)
+}
+
+#[cfg(not(unix))]
+async fn accept_unix(_: &Daemon) -> io::Result<TcpStream> {
+    std::future::pending().await
+}
+
+async fn accept_tcp(listener: &Option<TcpListener>) -> io::Result<TcpStream> {
+    match listener {
+        Some(listener) => listener.accept().await.map(|(stream, _)| stream),
+        None => std::future::pending().await,
+    }
+}
+
+async fn serve<S: AsyncRead + AsyncWrite + Unpin>(stream: S, shared: Arc<Shared>) {
+    let mut stream = BufReader::new(stream);
+    let mut authorized = shared.token.is_none();
+    while let Ok(Some(line)) = read_message(&mut stream).await {
+        if *shared.shutdown.borrow() {
+            break;
+        }
+        let (id, result) = match Request::parse(&line) {
+            Ok(request) => {
+                let result = handle(&shared, &request, &mut authorized).await;
+                (request.id, result)
+            }
+            Err((id, error)) => (Some(id), Err(error)),
+        };
+        // На уведомления не отвечают
+        let Some(id) = id else { continue };
+        if write_message(&mut stream, &response(id, result))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+async fn handle(
+    shared: &Shared,
+    request: &Request,
+    authorized: &mut bool,
+) -> Result<Value, RpcError> {
+    if request.method == "auth" {
+        let token = request.params["token"].as_str().unwrap_or_default();
+        *authorized = shared.token.as_deref().is_none_or(|t| same_token(t, token));
+        return match authorized {
+            true => Ok(json!({ "authorized": true })),
+            false => Err(RpcError::new(UNAUTHORIZED, "invalid token")),
+        };
+    }
+    if !*authorized {
+        return Err(RpcError::new(UNAUTHORIZED, "authentication required"));
+    }
+
+    shared.call(&request.method, &request.params).await
+}
+
+/// Сравнение за время, не зависящее от того, где токены расходятся.
+fn same_token(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
@@ -0,0 +1,66 @@
+/// Модуль с сессиями запущенных торрентов.
+use std::time::Instant;
+
+use crate::{
+    network::{bandwidth::Limiter, TorrentState},
+    repository::{types::Torrent, Id, WithId},
+};
+
+/// Запущенный торрент. Пока клиент не обменивается данными с пирами,
+/// сессия только держит состояние для трекера и счётчики скорости.
+pub struct Session {
+    pub id: Id,
+    pub state: TorrentState,
+    pub started: Instant,
+    /// Байт в секунду.
+    pub download_rate: u64,
+    pub upload_rate: u64,
+    pub connected_peers: usize,
+    /// Полезные байты из учёта ограничителя, уже добавленные к счётчикам
+    /// торрента: скачано и отдано.
+    counted: (u64, u64),
+}
+
+impl Session {
+    pub fn new(torrent: &WithId<Torrent>) -> Session {
+        Session {
+            id: torrent.id,
+            state: state(&torrent.value),
+            started: Instant::now(),
+            download_rate: 0,
+            upload_rate: 0,
+            connected_peers: 0,
+            counted: (0, 0),
+        }
+    }
+
+    /// Берёт скорости полезной нагрузки из учёта ограничителя. Возвращает,
+    /// сколько полезных байт скачано и отдано с прошлого раза.
+    pub fn refresh(&mut self, limiter: &Limiter) -> (u64, u64) {
+        let stats = limiter.stats(Some(self.id));
+        self.download_rate = stats.download.payload_rate;
+        self.upload_rate = stats.upload.payload_rate;
+
+        let total = (stats.download.payload, stats.upload.payload);
+        let delta = (
+            total.0.saturating_sub(self.counted.0),
+            total.1.saturating_sub(self.counted.1),
+        );
+        self.counted = total;
+        delta
+    }
+
+    /// Подхватывает изменения торрента в репозитории.
+    pub fn update(&mut self, torrent: &Torrent) {
+        if self.state.torrent != *torrent {
+            self.state = TorrentState {
+                uploaded: self.state.uploaded,
+                ..state(torrent)
+            };
+        }
+    }
+}
+
+fn state(torrent: &Torrent) -> TorrentState {
+    TorrentState::new(torrent.clone(), &torrent.storage())
+}
@@ -0,0 +1,913 @@
+/// Модуль с RPC, совместимым с Transmission.
+///
+/// Поддерживаются методы, которыми пользуются панели и скрипты:
+/// `torrent-get`, `torrent-add`, `torrent-start`, `torrent-stop`,
+/// `torrent-verify`, `torrent-remove`, `session-get`, `session-set` и
+/// `session-stats`. Запрос без действующего `X-Transmission-Session-Id`
+/// получает 409 с правильным значением заголовка, как у Transmission.
+/// Токен демона проверяется как пароль HTTP Basic, имя не важно.
+use std::{collections::HashMap, io, path::Path, sync::Arc};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Map, Value};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+
+use crate::{
+    repository::{
+        queue::Goal,
+        types::{Priority, Status, Torrent},
+        Id, WithId,
+    },
+    rpc::MAX_MESSAGE,
+    settings::{format_days, format_ratio, ALL_DAYS},
+    tools::to_hex,
+};
+
+use super::{same_token, Shared, State};
+
+pub const RPC_PATH: &str = "/transmission/rpc";
+pub const SESSION_HEADER: &str = "X-Transmission-Session-Id";
+const RPC_VERSION: u64 = 17;
+const RPC_VERSION_MINIMUM: u64 = 14;
+/// Скорости в Transmission измеряются в kB/s.
+const SPEED_BYTES: u64 = 1000;
+/// Самый длинный заголовок запроса.
+const MAX_HEAD: usize = 64 * 1024;
+
+/// Коды состояния торрента в Transmission.
+const STOPPED: u64 = 0;
+const DOWNLOAD_WAIT: u64 = 3;
+const DOWNLOAD: u64 = 4;
+const SEED_WAIT: u64 = 5;
+const SEED: u64 = 6;
+
+/// Режимы целей раздачи в Transmission.
+const MODE_GLOBAL: u64 = 0;
+const MODE_SINGLE: u64 = 1;
+const MODE_UNLIMITED: u64 = 2;
+
+/// Поля `torrent-get`, которые отдаются, если клиент не перечислил свои.
+const FIELDS: &[&str] = &[
+    "id",
+    "name",
+    "hashString",
+    "status",
+    "percentDone",
+    "totalSize",
+    "sizeWhenDone",
+    "leftUntilDone",
+    "haveValid",
+    "downloadDir",
+    "rateDownload",
+    "rateUpload",
+    "eta",
+    "labels",
+    "addedDate",
+    "doneDate",
+    "uploadedEver",
+    "downloadedEver",
+    "uploadRatio",
+    "error",
+    "errorString",
+    "isFinished",
+    "isPrivate",
+    "comment",
+    "creator",
+    "dateCreated",
+    "pieceCount",
+    "pieceSize",
+    "peersConnected",
+    "downloadLimit",
+    "downloadLimited",
+    "uploadLimit",
+    "uploadLimited",
+    "queuePosition",
+    "seedRatioMode",
+    "seedRatioLimit",
+    "seedIdleMode",
+    "seedIdleLimit",
+    "files",
+    "fileStats",
+    "trackers",
+];
+
+/// Числовые id торрентов. Как и Transmission, они раздаются по порядку и
+/// не переиспользуются до перезапуска демона.
+#[derive(Debug, Default)]
+pub struct Ids {
+    by_id: HashMap<Id, u64>,
+    last: u64,
+}
+
+impl Ids {
+    fn number(&mut self, id: Id) -> u64 {
+        *self.by_id.entry(id).or_insert_with(|| {
+            self.last += 1;
+            self.last
+        })
+    }
+
+    fn find(&self, number: u64) -> Option<Id> {
+        self.by_id
+            .iter()
+            .find(|(_, n)| **n == number)
+            .map(|(id, _)| *id)
+    }
+}
+
+/// Случайное значение `X-Transmission-Session-Id`.
+pub(super) fn session_id() -> io::Result<String> {
+    let mut bytes = [0; 24];
+    getrandom::getrandom(&mut bytes)?;
+    Ok(to_hex(&bytes))
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    /// Соединение закрывается после ответа.
+    close: bool,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Читает запрос. `None`, если клиент закрыл соединение между запросами.
+async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<HttpRequest>> {
+    let mut head = vec![];
+    loop {
+        let limit = (MAX_HEAD - head.len()) as u64;
+        let read = (&mut *reader)
+            .take(limit)
+            .read_until(b'\n', &mut head)
+            .await?;
+        if read == 0 {
+            return match head.is_empty() {
+                true => Ok(None),
+                false => Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+        }
+        if head.ends_with(b"\r\n\r\n") || head.ends_with(b"\n\n") {
+            break;
+        }
+        if head.len() >= MAX_HEAD {
+            return Err(invalid("request head is too long"));
+        }
+    }
+
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut request = httparse::Request::new(&mut headers);
+    if !matches!(request.parse(&head), Ok(httparse::Status::Complete(_))) {
+        return Err(invalid("malformed request"));
+    }
+    let headers: Vec<(String, String)> = request
+        .headers
+        .iter()
+        .map(|h| {
+            (
+                h.name.to_string(),
+                String::from_utf8_lossy(h.value).into_owned(),
+            )
+        })
+        .collect();
+    let mut request = HttpRequest {
+        method: request.method.unwrap_or_default().to_string(),
+        path: request.path.unwrap_or_default().to_string(),
+        close: request.version == Some(0),
+        headers,
+        body: vec![],
+    };
+    match request.header("Connection").map(str::to_ascii_lowercase) {
+        Some(c) if c == "close" => request.close = true,
+        Some(c) if c == "keep-alive" => request.close = false,
+        _ => {}
+    }
+
+    let length: u64 = match request.header("Content-Length") {
+        Some(length) => length.trim().parse().map_err(|_| invalid("bad length"))?,
+        None => 0,
+    };
+    if length > MAX_MESSAGE {
+        return Err(invalid("request body is too long"));
+    }
+    request.body = vec![0; length as usize];
+    reader.read_exact(&mut request.body).await?;
+    Ok(Some(request))
+}
+
+async fn write_response<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    status: u16,
+    headers: &[(&str, String)],
+    body: &[u8],
+) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Error",
+    };
+    let mut head = format!("HTTP/1.1 {status} {reason}\r\n");
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(body).await?;
+    writer.flush().await
+}
+
+pub(super) async fn serve<S: AsyncRead + AsyncWrite + Unpin>(stream: S, shared: Arc<Shared>) {
+    let mut stream = BufReader::new(stream);
+    loop {
+        let request = match read_request(&mut stream).await {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(_) => {
+                let _ = write_response(&mut stream, 400, &[], b"400: Bad Request").await;
+                break;
+            }
+        };
+        if *shared.shutdown.borrow() {
+            break;
+        }
+        let (status, headers, body) = respond(&shared, &request).await;
+        if write_response(&mut stream, status, &headers, &body)
+            .await
+            .is_err()
+            || request.close
+        {
+            break;
+        }
+    }
+}
+
+fn authorized(request: &HttpRequest, token: &str) -> bool {
+    request
+        .header("Authorization")
+        .and_then(|h| h.strip_prefix("Basic "))
+        .and_then(|credentials| STANDARD.decode(credentials.trim()).ok())
+        .and_then(|credentials| String::from_utf8(credentials).ok())
+        .and_then(|credentials| {
+            let (_, password) = credentials.split_once(':')?;
+            Some(same_token(token, password))
+        })
+        .unwrap_or(false)
+}
+
+async fn respond(
+    shared: &Shared,
+    request: &HttpRequest,
+) -> (u16, Vec<(&'static str, String)>, Vec<u8>) {
+    let path = request.path.split('?').next().unwrap_or_default();
+    if path.trim_end_matches('/') != RPC_PATH {
+        return (404, vec![], b"404: Not Found".to_vec());
+    }
+    if let Some(token) = &shared.token {
+        if !authorized(request, token) {
+            let challenge = "Basic realm=\"Transmission\"".to_string();
+            return (
+                401,
+                vec![("WWW-Authenticate", challenge)],
+                b"401: Unauthorized".to_vec(),
+            );
+        }
+    }
+    let session = &shared.transmission_session;
+    if request.header(SESSION_HEADER) != Some(session.as_str()) {
+        let body = format!(
+            "<h1>409: Conflict</h1><p>Your request had an invalid session-id header.</p>\
+             <p><code>{SESSION_HEADER}: {session}</code></p>"
+        );
+        return (409, vec![(SESSION_HEADER, session.clone())], body.into());
+    }
+    if request.method != "POST" {
+        return (405, vec![], b"405: Method Not Allowed".to_vec());
+    }
+    let Ok(body) = serde_json::from_slice::<Value>(&request.body) else {
+        return (400, vec![], b"400: Bad Request".to_vec());
+    };
+
+    let method = body["method"].as_str().unwrap_or_default();
+    let arguments = match &body["arguments"] {
+        Value::Null => json!({}),
+        arguments => arguments.clone(),
+    };
+    let mut response = match call(shared, method, &arguments).await {
+        Ok(arguments) => json!({ "result": "success", "arguments": arguments }),
+        Err(message) => json!({ "result": message, "arguments": {} }),
+    };
+    if let Some(tag) = body.get("tag") {
+        response["tag"] = tag.clone();
+    }
+    let headers = vec![
+        (SESSION_HEADER, session.clone()),
+        ("Content-Type", "application/json".to_string()),
+    ];
+    (200, headers, response.to_string().into())
+}
+
+/// Выполняет метод Transmission. Ошибка — это строка для поля `result`.
+async fn call(shared: &Shared, method: &str, args: &Value) -> Result<Value, String> {
+    let mut guard = shared.state.lock().await;
+    let state = &mut *guard;
+    // Номера получают все торренты, даже ещё не запрошенные
+    for torrent in state.repo.iter() {
+        state.transmission.number(torrent.id);
+    }
+    if method == "torrent-verify" {
+        // Проверка идёт через демон, который не держит состояние всё это время
+        let ids = selected(state, &args["ids"])?;
+        drop(guard);
+        for id in ids {
+            shared
+                .call("torrent.recheck", &json!({ "id": id.to_string() }))
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        return Ok(json!({}));
+    }
+
+    match method {
+        "torrent-get" => torrent_get(state, args),
+        "torrent-add" => torrent_add(state, args).await,
+        "torrent-start" | "torrent-start-now" => {
+            each(state, args, "torrent.start", json!({})).await
+        }
+        "torrent-stop" => each(state, args, "torrent.stop", json!({})).await,
+        "torrent-set" => {
+            let params = torrent_set_params(args)?;
+            each(state, args, "torrent.set", params).await?;
+            match args["queuePosition"].as_u64() {
+                Some(position) => {
+                    let position = json!({ "position": position });
+                    each(state, args, "torrent.queue", position).await
+                }
+                None => Ok(json!({})),
+            }
+        }
+        "queue-move-top" | "queue-move-up" | "queue-move-down" | "queue-move-bottom" => {
+            let to = json!({ "move": method.trim_start_matches("queue-move-") });
+            each(state, args, "torrent.queue", to).await
+        }
+        "torrent-remove" => {
+            let delete = json!({ "delete_data": flag(&args["delete-local-data"]) });
+            each(state, args, "torrent.remove", delete).await
+        }
+        "session-get" => Ok(session_get(state, shared, args)),
+        "session-set" => session_set(state, args),
+        "session-stats" => Ok(session_stats(state, shared)),
+        _ => Err("method name not recognized".to_string()),
+    }
+}
+
+/// Ограничения скорости и цели раздачи для `torrent.set`. Остальные поля
+/// `torrent-set` пока не поддерживаются и пропускаются.
+fn torrent_set_params(args: &Value) -> Result<Value, String> {
+    let mut params = json!({});
+    for (name, key) in [
+        ("downloadLimit", "download_limit"),
+        ("uploadLimit", "upload_limit"),
+    ] {
+        let flag = format!("{name}ed");
+        let limit = match (args[&flag].as_bool(), args[name].as_u64()) {
+            (Some(false), _) => 0,
+            (_, Some(limit)) => speed_bytes(name, limit)?,
+            (Some(true), None) => return Err(format!("{name} is required to enable it")),
+            (None, None) => continue,
+        };
+        params[key] = limit.into();
+    }
+    for (mode, name, key) in [
+        ("seedRatioMode", "seedRatioLimit", "ratio_goal"),
+        ("seedIdleMode", "seedIdleLimit", "idle_goal"),
+    ] {
+        let goal = match (args[mode].as_u64(), &args[name]) {
+            (Some(MODE_GLOBAL), _) => json!("global"),
+            (Some(MODE_UNLIMITED), _) => json!("unlimited"),
+            (Some(MODE_SINGLE) | None, Value::Number(limit)) => json!(limit),
+            (Some(MODE_SINGLE), _) => return Err(format!("{name} is required for {mode} 1")),
+            (None, _) => continue,
+            (Some(other), _) => return Err(format!("unknown {mode} {other}")),
+        };
+        params[key] = goal;
+    }
+    Ok(params)
+}
+
+/// Скорость из КБ/с Transmission в байты в секунду.
+fn speed_bytes(name: &str, rate: u64) -> Result<u64, String> {
+    rate.checked_mul(SPEED_BYTES)
+        .ok_or_else(|| format!("{name} is too large"))
+}
+
+fn goal_mode(goal: Goal) -> u64 {
+    match goal {
+        Goal::Global => MODE_GLOBAL,
+        Goal::Unlimited => MODE_UNLIMITED,
+        Goal::Limit(_) => MODE_SINGLE,
+    }
+}
+
+/// Булево значение, которое некоторые клиенты присылают числом.
+fn flag(value: &Value) -> bool {
+    value
+        .as_bool()
+        .or_else(|| value.as_u64().map(|n| n != 0))
+        .unwrap_or(false)
+}
+
+fn hash_string(torrent: &Torrent) -> String {
+    to_hex(&torrent.hash.short())
+}
+
+/// Торренты из `ids`: числа, хеши в hex или `recently-active`. Без `ids`
+/// выбираются все. Неизвестные id пропускаются, как в Transmission.
+fn selected(state: &State, ids: &Value) -> Result<Vec<Id>, String> {
+    let by_hash = |hash: &str| {
+        state
+            .repo
+            .iter()
+            .find(|t| {
+                let hash = hash.to_lowercase();
+                hash_string(&t.value) == hash
+                    || t.value.hash.v2().is_some_and(|h| to_hex(&h) == hash)
+            })
+            .map(|t| t.id)
+    };
+    let one = |id: &Value| match id {
+        Value::Number(n) => Ok(n.as_u64().and_then(|n| state.transmission.find(n))),
+        Value::String(hash) => Ok(by_hash(hash)),
+        _ => Err("invalid torrent id".to_string()),
+    };
+    match ids {
+        Value::Null => Ok(state.repo.iter().map(|t| t.id).collect()),
+        Value::String(s) if s == "recently-active" => Ok(state.repo.iter().map(|t| t.id).collect()),
+        Value::Array(ids) => ids.iter().filter_map(|id| one(id).transpose()).collect(),
+        id => Ok(one(id)?.into_iter().collect()),
+    }
+}
+
+fn torrent_get(state: &mut State, args: &Value) -> Result<Value, String> {
+    let fields: Vec<&str> = match &args["fields"] {
+        Value::Array(fields) => fields.iter().filter_map(Value::as_str).collect(),
+        _ => FIELDS.to_vec(),
+    };
+    let torrents: Vec<Value> = selected(state, &args["ids"])?
+        .into_iter()
+        .filter_map(|id| state.repo.get_by_id(id))
+        .map(|torrent| torrent_fields(state, torrent, &fields))
+        .collect();
+
+    let mut result = json!({ "torrents": torrents });
+    if args["ids"] == "recently-active" {
+        result["removed"] = json!([]);
+    }
+    Ok(result)
+}
+
+fn torrent_fields(state: &State, torrent: &WithId<Torrent>, fields: &[&str]) -> Value {
+    let value = &torrent.value;
+    let metadata = &value.metadata;
+    let storage = value.storage();
+    let session = state.sessions.get(&torrent.id);
+    let seeding = &state.settings.seeding;
+    let (rate_down, rate_up) = session.map_or((0, 0), |s| (s.download_rate, s.upload_rate));
+    let resume = value.resume.as_ref();
+    let total = metadata.info.total_length();
+    let wanted = storage.left(&[]);
+    let left = storage.left(&value.downloaded_pieces);
+    let uploaded = resume.map_or(0, |r| r.total_uploaded);
+    let downloaded = resume.map_or(0, |r| r.total_downloaded);
+    // Файлы выравнивания клиентам не показываются
+    let files: Vec<usize> = (0..storage.files().len())
+        .filter(|&i| !storage.files()[i].is_padding())
+        .collect();
+    let progress = storage.file_progress(&value.downloaded_pieces);
+
+    let field = |name: &str| -> Option<Value> {
+        Some(match name {
+            "id" => json!(state.transmission.by_id[&torrent.id]),
+            "name" => json!(metadata.info.name()),
+            "hashString" => json!(hash_string(value)),
+            "status" => json!(match value.status() {
+                Status::Paused => STOPPED,
+                Status::Queued if value.is_complete() => SEED_WAIT,
+                Status::Queued => DOWNLOAD_WAIT,
+                Status::Downloading => DOWNLOAD,
+                Status::Seeding => SEED,
+            }),
+            "percentDone" => json!(match wanted {
+                0 => 1.0,
+                wanted => (wanted - left) as f64 / wanted as f64,
+            }),
+            "totalSize" => json!(total),
+            "sizeWhenDone" => json!(wanted),
+            "leftUntilDone" => json!(left),
+            "haveValid" => json!(value.downloaded),
+            "downloadDir" => json!(value.save_path()),
+            "rateDownload" => json!(rate_down),
+            "rateUpload" => json!(rate_up),
+            "eta" => match rate_down {
+                0 => json!(-1),
+                rate => json!(left / rate),
+            },
+            "labels" => json!(value.labels),
+            "addedDate" => json!(resume.map_or(0, |r| r.added)),
+            "doneDate" => json!(resume.and_then(|r| r.completed).unwrap_or(0)),
+            "uploadedEver" => json!(uploaded),
+            "downloadedEver" => json!(downloaded),
+            "uploadRatio" => match downloaded {
+                0 => json!(-1),
+                downloaded => json!(uploaded as f64 / downloaded as f64),
+            },
+            "error" => json!(0),
+            "errorString" => json!(""),
+            "isFinished" => json!(value.queue.finished),
+            "isPrivate" => json!(metadata.info.private == Some(1)),
+            "comment" => json!(metadata.comment.as_deref().unwrap_or_default()),
+            "creator" => json!(metadata.created_by.as_deref().unwrap_or_default()),
+            "dateCreated" => json!(metadata.creation_date.unwrap_or(0)),
+            "pieceCount" => json!(metadata.info.piece_count()),
+            "pieceSize" => json!(metadata.info.piece_length),
+            "peersConnected" => json!(session.map_or(0, |s| s.connected_peers)),
+            "downloadLimit" => json!(value.download_limit.map_or(0, |l| l / SPEED_BYTES)),
+            "downloadLimited" => json!(value.download_limit.is_some()),
+            "uploadLimit" => json!(value.upload_limit.map_or(0, |l| l / SPEED_BYTES)),
+            "uploadLimited" => json!(value.upload_limit.is_some()),
+            "queuePosition" => json!(value.queue.position),
+            "seedRatioMode" => json!(goal_mode(value.queue.ratio_goal)),
+            "seedRatioLimit" => json!(value
+                .queue
+                .ratio_goal
+                .resolve(seeding.ratio)
+                .map_or(0.0, format_ratio)),
+            "seedIdleMode" => json!(goal_mode(value.queue.idle_goal)),
+            "seedIdleLimit" => json!(value.queue.idle_goal.resolve(seeding.idle).unwrap_or(0)),
+            "files" => files
+                .iter()
+                .map(|&i| {
+                    let file = &storage.files()[i];
+                    json!({
+                        "name": file.path.join("/"),
+                        "length": file.length,
+                        "bytesCompleted": progress[i],
+                    })
+                })
+                .collect(),
+            "fileStats" => files
+                .iter()
+                .map(|&i| {
+                    let priority = value.priority(i);
+                    json!({
+                        "bytesCompleted": progress[i],
+                        "wanted": priority != Priority::Skip,
+                        "priority": match priority {
+                            Priority::Low => -1,
+                            Priority::High => 1,
+                            Priority::Skip | Priority::Normal => 0,
+                        },
+                    })
+                })
+                .collect(),
+            "trackers" => {
+                let tiers = metadata
+                    .announce_list
+                    .clone()
+                    .unwrap_or_else(|| vec![vec![metadata.announce.clone()]]);
+                tiers
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(tier, urls)| urls.iter().map(move |url| (tier, url)))
+                    .enumerate()
+                    .map(|(id, (tier, url))| {
+                        json!({ "id": id, "tier": tier, "announce": url, "scrape": "" })
+                    })
+                    .collect()
+            }
+            _ => return None,
+        })
+    };
+
+    let object: Map<String, Value> = fields
+        .iter()
+        .filter_map(|name| field(name).map(|v| (name.to_string(), v)))
+        .collect();
+    object.into()
+}
+
+async fn torrent_add(state: &mut State, args: &Value) -> Result<Value, String> {
+    let metainfo = if let Some(metainfo) = args["metainfo"].as_str() {
+        let metainfo: String = metainfo.split_whitespace().collect();
+        STANDARD
+            .decode(metainfo)
+            .map_err(|e| format!("invalid metainfo: {e}"))?
+    } else if let Some(filename) = args["filename"].as_str() {
+        if filename.starts_with("magnet:") {
+            return Err("magnet links are not supported yet".to_string());
+        }
+        if filename.starts_with("http://") || filename.starts_with("https://") {
+            return Err("adding torrents by URL is not supported yet".to_string());
+        }
+        tokio::fs::read(filename)
+            .await
+            .map_err(|e| format!("{filename}: {e}"))?
+    } else {
+        return Err("no filename or metainfo specified".to_string());
+    };
+
+    let mut params = json!({
+        "metainfo": STANDARD.encode(metainfo),
+        "paused": flag(&args["paused"]),
+        "labels": args["labels"],
+    });
+    if let Some(dir) = args["download-dir"].as_str() {
+        params["save_path"] = dir.into();
+    }
+    let added = state
+        .call("torrent.add", &params)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let id: Id = added["id"].as_str().unwrap_or_default().parse().unwrap();
+    let torrent = &state.repo.get_by_id(id).unwrap().value;
+    let key = match added["merged"] == true {
+        true => "torrent-duplicate",
+        false => "torrent-added",
+    };
+    let hash = hash_string(torrent);
+    Ok(json!({
+        key: {
+            "id": state.transmission.number(id),
+            "name": added["name"],
+            "hashString": hash,
+        }
+    }))
+}
+
+/// Выполняет метод для каждого выбранного торрента.
+async fn each(
+    state: &mut State,
+    args: &Value,
+    method: &str,
+    extra: Value,
+) -> Result<Value, String> {
+    for id in selected(state, &args["ids"])? {
+        let mut params = extra.clone();
+        params["id"] = id.to_string().into();
+        state
+            .call(method, &params)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(json!({}))
+}
+
+fn session_get(state: &State, shared: &Shared, args: &Value) -> Value {
+    let settings = &state.settings;
+    let limits = &settings.limits;
+    let alt = &settings.alt_speed;
+    let (queue, seeding) = (&settings.queue, &settings.seeding);
+    let all = json!({
+        "version": format!("{} (bittorrent_client)", env!("CARGO_PKG_VERSION")),
+        "rpc-version": RPC_VERSION,
+        "rpc-version-minimum": RPC_VERSION_MINIMUM,
+        "session-id": shared.transmission_session,
+        "download-dir": settings.download_dir,
+        "peer-port": settings.listen_port,
+        "speed-limit-down-enabled": limits.download_rate.is_some(),
+        "speed-limit-down": limits.download_rate.map_or(0, |r| r / SPEED_BYTES),
+        "speed-limit-up-enabled": limits.upload_rate.is_some(),
+        "speed-limit-up": limits.upload_rate.map_or(0, |r| r / SPEED_BYTES),
+        "peer-limit-global": limits.connections,
+        "peer-limit-per-torrent": limits.connections_per_torrent,
+        "alt-speed-enabled": alt.enabled,
+        "alt-speed-down": alt.download_rate.map_or(0, |r| r / SPEED_BYTES),
+        "alt-speed-up": alt.upload_rate.map_or(0, |r| r / SPEED_BYTES),
+        "alt-speed-time-enabled": alt.scheduled,
+        "alt-speed-time-begin": alt.begin,
+        "alt-speed-time-end": alt.end,
+        // Биты дней совпадают: воскресенье — 1, суббота — 64
+        "alt-speed-time-day": alt.days,
+        "download-queue-enabled": queue.downloads.is_some(),
+        "download-queue-size": queue.downloads.unwrap_or(0),
+        "seed-queue-enabled": queue.seeds.is_some(),
+        "seed-queue-size": queue.seeds.unwrap_or(0),
+        "seedRatioLimited": seeding.ratio.is_some(),
+        "seedRatioLimit": seeding.ratio.map_or(0.0, format_ratio),
+        "idle-seeding-limit-enabled": seeding.idle.is_some(),
+        "idle-seeding-limit": seeding.idle.unwrap_or(0),
+        "dht-enabled": settings.protocols.dht,
+        "pex-enabled": settings.protocols.pex,
+        "lpd-enabled": settings.protocols.lsd,
+        "utp-enabled": settings.protocols.utp,
+        "cache-size-mb": settings.disk.write_cache >> 20,
+        // У Transmission нет значения для только открытых соединений, а
+        // "tolerated" означает, что шифрованные тоже принимаются
+        "encryption": settings.encryption.name(),
+        "units": {
+            "speed-units": ["kB/s", "MB/s", "GB/s", "TB/s"],
+            "speed-bytes": SPEED_BYTES,
+            "size-units": ["kB", "MB", "GB", "TB"],
+            "size-bytes": 1000,
+            "memory-units": ["KiB", "MiB", "GiB", "TiB"],
+            "memory-bytes": 1024,
+        },
+    });
+    match args["fields"].as_array() {
+        Some(fields) => fields
+            .iter()
+            .filter_map(Value::as_str)
+            .filter_map(|f| all.get(f).map(|v| (f.to_string(), v.clone())))
+            .collect::<Map<_, _>>()
+            .into(),
+        None => all,
+    }
+}
+
+/// Меняет настройки сессии. Ключи без соответствия в настройках
+/// пропускаются.
+fn session_set(state: &mut State, args: &Value) -> Result<Value, String> {
+    let mut changes = vec![];
+    let mut change = |key: &str, value: String| changes.push((key.to_string(), value));
+    let text = |name: &str| match &args[name] {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    };
+
+    if let Some(dir) = args.get("download-dir") {
+        let dir = dir.as_str().filter(|d| Path::new(d).is_absolute());
+        change(
+            "download_dir",
+            dir.ok_or("download-dir must be an absolute path")?.into(),
+        );
+    }
+    for (name, key) in [
+        ("peer-port", "listen_port"),
+        ("peer-limit-global", "limits.connections"),
+        ("peer-limit-per-torrent", "limits.connections_per_torrent"),
+        ("dht-enabled", "protocols.dht"),
+        ("pex-enabled", "protocols.pex"),
+        ("lpd-enabled", "protocols.lsd"),
+        ("utp-enabled", "protocols.utp"),
+    ] {
+        if let Some(value) = text(name) {
+            change(key, value);
+        }
+    }
+    for (name, key) in [
+        ("alt-speed-enabled", "alt_speed.enabled"),
+        ("alt-speed-time-enabled", "alt_speed.scheduled"),
+    ] {
+        if let Some(value) = text(name) {
+            change(key, value);
+        }
+    }
+    for (name, key) in [
+        ("alt-speed-down", "alt_speed.download_rate"),
+        ("alt-speed-up", "alt_speed.upload_rate"),
+    ] {
+        if let Some(rate) = args[name].as_u64() {
+            change(key, speed_bytes(name, rate)?.to_string());
+        }
+    }
+    for (name, key) in [
+        ("alt-speed-time-begin", "alt_speed.begin"),
+        ("alt-speed-time-end", "alt_speed.end"),
+    ] {
+        if let Some(minutes) = args[name].as_u64() {
+            if minutes >= 24 * 60 {
+                return Err(format!("{name} must be minutes after midnight"));
+            }
+            change(key, format!("{:02}:{:02}", minutes / 60, minutes % 60));
+        }
+    }
+    if let Some(days) = args["alt-speed-time-day"].as_u64() {
+        let days = u8::try_from(days)
+            .ok()
+            .filter(|&d| d != 0 && d <= ALL_DAYS)
+            .ok_or("alt-speed-time-day must be a non-empty set of days")?;
+        change("alt_speed.days", format_days(days));
+    }
+    if let Some(size) = args["cache-size-mb"].as_u64() {
+        change("disk.write_cache", format!("{size}MiB"));
+    }
+    if let Some(encryption) = args["encryption"].as_str() {
+        if encryption == "tolerated" {
+            return Err(
+                "encryption tolerated is not supported: connections are always plain".into(),
+            );
+        }
+        change("encryption", encryption.into());
+    }
+    // Ограничение скорости в Transmission — это число и отдельный флаг
+    let limits = &state.settings.limits;
+    for (name, key, current) in [
+        (
+            "speed-limit-down",
+            "limits.download_rate",
+            limits.download_rate,
+        ),
+        ("speed-limit-up", "limits.upload_rate", limits.upload_rate),
+    ] {
+        let flag = format!("{name}-enabled");
+        if args.get(name).is_none() && args.get(&flag).is_none() {
+            continue;
+        }
+        let enabled = args[&flag].as_bool().unwrap_or(current.is_some());
+        let limit = match args[name].as_u64() {
+            Some(limit) => Some(speed_bytes(name, limit)?),
+            None => current,
+        };
+        match (enabled, limit) {
+            (false, _) => change(key, "unlimited".into()),
+            (true, Some(limit)) => change(key, limit.to_string()),
+            (true, None) => return Err(format!("{name} is required to enable it")),
+        }
+    }
+    // Очереди и цели раздачи задаются так же
+    let (queue, seeding) = (&state.settings.queue, &state.settings.seeding);
+    for (flag, name, key, current) in [
+        (
+            "download-queue-enabled",
+            "download-queue-size",
+            "queue.downloads",
+            queue.downloads.map(|n| n.to_string()),
+        ),
+        (
+            "seed-queue-enabled",
+            "seed-queue-size",
+            "queue.seeds",
+            queue.seeds.map(|n| n.to_string()),
+        ),
+        (
+            "seedRatioLimited",
+            "seedRatioLimit",
+            "seeding.ratio",
+            seeding.ratio.map(|r| format_ratio(r).to_string()),
+        ),
+        (
+            "idle-seeding-limit-enabled",
+            "idle-seeding-limit",
+            "seeding.idle",
+            seeding.idle.map(|m| m.to_string()),
+        ),
+    ] {
+        if args.get(name).is_none() && args.get(flag).is_none() {
+            continue;
+        }
+        let enabled = args[flag].as_bool().unwrap_or(current.is_some());
+        let value = match &args[name] {
+            Value::Number(n) => Some(n.to_string()),
+            _ => current,
+        };
+        match (enabled, value) {
+            (false, _) => change(key, "none".into()),
+            (true, Some(value)) => change(key, value),
+            (true, None) => return Err(format!("{name} is required to enable it")),
+        }
+    }
+
+    state.configure(changes).map_err(|e| e.to_string())?;
+    Ok(json!({}))
+}
+
+fn session_stats(state: &State, shared: &Shared) -> Value {
+    let resumes = || state.repo.iter().filter_map(|t| t.value.resume.as_ref());
+    let stats = json!({
+        "uploadedBytes": resumes().map(|r| r.total_uploaded).sum::<u64>(),
+        "downloadedBytes": resumes().map(|r| r.total_downloaded).sum::<u64>(),
+        "filesAdded": 0,
+        "sessionCount": 1,
+        "secondsActive": shared.started.elapsed().as_secs(),
+    });
+    let sessions = state.sessions.values();
+    json!({
+        "activeTorrentCount": state.sessions.len(),
+        "pausedTorrentCount": state.repo.iter_by_status(Status::Paused).count(),
+        "torrentCount": state.repo.len(),
+        "downloadSpeed": sessions.clone().map(|s| s.download_rate).sum::<u64>(),
+        "uploadSpeed": sessions.map(|s| s.upload_rate).sum::<u64>(),
+        "cumulative-stats": stats,
+        "current-stats": stats,
+    })
+}
@@ -1,10 +1,14 @@
-/// The constats for parsing. Don't change to maintain backward compability.
+//! The constants for parsing. Specification keys never change. Repo keys may
+//! change only together with a format version bump and a migration in
+//! `repository::format`.
 
 // Specification constants
 pub const PATH: &[u8] = b"path";
+pub const PATH_UTF8: &[u8] = b"path.utf-8";
 pub const LENGTH: &[u8] = b"length";
 pub const MD5SUM: &[u8] = b"md5sum";
 pub const NAME: &[u8] = b"name";
+pub const NAME_UTF8: &[u8] = b"name.utf-8";
 pub const FILES: &[u8] = b"files";
 pub const PIECE_LENGTH: &[u8] = b"piece length";
 pub const PIECES: &[u8] = b"pieces";
@@ -17,12 +21,56 @@ pub const ANNOUNCE_LIST: &[u8] = b"announce-list";
 pub const CREATION_DATE: &[u8] = b"creation date";
 pub const COMMENT: &[u8] = b"comment";
 pub const CREATED_BY: &[u8] = b"created by";
+pub const URL_LIST: &[u8] = b"url-list";
+pub const META_VERSION: &[u8] = b"meta version";
+pub const FILE_TREE: &[u8] = b"file tree";
+pub const FILE_ENTRY: &[u8] = b"";
+pub const PIECES_ROOT: &[u8] = b"pieces root";
+pub const PIECE_LAYERS: &[u8] = b"piece layers";
+pub const ATTR: &[u8] = b"attr";
+pub const SYMLINK_PATH: &[u8] = b"symlink path";
+pub const SHA1: &[u8] = b"sha1";
 
 // Repo constants
 pub const DATA: &[u8] = b"data";
 pub const VALUE: &[u8] = b"value";
 pub const ID: &[u8] = b"id";
 pub const TORRENTS: &[u8] = b"torrents";
+pub const VERSION: &[u8] = b"version";
 pub const HASH: &[u8] = b"hash";
+pub const HASH_V2: &[u8] = b"hash_v2";
 pub const DOWNLOADED: &[u8] = b"downloaded";
+pub const DOWNLOAD_LIMIT: &[u8] = b"download_limit";
 pub const DOWNLOADED_PIECES: &[u8] = b"downloaded_pieces";
+pub const PRIORITIES: &[u8] = b"priorities";
+pub const PAUSED: &[u8] = b"paused";
+pub const LABELS: &[u8] = b"labels";
+pub const RESUME: &[u8] = b"resume";
+pub const UPLOAD_LIMIT: &[u8] = b"upload_limit";
+pub const SAVE_PATH: &[u8] = b"save_path";
+pub const SIZE: &[u8] = b"size";
+pub const MTIME: &[u8] = b"mtime";
+pub const UNFINISHED: &[u8] = b"unfinished";
+pub const PIECE: &[u8] = b"piece";
+pub const BLOCKS: &[u8] = b"blocks";
+pub const TOTAL_UPLOADED: &[u8] = b"total_uploaded";
+pub const TOTAL_DOWNLOADED: &[u8] = b"total_downloaded";
+pub const PEERS: &[u8] = b"peers";
+pub const PEERS6: &[u8] = b"peers6";
+pub const TRACKERS: &[u8] = b"trackers";
+pub const URL: &[u8] = b"url";
+pub const LAST_ANNOUNCE: &[u8] = b"last_announce";
+pub const INTERVAL: &[u8] = b"interval";
+pub const TRACKER_ID: &[u8] = b"tracker_id";
+pub const FAILURES: &[u8] = b"failures";
+pub const ADDED: &[u8] = b"added";
+pub const COMPLETED: &[u8] = b"completed";
+pub const QUEUE: &[u8] = b"queue";
+pub const POSITION: &[u8] = b"position";
+pub const QUEUED: &[u8] = b"queued";
+pub const FINISHED: &[u8] = b"finished";
+pub const SEEDING_TIME: &[u8] = b"seeding_time";
+pub const IDLE_TIME: &[u8] = b"idle_time";
+pub const RATIO_GOAL: &[u8] = b"ratio_goal";
+pub const SEED_TIME_GOAL: &[u8] = b"seed_time_goal";
+pub const IDLE_GOAL: &[u8] = b"idle_goal";
@@ -7,6 +7,11 @@ pub enum ParsingError {
     MissingField(String),
     InvalidFormat,
     TypeMismatch,
+    UnexpectedEnd,
+    UnexpectedByte(usize),
+    NumberOverflow(usize),
+    UnsortedKeys(usize),
+    TooDeep,
 }
 
 impl Display for ParsingError {
@@ -18,6 +23,11 @@ impl Display for ParsingError {
                 ParsingError::MissingField(s) => format!("MissingField: {s}"),
                 ParsingError::InvalidFormat => "InvalidFormat".to_string(),
                 ParsingError::TypeMismatch => "TypeMismatch".to_string(),
+                ParsingError::UnexpectedEnd => "UnexpectedEnd".to_string(),
+                ParsingError::UnexpectedByte(p) => format!("UnexpectedByte at {p}"),
+                ParsingError::NumberOverflow(p) => format!("NumberOverflow at {p}"),
+                ParsingError::UnsortedKeys(p) => format!("UnsortedKeys at {p}"),
+                ParsingError::TooDeep => "TooDeep".to_string(),
             }
         )
     }
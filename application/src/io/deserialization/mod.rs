@@ -4,5 +4,5 @@ mod primitives;
 mod util;
 
 pub use error::ParsingError;
-pub use parsing::parse_node;
-pub use util::{DataProvider, Node, TryDeserialize};
+pub use parsing::{parse_node, Decoder, DEFAULT_MAX_DEPTH};
+pub use util::{DataProvider, Dict, DictIter, List, ListIter, Node, TryDeserialize};
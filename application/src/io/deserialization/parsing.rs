@@ -1,87 +1,274 @@
 /// Модуль с парсерами.
-use std::collections::HashMap;
-
-use nom::{
-    branch::alt,
-    bytes::complete::{take, take_while},
-    character::{complete::char, is_digit},
-    combinator::map_res,
-    error::{Error as Err, ErrorKind},
-    multi::many0,
-    sequence::{delimited, pair, preceded, tuple},
-    Err::Error,
-    IResult,
+///
+/// Разбор идёт в два этапа. Сначала `Decoder` один раз проходит по входу
+/// без рекурсии и проверяет его корректность, ограничивая глубину вложенности.
+/// Затем узлы списков и словарей читаются лениво прямо из исходных байтов,
+/// поэтому ни дерево, ни отдельные словари в памяти не строятся.
+use super::{
+    error::ParsingError,
+    util::{Dict, List, Node},
 };
 
-use super::util::Node;
+/// Глубина вложенности по умолчанию. Реальным торрентам и репозиторию
+/// хватает с большим запасом.
+pub const DEFAULT_MAX_DEPTH: usize = 64;
 
 #[inline(always)]
-pub fn parse_node(inp: &[u8]) -> IResult<&[u8], Node> {
-    alt((parse_string, parse_number, parse_list, parse_dict))(inp)
+pub fn parse_node(inp: &[u8]) -> Result<(&[u8], Node<'_>), ParsingError> {
+    Decoder::new().decode(inp)
 }
 
-fn parse_digits(inp: &[u8]) -> IResult<&[u8], u64> {
-    let (inp, r) = take_while(is_digit)(inp)?;
+#[derive(Debug, Clone, Copy)]
+pub struct Decoder {
+    max_depth: usize,
+    strict: bool,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Frame {
+    List,
+    DictKey,
+    DictValue,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            max_depth: DEFAULT_MAX_DEPTH,
+            strict: false,
+        }
+    }
+
+    /// Ограничивает глубину вложенности списков и словарей.
+    pub fn max_depth(self, max_depth: usize) -> Decoder {
+        Decoder { max_depth, ..self }
+    }
+
+    /// В строгом режиме ключи словарей обязаны идти по возрастанию без повторов.
+    pub fn strict(self, strict: bool) -> Decoder {
+        Decoder { strict, ..self }
+    }
+
+    /// Проверяет одно значение в начале `inp` и возвращает его вместе с остатком.
+    pub fn decode<'a>(&self, inp: &'a [u8]) -> Result<(&'a [u8], Node<'a>), ParsingError> {
+        let end = self.validate(inp)?;
+        let node = peek_node(&inp[..end]).ok_or(ParsingError::InvalidFormat)?;
+
+        Ok((&inp[end..], node))
+    }
+
+    /// Возвращает длину первого значения во входе.
+    fn validate(&self, inp: &[u8]) -> Result<usize, ParsingError> {
+        // Память под стек ограничена глубиной, а не размером входа
+        let mut stack: Vec<Frame> = Vec::with_capacity(self.max_depth.min(DEFAULT_MAX_DEPTH));
+        // Последние ключи открытых словарей, нужны только в строгом режиме
+        let mut keys: Vec<Option<&[u8]>> = vec![];
+        let mut pos = 0;
+
+        loop {
+            let byte = *inp.get(pos).ok_or(ParsingError::UnexpectedEnd)?;
+            let top = stack.last().copied();
+
+            if byte == b'e' {
+                match top {
+                    Some(Frame::List) | Some(Frame::DictKey) => {
+                        stack.pop();
+                        if self.strict && top == Some(Frame::DictKey) {
+                            keys.pop();
+                        }
+                        pos += 1;
+                        // Родитель был сдвинут ещё при открытии контейнера
+                        if stack.is_empty() {
+                            return Ok(pos);
+                        }
+                        continue;
+                    }
+                    _ => return Err(ParsingError::UnexpectedByte(pos)),
+                }
+            } else {
+                if top == Some(Frame::DictKey) && !byte.is_ascii_digit() {
+                    return Err(ParsingError::UnexpectedByte(pos));
+                }
+
+                match byte {
+                    b'i' => pos = validate_number(inp, pos)?,
+                    b'0'..=b'9' => {
+                        let start = pos;
+                        pos = validate_string(inp, pos)?;
+                        if self.strict && top == Some(Frame::DictKey) {
+                            let (key, _) =
+                                read_string(&inp[start..pos]).ok_or(ParsingError::InvalidFormat)?;
+                            let last = keys.last_mut().ok_or(ParsingError::InvalidFormat)?;
+                            if matches!(last, Some(prev) if *prev >= key) {
+                                return Err(ParsingError::UnsortedKeys(start));
+                            }
+                            *last = Some(key);
+                        }
+                    }
+                    b'l' | b'd' => {
+                        if stack.len() >= self.max_depth {
+                            return Err(ParsingError::TooDeep);
+                        }
+                        self.advance(&mut stack);
+                        stack.push(if byte == b'l' {
+                            Frame::List
+                        } else {
+                            Frame::DictKey
+                        });
+                        if self.strict && byte == b'd' {
+                            keys.push(None);
+                        }
+                        pos += 1;
+                        continue;
+                    }
+                    _ => return Err(ParsingError::UnexpectedByte(pos)),
+                }
+            }
+
+            self.advance(&mut stack);
+            if stack.is_empty() {
+                return Ok(pos);
+            }
+        }
+    }
 
-    if r.len() != 0 {
-        let mut digits = 0;
-        for b in r {
-            digits *= 10;
-            digits += (b - b'0') as u64;
+    /// Сдвигает состояние словаря после прочитанного ключа или значения.
+    #[inline]
+    fn advance(&self, stack: &mut [Frame]) {
+        if let Some(top) = stack.last_mut() {
+            *top = match top {
+                Frame::List => Frame::List,
+                Frame::DictKey => Frame::DictValue,
+                Frame::DictValue => Frame::DictKey,
+            }
         }
+    }
+}
+
+fn parse_digits(inp: &[u8], mut pos: usize) -> Result<(u64, usize), ParsingError> {
+    let start = pos;
+    let mut digits: u64 = 0;
+
+    while let Some(b) = inp.get(pos).filter(|b| b.is_ascii_digit()) {
+        digits = digits
+            .checked_mul(10)
+            .and_then(|d| d.checked_add((b - b'0') as u64))
+            .ok_or(ParsingError::NumberOverflow(start))?;
+        pos += 1;
+    }
 
-        Ok((inp, digits))
+    if pos == start {
+        Err(ParsingError::UnexpectedByte(pos))
     } else {
-        Err(Error(Err {
-            input: inp,
-            code: ErrorKind::TakeWhile1,
-        }))
+        Ok((digits, pos))
     }
 }
 
-fn parse_number(inp: &[u8]) -> IResult<&[u8], Node> {
-    let (inp, (_, number, _)) = tuple((char('i'), parse_digits, char('e')))(inp)?;
+fn validate_number(inp: &[u8], pos: usize) -> Result<usize, ParsingError> {
+    let (_, pos) = parse_digits(inp, pos + 1)?;
 
-    Ok((inp, Node::UnsignedNum(number)))
+    match inp.get(pos) {
+        Some(b'e') => Ok(pos + 1),
+        Some(_) => Err(ParsingError::UnexpectedByte(pos)),
+        None => Err(ParsingError::UnexpectedEnd),
+    }
 }
 
-fn parse_string(inp: &[u8]) -> IResult<&[u8], Node> {
-    let (inp, length) = parse_digits(inp)?;
+fn validate_string(inp: &[u8], pos: usize) -> Result<usize, ParsingError> {
+    let (length, pos) = parse_digits(inp, pos)?;
 
-    let (inp, s) = preceded(char(':'), take(length))(inp)?;
+    match inp.get(pos) {
+        Some(b':') => {}
+        Some(_) => return Err(ParsingError::UnexpectedByte(pos)),
+        None => return Err(ParsingError::UnexpectedEnd),
+    }
 
-    Ok((inp, Node::String(s)))
+    // Длина сравнивается с остатком до какого-либо выделения памяти
+    let rest = (inp.len() - pos - 1) as u64;
+    if length > rest {
+        Err(ParsingError::UnexpectedEnd)
+    } else {
+        Ok(pos + 1 + length as usize)
+    }
 }
 
-fn parse_list(inp: &[u8]) -> IResult<&[u8], Node> {
-    map_res(delimited(char('l'), many0(parse_node), char('e')), |list| {
-        Result::<Node, ()>::Ok(Node::List(list))
-    })(inp)
+/// Читает уже проверенную строку.
+pub(super) fn read_string(inp: &[u8]) -> Option<(&[u8], &[u8])> {
+    let mut length: usize = 0;
+    let mut colon = 0;
+
+    while let Some(b) = inp.get(colon).filter(|b| b.is_ascii_digit()) {
+        length = length.checked_mul(10)?.checked_add((b - b'0') as usize)?;
+        colon += 1;
+    }
+    if inp.get(colon) != Some(&b':') {
+        return None;
+    }
+    let start = colon + 1;
+    let end = start.checked_add(length)?;
+
+    Some((inp.get(start..end)?, inp.get(end..)?))
 }
 
-fn parse_dict<'a>(inp: &'a [u8]) -> IResult<&[u8], Node> {
-    let pairs_to_dict =
-        |pairs: Vec<(Node<'a>, Node<'a>)>| -> Result<HashMap<&'a [u8], Node<'a>>, ()> {
-            let mut dict = HashMap::new();
-            for (key, value) in pairs {
-                if let Node::String(s) = key {
-                    dict.insert(s, value);
-                } else {
-                    return Result::Err(());
-                }
+/// Пропускает уже проверенное значение без рекурсии.
+pub(super) fn skip_node(inp: &[u8]) -> Option<&[u8]> {
+    let mut depth = 0usize;
+    let mut rest = inp;
+
+    loop {
+        rest = match rest.first()? {
+            b'i' => {
+                let end = rest.iter().position(|b| *b == b'e')?;
+                &rest[end + 1..]
+            }
+            b'0'..=b'9' => read_string(rest)?.1,
+            b'l' | b'd' => {
+                depth += 1;
+                &rest[1..]
             }
-            Result::Ok(dict)
+            b'e' => {
+                depth = depth.checked_sub(1)?;
+                &rest[1..]
+            }
+            _ => return None,
         };
 
-    let parse_pair = pair(parse_string, parse_node);
+        if depth == 0 {
+            return Some(rest);
+        }
+    }
+}
 
-    let (new_inp, dict) = map_res(
-        delimited(char('d'), many0(parse_pair), char('e')),
-        pairs_to_dict,
-    )(inp)?;
+/// Читает один узел из уже проверенного входа.
+pub(super) fn read_node(inp: &[u8]) -> Option<(Node<'_>, &[u8])> {
+    match inp.first()? {
+        b'i' => {
+            let end = inp.iter().position(|b| *b == b'e')?;
+            let num = std::str::from_utf8(&inp[1..end]).ok()?.parse().ok()?;
+            Some((Node::UnsignedNum(num), &inp[end + 1..]))
+        }
+        b'0'..=b'9' => {
+            let (s, rest) = read_string(inp)?;
+            Some((Node::String(s), rest))
+        }
+        b'l' => Some((Node::List(List::new(inp)), skip_node(inp)?)),
+        b'd' => Some((Node::Dict(Dict::new(inp)), skip_node(inp)?)),
+        _ => None,
+    }
+}
 
-    Ok((
-        new_inp,
-        Node::Dict(dict, &inp[0..(inp.len() - new_inp.len())]),
-    ))
+/// Читает первый узел. Конец контейнера ищется только тогда,
+/// когда он действительно нужен.
+pub(super) fn peek_node(inp: &[u8]) -> Option<Node<'_>> {
+    match inp.first()? {
+        b'l' => Some(Node::List(List::new(inp))),
+        b'd' => Some(Node::Dict(Dict::new(inp))),
+        _ => read_node(inp).map(|(node, _)| node),
+    }
 }
@@ -1,4 +1,6 @@
 /// Модуль с имплементациями для примитивных типов.
+use std::collections::BTreeMap;
+
 use super::{
     error::ParsingError,
     util::{Node, TryDeserialize},
@@ -46,8 +48,8 @@ where
     fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
         if let Node::List(list) = node {
             let mut new_list: Vec<T> = vec![];
-            for node in list {
-                new_list.push(T::try_deserialize_from_node(node.clone())?);
+            for node in list.iter() {
+                new_list.push(T::try_deserialize_from_node(node)?);
             }
             Ok(new_list)
         } else {
@@ -55,3 +57,34 @@ where
         }
     }
 }
+
+impl<'a, const N: usize> TryDeserialize<'a> for [u8; N] {
+    fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
+        if let Node::String(s) = node {
+            s.try_into().map_err(|_| ParsingError::InvalidFormat)
+        } else {
+            Err(ParsingError::TypeMismatch)
+        }
+    }
+}
+
+impl<'a, K, V> TryDeserialize<'a> for BTreeMap<K, V>
+where
+    K: TryDeserialize<'a> + Ord,
+    V: TryDeserialize<'a>,
+{
+    fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
+        if let Node::Dict(dict) = node {
+            dict.iter()
+                .map(|(k, v)| {
+                    Ok((
+                        K::try_deserialize_from_node(Node::String(k))?,
+                        V::try_deserialize_from_node(v)?,
+                    ))
+                })
+                .collect()
+        } else {
+            Err(ParsingError::TypeMismatch)
+        }
+    }
+}
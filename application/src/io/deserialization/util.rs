@@ -1,14 +1,171 @@
-use std::collections::HashMap;
-
-use super::{error::ParsingError, parse_node};
+use super::{
+    error::ParsingError,
+    parse_node,
+    parsing::{peek_node, read_node, read_string, skip_node},
+};
 
 /// Структура, которая размечает байты, передаваемые на парсинг.
-#[derive(Debug, PartialEq, Clone)]
+///
+/// Списки и словари не разбираются заранее, а хранят ссылку на свой кусок
+/// входа, поэтому узел дёшево копируется.
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Node<'a> {
     UnsignedNum(u64),
     String(&'a [u8]),
-    List(Vec<Node<'a>>),
-    Dict(HashMap<&'a [u8], Node<'a>>, &'a [u8]), // Также храним кусок, в котором этот словарь размещён, чтобы взять хеш от инфо-словарика
+    List(List<'a>),
+    Dict(Dict<'a>),
+}
+
+/// Ленивый список, элементы читаются при обходе.
+#[derive(Clone, Copy)]
+pub struct List<'a> {
+    // Начинается с самого контейнера, но может захватывать байты после него
+    raw: &'a [u8],
+}
+
+impl<'a> PartialEq for List<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw() == other.raw()
+    }
+}
+
+impl<'a> std::fmt::Debug for List<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("List")
+            .field(&self.iter().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<'a> List<'a> {
+    pub(super) fn new(raw: &'a [u8]) -> List<'a> {
+        List { raw }
+    }
+
+    pub fn iter(&self) -> ListIter<'a> {
+        ListIter {
+            rest: &self.raw[1..],
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<Node<'a>> {
+        self.iter().nth(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Байты контейнера вместе с открывающим и закрывающим символами.
+    pub fn raw(&self) -> &'a [u8] {
+        let rest = skip_node(self.raw).unwrap_or_default();
+        &self.raw[..self.raw.len() - rest.len()]
+    }
+}
+
+pub struct ListIter<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for ListIter<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.first() == Some(&b'e') {
+            return None;
+        }
+        let (node, rest) = read_node(self.rest)?;
+        self.rest = rest;
+        Some(node)
+    }
+}
+
+/// Ленивый словарь. Также хранит кусок, в котором он размещён,
+/// чтобы взять хеш от инфо-словарика.
+#[derive(Clone, Copy)]
+pub struct Dict<'a> {
+    // Начинается с самого контейнера, но может захватывать байты после него
+    raw: &'a [u8],
+}
+
+impl<'a> PartialEq for Dict<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw() == other.raw()
+    }
+}
+
+impl<'a> std::fmt::Debug for Dict<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Dict")
+            .field(&self.iter().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<'a> Dict<'a> {
+    pub(super) fn new(raw: &'a [u8]) -> Dict<'a> {
+        Dict { raw }
+    }
+
+    pub fn iter(&self) -> DictIter<'a> {
+        DictIter {
+            rest: &self.raw[1..],
+        }
+    }
+
+    /// Ищет значение по ключу, пропуская остальные значения без их разбора.
+    pub fn get(&self, key: &[u8]) -> Option<Node<'a>> {
+        let mut rest = &self.raw[1..];
+
+        while rest.first() != Some(&b'e') {
+            let (k, after_key) = read_string(rest)?;
+            if k == key {
+                return peek_node(after_key);
+            }
+            rest = skip_node(after_key)?;
+        }
+        None
+    }
+
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Байты контейнера вместе с открывающим и закрывающим символами.
+    pub fn raw(&self) -> &'a [u8] {
+        let rest = skip_node(self.raw).unwrap_or_default();
+        &self.raw[..self.raw.len() - rest.len()]
+    }
+}
+
+pub struct DictIter<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for DictIter<'a> {
+    type Item = (&'a [u8], Node<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.first() == Some(&b'e') {
+            return None;
+        }
+        let (key, rest) = read_string(self.rest)?;
+        let (value, rest) = read_node(rest)?;
+        self.rest = rest;
+        Some((key, value))
+    }
 }
 
 pub trait TryDeserialize<'a>
@@ -16,26 +173,29 @@ where
     Self: Sized,
 {
     fn try_deserialize(bytes: &'a [u8]) -> Result<Self, ParsingError> {
-        match parse_node(bytes) {
-            Ok((_, node)) => self::TryDeserialize::try_deserialize_from_node(node),
-            Err(_) => Err(ParsingError::InvalidFormat),
-        }
+        let (_, node) = parse_node(bytes)?;
+        self::TryDeserialize::try_deserialize_from_node(node)
     }
 
     fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError>;
 }
 
+/// Разбирает словарь один раз, чтобы поиск полей не пропускал
+/// одни и те же значения снова и снова.
 pub struct DataProvider<'a> {
-    pub dict: HashMap<&'a [u8], Node<'a>>,
-    pub slice: &'a [u8],
+    pub dict: Dict<'a>,
+    entries: Vec<(&'a [u8], Node<'a>)>,
 }
 
 impl<'a> TryFrom<Node<'a>> for DataProvider<'a> {
     type Error = ParsingError;
 
     fn try_from(value: Node<'a>) -> Result<Self, Self::Error> {
-        if let Node::Dict(dict, slice) = value {
-            Ok(DataProvider { dict, slice })
+        if let Node::Dict(dict) = value {
+            Ok(DataProvider {
+                dict,
+                entries: dict.iter().collect(),
+            })
         } else {
             Err(ParsingError::TypeMismatch)
         }
@@ -43,16 +203,24 @@ impl<'a> TryFrom<Node<'a>> for DataProvider<'a> {
 }
 
 impl<'a> DataProvider<'a> {
+    #[inline]
+    fn get(&self, key: &[u8]) -> Option<Node<'a>> {
+        self.entries
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, node)| *node)
+    }
+
     #[inline]
     pub fn required<T>(&self, key: &[u8]) -> Result<T, ParsingError>
     where
         T: TryDeserialize<'a>,
     {
-        if let Some(node) = self.dict.get(key) {
-            T::try_deserialize_from_node(node.clone())
+        if let Some(node) = self.get(key) {
+            T::try_deserialize_from_node(node)
         } else {
             Err(ParsingError::MissingField(
-                String::from_utf8(key.to_vec()).unwrap(),
+                String::from_utf8_lossy(key).into_owned(),
             ))
         }
     }
@@ -62,10 +230,15 @@ impl<'a> DataProvider<'a> {
     where
         T: TryDeserialize<'a>,
     {
-        if let Some(node) = self.dict.get(key) {
-            Ok(Some(T::try_deserialize_from_node(node.clone())?))
+        if let Some(node) = self.get(key) {
+            Ok(Some(T::try_deserialize_from_node(node)?))
         } else {
             Ok(None)
         }
     }
+
+    #[inline]
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
 }
@@ -0,0 +1,162 @@
+/// Модуль для обратимого преобразования bencode в JSON и обратно.
+///
+/// Строки, которые являются UTF-8 и не начинаются с `$`, записываются как есть.
+/// Остальные строки и ключи экранируются как `$hex:<hex>` или `$base64:<base64>`,
+/// поэтому любое каноническое значение переживает путь туда и обратно без потерь.
+use std::fmt::Display;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{Map, Value};
+
+use super::{
+    deserialization::{parse_node, Node, ParsingError},
+    serialization::Serialize,
+};
+use crate::tools::{from_hex, to_hex};
+
+const ESCAPE: char = '$';
+const HEX_PREFIX: &str = "$hex:";
+const BASE64_PREFIX: &str = "$base64:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BinaryEncoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+#[derive(Debug)]
+pub enum JsonError {
+    Parsing(ParsingError),
+    Json(serde_json::Error),
+    UnsupportedValue(String),
+    InvalidEscape(String),
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JsonError::Parsing(e) => write!(f, "{e}"),
+            JsonError::Json(e) => write!(f, "JSON error: {e}"),
+            JsonError::UnsupportedValue(v) => {
+                write!(f, "JSON error: {v} can't be represented in bencode")
+            }
+            JsonError::InvalidEscape(s) => write!(f, "JSON error: invalid escaped string {s:?}"),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+impl From<ParsingError> for JsonError {
+    fn from(e: ParsingError) -> Self {
+        JsonError::Parsing(e)
+    }
+}
+
+impl From<serde_json::Error> for JsonError {
+    fn from(e: serde_json::Error) -> Self {
+        JsonError::Json(e)
+    }
+}
+
+pub fn node_to_json(node: Node, encoding: BinaryEncoding) -> Value {
+    match node {
+        Node::UnsignedNum(num) => Value::from(num),
+        Node::String(s) => Value::String(escape(s, encoding)),
+        Node::List(list) => list.iter().map(|n| node_to_json(n, encoding)).collect(),
+        Node::Dict(dict) => Value::Object(
+            dict.iter()
+                .map(|(k, v)| (escape(k, encoding), node_to_json(v, encoding)))
+                .collect::<Map<String, Value>>(),
+        ),
+    }
+}
+
+/// Переводит байты bencode в текст JSON.
+pub fn to_json_string(
+    bytes: &[u8],
+    encoding: BinaryEncoding,
+    pretty: bool,
+) -> Result<String, JsonError> {
+    let (_, node) = parse_node(bytes)?;
+    let value = node_to_json(node, encoding);
+
+    Ok(if pretty {
+        serde_json::to_string_pretty(&value)?
+    } else {
+        serde_json::to_string(&value)?
+    })
+}
+
+/// Переводит JSON обратно в канонический bencode.
+pub fn json_to_bencode(value: &Value) -> Result<Vec<u8>, JsonError> {
+    let mut out = vec![];
+    encode_json(&mut out, value)?;
+    Ok(out)
+}
+
+pub fn from_json_str(s: &str) -> Result<Vec<u8>, JsonError> {
+    json_to_bencode(&serde_json::from_str(s)?)
+}
+
+fn encode_json(out: &mut Vec<u8>, value: &Value) -> Result<(), JsonError> {
+    match value {
+        Value::Number(num) => match num.as_u64() {
+            Some(num) => num.serialize_into(out),
+            None => return Err(JsonError::UnsupportedValue(num.to_string())),
+        },
+        Value::String(s) => unescape(s)?.serialize_into(out),
+        Value::Array(list) => {
+            out.push(b'l');
+            for item in list {
+                encode_json(out, item)?;
+            }
+            out.push(b'e');
+        }
+        Value::Object(map) => {
+            let mut entries = map
+                .iter()
+                .map(|(k, v)| Ok((unescape(k)?, v)))
+                .collect::<Result<Vec<_>, JsonError>>()?;
+            // Ключи bencode сортируются по сырым байтам, а не по экранированному виду
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            if entries.windows(2).any(|w| w[0].0 == w[1].0) {
+                return Err(JsonError::UnsupportedValue("a duplicate key".to_string()));
+            }
+            out.push(b'd');
+            for (k, v) in entries {
+                k.serialize_into(out);
+                encode_json(out, v)?;
+            }
+            out.push(b'e');
+        }
+        Value::Bool(_) => return Err(JsonError::UnsupportedValue("a boolean".to_string())),
+        Value::Null => return Err(JsonError::UnsupportedValue("null".to_string())),
+    }
+    Ok(())
+}
+
+fn escape(s: &[u8], encoding: BinaryEncoding) -> String {
+    match std::str::from_utf8(s) {
+        Ok(s) if !s.starts_with(ESCAPE) => s.to_string(),
+        _ => match encoding {
+            BinaryEncoding::Hex => format!("{HEX_PREFIX}{}", to_hex(s)),
+            BinaryEncoding::Base64 => format!("{BASE64_PREFIX}{}", STANDARD.encode(s)),
+        },
+    }
+}
+
+fn unescape(s: &str) -> Result<Vec<u8>, JsonError> {
+    if let Some(hex) = s.strip_prefix(HEX_PREFIX) {
+        from_hex(hex).ok_or_else(|| JsonError::InvalidEscape(s.to_string()))
+    } else if let Some(b64) = s.strip_prefix(BASE64_PREFIX) {
+        STANDARD
+            .decode(b64)
+            .map_err(|_| JsonError::InvalidEscape(s.to_string()))
+    } else if s.starts_with(ESCAPE) {
+        Err(JsonError::InvalidEscape(s.to_string()))
+    } else {
+        Ok(s.as_bytes().to_vec())
+    }
+}
@@ -1,3 +1,7 @@
 pub mod consts;
 pub mod deserialization;
+pub mod json;
+pub mod pretty;
+#[cfg(feature = "serde")]
+pub mod serde_bencode;
 pub mod serialization;
@@ -0,0 +1,78 @@
+/// Модуль для вывода дерева bencode в читаемом виде.
+use std::fmt::Write;
+
+use super::deserialization::Node;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PrettyOptions {
+    pub indent: usize,
+    /// Сколько байт бинарной строки показывать, остальное сокращается.
+    pub max_bytes: usize,
+}
+
+impl Default for PrettyOptions {
+    fn default() -> Self {
+        PrettyOptions {
+            indent: 2,
+            max_bytes: 32,
+        }
+    }
+}
+
+pub fn pretty(node: Node, options: PrettyOptions) -> String {
+    let mut out = String::new();
+    write_node(&mut out, node, 0, &options);
+    out.push('\n');
+    out
+}
+
+fn write_node(out: &mut String, node: Node, level: usize, options: &PrettyOptions) {
+    let pad = " ".repeat((level + 1) * options.indent);
+    let end_pad = " ".repeat(level * options.indent);
+
+    match node {
+        Node::UnsignedNum(num) => {
+            let _ = write!(out, "{num}");
+        }
+        Node::String(s) => write_string(out, s, options),
+        Node::List(list) if list.is_empty() => out.push_str("[]"),
+        Node::List(list) => {
+            out.push_str("[\n");
+            for item in list.iter() {
+                out.push_str(&pad);
+                write_node(out, item, level + 1, options);
+                out.push('\n');
+            }
+            out.push_str(&end_pad);
+            out.push(']');
+        }
+        Node::Dict(dict) if dict.is_empty() => out.push_str("{}"),
+        Node::Dict(dict) => {
+            out.push_str("{\n");
+            for (key, value) in dict.iter() {
+                out.push_str(&pad);
+                write_string(out, key, options);
+                out.push_str(": ");
+                write_node(out, value, level + 1, options);
+                out.push('\n');
+            }
+            out.push_str(&end_pad);
+            out.push('}');
+        }
+    }
+}
+
+fn write_string(out: &mut String, s: &[u8], options: &PrettyOptions) {
+    match std::str::from_utf8(s) {
+        Ok(s) if !s.chars().any(char::is_control) => {
+            let _ = write!(out, "{s:?}");
+        }
+        _ => {
+            let shown = &s[..s.len().min(options.max_bytes)];
+            let _ = write!(out, "<{} bytes> {}", s.len(), crate::tools::to_hex(shown));
+            if shown.len() < s.len() {
+                out.push_str("...");
+            }
+        }
+    }
+}
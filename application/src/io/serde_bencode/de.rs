@@ -0,0 +1,274 @@
+use serde::de::{
+    self, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess,
+    Visitor,
+};
+use serde::forward_to_deserialize_any;
+
+use super::error::Error;
+use crate::io::deserialization::{parse_node, Node, ParsingError};
+
+/// Десериализует значение из байтов в формате bencode.
+pub fn from_bytes<'de, T>(bytes: &'de [u8]) -> Result<T, Error>
+where
+    T: de::Deserialize<'de>,
+{
+    let (rest, node) = parse_node(bytes)?;
+    if !rest.is_empty() {
+        return Err(Error::TrailingData);
+    }
+    T::deserialize(NodeDeserializer::new(node))
+}
+
+/// Десериализатор поверх уже разобранного узла.
+pub struct NodeDeserializer<'de> {
+    node: Node<'de>,
+}
+
+impl<'de> NodeDeserializer<'de> {
+    pub fn new(node: Node<'de>) -> NodeDeserializer<'de> {
+        NodeDeserializer { node }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for NodeDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.node {
+            Node::UnsignedNum(num) => visitor.visit_u64(num),
+            Node::String(s) => visitor.visit_borrowed_bytes(s),
+            Node::List(list) => visitor.visit_seq(ListAccess { iter: list.iter() }),
+            Node::Dict(dict) => visitor.visit_map(DictAccess {
+                iter: dict.iter(),
+                value: None,
+            }),
+        }
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.node {
+            Node::UnsignedNum(0) => visitor.visit_bool(false),
+            Node::UnsignedNum(1) => visitor.visit_bool(true),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.node {
+            Node::String(s) => match std::str::from_utf8(s) {
+                Ok(s) => visitor.visit_borrowed_str(s),
+                Err(_) => Err(Error::Parsing(ParsingError::InvalidFormat)),
+            },
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        // Отсутствующие значения в bencode не записываются вовсе
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.node {
+            Node::String(_) => visitor.visit_enum(Enum {
+                variant: self.node,
+                value: None,
+            }),
+            Node::Dict(dict) if dict.len() == 1 => {
+                let (variant, value) = dict.iter().next().unwrap();
+                visitor.visit_enum(Enum {
+                    variant: Node::String(variant),
+                    value: Some(value),
+                })
+            }
+            _ => Err(Error::Parsing(ParsingError::TypeMismatch)),
+        }
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char bytes byte_buf
+        unit unit_struct seq tuple tuple_struct map struct
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Node<'de> {
+    type Deserializer = NodeDeserializer<'de>;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        NodeDeserializer::new(self)
+    }
+}
+
+struct ListAccess<I> {
+    iter: I,
+}
+
+impl<'de, I> SeqAccess<'de> for ListAccess<I>
+where
+    I: Iterator<Item = Node<'de>>,
+{
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.iter.next() {
+            Some(node) => seed.deserialize(NodeDeserializer::new(node)).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+struct DictAccess<'de, I> {
+    iter: I,
+    value: Option<Node<'de>>,
+}
+
+impl<'de, I> MapAccess<'de> for DictAccess<'de, I>
+where
+    I: Iterator<Item = (&'de [u8], Node<'de>)>,
+{
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(NodeDeserializer::new(Node::String(key)))
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        match self.value.take() {
+            Some(value) => seed.deserialize(NodeDeserializer::new(value)),
+            None => Err(Error::Message(
+                "a value is requested before a key".to_string(),
+            )),
+        }
+    }
+}
+
+struct Enum<'de> {
+    variant: Node<'de>,
+    value: Option<Node<'de>>,
+}
+
+impl<'de> EnumAccess<'de> for Enum<'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self), Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(NodeDeserializer::new(self.variant))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Enum<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        match self.value {
+            None => Ok(()),
+            Some(_) => Err(Error::Parsing(ParsingError::TypeMismatch)),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.value {
+            Some(value) => seed.deserialize(NodeDeserializer::new(value)),
+            None => Err(Error::Parsing(ParsingError::TypeMismatch)),
+        }
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Some(value) => de::Deserializer::deserialize_seq(NodeDeserializer::new(value), visitor),
+            None => Err(Error::Parsing(ParsingError::TypeMismatch)),
+        }
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.value {
+            Some(value) => de::Deserializer::deserialize_map(NodeDeserializer::new(value), visitor),
+            None => Err(Error::Parsing(ParsingError::TypeMismatch)),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::fmt::Display;
+
+use serde::{de, ser};
+
+use crate::io::deserialization::ParsingError;
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    Message(String),
+    Parsing(ParsingError),
+    UnsupportedType(&'static str),
+    KeyMustBeAString,
+    TrailingData,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Message(s) => write!(f, "Bencode serde error: {s}"),
+            Error::Parsing(e) => write!(f, "{e}"),
+            Error::UnsupportedType(t) => write!(f, "Bencode serde error: {t} is not supported"),
+            Error::KeyMustBeAString => {
+                write!(f, "Bencode serde error: a dict key must be a string")
+            }
+            Error::TrailingData => write!(f, "Bencode serde error: trailing data after a value"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+impl From<ParsingError> for Error {
+    fn from(e: ParsingError) -> Self {
+        Error::Parsing(e)
+    }
+}
@@ -0,0 +1,11 @@
+//! Интеграция формата bencode с serde.
+//!
+//! Десериализатор построен поверх `parse_node`, сериализатор пишет ключи
+//! словарей в отсортированном порядке, как того требует спецификация.
+mod de;
+mod error;
+mod ser;
+
+pub use de::{from_bytes, NodeDeserializer};
+pub use error::Error;
+pub use ser::{to_bytes, Serializer};
@@ -0,0 +1,619 @@
+use std::collections::BTreeMap;
+
+use serde::ser::{self, Impossible, Serialize};
+
+use super::error::Error;
+
+/// Сериализует значение в байты в формате bencode.
+pub fn to_bytes<T>(value: &T) -> Result<Vec<u8>, Error>
+where
+    T: ?Sized + Serialize,
+{
+    let mut serializer = Serializer::new();
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_inner())
+}
+
+/// Сериализатор, пишущий bencode в буфер.
+///
+/// `None` не порождает ни одного байта, поэтому поля словаря
+/// со значением `None` просто пропускаются.
+#[derive(Default)]
+pub struct Serializer {
+    out: Vec<u8>,
+}
+
+impl Serializer {
+    pub fn new() -> Serializer {
+        Serializer { out: vec![] }
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.out
+    }
+
+    fn write_unsigned(&mut self, v: u64) {
+        self.out.push(b'i');
+        self.out.extend_from_slice(v.to_string().as_bytes());
+        self.out.push(b'e');
+    }
+
+    fn write_signed(&mut self, v: i64) -> Result<(), Error> {
+        if v < 0 {
+            Err(Error::UnsupportedType("a negative integer"))
+        } else {
+            self.write_unsigned(v as u64);
+            Ok(())
+        }
+    }
+
+    fn write_bytes(&mut self, v: &[u8]) {
+        self.out.extend_from_slice(v.len().to_string().as_bytes());
+        self.out.push(b':');
+        self.out.extend_from_slice(v);
+    }
+}
+
+impl<'a> ser::Serializer for &'a mut Serializer {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = ListSerializer<'a>;
+    type SerializeTuple = ListSerializer<'a>;
+    type SerializeTupleStruct = ListSerializer<'a>;
+    type SerializeTupleVariant = ListSerializer<'a>;
+    type SerializeMap = DictSerializer<'a>;
+    type SerializeStruct = DictSerializer<'a>;
+    type SerializeStructVariant = DictSerializer<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.write_unsigned(v as u64);
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Error> {
+        self.write_signed(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Error> {
+        self.write_signed(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Error> {
+        self.write_signed(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), Error> {
+        self.write_signed(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Error> {
+        self.write_unsigned(v as u64);
+        Ok(())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Error> {
+        self.write_unsigned(v as u64);
+        Ok(())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Error> {
+        self.write_unsigned(v as u64);
+        Ok(())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Error> {
+        self.write_unsigned(v);
+        Ok(())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<(), Error> {
+        Err(Error::UnsupportedType("a float"))
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<(), Error> {
+        Err(Error::UnsupportedType("a float"))
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        self.write_bytes(v.encode_utf8(&mut [0; 4]).as_bytes());
+        Ok(())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.write_bytes(v.as_bytes());
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        self.write_bytes(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), Error> {
+        Err(Error::UnsupportedType("a unit"))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        Err(Error::UnsupportedType("a unit struct"))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.out.push(b'd');
+        self.write_bytes(variant.as_bytes());
+        value.serialize(&mut *self)?;
+        self.out.push(b'e');
+        Ok(())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<ListSerializer<'a>, Error> {
+        self.out.push(b'l');
+        Ok(ListSerializer {
+            ser: self,
+            variant: false,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<ListSerializer<'a>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<ListSerializer<'a>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<ListSerializer<'a>, Error> {
+        self.out.push(b'd');
+        self.write_bytes(variant.as_bytes());
+        self.out.push(b'l');
+        Ok(ListSerializer {
+            ser: self,
+            variant: true,
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<DictSerializer<'a>, Error> {
+        Ok(DictSerializer::new(self, None))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<DictSerializer<'a>, Error> {
+        Ok(DictSerializer::new(self, None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<DictSerializer<'a>, Error> {
+        Ok(DictSerializer::new(self, Some(variant)))
+    }
+}
+
+pub struct ListSerializer<'a> {
+    ser: &'a mut Serializer,
+    variant: bool,
+}
+
+impl<'a> ListSerializer<'a> {
+    fn element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let len = self.ser.out.len();
+        value.serialize(&mut *self.ser)?;
+        if self.ser.out.len() == len {
+            // В списке нельзя пропустить элемент, не изменив его смысл
+            Err(Error::UnsupportedType("None inside a list"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn fin(self) -> Result<(), Error> {
+        self.ser.out.push(b'e');
+        if self.variant {
+            self.ser.out.push(b'e');
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeSeq for ListSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.fin()
+    }
+}
+
+impl<'a> ser::SerializeTuple for ListSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.fin()
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for ListSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.fin()
+    }
+}
+
+impl<'a> ser::SerializeTupleVariant for ListSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.fin()
+    }
+}
+
+/// Накапливает пары словаря, чтобы записать их отсортированными по ключу.
+pub struct DictSerializer<'a> {
+    ser: &'a mut Serializer,
+    variant: Option<&'static str>,
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+    key: Option<Vec<u8>>,
+}
+
+impl<'a> DictSerializer<'a> {
+    fn new(ser: &'a mut Serializer, variant: Option<&'static str>) -> DictSerializer<'a> {
+        DictSerializer {
+            ser,
+            variant,
+            entries: BTreeMap::new(),
+            key: None,
+        }
+    }
+
+    fn entry<T>(&mut self, key: Vec<u8>, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let value = to_bytes(value)?;
+        if value.is_empty() {
+            return Ok(());
+        }
+        if self.entries.insert(key, value).is_some() {
+            return Err(Error::Message("duplicate key in a dict".to_string()));
+        }
+        Ok(())
+    }
+
+    fn fin(self) -> Result<(), Error> {
+        let out = &mut self.ser.out;
+        if let Some(variant) = self.variant {
+            out.push(b'd');
+            out.extend_from_slice(variant.len().to_string().as_bytes());
+            out.push(b':');
+            out.extend_from_slice(variant.as_bytes());
+        }
+        out.push(b'd');
+        for (key, value) in self.entries {
+            out.extend_from_slice(key.len().to_string().as_bytes());
+            out.push(b':');
+            out.extend(key);
+            out.extend(value);
+        }
+        out.push(b'e');
+        if self.variant.is_some() {
+            out.push(b'e');
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeMap for DictSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        match self.key.take() {
+            Some(key) => self.entry(key, value),
+            None => Err(Error::Message(
+                "a value is serialized before a key".to_string(),
+            )),
+        }
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.fin()
+    }
+}
+
+impl<'a> ser::SerializeStruct for DictSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.entry(key.as_bytes().to_vec(), value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.fin()
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for DictSerializer<'a> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.entry(key.as_bytes().to_vec(), value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        self.fin()
+    }
+}
+
+/// Ключами словаря в bencode могут быть только строки.
+struct KeySerializer;
+
+impl ser::Serializer for KeySerializer {
+    type Ok = Vec<u8>;
+    type Error = Error;
+
+    type SerializeSeq = Impossible<Vec<u8>, Error>;
+    type SerializeTuple = Impossible<Vec<u8>, Error>;
+    type SerializeTupleStruct = Impossible<Vec<u8>, Error>;
+    type SerializeTupleVariant = Impossible<Vec<u8>, Error>;
+    type SerializeMap = Impossible<Vec<u8>, Error>;
+    type SerializeStruct = Impossible<Vec<u8>, Error>;
+    type SerializeStructVariant = Impossible<Vec<u8>, Error>;
+
+    fn serialize_str(self, v: &str) -> Result<Vec<u8>, Error> {
+        Ok(v.as_bytes().to_vec())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(v.to_vec())
+    }
+
+    fn serialize_char(self, v: char) -> Result<Vec<u8>, Error> {
+        Ok(v.encode_utf8(&mut [0; 4]).as_bytes().to_vec())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Vec<u8>, Error> {
+        Ok(variant.as_bytes().to_vec())
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<Vec<u8>, Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<Vec<u8>, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<Vec<u8>, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<Vec<u8>, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<Vec<u8>, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<Vec<u8>, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<Vec<u8>, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<Vec<u8>, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<Vec<u8>, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<Vec<u8>, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Vec<u8>, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Vec<u8>, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_none(self) -> Result<Vec<u8>, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_some<T>(self, _value: &T) -> Result<Vec<u8>, Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_unit(self) -> Result<Vec<u8>, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Vec<u8>, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Vec<u8>, Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(Error::KeyMustBeAString)
+    }
+}
@@ -0,0 +1,148 @@
+/// Модуль с потоковым кодировщиком, пишущим прямо в `io::Write`.
+use std::io::{self, Write};
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use super::Serialize;
+
+pub struct Encoder<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(writer: W) -> Encoder<W> {
+        Encoder { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    pub fn write_unsigned(&mut self, v: u64) -> io::Result<()> {
+        write!(self.writer, "i{v}e")
+    }
+
+    pub fn write_bytes(&mut self, v: &[u8]) -> io::Result<()> {
+        write!(self.writer, "{}:", v.len())?;
+        self.writer.write_all(v)
+    }
+
+    pub fn write_list<'i, T, I>(&mut self, items: I) -> io::Result<()>
+    where
+        T: Serialize + 'i,
+        I: IntoIterator<Item = &'i T>,
+    {
+        self.writer.write_all(b"l")?;
+        for item in items {
+            item.encode(self)?;
+        }
+        self.writer.write_all(b"e")
+    }
+
+    /// Начинает словарь. Пары пишутся в порядке вызовов, поэтому ключи
+    /// нужно передавать по возрастанию, иначе вывод не будет каноническим.
+    pub fn dict(&mut self) -> io::Result<DictEncoder<'_, W>> {
+        self.writer.write_all(b"d")?;
+        Ok(DictEncoder {
+            encoder: self,
+            #[cfg(debug_assertions)]
+            last_key: None,
+        })
+    }
+}
+
+pub struct DictEncoder<'e, W: Write> {
+    encoder: &'e mut Encoder<W>,
+    #[cfg(debug_assertions)]
+    last_key: Option<Vec<u8>>,
+}
+
+impl<'e, W: Write> DictEncoder<'e, W> {
+    pub fn required<T>(mut self, k: &[u8], v: &T) -> io::Result<DictEncoder<'e, W>>
+    where
+        T: Serialize + ?Sized,
+    {
+        #[cfg(debug_assertions)]
+        {
+            debug_assert!(
+                self.last_key.as_deref().is_none_or(|last| last < k),
+                "dict keys must be written in ascending order"
+            );
+            self.last_key = Some(k.to_vec());
+        }
+        self.encoder.write_bytes(k)?;
+        v.encode(self.encoder)?;
+        Ok(self)
+    }
+
+    pub fn optional<T>(self, k: &[u8], v: Option<&T>) -> io::Result<DictEncoder<'e, W>>
+    where
+        T: Serialize + ?Sized,
+    {
+        if let Some(v) = v {
+            self.required(k, v)
+        } else {
+            Ok(self)
+        }
+    }
+
+    pub fn fin(self) -> io::Result<()> {
+        self.encoder.writer.write_all(b"e")
+    }
+}
+
+/// Сколько байт копится в буфере `AsyncEncoder` перед записью.
+const CHUNK: usize = 64 * 1024;
+
+/// Кодировщик в асинхронный писатель. Значения кодируются в
+/// переиспользуемый буфер, который уходит в писатель порциями по `CHUNK`.
+pub struct AsyncEncoder<'b, W> {
+    writer: W,
+    buf: &'b mut Vec<u8>,
+}
+
+impl<'b, W: AsyncWrite + Unpin> AsyncEncoder<'b, W> {
+    pub fn new(writer: W, buf: &'b mut Vec<u8>) -> AsyncEncoder<'b, W> {
+        buf.clear();
+        AsyncEncoder { writer, buf }
+    }
+
+    /// Кодирует значение в буфер целиком. В писатель данные уходят на
+    /// `spill`.
+    pub fn encode<T: Serialize + ?Sized>(&mut self, value: &T) -> io::Result<()> {
+        value.encode(&mut Encoder::new(&mut *self.buf))
+    }
+
+    /// Дописывает готовые байты, например скобки списка.
+    pub fn raw(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Отправляет буфер в писатель, если в нём набралась порция.
+    pub async fn spill(&mut self) -> io::Result<()> {
+        if self.buf.len() >= CHUNK {
+            self.writer.write_all(self.buf).await?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Дописывает остаток буфера и сбрасывает писатель.
+    pub async fn finish(mut self) -> io::Result<()> {
+        self.writer.write_all(self.buf).await?;
+        self.buf.clear();
+        self.writer.flush().await
+    }
+}
+
+/// Кодирует значение в асинхронный писатель порциями через переиспользуемый
+/// буфер.
+pub async fn write_async<T, W>(value: &T, writer: &mut W, buf: &mut Vec<u8>) -> io::Result<()>
+where
+    T: Serialize + Sync + ?Sized,
+    W: AsyncWrite + Unpin + Send,
+{
+    let mut encoder = AsyncEncoder::new(writer, buf);
+    value.encode_async(&mut encoder).await?;
+    encoder.finish().await
+}
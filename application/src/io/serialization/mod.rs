@@ -1,4 +1,6 @@
+mod encoder;
 mod primitives;
 mod util;
 
+pub use encoder::{write_async, AsyncEncoder, DictEncoder, Encoder};
 pub use util::{BencodeDictBuilder, Serialize};
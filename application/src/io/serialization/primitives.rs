@@ -1,32 +1,71 @@
-use super::Serialize;
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use tokio::io::AsyncWrite;
+
+use super::{AsyncEncoder, Encoder, Serialize};
+use crate::io::deserialization::Node;
 
 impl Serialize for u64 {
-    fn serialize(&self) -> Vec<u8> {
-        let value = self.to_string();
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder.write_unsigned(*self)
+    }
+}
 
-        let mut res = vec![b'i'];
-        res.extend_from_slice(value.as_bytes());
-        res.push(b'e');
-        res
+impl Serialize for [u8] {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder.write_bytes(self)
     }
 }
 
 impl Serialize for Vec<u8> {
-    fn serialize(&self) -> Vec<u8> {
-        let mut res = vec![];
-        let len = self.len().to_string();
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder.write_bytes(self)
+    }
+}
 
-        res.extend_from_slice(len.as_bytes());
-        res.push(b':');
-        res.extend(self);
+impl<const N: usize> Serialize for [u8; N] {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder.write_bytes(self)
+    }
+}
 
-        res
+impl Serialize for str {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder.write_bytes(self.as_bytes())
     }
 }
 
 impl Serialize for String {
-    fn serialize(&self) -> Vec<u8> {
-        self.as_bytes().to_vec().serialize()
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder.write_bytes(self.as_bytes())
+    }
+}
+
+impl<T> Serialize for [T]
+where
+    T: Serialize,
+{
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder.write_list(self)
+    }
+
+    // По индексу, чтобы через `await` не переживал итератор по элементам
+    #[allow(clippy::needless_range_loop)]
+    async fn encode_async<W>(&self, encoder: &mut AsyncEncoder<'_, W>) -> io::Result<()>
+    where
+        Self: Sync,
+        W: AsyncWrite + Unpin + Send,
+    {
+        encoder.raw(b"l");
+        for i in 0..self.len() {
+            encoder.encode(&self[i])?;
+            encoder.spill().await?;
+        }
+        encoder.raw(b"e");
+        encoder.spill().await
     }
 }
 
@@ -34,15 +73,59 @@ impl<T> Serialize for Vec<T>
 where
     T: Serialize,
 {
-    fn serialize(&self) -> Vec<u8> {
-        let mut res = vec![];
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder.write_list(self)
+    }
+
+    #[allow(clippy::needless_range_loop)]
+    async fn encode_async<W>(&self, encoder: &mut AsyncEncoder<'_, W>) -> io::Result<()>
+    where
+        Self: Sync,
+        W: AsyncWrite + Unpin + Send,
+    {
+        encoder.raw(b"l");
+        for i in 0..self.len() {
+            encoder.encode(&self[i])?;
+            encoder.spill().await?;
+        }
+        encoder.raw(b"e");
+        encoder.spill().await
+    }
+}
+
+impl<T> Serialize for &T
+where
+    T: Serialize + ?Sized,
+{
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        (**self).encode(encoder)
+    }
+}
 
-        res.push(b'l');
-        for t in self.into_iter() {
-            res.extend(t.serialize());
+/// Ключи упорядочены так же, как их байты, поэтому словарь выходит каноническим.
+impl<K, V> Serialize for BTreeMap<K, V>
+where
+    K: AsRef<[u8]>,
+    V: Serialize,
+{
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        let mut dict = encoder.dict()?;
+        for (k, v) in self {
+            dict = dict.required(k.as_ref(), v)?;
         }
-        res.push(b'e');
+        dict.fin()
+    }
+}
 
-        res
+/// Переписывает разобранное значение в каноническом виде: ключи словарей
+/// сортируются, у повторяющихся остаётся последнее значение.
+impl Serialize for Node<'_> {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        match self {
+            Node::UnsignedNum(num) => encoder.write_unsigned(*num),
+            Node::String(s) => encoder.write_bytes(s),
+            Node::List(list) => encoder.write_list(&list.iter().collect::<Vec<_>>()),
+            Node::Dict(dict) => dict.iter().collect::<BTreeMap<_, _>>().encode(encoder),
+        }
     }
 }
@@ -1,24 +1,71 @@
+use std::{
+    future::Future,
+    io::{self, Write},
+};
+
+use tokio::io::AsyncWrite;
+
+use super::encoder::{AsyncEncoder, Encoder};
+
 pub trait Serialize {
-    fn serialize(&self) -> Vec<u8>;
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()>;
+
+    /// Дописывает значение в конец буфера, не выделяя промежуточных векторов.
+    fn serialize_into(&self, buf: &mut Vec<u8>) {
+        self.encode(&mut Encoder::new(buf))
+            .expect("writing into a Vec never fails")
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        self.serialize_into(&mut buf);
+        buf
+    }
+
+    fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.encode(&mut Encoder::new(writer))
+    }
+
+    /// Пишет значение в асинхронный кодировщик. Списки и репозиторий
+    /// переопределяют его и пишут по элементу, а не целиком.
+    fn encode_async<W>(
+        &self,
+        encoder: &mut AsyncEncoder<'_, W>,
+    ) -> impl Future<Output = io::Result<()>> + Send
+    where
+        Self: Sync,
+        W: AsyncWrite + Unpin + Send,
+    {
+        async move {
+            encoder.encode(self)?;
+            encoder.spill().await
+        }
+    }
 }
 
+/// Прежний построитель словаря, оставлен для удобства.
 pub struct BencodeDictBuilder {
     data: Vec<u8>,
 }
 
+impl Default for BencodeDictBuilder {
+    fn default() -> Self {
+        BencodeDictBuilder::new()
+    }
+}
+
 impl BencodeDictBuilder {
     pub fn new() -> BencodeDictBuilder {
         BencodeDictBuilder { data: vec![b'd'] }
     }
 
-    pub fn required<T>(self, k: &[u8], v: T) -> BencodeDictBuilder
+    pub fn required<T>(mut self, k: &[u8], v: T) -> BencodeDictBuilder
     where
         T: Serialize,
     {
-        let mut data = self.data;
-        data.extend(k.to_vec().serialize());
-        data.extend(v.serialize().into_iter());
-        BencodeDictBuilder { data }
+        k.serialize_into(&mut self.data);
+        v.serialize_into(&mut self.data);
+        self
     }
 
     pub fn optional<T>(self, k: &[u8], v: Option<T>) -> BencodeDictBuilder
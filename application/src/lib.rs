@@ -0,0 +1,16 @@
+pub mod cli;
+pub mod client;
+pub mod creation;
+pub mod daemon;
+pub mod error;
+pub mod io;
+pub mod network;
+pub mod repository;
+pub mod rpc;
+pub mod settings;
+pub mod storage;
+
+#[cfg(test)]
+mod tests;
+pub mod tools;
+pub mod tui;
@@ -1,73 +1,9 @@
-mod client;
-mod error;
-mod io;
-mod network;
-mod repository;
-
-#[cfg(test)]
-mod tests;
-mod tools;
-
-use network::{get_start, TorrentState};
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
-
-use error::AsyncErr;
-
-use repository::types::{Torrent, TorrentMetadata};
+use application::cli::{run, Cli};
+use application::error::AsyncErr;
+use clap::Parser;
 
 #[tokio::main]
 async fn main() -> Result<(), AsyncErr> {
-    let path = "./1.torrent";
-
-    let mut f = File::open(path).await?;
-    let mut buf: Vec<u8> = vec![];
-
-    f.read_to_end(&mut buf).await?;
-
-    let (metadata, hash) = TorrentMetadata::new(&buf[..])?;
-    let torrent = Torrent::new(metadata, hash);
-    render_torrent(&torrent);
-
-    let client = reqwest::Client::new();
-    let state = TorrentState {
-        torrent,
-        uploaded: 0,
-        downloaded: 0,
-        left: 0,
-    };
-    let res = get_start(client, state).await;
-    println!("{:#?}", res);
-
-    Ok(())
-}
-
-fn render_torrent(torrent: &Torrent) {
-    println!("hash:\t{:?}", torrent.hash);
-    let torrent = &torrent.metadata;
-    // if let FilesMetadata::Multiple(e) = &torrent.info.files {
-    //     println!("file base:\t{:?}", e.base_name);
-    //     for f in &e.files {
-    //         println!("file path:\t{:?}", f.path);
-    //         println!("file length:\t{}", f.length);
-    //         println!("file md5sum:\t{:?}", f.md5sum);
-    //     }
-    // } else if let FilesMetadata::Single(e) = &torrent.info.files {
-    //     println!("file path:\t{:?}", e.name);
-    //     println!("file length:\t{}", e.length);
-    //     println!("file md5sum:\t{:?}", e.md5sum);
-    // }
-    println!("announce:\t{:?}", torrent.announce);
-    if let Some(al) = &torrent.announce_list {
-        for a in al {
-            println!("announce list:\t{}", a[0]);
-        }
-    }
-    println!("httpseeds:\t{:?}", torrent.httpseeds);
-    println!("creation date:\t{:?}", torrent.creation_date);
-    println!("comment:\t{:?}", torrent.comment);
-    println!("created by:\t{:?}", torrent.created_by);
-    println!("encoding:\t{:?}", torrent.encoding);
-    println!("piece length:\t{:?}", torrent.info.piece_length);
-    println!("private:\t{:?}", torrent.info.private);
+    let cli = Cli::parse();
+    run(cli, &mut std::io::stdout().lock()).await
 }
@@ -0,0 +1,576 @@
+/// Модуль с ограничением скорости и учётом трафика.
+///
+/// Каждая передача проходит через два ведра токенов: общее и торрента.
+/// Ограничивается весь трафик на проводе, а полезная нагрузка (блоки
+/// кусков и данные веб-сидов) учитывается отдельно от служебной части
+/// протокола.
+///
+/// Анонсы трекерам и загрузки с веб-сидов читаются через [`read_tracker`]
+/// и [`read_web_seed`]. Соединений с пирами в клиенте пока нет, поэтому
+/// [`Limited`] никто не вызывает, а демон ещё не анонсирует торренты и не
+/// качает их: ограничения запоминаются, но скорости остаются нулевыми.
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+
+use reqwest::Response;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Sleep,
+};
+
+use crate::repository::Id;
+
+/// Ведро вмещает трафик за столько времени: это наибольший всплеск.
+const BURST: Duration = Duration::from_secs(1);
+/// Трафик, который проходит через ограничитель. Обмена с пирами среди
+/// него нет, так что на загрузку и раздачу ограничения не влияют.
+pub const LIMITED_TRAFFIC: [&str; 2] = ["tracker", "web_seed"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Download,
+    Upload,
+}
+
+/// Ведро токенов. Токен — один байт, ведро наполняется со скоростью
+/// `rate` байт в секунду.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: Option<u64>,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// `None` — без ограничения.
+    pub fn new(rate: Option<u64>, now: Instant) -> TokenBucket {
+        TokenBucket {
+            rate,
+            tokens: rate.map_or(0.0, |r| r as f64 * BURST.as_secs_f64()),
+            updated: now,
+        }
+    }
+
+    pub fn rate(&self) -> Option<u64> {
+        self.rate
+    }
+
+    /// Меняет скорость. Накопленное сверх новой ёмкости пропадает.
+    pub fn set_rate(&mut self, rate: Option<u64>, now: Instant) {
+        if self.rate != rate {
+            self.refill(now);
+            // Без ограничения ведро считается бездонным
+            if self.rate.is_none() {
+                self.tokens = f64::INFINITY;
+            }
+            self.rate = rate;
+            self.tokens = self.tokens.min(self.capacity());
+        }
+    }
+
+    fn capacity(&self) -> f64 {
+        self.rate
+            .map_or(f64::INFINITY, |r| r as f64 * BURST.as_secs_f64())
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if let Some(rate) = self.rate {
+            let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate as f64).min(self.capacity());
+        }
+        self.updated = now;
+    }
+
+    /// Сколько байт можно передать сейчас.
+    pub fn available(&mut self, now: Instant) -> u64 {
+        self.refill(now);
+        match self.rate {
+            None => u64::MAX,
+            Some(_) => self.tokens as u64,
+        }
+    }
+
+    pub fn consume(&mut self, bytes: u64) {
+        if self.rate.is_some() {
+            self.tokens -= bytes as f64;
+        }
+    }
+
+    /// Возвращает неизрасходованные токены.
+    pub fn refund(&mut self, bytes: u64) {
+        if self.rate.is_some() {
+            self.tokens = (self.tokens + bytes as f64).min(self.capacity());
+        }
+    }
+
+    /// Через сколько накопится хотя бы `bytes` байт, но не больше ёмкости.
+    pub fn wait(&self, bytes: u64) -> Duration {
+        match self.rate {
+            None => Duration::ZERO,
+            // Нулевая скорость останавливает передачу до смены настроек
+            Some(0) => BURST,
+            Some(rate) => {
+                let want = (bytes as f64).min(self.capacity());
+                Duration::from_secs_f64(((want - self.tokens) / rate as f64).max(0.0))
+            }
+        }
+    }
+}
+
+/// Счётчик байт со скоростью за последнюю полную секунду.
+#[derive(Debug, Default, Clone)]
+struct Meter {
+    total: u64,
+    second: u64,
+    current: u64,
+    previous: u64,
+}
+
+impl Meter {
+    fn roll(&mut self, second: u64) {
+        if second != self.second {
+            self.previous = if second == self.second + 1 {
+                self.current
+            } else {
+                0
+            };
+            self.current = 0;
+            self.second = second;
+        }
+    }
+
+    fn add(&mut self, bytes: u64, second: u64) {
+        self.roll(second);
+        self.current += bytes;
+        self.total += bytes;
+    }
+
+    fn rate(&mut self, second: u64) -> u64 {
+        self.roll(second);
+        self.previous
+    }
+}
+
+/// Трафик в одну сторону.
+#[derive(Debug, Default, Clone)]
+struct Traffic {
+    wire: Meter,
+    payload: Meter,
+}
+
+/// Сколько передано и с какой скоростью. Служебный трафик — всё, что не
+/// полезная нагрузка: заголовки сообщений, запросы, битовые поля.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Transfer {
+    pub payload: u64,
+    pub overhead: u64,
+    pub payload_rate: u64,
+    pub overhead_rate: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub download: Transfer,
+    pub upload: Transfer,
+}
+
+struct Channel {
+    buckets: [TokenBucket; 2],
+    traffic: [Traffic; 2],
+}
+
+impl Channel {
+    fn new(download: Option<u64>, upload: Option<u64>, now: Instant) -> Channel {
+        Channel {
+            buckets: [
+                TokenBucket::new(download, now),
+                TokenBucket::new(upload, now),
+            ],
+            traffic: Default::default(),
+        }
+    }
+
+    fn stats(&mut self, second: u64) -> Stats {
+        let mut transfer = |d: usize| {
+            let traffic = &mut self.traffic[d];
+            let (wire, payload) = (traffic.wire.rate(second), traffic.payload.rate(second));
+            Transfer {
+                payload: traffic.payload.total,
+                overhead: traffic.wire.total.saturating_sub(traffic.payload.total),
+                payload_rate: payload,
+                overhead_rate: wire.saturating_sub(payload),
+            }
+        };
+        Stats {
+            download: transfer(0),
+            upload: transfer(1),
+        }
+    }
+}
+
+fn index(direction: Direction) -> usize {
+    match direction {
+        Direction::Download => 0,
+        Direction::Upload => 1,
+    }
+}
+
+struct Inner {
+    started: Instant,
+    global: Channel,
+    torrents: HashMap<Id, Channel>,
+}
+
+impl Inner {
+    fn second(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.started).as_secs()
+    }
+
+    fn torrent(&mut self, id: Id, now: Instant) -> &mut Channel {
+        self.torrents
+            .entry(id)
+            .or_insert_with(|| Channel::new(None, None, now))
+    }
+}
+
+/// Общий на клиента ограничитель. Соединения держат его через `Arc`.
+pub struct Limiter {
+    inner: Mutex<Inner>,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Limiter::new()
+    }
+}
+
+impl Limiter {
+    pub fn new() -> Limiter {
+        let now = Instant::now();
+        Limiter {
+            inner: Mutex::new(Inner {
+                started: now,
+                global: Channel::new(None, None, now),
+                torrents: HashMap::new(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_global(&self, download: Option<u64>, upload: Option<u64>) {
+        let now = Instant::now();
+        let [d, u] = &mut self.lock().global.buckets;
+        d.set_rate(download, now);
+        u.set_rate(upload, now);
+    }
+
+    pub fn global_rates(&self) -> (Option<u64>, Option<u64>) {
+        let [d, u] = &self.lock().global.buckets;
+        (d.rate(), u.rate())
+    }
+
+    pub fn set_torrent(&self, id: Id, download: Option<u64>, upload: Option<u64>) {
+        let now = Instant::now();
+        let mut inner = self.lock();
+        let [d, u] = &mut inner.torrent(id, now).buckets;
+        d.set_rate(download, now);
+        u.set_rate(upload, now);
+    }
+
+    /// Забывает торрент вместе со статистикой.
+    pub fn remove_torrent(&self, id: Id) {
+        self.lock().torrents.remove(&id);
+    }
+
+    /// Разрешает передать до `want` байт и списывает их. Если сейчас нельзя
+    /// ничего, возвращает, сколько подождать.
+    pub fn request(
+        &self,
+        torrent: Option<Id>,
+        direction: Direction,
+        want: usize,
+    ) -> Result<usize, Duration> {
+        self.request_at(torrent, direction, want, Instant::now())
+    }
+
+    pub fn request_at(
+        &self,
+        torrent: Option<Id>,
+        direction: Direction,
+        want: usize,
+        now: Instant,
+    ) -> Result<usize, Duration> {
+        let d = index(direction);
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        let mut buckets = vec![&mut inner.global.buckets[d]];
+        if let Some(id) = torrent {
+            let channel = inner
+                .torrents
+                .entry(id)
+                .or_insert_with(|| Channel::new(None, None, now));
+            buckets.push(&mut channel.buckets[d]);
+        }
+        let granted = buckets
+            .iter_mut()
+            .map(|b| b.available(now))
+            .min()
+            .unwrap_or(u64::MAX)
+            .min(want as u64);
+        if granted == 0 && want > 0 {
+            let wait = buckets.iter().map(|b| b.wait(want as u64)).max();
+            return Err(wait.unwrap_or_default());
+        }
+        buckets.iter_mut().for_each(|b| b.consume(granted));
+        Ok(granted as usize)
+    }
+
+    /// Возвращает неизрасходованное из разрешённого.
+    pub fn refund(&self, torrent: Option<Id>, direction: Direction, bytes: usize) {
+        let d = index(direction);
+        let mut inner = self.lock();
+        inner.global.buckets[d].refund(bytes as u64);
+        if let Some(channel) = torrent.and_then(|id| inner.torrents.get_mut(&id)) {
+            channel.buckets[d].refund(bytes as u64);
+        }
+    }
+
+    /// Ждёт разрешения хотя бы на часть из `want` байт.
+    pub async fn acquire(&self, torrent: Option<Id>, direction: Direction, want: usize) -> usize {
+        loop {
+            match self.request(torrent, direction, want) {
+                Ok(granted) => return granted,
+                Err(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+
+    /// Ждёт, пока не будут оплачены `bytes` уже переданных байт. Для
+    /// источников, которые нельзя читать порциями, например HTTP.
+    pub async fn consume(&self, torrent: Option<Id>, direction: Direction, mut bytes: usize) {
+        while bytes > 0 {
+            bytes -= self.acquire(torrent, direction, bytes).await;
+        }
+    }
+
+    /// Учитывает переданное: `wire` — всё, что прошло по сети, `payload` —
+    /// полезная часть из этого.
+    pub fn record(&self, torrent: Option<Id>, direction: Direction, wire: usize, payload: usize) {
+        let now = Instant::now();
+        let d = index(direction);
+        let mut inner = self.lock();
+        let second = inner.second(now);
+        let add = |channel: &mut Channel| {
+            channel.traffic[d].wire.add(wire as u64, second);
+            channel.traffic[d].payload.add(payload as u64, second);
+        };
+        add(&mut inner.global);
+        if let Some(id) = torrent {
+            add(inner.torrent(id, now));
+        }
+    }
+
+    /// Полезная нагрузка, найденная уже после передачи, например при
+    /// разборе сообщений поверх [`Limited`].
+    pub fn record_payload(&self, torrent: Option<Id>, direction: Direction, payload: usize) {
+        let now = Instant::now();
+        let d = index(direction);
+        let mut inner = self.lock();
+        let second = inner.second(now);
+        inner.global.traffic[d].payload.add(payload as u64, second);
+        if let Some(id) = torrent {
+            inner.torrent(id, now).traffic[d]
+                .payload
+                .add(payload as u64, second);
+        }
+    }
+
+    /// Статистика всего клиента или одного торрента.
+    pub fn stats(&self, torrent: Option<Id>) -> Stats {
+        let now = Instant::now();
+        let mut inner = self.lock();
+        let second = inner.second(now);
+        match torrent {
+            None => inner.global.stats(second),
+            Some(id) => inner
+                .torrents
+                .get_mut(&id)
+                .map_or_else(Stats::default, |c| c.stats(second)),
+        }
+    }
+}
+
+/// Соединение, чтение и запись которого проходят через ограничитель.
+/// Весь трафик учитывается как служебный, полезную нагрузку отмечает тот,
+/// кто разбирает сообщения, через [`Limiter::record_payload`].
+pub struct Limited<S> {
+    inner: S,
+    limiter: Arc<Limiter>,
+    torrent: Option<Id>,
+    read_delay: Option<Pin<Box<Sleep>>>,
+    write_delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> Limited<S> {
+    pub fn new(inner: S, limiter: Arc<Limiter>, torrent: Option<Id>) -> Limited<S> {
+        Limited {
+            inner,
+            limiter,
+            torrent,
+            read_delay: None,
+            write_delay: None,
+        }
+    }
+
+    /// Соединение узнаёт свой торрент после рукопожатия.
+    pub fn set_torrent(&mut self, torrent: Id) {
+        self.torrent = Some(torrent);
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+/// Ждёт разрешения на передачу, засыпая через `delay`.
+fn poll_grant(
+    limiter: &Limiter,
+    torrent: Option<Id>,
+    direction: Direction,
+    want: usize,
+    delay: &mut Option<Pin<Box<Sleep>>>,
+    cx: &mut Context<'_>,
+) -> Poll<usize> {
+    loop {
+        if let Some(sleep) = delay {
+            ready!(Future::poll(sleep.as_mut(), cx));
+            *delay = None;
+        }
+        match limiter.request(torrent, direction, want) {
+            Ok(granted) => return Poll::Ready(granted),
+            Err(wait) => *delay = Some(Box::pin(tokio::time::sleep(wait))),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Limited<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        let granted = ready!(poll_grant(
+            &this.limiter,
+            this.torrent,
+            Direction::Download,
+            buf.remaining(),
+            &mut this.read_delay,
+            cx,
+        ));
+
+        let mut limited = ReadBuf::new(buf.initialize_unfilled_to(granted));
+        let result = Pin::new(&mut this.inner).poll_read(cx, &mut limited);
+        let read = limited.filled().len();
+        buf.advance(read);
+        this.limiter
+            .refund(this.torrent, Direction::Download, granted - read);
+        if read > 0 {
+            this.limiter
+                .record(this.torrent, Direction::Download, read, 0);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Limited<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if data.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let granted = ready!(poll_grant(
+            &this.limiter,
+            this.torrent,
+            Direction::Upload,
+            data.len(),
+            &mut this.write_delay,
+            cx,
+        ));
+
+        let result = Pin::new(&mut this.inner).poll_write(cx, &data[..granted]);
+        let written = match &result {
+            Poll::Ready(Ok(written)) => *written,
+            _ => 0,
+        };
+        this.limiter
+            .refund(this.torrent, Direction::Upload, granted - written);
+        if written > 0 {
+            this.limiter
+                .record(this.torrent, Direction::Upload, written, 0);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Читает ответ веб-сида с ограничением скорости. Тело ответа — полезная
+/// нагрузка целиком.
+pub async fn read_web_seed(
+    response: Response,
+    limiter: &Limiter,
+    torrent: Id,
+) -> reqwest::Result<Vec<u8>> {
+    read_body(response, limiter, torrent, true).await
+}
+
+/// Читает ответ трекера с ограничением скорости. Весь ответ служебный.
+pub async fn read_tracker(
+    response: Response,
+    limiter: &Limiter,
+    torrent: Id,
+) -> reqwest::Result<Vec<u8>> {
+    read_body(response, limiter, torrent, false).await
+}
+
+async fn read_body(
+    mut response: Response,
+    limiter: &Limiter,
+    torrent: Id,
+    payload: bool,
+) -> reqwest::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        limiter
+            .consume(Some(torrent), Direction::Download, chunk.len())
+            .await;
+        let useful = if payload { chunk.len() } else { 0 };
+        limiter.record(Some(torrent), Direction::Download, chunk.len(), useful);
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
@@ -0,0 +1,314 @@
+/// Модуль с сообщениями протокола пиров, включая хеш-сообщения v2 (BEP 52).
+///
+/// Каждое сообщение начинается с длины в 4 байта (big endian), затем идёт id.
+use std::fmt::Display;
+
+use crate::repository::{
+    merkle::{self, Hash},
+    types::TorrentMetadata,
+};
+
+/// Самое длинное сообщение, которое мы готовы принять.
+pub const MAX_MESSAGE_LENGTH: u32 = 1 << 20;
+
+const CHOKE: u8 = 0;
+const UNCHOKE: u8 = 1;
+const INTERESTED: u8 = 2;
+const NOT_INTERESTED: u8 = 3;
+const HAVE: u8 = 4;
+const BITFIELD: u8 = 5;
+const REQUEST: u8 = 6;
+const PIECE: u8 = 7;
+const CANCEL: u8 = 8;
+const HASH_REQUEST: u8 = 21;
+const HASHES: u8 = 22;
+const HASH_REJECT: u8 = 23;
+
+/// Запрос хешей одного слоя дерева файла.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashRequest {
+    pub pieces_root: Hash,
+    pub base_layer: u32,
+    pub index: u32,
+    pub length: u32,
+    pub proof_layers: u32,
+}
+
+const HASH_REQUEST_LEN: usize = 32 + 4 * 4;
+
+impl HashRequest {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.pieces_root);
+        for v in [self.base_layer, self.index, self.length, self.proof_layers] {
+            out.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+
+    fn decode(payload: &[u8]) -> Option<HashRequest> {
+        let u32_at =
+            |i: usize| u32::from_be_bytes(payload[32 + i * 4..36 + i * 4].try_into().unwrap());
+        if payload.len() < HASH_REQUEST_LEN {
+            return None;
+        }
+        Some(HashRequest {
+            pieces_root: payload[..32].try_into().unwrap(),
+            base_layer: u32_at(0),
+            index: u32_at(1),
+            length: u32_at(2),
+            proof_layers: u32_at(3),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    KeepAlive,
+    Choke,
+    Unchoke,
+    Interested,
+    NotInterested,
+    Have(u32),
+    Bitfield(Vec<u8>),
+    Request {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    Piece {
+        index: u32,
+        begin: u32,
+        block: Vec<u8>,
+    },
+    Cancel {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    HashRequest(HashRequest),
+    /// Хеши запрошенного слоя, за которыми идут хеши-дяди для проверки.
+    Hashes {
+        request: HashRequest,
+        hashes: Vec<Hash>,
+    },
+    HashReject(HashRequest),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MessageError {
+    UnknownId(u8),
+    InvalidLength(u8),
+    TooLong(u32),
+}
+
+impl Display for MessageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MessageError::UnknownId(id) => write!(f, "unknown message id {id}"),
+            MessageError::InvalidLength(id) => write!(f, "invalid length of message {id}"),
+            MessageError::TooLong(len) => write!(f, "message of {len} bytes is too long"),
+        }
+    }
+}
+
+impl std::error::Error for MessageError {}
+
+impl Message {
+    /// Сколько байт сообщения — полезная нагрузка, остальное служебное.
+    pub fn payload_len(&self) -> usize {
+        match self {
+            Message::Piece { block, .. } => block.len(),
+            _ => 0,
+        }
+    }
+
+    /// Дописывает сообщение вместе с длиной в `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let start = out.len();
+        out.extend_from_slice(&[0; 4]);
+
+        let triple = |out: &mut Vec<u8>, id: u8, a: u32, b: u32, c: u32| {
+            out.push(id);
+            for v in [a, b, c] {
+                out.extend_from_slice(&v.to_be_bytes());
+            }
+        };
+        match self {
+            Message::KeepAlive => {}
+            Message::Choke => out.push(CHOKE),
+            Message::Unchoke => out.push(UNCHOKE),
+            Message::Interested => out.push(INTERESTED),
+            Message::NotInterested => out.push(NOT_INTERESTED),
+            Message::Have(index) => {
+                out.push(HAVE);
+                out.extend_from_slice(&index.to_be_bytes());
+            }
+            Message::Bitfield(bits) => {
+                out.push(BITFIELD);
+                out.extend_from_slice(bits);
+            }
+            Message::Request {
+                index,
+                begin,
+                length,
+            } => triple(out, REQUEST, *index, *begin, *length),
+            Message::Piece {
+                index,
+                begin,
+                block,
+            } => {
+                out.push(PIECE);
+                out.extend_from_slice(&index.to_be_bytes());
+                out.extend_from_slice(&begin.to_be_bytes());
+                out.extend_from_slice(block);
+            }
+            Message::Cancel {
+                index,
+                begin,
+                length,
+            } => triple(out, CANCEL, *index, *begin, *length),
+            Message::HashRequest(request) => {
+                out.push(HASH_REQUEST);
+                request.encode(out);
+            }
+            Message::Hashes { request, hashes } => {
+                out.push(HASHES);
+                request.encode(out);
+                hashes.iter().for_each(|h| out.extend_from_slice(h));
+            }
+            Message::HashReject(request) => {
+                out.push(HASH_REJECT);
+                request.encode(out);
+            }
+        }
+
+        let len = (out.len() - start - 4) as u32;
+        out[start..start + 4].copy_from_slice(&len.to_be_bytes());
+    }
+
+    /// Читает сообщение из начала буфера. `Ok(None)` — данных пока не хватает.
+    pub fn decode(buf: &[u8]) -> Result<Option<(Message, usize)>, MessageError> {
+        let Some(len) = buf.get(..4) else {
+            return Ok(None);
+        };
+        let len = u32::from_be_bytes(len.try_into().unwrap());
+        if len > MAX_MESSAGE_LENGTH {
+            return Err(MessageError::TooLong(len));
+        }
+        let Some(body) = buf.get(4..4 + len as usize) else {
+            return Ok(None);
+        };
+        let consumed = 4 + len as usize;
+
+        let Some((&id, payload)) = body.split_first() else {
+            return Ok(Some((Message::KeepAlive, consumed)));
+        };
+        let u32_at = |i: usize| u32::from_be_bytes(payload[i * 4..i * 4 + 4].try_into().unwrap());
+        let expect = |n: usize| {
+            if payload.len() == n {
+                Ok(())
+            } else {
+                Err(MessageError::InvalidLength(id))
+            }
+        };
+
+        let message = match id {
+            CHOKE => expect(0).map(|_| Message::Choke)?,
+            UNCHOKE => expect(0).map(|_| Message::Unchoke)?,
+            INTERESTED => expect(0).map(|_| Message::Interested)?,
+            NOT_INTERESTED => expect(0).map(|_| Message::NotInterested)?,
+            HAVE => expect(4).map(|_| Message::Have(u32_at(0)))?,
+            BITFIELD => Message::Bitfield(payload.to_vec()),
+            REQUEST | CANCEL => {
+                expect(12)?;
+                let (index, begin, length) = (u32_at(0), u32_at(1), u32_at(2));
+                if id == REQUEST {
+                    Message::Request {
+                        index,
+                        begin,
+                        length,
+                    }
+                } else {
+                    Message::Cancel {
+                        index,
+                        begin,
+                        length,
+                    }
+                }
+            }
+            PIECE if payload.len() >= 8 => Message::Piece {
+                index: u32_at(0),
+                begin: u32_at(1),
+                block: payload[8..].to_vec(),
+            },
+            HASH_REQUEST | HASH_REJECT => {
+                expect(HASH_REQUEST_LEN)?;
+                let request = HashRequest::decode(payload).unwrap();
+                if id == HASH_REQUEST {
+                    Message::HashRequest(request)
+                } else {
+                    Message::HashReject(request)
+                }
+            }
+            HASHES => {
+                let request =
+                    HashRequest::decode(payload).ok_or(MessageError::InvalidLength(id))?;
+                let hashes = &payload[HASH_REQUEST_LEN..];
+                if hashes.len() % 32 != 0 {
+                    return Err(MessageError::InvalidLength(id));
+                }
+                Message::Hashes {
+                    request,
+                    hashes: hashes.chunks(32).map(|h| h.try_into().unwrap()).collect(),
+                }
+            }
+            PIECE => return Err(MessageError::InvalidLength(id)),
+            _ => return Err(MessageError::UnknownId(id)),
+        };
+        Ok(Some((message, consumed)))
+    }
+}
+
+/// Отвечает на запрос хешей из слоёв кусков торрента.
+/// Запросы ниже слоя кусков отклоняются: листьев у нас нет.
+pub fn answer_hash_request(metadata: &TorrentMetadata, request: HashRequest) -> Message {
+    let hashes = metadata
+        .piece_layer(&request.pieces_root)
+        .and_then(|layer| {
+            merkle::MerkleTree::from_piece_layer(layer, metadata.info.piece_length).hashes(
+                request.base_layer,
+                request.index,
+                request.length,
+                request.proof_layers,
+            )
+        });
+
+    match hashes {
+        Some(hashes) => Message::Hashes { request, hashes },
+        None => Message::HashReject(request),
+    }
+}
+
+/// Проверяет ответ `hashes` по корню файла из дерева торрента.
+pub fn verify_hashes(metadata: &TorrentMetadata, request: &HashRequest, hashes: &[Hash]) -> bool {
+    let Some(file) = metadata
+        .info
+        .tree_files()
+        .into_iter()
+        .find(|f| f.pieces_root == Some(request.pieces_root))
+    else {
+        return false;
+    };
+    let length = request.length as usize;
+    if hashes.len() < length {
+        return false;
+    }
+
+    merkle::verify_hashes(
+        &request.pieces_root,
+        file.length,
+        request.base_layer,
+        request.index,
+        &hashes[..length],
+        &hashes[length..],
+    )
+}
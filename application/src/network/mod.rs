@@ -1,13 +1,19 @@
-mod tcp;
+pub mod bandwidth;
+pub mod message;
+pub mod picker;
 
-use base64::{engine::general_purpose::STANDARD, Engine};
-use reqwest::{Client, Response};
+use reqwest::{header::RANGE, Client};
 
 use crate::{
-    client::{COMPACT, PEER_ID, PORT},
-    repository::types::Torrent,
+    client::COMPACT,
+    repository::{types::Torrent, Id},
+    settings::Settings,
+    storage::Storage,
+    tools::url_encode,
 };
 
+use self::bandwidth::{read_tracker, read_web_seed, Direction, Limiter};
+
 pub struct TorrentState {
     pub torrent: Torrent,
     pub uploaded: u64,
@@ -15,20 +21,80 @@ pub struct TorrentState {
     pub left: u64,
 }
 
-pub async fn get_start(client: Client, state: TorrentState) -> Response {
-    let hash = STANDARD.encode(&state.torrent.hash[..]);
-    let url = &state.torrent.metadata.announce;
-    client
+impl TorrentState {
+    /// Состояние для трекера: `left` считается только по нужным файлам.
+    pub fn new(torrent: Torrent, storage: &Storage) -> TorrentState {
+        let left = storage.left(&torrent.downloaded_pieces);
+        TorrentState {
+            downloaded: torrent.downloaded,
+            torrent,
+            uploaded: 0,
+            left,
+        }
+    }
+}
+
+/// Анонсирует начало загрузки и возвращает тело ответа трекера.
+/// `client` — из `Settings::tracker_client`. Все поля анонса идут в
+/// строке запроса; `info_hash` и `peer_id` — сырые байты, поэтому они
+/// кодируются вручную, а не через `RequestBuilder::query`. Запрос и
+/// ответ проходят через ограничитель как служебный трафик торрента `id`,
+/// заголовки HTTP не учитываются.
+pub async fn get_start(
+    client: &Client,
+    state: &TorrentState,
+    settings: &Settings,
+    limiter: &Limiter,
+    id: Id,
+) -> reqwest::Result<Vec<u8>> {
+    let torrent = &state.torrent;
+    let key = settings.identity.tracker_key(&torrent.hash);
+    let query = [
+        ("info_hash", url_encode(&torrent.hash.short())),
+        ("peer_id", url_encode(&settings.identity.peer_id)),
+        ("port", settings.listen_port.to_string()),
+        ("uploaded", state.uploaded.to_string()),
+        ("downloaded", state.downloaded.to_string()),
+        ("left", state.left.to_string()),
+        ("compact", COMPACT.to_string()),
+        ("event", "started".to_string()),
+        ("key", format!("{key:08X}")),
+    ]
+    .map(|(name, value)| format!("{name}={value}"))
+    .join("&");
+
+    // В адресе трекера уже может быть свой запрос, например passkey
+    let url = &torrent.metadata.announce;
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let url = format!("{url}{separator}{query}");
+    limiter
+        .consume(Some(id), Direction::Upload, url.len())
+        .await;
+    limiter.record(Some(id), Direction::Upload, url.len(), 0);
+    let response = client.get(url).send().await?.error_for_status()?;
+    read_tracker(response, limiter, id).await
+}
+
+/// Скачивает `len` байт файла веб-сида (BEP 19) начиная с `offset`.
+/// Скорость ограничивается, а данные учитываются как полезная нагрузка
+/// торрента `id`.
+pub async fn get_web_seed(
+    client: &Client,
+    url: &str,
+    offset: u64,
+    len: u64,
+    limiter: &Limiter,
+    id: Id,
+) -> reqwest::Result<Vec<u8>> {
+    if len == 0 {
+        return Ok(vec![]);
+    }
+    let range = format!("bytes={offset}-{}", offset + len - 1);
+    let response = client
         .get(url)
-        .header("peer_id", &PEER_ID[..])
-        .header("port", PORT)
-        .header("compact", COMPACT as u16)
-        .header("info_hash", hash)
-        .header("uploaded", state.uploaded)
-        .header("downloaded", state.downloaded)
-        .header("left", state.left)
-        .header("event", "started")
+        .header(RANGE, range)
         .send()
-        .await
-        .unwrap()
+        .await?
+        .error_for_status()?;
+    read_web_seed(response, limiter, id).await
 }
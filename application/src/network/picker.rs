@@ -0,0 +1,83 @@
+/// Модуль, выбирающий следующий кусок для запроса у пира.
+///
+/// Сначала куски с большим приоритетом, среди них — самые редкие у пиров.
+/// Куски только из пропущенных файлов не запрашиваются вовсе.
+use crate::{repository::types::Priority, tools::get_bit};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PiecePicker {
+    priorities: Vec<Priority>,
+    // Сколько известных пиров имеют кусок
+    availability: Vec<u32>,
+    have: Vec<bool>,
+}
+
+impl PiecePicker {
+    /// `priorities` — приоритеты кусков из `Storage::piece_priorities`,
+    /// `have` — битовое поле уже скачанных кусков.
+    pub fn new(priorities: Vec<Priority>, have: &[u8]) -> PiecePicker {
+        let count = priorities.len();
+        PiecePicker {
+            priorities,
+            availability: vec![0; count],
+            have: (0..count).map(|i| get_bit(have, i)).collect(),
+        }
+    }
+
+    pub fn set_priorities(&mut self, priorities: Vec<Priority>) {
+        debug_assert_eq!(self.priorities.len(), priorities.len());
+        self.priorities = priorities;
+    }
+
+    pub fn set_have(&mut self, index: usize) {
+        if let Some(have) = self.have.get_mut(index) {
+            *have = true;
+        }
+    }
+
+    /// Учитывает битовое поле нового пира.
+    pub fn add_peer(&mut self, bitfield: &[u8]) {
+        for (i, count) in self.availability.iter_mut().enumerate() {
+            if get_bit(bitfield, i) {
+                *count += 1;
+            }
+        }
+    }
+
+    pub fn remove_peer(&mut self, bitfield: &[u8]) {
+        for (i, count) in self.availability.iter_mut().enumerate() {
+            if get_bit(bitfield, i) {
+                *count = count.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Пир сообщил о новом куске через `have`.
+    pub fn peer_has(&mut self, index: usize) {
+        if let Some(count) = self.availability.get_mut(index) {
+            *count += 1;
+        }
+    }
+
+    fn wanted(&self, index: usize) -> bool {
+        !self.have[index] && self.priorities[index] != Priority::Skip
+    }
+
+    /// Следующий кусок из тех, что есть у пира с битовым полем `peer`.
+    pub fn pick(&self, peer: &[u8]) -> Option<usize> {
+        (0..self.priorities.len())
+            .filter(|&i| self.wanted(i) && get_bit(peer, i))
+            .min_by_key(|&i| {
+                (
+                    std::cmp::Reverse(self.priorities[i]),
+                    self.availability[i],
+                    i,
+                )
+            })
+    }
+
+    /// Скачаны ли все нужные куски.
+    pub fn is_finished(&self) -> bool {
+        (0..self.priorities.len()).all(|i| !self.wanted(i))
+    }
+}
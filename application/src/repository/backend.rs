@@ -0,0 +1,380 @@
+/// Модуль с хранилищами репозитория на диске.
+///
+/// `SingleFile` держит весь репозиторий в одном файле. `Directory` хранит
+/// по паре файлов на торрент, как другие клиенты: `<хеш>.torrent` с
+/// метаданными и `<хеш>.resume` с состоянием загрузки. Оба хранилища
+/// помнят, что уже записано, и не переписывают неизменившиеся данные, а
+/// `Directory` по номерам правок даже не сериализует нетронутые торренты.
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use sha1::{Digest, Sha1};
+use tokio::sync::Mutex;
+
+use crate::{
+    error::AsyncErr,
+    io::{
+        consts::*,
+        deserialization::{parse_node, DataProvider, ParsingError},
+        serialization::{Encoder, Serialize},
+    },
+    repository::{
+        format::{version_of, FormatError, FORMAT_VERSION},
+        sibling,
+        types::{Torrent, TorrentMetadata},
+        write_atomic, Id, TorrentRepo, WithId,
+    },
+    tools::to_hex,
+};
+
+pub trait RepoBackend {
+    /// Загружает репозиторий. Если сохранённого состояния нет, он пуст.
+    fn load(&mut self) -> impl Future<Output = Result<TorrentRepo, AsyncErr>> + Send;
+
+    fn save(&mut self, repo: &TorrentRepo) -> impl Future<Output = Result<(), AsyncErr>> + Send;
+}
+
+fn digest(data: &[u8]) -> [u8; 20] {
+    Sha1::digest(data).into()
+}
+
+fn not_found(e: &AsyncErr) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+}
+
+/// Весь репозиторий в одном файле с резервной копией прошлого поколения.
+pub struct SingleFile {
+    path: PathBuf,
+    saved: Option<[u8; 20]>,
+    /// Переиспользуется между сохранениями.
+    buf: Vec<u8>,
+}
+
+impl SingleFile {
+    pub fn new(path: impl Into<PathBuf>) -> SingleFile {
+        SingleFile {
+            path: path.into(),
+            saved: None,
+            buf: vec![],
+        }
+    }
+}
+
+impl RepoBackend for SingleFile {
+    async fn load(&mut self) -> Result<TorrentRepo, AsyncErr> {
+        let repo = match TorrentRepo::load_from(&self.path).await {
+            Ok(repo) => repo,
+            Err(e) if not_found(&e) => TorrentRepo::empty(),
+            Err(e) => return Err(e),
+        };
+        self.saved = Some(digest(&repo.serialize()));
+        Ok(repo)
+    }
+
+    async fn save(&mut self, repo: &TorrentRepo) -> Result<(), AsyncErr> {
+        self.buf.clear();
+        repo.serialize_into(&mut self.buf);
+        let digest = digest(&self.buf);
+        if self.saved != Some(digest) {
+            write_atomic(&self.path, &self.buf, true).await?;
+            self.saved = Some(digest);
+        }
+        Ok(())
+    }
+}
+
+/// Содержимое `.resume`-файла: id и состояние торрента без метаданных.
+struct ResumeFile<'a> {
+    id: Id,
+    torrent: &'a Torrent,
+}
+
+struct State<'a>(&'a Torrent);
+
+impl Serialize for State<'_> {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        self.0.encode_with(encoder, false)
+    }
+}
+
+impl Serialize for ResumeFile<'_> {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder
+            .dict()?
+            .required(ID, &self.id)?
+            .required(VALUE, &State(self.torrent))?
+            .required(VERSION, &FORMAT_VERSION)?
+            .fin()
+    }
+}
+
+/// Каталог с парой файлов на торрент. Порядок торрентов не хранится:
+/// после загрузки они идут по именам файлов.
+pub struct Directory {
+    dir: PathBuf,
+    saved: HashMap<PathBuf, [u8; 20]>,
+    /// Записанные торренты: номер правки и имя их файлов.
+    written: HashMap<Id, (u64, String)>,
+    corrupt: Vec<(PathBuf, String)>,
+}
+
+impl Directory {
+    pub fn new(dir: impl Into<PathBuf>) -> Directory {
+        Directory {
+            dir: dir.into(),
+            saved: HashMap::new(),
+            written: HashMap::new(),
+            corrupt: vec![],
+        }
+    }
+
+    /// Битые записи, отложенные последней загрузкой: путь `.resume`-файла
+    /// и причина.
+    pub fn corrupt(&self) -> &[(PathBuf, String)] {
+        &self.corrupt
+    }
+
+    /// Имя файлов торрента без расширения: шестнадцатеричный короткий хеш.
+    pub fn stem(torrent: &Torrent) -> String {
+        to_hex(&torrent.hash.short())
+    }
+
+    async fn load_one(&mut self, resume: &Path) -> Result<WithId<Torrent>, AsyncErr> {
+        let metainfo = resume.with_extension("torrent");
+        let resume_bytes = tokio::fs::read(resume).await?;
+        let torrent_bytes = tokio::fs::read(&metainfo).await?;
+
+        let dp = DataProvider::try_from(parse_node(&resume_bytes)?.1)?;
+        let version = version_of(dp.dict)?;
+        if version > FORMAT_VERSION {
+            return Err(Box::new(FormatError::UnsupportedVersion {
+                found: version,
+                supported: FORMAT_VERSION,
+            }));
+        }
+        // Хеш берётся из `.resume`, а не пересчитывается по метаданным
+        let (metadata, _) = TorrentMetadata::new(&torrent_bytes)?;
+        let value = dp.dict.get(VALUE).ok_or_else(|| {
+            ParsingError::MissingField(String::from_utf8_lossy(VALUE).into_owned())
+        })?;
+        let torrent = WithId {
+            id: dp.required(ID)?,
+            value: Torrent::from_node(value, Some(metadata))?,
+        };
+
+        self.saved
+            .insert(resume.to_path_buf(), digest(&resume_bytes));
+        self.saved.insert(metainfo, digest(&torrent_bytes));
+        Ok(torrent)
+    }
+
+    /// Откладывает файлы битой записи в `<имя>.corrupt`, чтобы сохранение
+    /// не удалило их как файлы удалённого торрента.
+    async fn set_aside(&self, resume: &Path) -> io::Result<()> {
+        for path in [resume.to_path_buf(), resume.with_extension("torrent")] {
+            if tokio::fs::try_exists(&path).await? {
+                tokio::fs::rename(&path, sibling(&path, "corrupt")).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Пишет файл, только если его содержимое поменялось с прошлого раза.
+    async fn write(&mut self, path: PathBuf, data: &[u8]) -> io::Result<()> {
+        let digest = digest(data);
+        if self.saved.get(&path) != Some(&digest) {
+            write_atomic(&path, data, false).await?;
+            self.saved.insert(path, digest);
+        }
+        Ok(())
+    }
+
+    /// Удаляет файлы торрента с именем `stem`.
+    async fn remove(&mut self, stem: &str) -> io::Result<()> {
+        for ext in ["torrent", "resume"] {
+            let path = self.dir.join(format!("{stem}.{ext}"));
+            match tokio::fs::remove_file(&path).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            self.saved.remove(&path);
+        }
+        Ok(())
+    }
+
+    /// Пути `.torrent`- и `.resume`-файлов в каталоге.
+    async fn entries(&self) -> io::Result<Vec<PathBuf>> {
+        let mut entries = vec![];
+        let mut dir = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+            let ext = path.extension().and_then(|e| e.to_str());
+            if matches!(ext, Some("torrent" | "resume")) {
+                entries.push(path);
+            }
+        }
+        entries.sort();
+        Ok(entries)
+    }
+}
+
+impl RepoBackend for Directory {
+    async fn load(&mut self) -> Result<TorrentRepo, AsyncErr> {
+        if !tokio::fs::try_exists(&self.dir).await? {
+            return Ok(TorrentRepo::empty());
+        }
+
+        self.corrupt.clear();
+        self.written.clear();
+        let mut torrents = vec![];
+        let mut stems = vec![];
+        for path in self.entries().await? {
+            if path.extension().is_none_or(|e| e != "resume") {
+                continue;
+            }
+            match self.load_one(&path).await {
+                Ok(torrent) => {
+                    let stem = path.file_stem().unwrap().to_string_lossy().into_owned();
+                    stems.push((torrent.id, stem));
+                    torrents.push(torrent);
+                }
+                // Запись более нового клиента не битая, её нельзя откладывать
+                Err(e)
+                    if matches!(
+                        e.downcast_ref(),
+                        Some(FormatError::UnsupportedVersion { .. })
+                    ) =>
+                {
+                    return Err(e)
+                }
+                Err(e) => {
+                    self.set_aside(&path).await?;
+                    self.corrupt.push((path, e.to_string()));
+                }
+            }
+        }
+        let repo = TorrentRepo::from_torrents(torrents);
+        for (id, stem) in stems {
+            let Some(torrent) = repo.get_by_id(id) else {
+                continue;
+            };
+            // Файлы под чужим именем перепишутся под верным при сохранении
+            let revision = if stem == Directory::stem(&torrent.value) {
+                repo.revision(id)
+            } else {
+                0
+            };
+            self.written.insert(id, (revision, stem));
+        }
+        Ok(repo)
+    }
+
+    async fn save(&mut self, repo: &TorrentRepo) -> Result<(), AsyncErr> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        let mut stems = HashSet::new();
+        let mut stale = vec![];
+        for torrent in repo.iter() {
+            let revision = repo.revision(torrent.id);
+            if self
+                .written
+                .get(&torrent.id)
+                .is_some_and(|(saved, _)| *saved == revision)
+            {
+                continue;
+            }
+
+            let stem = Directory::stem(&torrent.value);
+            let resume = ResumeFile {
+                id: torrent.id,
+                torrent: &torrent.value,
+            };
+            // Метаданные пишутся первыми: `.resume` без `.torrent` не загрузится
+            self.write(
+                self.dir.join(format!("{stem}.torrent")),
+                &torrent.value.metadata.serialize(),
+            )
+            .await?;
+            self.write(self.dir.join(format!("{stem}.resume")), &resume.serialize())
+                .await?;
+            // У торрента сменился хеш, и старые файлы не нужны
+            if let Some((_, old)) = self.written.insert(torrent.id, (revision, stem.clone())) {
+                if old != stem {
+                    stale.push(old);
+                }
+            }
+            stems.insert(stem);
+        }
+
+        // Файлы удалённых торрентов
+        let removed: Vec<Id> = self
+            .written
+            .keys()
+            .filter(|id| repo.get_by_id(**id).is_none())
+            .copied()
+            .collect();
+        for id in removed {
+            stale.extend(self.written.remove(&id).map(|(_, stem)| stem));
+        }
+        // Имя могло перейти к другому торренту в этом же сохранении
+        for stem in stale {
+            if !stems.contains(&stem) {
+                self.remove(&stem).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Переносит репозиторий из одного хранилища в другое и проверяет, что
+/// он читается обратно без потерь. Исходное хранилище не трогается.
+/// Возвращает число перенесённых торрентов.
+pub async fn migrate<F, T>(from: &mut F, to: &mut T) -> Result<usize, AsyncErr>
+where
+    F: RepoBackend,
+    T: RepoBackend,
+{
+    let repo = from.load().await?;
+    to.save(&repo).await?;
+
+    let copy = to.load().await?;
+    let same = copy.len() == repo.len()
+        && repo
+            .iter()
+            .all(|t| copy.get_by_id(t.id).is_some_and(|c| c.value == t.value));
+    if !same {
+        return Err("migrated repo does not match the source".into());
+    }
+    Ok(repo.len())
+}
+
+/// Сохраняет общий репозиторий раз в `period`. Неизменившиеся данные
+/// хранилище не переписывает. Ошибки отдаются в `on_error`, после чего
+/// попытка повторяется на следующем тике.
+pub async fn autosave<B, F>(
+    repo: Arc<Mutex<TorrentRepo>>,
+    mut backend: B,
+    period: Duration,
+    mut on_error: F,
+) where
+    B: RepoBackend,
+    F: FnMut(AsyncErr),
+{
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        let repo = repo.lock().await;
+        if let Err(e) = backend.save(&repo).await {
+            on_error(e);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+/// Модуль с версиями формата репозитория и миграциями между ними.
+///
+/// Версия лежит в корне файла под ключом `version`. Файлы без неё — это
+/// нулевая версия, которую писали клиенты до появления миграций.
+/// Чтобы поменять раскладку, нужно поднять `FORMAT_VERSION` и дописать
+/// в `MIGRATIONS` шаг из предыдущей версии.
+use std::{borrow::Cow, collections::BTreeMap, fmt::Display};
+
+use crate::io::{
+    consts::*,
+    deserialization::{parse_node, Dict, Node, ParsingError, TryDeserialize},
+    serialization::Serialize,
+};
+
+/// Версия, которую пишет этот клиент.
+pub const FORMAT_VERSION: u64 = 1;
+
+/// Шаг миграции: документ версии `i` превращается в документ версии `i + 1`.
+type Migration = fn(Dict<'_>) -> Vec<u8>;
+
+const MIGRATIONS: [Migration; FORMAT_VERSION as usize] = [v0_to_v1];
+
+#[derive(Debug, Clone)]
+pub enum FormatError {
+    Parsing(ParsingError),
+    /// Файл записан более новым клиентом, и как его читать, неизвестно.
+    UnsupportedVersion {
+        found: u64,
+        supported: u64,
+    },
+}
+
+impl Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::Parsing(e) => write!(f, "{e}"),
+            FormatError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "repo format version {found} is newer than the supported version {supported}, \
+                 update the client to open it"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl From<ParsingError> for FormatError {
+    fn from(value: ParsingError) -> Self {
+        FormatError::Parsing(value)
+    }
+}
+
+/// Версия документа. Отсутствие ключа означает нулевую версию.
+pub fn version_of(dict: Dict<'_>) -> Result<u64, ParsingError> {
+    dict.get(VERSION)
+        .map_or(Ok(0), u64::try_deserialize_from_node)
+}
+
+/// Доводит документ до `FORMAT_VERSION`. Документ текущей версии
+/// возвращается как есть, без копирования.
+pub fn upgrade(bytes: &[u8]) -> Result<Cow<'_, [u8]>, FormatError> {
+    let mut doc = Cow::Borrowed(bytes);
+    loop {
+        let Node::Dict(dict) = parse_node(&doc)?.1 else {
+            return Err(ParsingError::TypeMismatch.into());
+        };
+        let version = version_of(dict)?;
+        if version == FORMAT_VERSION {
+            return Ok(doc);
+        }
+        let Some(migrate) = MIGRATIONS.get(version as usize) else {
+            return Err(FormatError::UnsupportedVersion {
+                found: version,
+                supported: FORMAT_VERSION,
+            });
+        };
+        doc = Cow::Owned(migrate(dict));
+    }
+}
+
+/// Первая версия только добавляет номер версии. Заодно документ
+/// переписывается канонически: старые клиенты не сортировали ключи.
+fn v0_to_v1(doc: Dict<'_>) -> Vec<u8> {
+    let mut entries: BTreeMap<&[u8], Node> = doc.iter().collect();
+    entries.insert(VERSION, Node::UnsignedNum(1));
+    entries.serialize()
+}
@@ -0,0 +1,167 @@
+/// Модуль с деревьями хешей торрентов v2 (BEP 52).
+///
+/// Листья — SHA-256 от блоков по 16 КиБ. Недостающие до степени двойки листья
+/// заполняются нулями, поэтому корень файла однозначно задаётся его длиной и данными.
+use sha2::{Digest, Sha256};
+
+pub const BLOCK_SIZE: u64 = 16 * 1024;
+
+pub type Hash = [u8; 32];
+
+fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Хеши блоков, из которых строится нижний слой дерева.
+pub fn block_hashes(data: &[u8]) -> Vec<Hash> {
+    data.chunks(BLOCK_SIZE as usize)
+        .map(|block| Sha256::digest(block).into())
+        .collect()
+}
+
+/// Корень поддерева из `leaves` нулевых листьев.
+pub fn pad_hash(leaves: u64) -> Hash {
+    let mut pad = [0; 32];
+    let mut width = 1;
+    while width < leaves {
+        pad = hash_pair(&pad, &pad);
+        width *= 2;
+    }
+    pad
+}
+
+/// Сколько листьев приходится на один кусок.
+pub fn leaves_per_piece(piece_length: u64) -> u64 {
+    (piece_length / BLOCK_SIZE).max(1)
+}
+
+/// Корень файла или `None` для пустого файла.
+pub fn file_root(data: &[u8]) -> Option<Hash> {
+    if data.is_empty() {
+        None
+    } else {
+        Some(MerkleTree::from_blocks(block_hashes(data)).root())
+    }
+}
+
+/// Хеши кусков файла, которые лежат в `piece layers`.
+pub fn piece_layer(data: &[u8], piece_length: u64) -> Vec<Hash> {
+    let leaves = leaves_per_piece(piece_length);
+    data.chunks(piece_length as usize)
+        .map(|piece| MerkleTree::new(block_hashes(piece), 0, leaves, [0; 32]).root())
+        .collect()
+}
+
+/// Все слои дерева, начиная с `base_layer` полного дерева файла.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerkleTree {
+    base_layer: u32,
+    layers: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    /// Дополняет `leaves` копиями `pad` до ширины не меньше `min_width`.
+    fn new(mut leaves: Vec<Hash>, base_layer: u32, min_width: u64, pad: Hash) -> MerkleTree {
+        let width = (leaves.len() as u64).max(min_width).next_power_of_two();
+        leaves.resize(width as usize, pad);
+
+        let mut layers = vec![leaves];
+        while let Some(last) = layers.last().filter(|l| l.len() > 1) {
+            let next = last.chunks(2).map(|c| hash_pair(&c[0], &c[1])).collect();
+            layers.push(next);
+        }
+        MerkleTree { base_layer, layers }
+    }
+
+    pub fn from_blocks(blocks: Vec<Hash>) -> MerkleTree {
+        MerkleTree::new(blocks, 0, 1, [0; 32])
+    }
+
+    /// Дерево над слоем кусков. Слои ниже него недоступны.
+    pub fn from_piece_layer(layer: Vec<Hash>, piece_length: u64) -> MerkleTree {
+        let leaves = leaves_per_piece(piece_length);
+        MerkleTree::new(layer, leaves.trailing_zeros(), 1, pad_hash(leaves))
+    }
+
+    pub fn root(&self) -> Hash {
+        self.layers.last().map_or([0; 32], |l| l[0])
+    }
+
+    /// Слой с номером `layer` в полном дереве файла.
+    pub fn layer(&self, layer: u32) -> Option<&[Hash]> {
+        let index = layer.checked_sub(self.base_layer)?;
+        self.layers.get(index as usize).map(Vec::as_slice)
+    }
+
+    /// Хеши для ответа на `hash request`: `length` хешей слоя `base_layer`
+    /// начиная с `index`, а за ними до `proof_layers` хешей-дядь.
+    pub fn hashes(
+        &self,
+        base_layer: u32,
+        index: u32,
+        length: u32,
+        proof_layers: u32,
+    ) -> Option<Vec<Hash>> {
+        if !length.is_power_of_two() || !index.is_multiple_of(length) {
+            return None;
+        }
+        let layer = self.layer(base_layer)?;
+        let start = index as usize;
+        let mut out = layer
+            .get(start..start.checked_add(length as usize)?)?
+            .to_vec();
+
+        // Поднимаемся от корня запрошенного поддерева
+        let first = base_layer + length.trailing_zeros();
+        let mut pos = (index / length) as usize;
+        for level in first..first.saturating_add(proof_layers) {
+            let Some(layer) = self.layer(level).filter(|l| l.len() > 1) else {
+                break;
+            };
+            out.push(layer[pos ^ 1]);
+            pos /= 2;
+        }
+        Some(out)
+    }
+}
+
+/// Проверяет ответ `hashes` по корню файла. Доказательство должно
+/// доходить до самого корня.
+pub fn verify_hashes(
+    root: &Hash,
+    file_length: u64,
+    base_layer: u32,
+    index: u32,
+    hashes: &[Hash],
+    proof: &[Hash],
+) -> bool {
+    let length = hashes.len() as u32;
+    if !length.is_power_of_two() || !index.is_multiple_of(length) {
+        return false;
+    }
+    let height = file_length
+        .div_ceil(BLOCK_SIZE)
+        .next_power_of_two()
+        .trailing_zeros();
+    let Some(subtree_layer) = base_layer.checked_add(length.trailing_zeros()) else {
+        return false;
+    };
+    if height.checked_sub(subtree_layer) != Some(proof.len() as u32) {
+        return false;
+    }
+
+    let mut current = MerkleTree::new(hashes.to_vec(), 0, 1, [0; 32]).root();
+    let mut pos = index / length;
+    for uncle in proof {
+        current = if pos.is_multiple_of(2) {
+            hash_pair(&current, uncle)
+        } else {
+            hash_pair(uncle, &current)
+        };
+        pos /= 2;
+    }
+    pos == 0 && current == *root
+}
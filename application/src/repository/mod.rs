@@ -1,6 +1,20 @@
+pub mod backend;
+pub mod format;
+pub mod merkle;
+pub mod queue;
+pub mod resume;
 pub mod types;
 
-use std::{fmt::Debug, path::Path};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fmt::Debug,
+    io::{self, Write},
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use uuid::Uuid;
 
 use crate::{
@@ -8,16 +22,27 @@ use crate::{
     io::{
         consts::*,
         deserialization::{DataProvider, Node, ParsingError, TryDeserialize},
-        serialization::{BencodeDictBuilder, Serialize},
+        serialization::{write_async, AsyncEncoder, Encoder, Serialize},
+    },
+    repository::{
+        format::{upgrade, FormatError, FORMAT_VERSION},
+        types::{InfoHash, Priority, Status, Torrent},
     },
-    repository::types::Torrent,
 };
 
 pub type Id = Uuid;
 
+/// Номера правок общие на весь процесс, так что правки разных
+/// репозиториев, в том числе копий одного, не совпадают.
+static REVISION: AtomicU64 = AtomicU64::new(1);
+
+fn next_revision() -> u64 {
+    REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
 impl Serialize for Id {
-    fn serialize(&self) -> Vec<u8> {
-        self.as_bytes().to_vec().serialize()
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder.write_bytes(self.as_bytes())
     }
 }
 
@@ -50,11 +75,11 @@ impl<T> Serialize for WithId<T>
 where
     T: Serialize + Clone + PartialEq + Debug,
 {
-    fn serialize(&self) -> Vec<u8> {
-        let e = self.clone();
-        BencodeDictBuilder::new()
-            .required(VALUE, e.value)
-            .required(ID, e.id)
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder
+            .dict()?
+            .required(ID, &self.id)?
+            .required(VALUE, &self.value)?
             .fin()
     }
 }
@@ -72,90 +97,437 @@ where
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Репозиторий торрентов. Кроме списка держит индексы по id и по
+/// инфо-хешам, поэтому список меняется только через методы репозитория.
+#[derive(Debug, Clone)]
 pub struct TorrentRepo {
-    pub torrents: Vec<WithId<Torrent>>,
+    torrents: Vec<WithId<Torrent>>,
+    by_id: HashMap<Id, usize>,
+    by_v1: HashMap<[u8; 20], usize>,
+    by_v2: HashMap<[u8; 32], usize>,
+    /// Номер последней правки каждого торрента.
+    revisions: HashMap<Id, u64>,
+}
+
+/// Индексы и номера правок выводятся из списка и в сравнении не участвуют.
+impl PartialEq for TorrentRepo {
+    fn eq(&self, other: &TorrentRepo) -> bool {
+        self.torrents == other.torrents
+    }
+}
+
+/// Чем закончилось добавление торрента.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Added {
+    New(Id),
+    /// Торрент уже был, в него перенесены новые трекеры.
+    Merged(Id),
+}
+
+impl Added {
+    pub fn id(&self) -> Id {
+        match self {
+            Added::New(id) | Added::Merged(id) => *id,
+        }
+    }
 }
 
 impl Serialize for TorrentRepo {
-    fn serialize(&self) -> Vec<u8> {
-        BencodeDictBuilder::new()
-            .required(TORRENTS, self.get_torrent_list().clone())
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder
+            .dict()?
+            .required(TORRENTS, self.get_torrent_list())?
+            .required(VERSION, &FORMAT_VERSION)?
             .fin()
     }
+
+    /// Торренты пишутся по одному, так что в буфере не бывает всего
+    /// репозитория сразу.
+    async fn encode_async<W>(&self, encoder: &mut AsyncEncoder<'_, W>) -> io::Result<()>
+    where
+        Self: Sync,
+        W: AsyncWrite + Unpin + Send,
+    {
+        encoder.raw(b"d");
+        encoder.encode(TORRENTS)?;
+        self.torrents.encode_async(encoder).await?;
+        encoder.encode(VERSION)?;
+        encoder.encode(&FORMAT_VERSION)?;
+        encoder.raw(b"e");
+        encoder.spill().await
+    }
 }
 
 impl<'a> TryDeserialize<'a> for TorrentRepo {
     fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
         let dp = DataProvider::try_from(node)?;
-        Ok(TorrentRepo {
-            torrents: dp.required(TORRENTS)?,
-        })
+        Ok(TorrentRepo::from_torrents(dp.required(TORRENTS)?))
     }
 }
 
 impl TorrentRepo {
     pub fn empty() -> TorrentRepo {
-        TorrentRepo { torrents: vec![] }
+        TorrentRepo {
+            torrents: vec![],
+            by_id: HashMap::new(),
+            by_v1: HashMap::new(),
+            by_v2: HashMap::new(),
+            revisions: HashMap::new(),
+        }
+    }
+
+    /// Собирает репозиторий из списка. Повторы одного торрента, которые
+    /// могли накопиться в старых файлах, сливаются в первое вхождение.
+    pub fn from_torrents(torrents: Vec<WithId<Torrent>>) -> TorrentRepo {
+        let mut repo = TorrentRepo::empty();
+        for torrent in torrents {
+            repo.insert(torrent);
+        }
+        repo
+    }
+
+    /// Разбирает файл репозитория любой известной версии, обновляя его
+    /// раскладку миграциями. Файлы более новых версий не читаются.
+    pub fn from_bytes(bytes: &[u8]) -> Result<TorrentRepo, FormatError> {
+        let doc = upgrade(bytes)?;
+        Ok(TorrentRepo::try_deserialize(&doc)?)
     }
 
+    /// Загружает репозиторий. Если файл повреждён или пропал посреди
+    /// сохранения, берётся предыдущее поколение из `<path>.bak`, а
+    /// повреждённый файл откладывается в `<path>.corrupt`, чтобы следующее
+    /// сохранение не вытеснило им резервную копию.
     pub async fn load_from(path: &Path) -> Result<TorrentRepo, AsyncErr> {
+        let err = match TorrentRepo::read(path).await {
+            Ok(repo) => return Ok(repo),
+            // Файл цел, просто записан более новым клиентом: откат на
+            // резервную копию потерял бы его изменения
+            Err(e)
+                if matches!(
+                    e.downcast_ref(),
+                    Some(FormatError::UnsupportedVersion { .. })
+                ) =>
+            {
+                return Err(e)
+            }
+            Err(e) => e,
+        };
+
+        match TorrentRepo::read(&sibling(path, "bak")).await {
+            Ok(repo) => {
+                if tokio::fs::try_exists(path).await? {
+                    tokio::fs::rename(path, sibling(path, "corrupt")).await?;
+                }
+                Ok(repo)
+            }
+            Err(_) => Err(err),
+        }
+    }
+
+    async fn read(path: &Path) -> Result<TorrentRepo, AsyncErr> {
         let file = &tokio::fs::read(path).await?;
 
-        match TorrentRepo::try_deserialize(&file[..]) {
+        match TorrentRepo::from_bytes(&file[..]) {
             Ok(repo) => Ok(repo),
             Err(e) => Err(Box::new(e)),
         }
     }
 
-    pub async fn save_to(&self, path: &Path) -> Result<(), AsyncErr> {
-        if let Err(e) = tokio::fs::write(path, self.serialize()).await {
-            Err(Box::new(e))
-        } else {
-            Ok(())
-        }
+    /// Атомарно сохраняет репозиторий, оставляя прошлое поколение в
+    /// `<path>.bak`. Падение в любой момент оставляет на диске целый файл.
+    /// Данные пишутся порциями через `buf`, который можно переиспользовать
+    /// между сохранениями.
+    pub async fn save_to(&self, path: &Path, buf: &mut Vec<u8>) -> Result<(), AsyncErr> {
+        let (tmp, mut file) = create_tmp(path).await?;
+        write_async(self, &mut file, buf).await?;
+        replace(path, &tmp, file, true).await?;
+        Ok(())
     }
 
     pub fn get_torrent_list(&self) -> &Vec<WithId<Torrent>> {
         &self.torrents
     }
 
-    pub fn add_new_torrent(&mut self, torrent: Torrent) {
-        let torrent = WithId {
+    pub fn len(&self) -> usize {
+        self.torrents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.torrents.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &WithId<Torrent>> {
+        self.torrents.iter()
+    }
+
+    pub fn get_by_id(&self, id: Id) -> Option<&WithId<Torrent>> {
+        self.by_id.get(&id).map(|&i| &self.torrents[i])
+    }
+
+    /// Изменяемый доступ к торренту. Индекс по хешам поправляется, когда
+    /// доступ отпускают, см. `TorrentMut`.
+    pub fn get_by_id_mut(&mut self, id: Id) -> Option<TorrentMut<'_>> {
+        let i = *self.by_id.get(&id)?;
+        let hash = self.torrents[i].value.hash;
+        Some(TorrentMut {
+            repo: self,
+            i,
+            hash,
+            touched: false,
+        })
+    }
+
+    /// Номер последней правки торрента, `0` для неизвестного. Меняется с
+    /// каждым изменением, так что хранилище переписывает только тронутые
+    /// торренты.
+    pub fn revision(&self, id: Id) -> u64 {
+        self.revisions.get(&id).copied().unwrap_or(0)
+    }
+
+    fn touch(&mut self, id: Id) {
+        self.revisions.insert(id, next_revision());
+    }
+
+    /// Ищет торрент по любому из его хешей: гибридный находится и по v1, и по v2.
+    pub fn get_by_hash(&self, hash: &InfoHash) -> Option<&WithId<Torrent>> {
+        self.index_of(hash).map(|i| &self.torrents[i])
+    }
+
+    pub fn iter_by_status(&self, status: Status) -> impl Iterator<Item = &WithId<Torrent>> {
+        self.iter().filter(move |t| t.value.status() == status)
+    }
+
+    pub fn iter_by_label<'s>(
+        &'s self,
+        label: &'s str,
+    ) -> impl Iterator<Item = &'s WithId<Torrent>> {
+        self.iter().filter(move |t| t.value.has_label(label))
+    }
+
+    /// Добавляет торрент. Если такой уже есть, новая копия не добавляется,
+    /// а её трекеры дописываются к имеющимся.
+    pub fn add_new_torrent(&mut self, mut torrent: Torrent) -> Added {
+        torrent.queue.position = self.next_position();
+        self.insert(WithId {
             id: Uuid::new_v4(),
             value: torrent,
-        };
+        })
+    }
+
+    fn insert(&mut self, torrent: WithId<Torrent>) -> Added {
+        if let Some(i) = self.index_of(&torrent.value.hash) {
+            let existing = &mut self.torrents[i];
+            let id = existing.id;
+            if existing
+                .value
+                .metadata
+                .merge_trackers(&torrent.value.metadata)
+            {
+                self.touch(id);
+            }
+            return Added::Merged(id);
+        }
+
+        let id = torrent.id;
+        self.index(self.torrents.len(), id, torrent.value.hash);
+        self.torrents.push(torrent);
+        self.touch(id);
+        Added::New(id)
+    }
+
+    fn index_of(&self, hash: &InfoHash) -> Option<usize> {
+        let v1 = hash.v1().and_then(|v1| self.by_v1.get(&v1));
+        let v2 = || hash.v2().and_then(|v2| self.by_v2.get(&v2));
+        v1.or_else(v2).copied()
+    }
+
+    /// Занят ли какой-нибудь из хешей `hash` торрентом не с номером `i`.
+    fn taken_by_other(&self, hash: &InfoHash, i: usize) -> bool {
+        let v1 = hash.v1().and_then(|v1| self.by_v1.get(&v1));
+        let v2 = hash.v2().and_then(|v2| self.by_v2.get(&v2));
+        v1.into_iter().chain(v2).any(|&j| j != i)
+    }
+
+    fn index(&mut self, i: usize, id: Id, hash: InfoHash) {
+        self.by_id.insert(id, i);
+        if let Some(v1) = hash.v1() {
+            self.by_v1.insert(v1, i);
+        }
+        if let Some(v2) = hash.v2() {
+            self.by_v2.insert(v2, i);
+        }
+    }
 
-        self.torrents.push(torrent)
+    fn reindex(&mut self) {
+        self.by_id.clear();
+        self.by_v1.clear();
+        self.by_v2.clear();
+        let keys: Vec<_> = self.torrents.iter().map(|t| (t.id, t.value.hash)).collect();
+        for (i, (id, hash)) in keys.into_iter().enumerate() {
+            self.index(i, id, hash);
+        }
     }
 
-    /// Возвращает `true`, если значение было изменено.'7kl
+    /// Возвращает `true`, если значение было изменено. Замена с хешем
+    /// другого торрента отклоняется, иначе в репозитории появился бы дубль.
     pub fn edit_torrent(&mut self, torrent: WithId<Torrent>) -> bool {
-        let old = self
-            .torrents
-            .iter()
-            .enumerate()
-            .find(|t| t.1.id == torrent.id);
-
-        if let Some(old) = old {
-            let (i, _) = old;
-            self.torrents[i] = torrent;
-            true
-        } else {
-            false
+        match self.by_id.get(&torrent.id) {
+            Some(&i) if !self.taken_by_other(&torrent.value.hash, i) => {
+                self.touch(torrent.id);
+                self.torrents[i] = torrent;
+                self.reindex();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Меняет приоритеты файлов: `changes` — пары из номера файла и
+    /// приоритета. Края файлов, которые начали или перестали пропускаться,
+    /// переносятся на диске до изменения записи, так что запись всегда
+    /// описывает, где лежат данные. Возвращает `true`, если торрент найден.
+    pub async fn set_file_priorities(
+        &mut self,
+        id: Id,
+        changes: &[(usize, Priority)],
+    ) -> io::Result<bool> {
+        let Some(mut torrent) = self.get_by_id_mut(id) else {
+            return Ok(false);
+        };
+        let mut priorities = torrent.priorities.clone();
+        for &(file, priority) in changes {
+            if priorities.len() <= file {
+                priorities.resize(file + 1, Priority::Normal);
+            }
+            priorities[file] = priority;
         }
+
+        let mut storage = torrent.storage();
+        storage.set_priorities(priorities.clone()).await?;
+        torrent.priorities = priorities;
+        Ok(true)
     }
 
     /// Возвращает 'true', если значение было удалено.
     pub fn remove_torrent_by_id(&mut self, id: Id) -> bool {
-        let torrent = self.torrents.iter().enumerate().find(|t| t.1.id == id);
+        match self.by_id.get(&id) {
+            Some(&i) => {
+                self.torrents.remove(i);
+                self.revisions.remove(&id);
+                self.reindex();
+                self.compact_queue();
+                true
+            }
+            None => false,
+        }
+    }
+}
 
-        if let Some(torrent) = torrent {
-            let (i, _) = torrent;
-            self.torrents.remove(i);
-            true
+/// Торрент из репозитория, открытый на изменение. Если хеш поменялся,
+/// при освобождении индекс перестраивается. Хеш другого торрента
+/// откатывается к прежнему, как отклоняет такую замену `edit_torrent`.
+pub struct TorrentMut<'a> {
+    repo: &'a mut TorrentRepo,
+    i: usize,
+    hash: InfoHash,
+    /// Торрент открывали на запись, и его номер правки надо сменить.
+    touched: bool,
+}
+
+impl Deref for TorrentMut<'_> {
+    type Target = Torrent;
+
+    fn deref(&self) -> &Torrent {
+        &self.repo.torrents[self.i].value
+    }
+}
+
+impl DerefMut for TorrentMut<'_> {
+    fn deref_mut(&mut self) -> &mut Torrent {
+        self.touched = true;
+        &mut self.repo.torrents[self.i].value
+    }
+}
+
+impl Drop for TorrentMut<'_> {
+    fn drop(&mut self) {
+        if self.touched {
+            self.repo.touch(self.repo.torrents[self.i].id);
+        }
+        let hash = self.repo.torrents[self.i].value.hash;
+        if hash == self.hash {
+            return;
+        }
+        if self.repo.taken_by_other(&hash, self.i) {
+            self.repo.torrents[self.i].value.hash = self.hash;
         } else {
-            false
+            self.repo.reindex();
         }
     }
 }
+
+/// `<path>.<suffix>` рядом с исходным файлом.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Пишет во временный файл, сбрасывает его на диск и подменяет им `path`.
+/// С `backup` старое содержимое `path` переезжает в `<path>.bak`.
+async fn write_atomic(path: &Path, data: &[u8], backup: bool) -> io::Result<()> {
+    let (tmp, mut file) = create_tmp(path).await?;
+    file.write_all(data).await?;
+    replace(path, &tmp, file, backup).await
+}
+
+/// Временный файл рядом с `path`.
+async fn create_tmp(path: &Path) -> io::Result<(PathBuf, tokio::fs::File)> {
+    let tmp = sibling(path, "tmp");
+    let file = tokio::fs::File::create(&tmp).await?;
+    Ok((tmp, file))
+}
+
+/// Сбрасывает записанный временный файл на диск и подменяет им `path`.
+async fn replace(
+    path: &Path,
+    tmp: &Path,
+    mut file: tokio::fs::File,
+    backup: bool,
+) -> io::Result<()> {
+    file.flush().await?;
+    file.sync_all().await?;
+    drop(file);
+
+    // Резервная копия появляется рядом с `path`, а не вместо него, так что
+    // основной файл не пропадает ни на миг
+    if backup && tokio::fs::try_exists(path).await? {
+        let bak = sibling(path, "bak");
+        match tokio::fs::remove_file(&bak).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        if tokio::fs::hard_link(path, &bak).await.is_err() {
+            tokio::fs::copy(path, &bak).await?;
+        }
+    }
+    tokio::fs::rename(tmp, path).await?;
+    sync_dir(path).await
+}
+
+/// Сбрасывает на диск запись каталога, иначе переименование может
+/// потеряться при отключении питания.
+#[cfg(unix)]
+async fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    tokio::fs::File::open(dir).await?.sync_all().await
+}
+
+#[cfg(not(unix))]
+async fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
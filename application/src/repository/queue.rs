@@ -0,0 +1,287 @@
+/// Модуль с очередью торрентов и целями раздачи.
+///
+/// Активны только первые по очереди торренты, сколько разрешают настройки,
+/// остальные ждут свободного места. Раздача останавливается, когда
+/// достигнуто отношение, время раздачи или время простоя.
+use std::io::{self, Write};
+
+use crate::{
+    io::{
+        consts::*,
+        deserialization::{DataProvider, Node, ParsingError, TryDeserialize},
+        serialization::{Encoder, Serialize},
+    },
+    repository::{
+        types::{Status, Torrent},
+        Id, TorrentRepo,
+    },
+    settings::{QueueLimits, SeedGoals, Settings},
+};
+
+/// Цель раздачи торрента: отношение в тысячных или время в минутах.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Goal {
+    /// Как в настройках.
+    #[default]
+    Global,
+    Unlimited,
+    Limit(u64),
+}
+
+impl Goal {
+    pub fn resolve(self, global: Option<u64>) -> Option<u64> {
+        match self {
+            Goal::Global => global,
+            Goal::Unlimited => None,
+            Goal::Limit(limit) => Some(limit),
+        }
+    }
+
+    /// В файле нет ключа у `Global`, а ноль означает `Unlimited`.
+    fn encoded(self) -> Option<u64> {
+        match self {
+            Goal::Global => None,
+            Goal::Unlimited => Some(0),
+            Goal::Limit(limit) => Some(limit),
+        }
+    }
+
+    fn decode(value: Option<u64>) -> Goal {
+        match value {
+            None => Goal::Global,
+            Some(0) => Goal::Unlimited,
+            Some(limit) => Goal::Limit(limit),
+        }
+    }
+}
+
+/// Место торрента в очереди и учёт его раздачи.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueueState {
+    /// Меньше — раньше.
+    pub position: u64,
+    /// Ждёт свободного места.
+    pub queued: bool,
+    /// Остановлен, потому что достиг цели раздачи.
+    pub finished: bool,
+    /// Секунды раздачи и секунды раздачи подряд без обмена данными.
+    pub seeding_time: u64,
+    pub idle_time: u64,
+    pub ratio_goal: Goal,
+    pub seed_time_goal: Goal,
+    pub idle_goal: Goal,
+}
+
+impl Serialize for QueueState {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder
+            .dict()?
+            .optional(FINISHED, Some(&1u64).filter(|_| self.finished))?
+            .optional(IDLE_GOAL, self.idle_goal.encoded().as_ref())?
+            .required(IDLE_TIME, &self.idle_time)?
+            .required(POSITION, &self.position)?
+            .optional(QUEUED, Some(&1u64).filter(|_| self.queued))?
+            .optional(RATIO_GOAL, self.ratio_goal.encoded().as_ref())?
+            .optional(SEED_TIME_GOAL, self.seed_time_goal.encoded().as_ref())?
+            .required(SEEDING_TIME, &self.seeding_time)?
+            .fin()
+    }
+}
+
+impl<'a> TryDeserialize<'a> for QueueState {
+    fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
+        let dp = DataProvider::try_from(node)?;
+        Ok(QueueState {
+            position: dp.required(POSITION)?,
+            queued: dp.optional::<u64>(QUEUED)?.is_some_and(|q| q != 0),
+            finished: dp.optional::<u64>(FINISHED)?.is_some_and(|f| f != 0),
+            seeding_time: dp.required(SEEDING_TIME)?,
+            idle_time: dp.required(IDLE_TIME)?,
+            ratio_goal: Goal::decode(dp.optional(RATIO_GOAL)?),
+            seed_time_goal: Goal::decode(dp.optional(SEED_TIME_GOAL)?),
+            idle_goal: Goal::decode(dp.optional(IDLE_GOAL)?),
+        })
+    }
+}
+
+/// Куда переставить торрент в очереди.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueMove {
+    Top,
+    Up,
+    Down,
+    Bottom,
+    To(usize),
+}
+
+/// Отношение отданного к скачанному в тысячных. Торрент, который не
+/// качали, а сразу раздавали, делится на свой размер.
+pub fn ratio(torrent: &Torrent) -> Option<u64> {
+    let resume = torrent.resume.as_ref();
+    let uploaded = resume.map_or(0, |r| r.total_uploaded);
+    let downloaded = match resume.map_or(0, |r| r.total_downloaded) {
+        0 => torrent.metadata.info.total_length(),
+        downloaded => downloaded,
+    };
+    (downloaded > 0).then(|| (u128::from(uploaded) * 1000 / u128::from(downloaded)) as u64)
+}
+
+impl TorrentRepo {
+    /// Id торрентов в порядке очереди.
+    pub fn queue_order(&self) -> Vec<Id> {
+        let mut order: Vec<_> = self
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (t.value.queue.position, i, t.id))
+            .collect();
+        order.sort_unstable();
+        order.into_iter().map(|(_, _, id)| id).collect()
+    }
+
+    /// Место нового торрента — в конце очереди.
+    pub(crate) fn next_position(&self) -> u64 {
+        self.iter()
+            .map(|t| t.value.queue.position + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Переставляет торрент. Возвращает `false`, если торрента нет.
+    pub fn move_in_queue(&mut self, id: Id, to: QueueMove) -> bool {
+        let mut order = self.queue_order();
+        let Some(from) = order.iter().position(|&i| i == id) else {
+            return false;
+        };
+        let last = order.len() - 1;
+        let to = match to {
+            QueueMove::Top => 0,
+            QueueMove::Up => from.saturating_sub(1),
+            QueueMove::Down => (from + 1).min(last),
+            QueueMove::Bottom => last,
+            QueueMove::To(position) => position.min(last),
+        };
+        order.remove(from);
+        order.insert(to, id);
+        self.renumber_queue(&order);
+        true
+    }
+
+    /// Нумерует очередь подряд с нуля, например после удаления.
+    pub(crate) fn compact_queue(&mut self) {
+        let order = self.queue_order();
+        self.renumber_queue(&order);
+    }
+
+    fn renumber_queue(&mut self, order: &[Id]) {
+        for (position, id) in order.iter().enumerate() {
+            if let Some(mut torrent) = self.get_by_id_mut(*id) {
+                torrent.queue.position = position as u64;
+            }
+        }
+    }
+}
+
+/// Очередь с ограничениями и целями из настроек.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueManager {
+    limits: QueueLimits,
+    goals: SeedGoals,
+}
+
+impl QueueManager {
+    pub fn new(settings: &Settings) -> QueueManager {
+        QueueManager {
+            limits: settings.queue.clone(),
+            goals: settings.seeding.clone(),
+        }
+    }
+
+    /// Раздаёт места по порядку очереди, торренты сверх ограничений ждут.
+    /// Возвращает `true`, если чьё-то состояние поменялось.
+    pub fn schedule(&self, repo: &mut TorrentRepo) -> bool {
+        let (mut downloads, mut seeds) = (0, 0);
+        let mut changed = false;
+        for id in repo.queue_order() {
+            let mut torrent = repo.get_by_id_mut(id).unwrap();
+            let queued = !torrent.paused && {
+                let (active, limit) = if torrent.is_complete() {
+                    (&mut seeds, self.limits.seeds)
+                } else {
+                    (&mut downloads, self.limits.downloads)
+                };
+                let fits = limit.is_none_or(|limit| *active < limit);
+                *active += usize::from(fits);
+                !fits
+            };
+            if torrent.queue.queued != queued {
+                torrent.queue.queued = queued;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Учитывает `elapsed` секунд работы; `transferring` говорит, шли ли у
+    /// торрента данные. Достигшие целей торренты останавливаются, а их места
+    /// отдаются следующим. Возвращает `true`, если чьё-то состояние
+    /// поменялось.
+    pub fn tick(
+        &self,
+        repo: &mut TorrentRepo,
+        elapsed: u64,
+        transferring: impl Fn(Id) -> bool,
+    ) -> bool {
+        let mut changed = false;
+        for id in repo.queue_order() {
+            let mut torrent = repo.get_by_id_mut(id).unwrap();
+            if torrent.status() != Status::Seeding {
+                continue;
+            }
+            let state = &mut torrent.queue;
+            state.seeding_time += elapsed;
+            state.idle_time = match transferring(id) {
+                true => 0,
+                false => state.idle_time + elapsed,
+            };
+            if self.reached(&torrent).contains(&true) {
+                torrent.paused = true;
+                torrent.queue.finished = true;
+                changed = true;
+            }
+        }
+        self.schedule(repo) || changed
+    }
+
+    /// Готовит торрент к ручному запуску. Достигнутые цели снимаются, иначе
+    /// раздача тут же остановилась бы снова.
+    pub fn release(&self, torrent: &mut Torrent) {
+        let [ratio, time, _] = self.reached(torrent);
+        let state = &mut torrent.queue;
+        if ratio {
+            state.ratio_goal = Goal::Unlimited;
+        }
+        if time {
+            state.seed_time_goal = Goal::Unlimited;
+        }
+        state.idle_time = 0;
+        state.finished = false;
+    }
+
+    /// Какие цели достигнуты: отношение, время раздачи, простой.
+    fn reached(&self, torrent: &Torrent) -> [bool; 3] {
+        let state = &torrent.queue;
+        let minutes = |goal: Goal, global, seconds| {
+            goal.resolve(global)
+                .is_some_and(|minutes| seconds >= minutes.saturating_mul(60))
+        };
+        [
+            state
+                .ratio_goal
+                .resolve(self.goals.ratio)
+                .zip(ratio(torrent))
+                .is_some_and(|(goal, ratio)| ratio >= goal),
+            minutes(state.seed_time_goal, self.goals.time, state.seeding_time),
+            minutes(state.idle_goal, self.goals.idle, state.idle_time),
+        ]
+    }
+}
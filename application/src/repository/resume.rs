@@ -0,0 +1,387 @@
+/// Модуль с данными быстрого возобновления торрента.
+///
+/// Вместе с битовым полем кусков хранятся размеры и времена изменения файлов.
+/// Если на диске что-то поменялось без нас, состояние перепроверяется по хешам.
+use std::{
+    io::{self, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::task::spawn_blocking;
+
+use crate::{
+    io::{
+        consts::*,
+        deserialization::{DataProvider, Node, ParsingError, TryDeserialize},
+        serialization::{Encoder, Serialize},
+    },
+    repository::{
+        merkle::BLOCK_SIZE,
+        types::{Torrent, TorrentMetadata},
+        Id, TorrentRepo,
+    },
+    storage::Storage,
+    tools::{get_bit, set_bit},
+};
+
+/// Секунды с начала эпохи.
+pub fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Размер и время изменения файла. У отсутствующего файла оба равны нулю.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FileState {
+    pub size: u64,
+    pub mtime: u64,
+}
+
+impl Serialize for FileState {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder
+            .dict()?
+            .required(MTIME, &self.mtime)?
+            .required(SIZE, &self.size)?
+            .fin()
+    }
+}
+
+impl<'a> TryDeserialize<'a> for FileState {
+    fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
+        let dp = DataProvider::try_from(node)?;
+        Ok(FileState {
+            size: dp.required(SIZE)?,
+            mtime: dp.required(MTIME)?,
+        })
+    }
+}
+
+/// Недокачанный кусок: по биту на блок в 16 КиБ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialPiece {
+    pub index: u64,
+    pub blocks: Vec<u8>,
+}
+
+impl PartialPiece {
+    pub fn new(index: u64, piece_size: u64) -> PartialPiece {
+        let blocks = piece_size.div_ceil(BLOCK_SIZE) as usize;
+        PartialPiece {
+            index,
+            blocks: vec![0; blocks.div_ceil(8)],
+        }
+    }
+
+    pub fn has_block(&self, block: usize) -> bool {
+        get_bit(&self.blocks, block)
+    }
+
+    pub fn set_block(&mut self, block: usize) {
+        set_bit(&mut self.blocks, block)
+    }
+}
+
+impl Serialize for PartialPiece {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder
+            .dict()?
+            .required(BLOCKS, &self.blocks)?
+            .required(PIECE, &self.index)?
+            .fin()
+    }
+}
+
+impl<'a> TryDeserialize<'a> for PartialPiece {
+    fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
+        let dp = DataProvider::try_from(node)?;
+        Ok(PartialPiece {
+            index: dp.required(PIECE)?,
+            blocks: dp.required(BLOCKS)?,
+        })
+    }
+}
+
+/// Что известно о трекере с прошлого запуска.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TrackerState {
+    pub url: String,
+    pub last_announce: Option<u64>,
+    pub interval: Option<u64>,
+    pub tracker_id: Option<String>,
+    pub failures: u64,
+}
+
+impl Serialize for TrackerState {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder
+            .dict()?
+            .required(FAILURES, &self.failures)?
+            .optional(INTERVAL, self.interval.as_ref())?
+            .optional(LAST_ANNOUNCE, self.last_announce.as_ref())?
+            .optional(TRACKER_ID, self.tracker_id.as_ref())?
+            .required(URL, &self.url)?
+            .fin()
+    }
+}
+
+impl<'a> TryDeserialize<'a> for TrackerState {
+    fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
+        let dp = DataProvider::try_from(node)?;
+        Ok(TrackerState {
+            url: dp.required(URL)?,
+            last_announce: dp.optional(LAST_ANNOUNCE)?,
+            interval: dp.optional(INTERVAL)?,
+            tracker_id: dp.optional(TRACKER_ID)?,
+            failures: dp.required(FAILURES)?,
+        })
+    }
+}
+
+/// Адреса пиров в компактном виде: 6 байт на IPv4, 18 на IPv6.
+fn compact_peers(peers: &[SocketAddr], v6: bool) -> Vec<u8> {
+    let mut out = vec![];
+    for peer in peers.iter().filter(|p| p.is_ipv6() == v6) {
+        match peer.ip() {
+            IpAddr::V4(ip) => out.extend_from_slice(&ip.octets()),
+            IpAddr::V6(ip) => out.extend_from_slice(&ip.octets()),
+        }
+        out.extend_from_slice(&peer.port().to_be_bytes());
+    }
+    out
+}
+
+fn parse_peers(bytes: &[u8], v6: bool) -> Result<Vec<SocketAddr>, ParsingError> {
+    let len = if v6 { 18 } else { 6 };
+    if !bytes.len().is_multiple_of(len) {
+        return Err(ParsingError::InvalidFormat);
+    }
+    Ok(bytes
+        .chunks(len)
+        .map(|c| {
+            let port = u16::from_be_bytes([c[len - 2], c[len - 1]]);
+            let ip = if v6 {
+                IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&c[..16]).unwrap()))
+            } else {
+                IpAddr::V4(Ipv4Addr::new(c[0], c[1], c[2], c[3]))
+            };
+            SocketAddr::new(ip, port)
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ResumeData {
+    pub save_path: String,
+    /// Состояние файлов в порядке `Storage::files` на момент сохранения.
+    pub files: Vec<FileState>,
+    pub unfinished: Vec<PartialPiece>,
+    pub total_uploaded: u64,
+    pub total_downloaded: u64,
+    pub peers: Vec<SocketAddr>,
+    pub trackers: Vec<TrackerState>,
+    pub added: u64,
+    pub completed: Option<u64>,
+}
+
+impl Serialize for ResumeData {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder
+            .dict()?
+            .required(ADDED, &self.added)?
+            .optional(COMPLETED, self.completed.as_ref())?
+            .required(FILES, &self.files)?
+            .required(PEERS, &compact_peers(&self.peers, false))?
+            .required(PEERS6, &compact_peers(&self.peers, true))?
+            .required(SAVE_PATH, &self.save_path)?
+            .required(TOTAL_DOWNLOADED, &self.total_downloaded)?
+            .required(TOTAL_UPLOADED, &self.total_uploaded)?
+            .required(TRACKERS, &self.trackers)?
+            .required(UNFINISHED, &self.unfinished)?
+            .fin()
+    }
+}
+
+impl<'a> TryDeserialize<'a> for ResumeData {
+    fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
+        let dp = DataProvider::try_from(node)?;
+        let peers: Vec<u8> = dp.required(PEERS)?;
+        let peers6: Vec<u8> = dp.required(PEERS6)?;
+
+        let mut peers = parse_peers(&peers, false)?;
+        peers.extend(parse_peers(&peers6, true)?);
+        Ok(ResumeData {
+            save_path: dp.required(SAVE_PATH)?,
+            files: dp.required(FILES)?,
+            unfinished: dp.required(UNFINISHED)?,
+            total_uploaded: dp.required(TOTAL_UPLOADED)?,
+            total_downloaded: dp.required(TOTAL_DOWNLOADED)?,
+            peers,
+            trackers: dp.required(TRACKERS)?,
+            added: dp.required(ADDED)?,
+            completed: dp.optional(COMPLETED)?,
+        })
+    }
+}
+
+/// Чем закончилась загрузка состояния с диска.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResumeOutcome {
+    /// Файлы не менялись, сохранённому состоянию можно верить.
+    Resumed,
+    /// Данных не было или файлы изменились: куски проверены заново.
+    Rechecked,
+}
+
+impl ResumeData {
+    pub fn new(storage: &Storage, added: SystemTime) -> ResumeData {
+        ResumeData {
+            save_path: storage.root().to_string_lossy().into_owned(),
+            added: unix_time(added),
+            ..ResumeData::default()
+        }
+    }
+
+    /// Запоминает текущее состояние файлов. Вызывается при сохранении.
+    /// Возвращает `true`, если оно поменялось.
+    pub async fn capture(&mut self, storage: &Storage) -> io::Result<bool> {
+        let files = storage.file_states().await?;
+        let changed = self.files != files;
+        self.files = files;
+        Ok(changed)
+    }
+}
+
+impl Torrent {
+    /// Каталог, в который качается торрент. Торренты, добавленные до
+    /// появления данных возобновления, качались в текущий каталог.
+    pub fn save_path(&self) -> PathBuf {
+        self.resume
+            .as_ref()
+            .map_or_else(|| PathBuf::from("."), |r| PathBuf::from(&r.save_path))
+    }
+
+    /// Раскладка торрента по файлам с приоритетами из репозитория.
+    pub fn storage(&self) -> Storage {
+        Storage::new(&self.metadata.info, self.save_path()).with_priorities(self.priorities.clone())
+    }
+
+    /// Восстанавливает состояние торрента. Если данных нет или файлы на
+    /// диске не совпадают с сохранёнными, перепроверяет все куски.
+    pub async fn resume(&mut self, storage: &Storage) -> io::Result<ResumeOutcome> {
+        if let Some(resume) = &self.resume {
+            if resume.files == storage.file_states().await? {
+                return Ok(ResumeOutcome::Resumed);
+            }
+        }
+
+        self.recheck(storage).await?;
+        Ok(ResumeOutcome::Rechecked)
+    }
+
+    /// Проверяет все куски по хешам и заново строит битовое поле.
+    /// Недокачанные куски забываются: их блоки могли измениться.
+    pub async fn recheck(&mut self, storage: &Storage) -> io::Result<()> {
+        let check = Recheck::run(&self.metadata, storage).await?;
+        self.apply_recheck(check, storage);
+        Ok(())
+    }
+
+    /// Применяет результат проверки, сделанной `Recheck::run`.
+    pub fn apply_recheck(&mut self, check: Recheck, storage: &Storage) {
+        // Битовое поле строится заново: старое может быть коротким
+        self.downloaded_pieces = check.have;
+        self.downloaded = check.downloaded;
+
+        let resume = self
+            .resume
+            .get_or_insert_with(|| ResumeData::new(storage, SystemTime::now()));
+        resume.unfinished.clear();
+        resume.files = check.files;
+    }
+}
+
+impl TorrentRepo {
+    /// Восстанавливает все торренты при запуске. Ошибка одного торрента не
+    /// мешает остальным и возвращается вместе с его id.
+    pub async fn resume_all(&mut self) -> Vec<(Id, io::Result<ResumeOutcome>)> {
+        let mut outcomes = vec![];
+        for torrent in &mut self.torrents {
+            let storage = torrent.value.storage();
+            outcomes.push((torrent.id, torrent.value.resume(&storage).await));
+        }
+        for (id, outcome) in &outcomes {
+            if matches!(outcome, Ok(ResumeOutcome::Rechecked)) {
+                self.touch(*id);
+            }
+        }
+        outcomes
+    }
+
+    /// Запоминает состояние файлов всех торрентов. Вызывается перед
+    /// сохранением, чтобы следующий запуск узнал изменённые без нас файлы.
+    pub async fn capture_all(&mut self) -> io::Result<()> {
+        let mut changed = vec![];
+        for torrent in &mut self.torrents {
+            let storage = torrent.value.storage();
+            if let Some(resume) = &mut torrent.value.resume {
+                if resume.capture(&storage).await? {
+                    changed.push(torrent.id);
+                }
+            }
+        }
+        for id in changed {
+            self.touch(id);
+        }
+        Ok(())
+    }
+}
+
+/// Результат проверки данных торрента на диске.
+#[derive(Debug)]
+pub struct Recheck {
+    have: Vec<u8>,
+    downloaded: u64,
+    files: Vec<FileState>,
+}
+
+impl Recheck {
+    /// Сверяет все куски с хешами. Самого торрента проверка не трогает,
+    /// так что её можно вести, не держа репозиторий. Хеши считаются в
+    /// блокирующих потоках, чтобы большая проверка не останавливала рантайм.
+    pub async fn run(metadata: &TorrentMetadata, storage: &Storage) -> io::Result<Recheck> {
+        let files = storage.file_states().await?;
+        let target = Arc::new((metadata.clone(), storage.clone()));
+        let mut have = vec![0; storage.piece_count().div_ceil(8)];
+        let mut downloaded = 0;
+        for index in 0..storage.piece_count() {
+            let valid = match storage.read_piece(index).await {
+                Ok(data) => {
+                    let target = target.clone();
+                    spawn_blocking(move || target.1.piece_matches(&target.0, index, &data)).await?
+                }
+                // Файла нет или он короче, чем нужно: куска тоже нет
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::NotFound | io::ErrorKind::UnexpectedEof
+                    ) =>
+                {
+                    false
+                }
+                Err(e) => return Err(e),
+            };
+            if valid {
+                set_bit(&mut have, index);
+                downloaded += storage.piece_size(index);
+            }
+        }
+        Ok(Recheck {
+            have,
+            downloaded,
+            files,
+        })
+    }
+}
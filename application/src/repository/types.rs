@@ -1,16 +1,143 @@
+use encoding_rs::Encoding;
 use sha1::{Digest, Sha1};
+use sha2::Sha256;
 
-use crate::io::{
-    consts::*,
-    deserialization::{parse_node, DataProvider, Node, ParsingError, TryDeserialize},
-    serialization::{BencodeDictBuilder, Serialize},
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
 };
 
+use crate::{
+    io::{
+        consts::*,
+        deserialization::{parse_node, DataProvider, Node, ParsingError, TryDeserialize},
+        serialization::{Encoder, Serialize},
+    },
+    repository::{
+        merkle::{self, MerkleTree, BLOCK_SIZE},
+        queue::QueueState,
+        resume::ResumeData,
+    },
+    tools::get_bit,
+};
+
+/// Атрибуты файла (BEP 47). Строка хранится как есть, чтобы пересборка
+/// инфо-словаря не меняла его хеш.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FileAttributes(pub String);
+
+impl FileAttributes {
+    pub fn is_padding(&self) -> bool {
+        self.0.contains('p')
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.0.contains('x')
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.0.contains('h')
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.0.contains('l')
+    }
+}
+
+impl Serialize for FileAttributes {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        self.0.encode(encoder)
+    }
+}
+
+impl<'a> TryDeserialize<'a> for FileAttributes {
+    fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
+        Ok(FileAttributes(String::try_deserialize_from_node(node)?))
+    }
+}
+
+/// Приоритет файла. Файлы с `Skip` не скачиваются.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    Skip,
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Serialize for Priority {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        (*self as u64).encode(encoder)
+    }
+}
+
+impl<'a> TryDeserialize<'a> for Priority {
+    fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
+        match u64::try_deserialize_from_node(node)? {
+            0 => Ok(Priority::Skip),
+            1 => Ok(Priority::Low),
+            2 => Ok(Priority::Normal),
+            3 => Ok(Priority::High),
+            _ => Err(ParsingError::InvalidFormat),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct FileMetadata {
+    /// Путь для показа и для диска, всегда в UTF-8.
     pub path: Vec<String>,
+    /// Исходные байты `path`, если они отличаются от показываемого пути.
+    pub path_raw: Option<Vec<Vec<u8>>>,
+    pub path_utf8: Option<Vec<String>>,
     pub length: u64,
     pub md5sum: Option<String>,
+    pub attr: Option<FileAttributes>,
+    /// Куда ведёт ссылка, путь от корня торрента.
+    pub symlink_path: Option<Vec<String>>,
+    pub sha1: Option<[u8; 20]>,
+}
+
+impl FileMetadata {
+    pub fn is_padding(&self) -> bool {
+        self.attr.as_ref().is_some_and(FileAttributes::is_padding)
+    }
+
+    /// Расшифровывает путь заново в кодировке `encoding`, если для него
+    /// нет `path.utf-8`.
+    pub fn decode_path(&mut self, encoding: &'static Encoding) {
+        if self.path_utf8.is_some() {
+            return;
+        }
+        let raw = self
+            .path_raw
+            .take()
+            .unwrap_or_else(|| self.path.iter().map(|c| c.as_bytes().to_vec()).collect());
+        self.path = raw.iter().map(|c| decode(c, encoding)).collect();
+        self.path_raw = differs(&raw, &self.path).then_some(raw);
+    }
+}
+
+fn differs(raw: &[Vec<u8>], path: &[String]) -> bool {
+    raw.len() != path.len() || raw.iter().zip(path).any(|(r, p)| r != p.as_bytes())
+}
+
+/// Расшифровывает строку из торрента. Ошибки заменяются на U+FFFD,
+/// исходные байты при этом сохраняются отдельно.
+fn decode(raw: &[u8], encoding: &'static Encoding) -> String {
+    encoding.decode_without_bom_handling(raw).0.into_owned()
+}
+
+/// Строка для показа и исходные байты, если они с ней расходятся.
+fn display_name(
+    raw: Vec<u8>,
+    utf8: Option<&String>,
+    encoding: &'static Encoding,
+) -> (String, Option<Vec<u8>>) {
+    let display = utf8.cloned().unwrap_or_else(|| decode(&raw, encoding));
+    let raw = (raw != display.as_bytes()).then_some(raw);
+    (display, raw)
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -30,27 +157,89 @@ pub enum FilesMetadata {
         base_name: String,
         files: Vec<FileMetadata>,
     },
+    /// Торрент только v2: файлы описаны в `Info::file_tree`.
+    Tree { name: String },
 }
 
-fn get_info_hash(node: &Node) -> Result<[u8; 20], ParsingError> {
-    if let Node::Dict(torrent_meta, _) = node {
-        if let Some(info) = torrent_meta.get(INFO) {
-            if let Node::Dict(_, raw) = info {
-                let mut hasher = Sha1::new();
-                hasher.update(raw);
-                return Ok(hasher.finalize().into());
-            }
+/// Версия метаданных по BEP 52.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetaVersion {
+    V1,
+    V2,
+    Hybrid,
+}
+
+/// Инфо-хеш торрента. У гибридных торрентов их два.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InfoHash {
+    V1([u8; 20]),
+    V2([u8; 32]),
+    Hybrid([u8; 20], [u8; 32]),
+}
+
+impl InfoHash {
+    /// Считает хеши от байтов инфо-словаря.
+    pub fn from_info_bytes(info: &[u8], version: MetaVersion) -> InfoHash {
+        let v1 = || Sha1::digest(info).into();
+        let v2 = || Sha256::digest(info).into();
+        match version {
+            MetaVersion::V1 => InfoHash::V1(v1()),
+            MetaVersion::V2 => InfoHash::V2(v2()),
+            MetaVersion::Hybrid => InfoHash::Hybrid(v1(), v2()),
+        }
+    }
+
+    pub fn v1(&self) -> Option<[u8; 20]> {
+        match self {
+            InfoHash::V1(v1) | InfoHash::Hybrid(v1, _) => Some(*v1),
+            InfoHash::V2(_) => None,
+        }
+    }
+
+    pub fn v2(&self) -> Option<[u8; 32]> {
+        match self {
+            InfoHash::V2(v2) | InfoHash::Hybrid(_, v2) => Some(*v2),
+            InfoHash::V1(_) => None,
+        }
+    }
+
+    /// Первые 20 байт хеша v2, под которыми торрент ищут трекеры и DHT.
+    pub fn truncated_v2(&self) -> Option<[u8; 20]> {
+        self.v2().map(|v2| v2[..20].try_into().unwrap())
+    }
+
+    /// Хеш для трекеров, DHT и рукопожатия: v1, а если его нет — усечённый v2.
+    pub fn short(&self) -> [u8; 20] {
+        match self {
+            InfoHash::V1(v1) | InfoHash::Hybrid(v1, _) => *v1,
+            InfoHash::V2(v2) => v2[..20].try_into().unwrap(),
         }
     }
-    return Err(ParsingError::TypeMismatch);
+}
+
+fn get_info_bytes<'a>(node: &Node<'a>) -> Result<&'a [u8], ParsingError> {
+    if let Node::Dict(torrent_meta) = node {
+        if let Some(Node::Dict(info)) = torrent_meta.get(INFO) {
+            return Ok(info.raw());
+        }
+    }
+    Err(ParsingError::TypeMismatch)
 }
 
 impl Serialize for FileMetadata {
-    fn serialize(&self) -> Vec<u8> {
-        BencodeDictBuilder::new()
-            .required(PATH, self.path.clone())
-            .required(LENGTH, self.length)
-            .optional(MD5SUM, self.md5sum.clone())
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        let dict = encoder
+            .dict()?
+            .optional(ATTR, self.attr.as_ref())?
+            .required(LENGTH, &self.length)?
+            .optional(MD5SUM, self.md5sum.as_ref())?;
+        let dict = match &self.path_raw {
+            Some(raw) => dict.required(PATH, raw)?,
+            None => dict.required(PATH, &self.path)?,
+        };
+        dict.optional(PATH_UTF8, self.path_utf8.as_ref())?
+            .optional(SHA1, self.sha1.as_ref())?
+            .optional(SYMLINK_PATH, self.symlink_path.as_ref())?
             .fin()
     }
 }
@@ -58,41 +247,271 @@ impl Serialize for FileMetadata {
 impl<'a> TryDeserialize<'a> for FileMetadata {
     fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
         let dp = DataProvider::try_from(node)?;
+        let raw: Vec<Vec<u8>> = dp.required(PATH)?;
+        let path_utf8: Option<Vec<String>> = dp.optional(PATH_UTF8)?;
+
+        let path: Vec<String> = match &path_utf8 {
+            Some(path) => path.clone(),
+            None => raw.iter().map(|c| decode(c, encoding_rs::UTF_8)).collect(),
+        };
         Ok(FileMetadata {
-            path: dp.required(PATH)?,
+            path_raw: differs(&raw, &path).then_some(raw),
+            path,
+            path_utf8,
             length: dp.required(LENGTH)?,
             md5sum: dp.optional(MD5SUM)?,
+            attr: dp.optional(ATTR)?,
+            symlink_path: dp.optional(SYMLINK_PATH)?,
+            sha1: dp.optional(SHA1)?,
         })
     }
 }
 
+/// Узел дерева файлов v2. Файл хранится под пустым ключом.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileTreeNode {
+    File {
+        length: u64,
+        // Нет только у пустых файлов
+        pieces_root: Option<[u8; 32]>,
+        attr: Option<FileAttributes>,
+    },
+    Dir(FileTree),
+}
+
+pub type FileTree = BTreeMap<String, FileTreeNode>;
+
+impl Serialize for FileTreeNode {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        match self {
+            FileTreeNode::File {
+                length,
+                pieces_root,
+                attr,
+            } => encoder
+                .dict()?
+                .required(
+                    FILE_ENTRY,
+                    &FileEntry {
+                        length: *length,
+                        pieces_root: pieces_root.as_ref(),
+                        attr: attr.as_ref(),
+                    },
+                )?
+                .fin(),
+            FileTreeNode::Dir(tree) => tree.encode(encoder),
+        }
+    }
+}
+
+struct FileEntry<'f> {
+    length: u64,
+    pieces_root: Option<&'f [u8; 32]>,
+    attr: Option<&'f FileAttributes>,
+}
+
+impl Serialize for FileEntry<'_> {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder
+            .dict()?
+            .optional(ATTR, self.attr)?
+            .required(LENGTH, &self.length)?
+            .optional(PIECES_ROOT, self.pieces_root)?
+            .fin()
+    }
+}
+
+impl<'a> TryDeserialize<'a> for FileTreeNode {
+    fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
+        let dp = DataProvider::try_from(node)?;
+
+        // Каталог не может называться пустой строкой
+        if dp.contains(FILE_ENTRY) {
+            let entry = DataProvider::try_from(dp.dict.get(FILE_ENTRY).unwrap())?;
+            let length = entry.required(LENGTH)?;
+            let pieces_root = entry.optional(PIECES_ROOT)?;
+            if length > 0 && pieces_root.is_none() {
+                return Err(ParsingError::MissingField(
+                    String::from_utf8_lossy(PIECES_ROOT).into_owned(),
+                ));
+            }
+            Ok(FileTreeNode::File {
+                length,
+                pieces_root,
+                attr: entry.optional(ATTR)?,
+            })
+        } else {
+            Ok(FileTreeNode::Dir(FileTree::try_deserialize_from_node(
+                node,
+            )?))
+        }
+    }
+}
+
+/// Файл из дерева v2 вместе с полным путём.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeFile {
+    pub path: Vec<String>,
+    pub length: u64,
+    pub pieces_root: Option<[u8; 32]>,
+    pub attr: Option<FileAttributes>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Info {
     pub piece_length: u64,
-    pub pieces: Vec<u8>,
+    /// SHA-1 хеши кусков. Их нет только у торрентов v2.
+    pub pieces: Option<Vec<u8>>,
     pub private: Option<u64>,
     pub files: FilesMetadata,
+    pub meta_version: Option<u64>,
+    pub file_tree: Option<FileTree>,
+    /// Исходные байты `name`, если они отличаются от имени в `files`.
+    pub name_raw: Option<Vec<u8>>,
+    pub name_utf8: Option<String>,
 }
 
-impl Serialize for Info {
-    fn serialize(&self) -> Vec<u8> {
+impl Info {
+    pub fn name(&self) -> &str {
         match &self.files {
+            FilesMetadata::Single { name, .. } => name,
+            FilesMetadata::Multiple { base_name, .. } => base_name,
+            FilesMetadata::Tree { name } => name,
+        }
+    }
+
+    /// Расшифровывает имена без ключей `.utf-8` в кодировке из поля `encoding`.
+    pub fn decode_names(&mut self, encoding: &'static Encoding) {
+        if self.name_utf8.is_none() {
+            let raw = self
+                .name_raw
+                .take()
+                .unwrap_or_else(|| self.name().as_bytes().to_vec());
+            let (name, raw) = display_name(raw, None, encoding);
+            self.name_raw = raw;
+            match &mut self.files {
+                FilesMetadata::Single { name: n, .. } => *n = name,
+                FilesMetadata::Multiple { base_name, .. } => *base_name = name,
+                FilesMetadata::Tree { name: n } => *n = name,
+            }
+        }
+        if let FilesMetadata::Multiple { files, .. } = &mut self.files {
+            files.iter_mut().for_each(|f| f.decode_path(encoding));
+        }
+    }
+
+    pub fn version(&self) -> MetaVersion {
+        match (self.pieces.is_some(), self.file_tree.is_some()) {
+            (true, true) => MetaVersion::Hybrid,
+            (false, true) => MetaVersion::V2,
+            _ => MetaVersion::V1,
+        }
+    }
+
+    /// Файлы дерева v2 в порядке обхода, то есть по возрастанию путей.
+    pub fn tree_files(&self) -> Vec<TreeFile> {
+        let mut out = vec![];
+        let Some(tree) = &self.file_tree else {
+            return out;
+        };
+
+        let mut stack = vec![(vec![], tree.iter())];
+        while let Some((prefix, iter)) = stack.last_mut() {
+            let Some((name, node)) = iter.next() else {
+                stack.pop();
+                continue;
+            };
+            let mut path: Vec<String> = prefix.clone();
+            path.push(name.clone());
+            match node {
+                FileTreeNode::File {
+                    length,
+                    pieces_root,
+                    attr,
+                } => out.push(TreeFile {
+                    path,
+                    length: *length,
+                    pieces_root: *pieces_root,
+                    attr: attr.clone(),
+                }),
+                FileTreeNode::Dir(dir) => stack.push((path, dir.iter())),
+            }
+        }
+        out
+    }
+
+    pub fn total_length(&self) -> u64 {
+        match &self.files {
+            FilesMetadata::Single { length, .. } => *length,
+            FilesMetadata::Multiple { files, .. } => files.iter().map(|f| f.length).sum(),
+            FilesMetadata::Tree { .. } => self.tree_files().iter().map(|f| f.length).sum(),
+        }
+    }
+
+    /// Число кусков. В v2 каждый файл начинается с нового куска.
+    pub fn piece_count(&self) -> usize {
+        match &self.pieces {
+            Some(pieces) => pieces.len() / 20,
+            None => self
+                .tree_files()
+                .iter()
+                .map(|f| f.length.div_ceil(self.piece_length) as usize)
+                .sum(),
+        }
+    }
+
+    /// У гибридного торрента списки файлов v1 и v2 должны совпадать,
+    /// не считая файлов выравнивания.
+    pub fn hybrid_files_match(&self) -> bool {
+        let v1: Vec<(Vec<String>, u64)> = match &self.files {
+            FilesMetadata::Single { name, length, .. } => vec![(vec![name.clone()], *length)],
+            FilesMetadata::Multiple { files, .. } => files
+                .iter()
+                .filter(|f| !f.is_padding())
+                .map(|f| (f.path.clone(), f.length))
+                .collect(),
+            FilesMetadata::Tree { .. } => return false,
+        };
+        let v2: Vec<(Vec<String>, u64)> = self
+            .tree_files()
+            .into_iter()
+            .map(|f| (f.path, f.length))
+            .collect();
+
+        v1 == v2
+    }
+}
+
+impl Serialize for Info {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        let dict = encoder
+            .dict()?
+            .optional(FILE_TREE, self.file_tree.as_ref())?;
+        let (dict, name) = match &self.files {
             FilesMetadata::Single {
                 name,
                 length,
                 md5sum,
-            } => BencodeDictBuilder::new()
-                .required(NAME, name.clone())
-                .required(LENGTH, *length)
-                .optional(MD5SUM, md5sum.clone()),
-            FilesMetadata::Multiple { base_name, files } => BencodeDictBuilder::new()
-                .required(NAME, base_name.clone())
-                .required(FILES, files.clone()),
-        }
-        .required(PIECE_LENGTH, self.piece_length)
-        .required(PIECES, self.pieces.clone())
-        .optional(PRIVATE, self.private)
-        .fin()
+            } => (
+                dict.required(LENGTH, length)?
+                    .optional(MD5SUM, md5sum.as_ref())?,
+                name,
+            ),
+            FilesMetadata::Multiple { base_name, files } => {
+                (dict.required(FILES, files)?, base_name)
+            }
+            FilesMetadata::Tree { name } => (dict, name),
+        };
+        let dict = dict.optional(META_VERSION, self.meta_version.as_ref())?;
+        let dict = match &self.name_raw {
+            Some(raw) => dict.required(NAME, raw)?,
+            None => dict.required(NAME, name)?,
+        };
+        dict.optional(NAME_UTF8, self.name_utf8.as_ref())?
+            .required(PIECE_LENGTH, &self.piece_length)?
+            .optional(PIECES, self.pieces.as_ref())?
+            .optional(PRIVATE, self.private.as_ref())?
+            .fin()
     }
 }
 
@@ -100,19 +519,40 @@ impl<'a> TryDeserialize<'a> for Info {
     fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
         let dp = DataProvider::try_from(node)?;
 
+        let meta_version = dp.optional(META_VERSION)?;
+        let file_tree: Option<FileTree> = dp.optional(FILE_TREE)?;
+        let pieces: Option<Vec<u8>> = dp.optional(PIECES)?;
+        let piece_length: u64 = dp.required(PIECE_LENGTH)?;
+
+        match (meta_version, &file_tree) {
+            (None, None) => {}
+            // Куски v2 выравниваются по блокам дерева
+            (Some(2), Some(_)) if piece_length >= BLOCK_SIZE && piece_length.is_power_of_two() => {}
+            _ => return Err(ParsingError::InvalidFormat),
+        }
+
+        let name_utf8: Option<String> = dp.optional(NAME_UTF8)?;
+        let (name, name_raw) =
+            display_name(dp.required(NAME)?, name_utf8.as_ref(), encoding_rs::UTF_8);
+
         let files = {
-            let single = dp.optional::<u64>(LENGTH)?.is_some();
-            let multi = dp.optional::<Vec<FileMetadata>>(FILES)?.is_some();
+            let single = dp.contains(LENGTH);
+            let multi = dp.contains(FILES);
 
-            if single && !multi {
+            if pieces.is_none() {
+                if single || multi || file_tree.is_none() {
+                    return Err(ParsingError::InvalidFormat);
+                }
+                FilesMetadata::Tree { name }
+            } else if single && !multi {
                 FilesMetadata::Single {
-                    name: dp.required(NAME)?,
+                    name,
                     length: dp.required(LENGTH)?,
                     md5sum: dp.optional(MD5SUM)?,
                 }
             } else if !single && multi {
                 FilesMetadata::Multiple {
-                    base_name: dp.required(NAME)?,
+                    base_name: name,
                     files: dp.required(FILES)?,
                 }
             } else {
@@ -121,10 +561,14 @@ impl<'a> TryDeserialize<'a> for Info {
         };
 
         Ok(Info {
-            piece_length: dp.required(PIECE_LENGTH)?,
-            pieces: dp.required(PIECES)?,
+            piece_length,
+            pieces,
             private: dp.optional(PRIVATE)?,
             files,
+            meta_version,
+            file_tree,
+            name_raw,
+            name_utf8,
         })
     }
 }
@@ -139,32 +583,104 @@ pub struct TorrentMetadata {
     pub creation_date: Option<u64>,
     pub comment: Option<String>,
     pub created_by: Option<String>,
+    /// Веб-сиды (BEP 19). В файле может лежать как строка, так и список.
+    pub url_list: Option<Vec<String>>,
+    /// Слои кусков v2 по корням файлов, склеенные хеши по 32 байта.
+    pub piece_layers: Option<BTreeMap<[u8; 32], Vec<u8>>>,
 }
 
 impl TorrentMetadata {
-    pub fn new<'a>(bytes: &'a [u8]) -> Result<(TorrentMetadata, [u8; 20]), ParsingError> {
-        // Некий костыль, чтобы считать хеш от этого чуда
-        if let Ok((_, node)) = parse_node(bytes) {
-            let hash = get_info_hash(&node)?;
+    pub fn new(bytes: &[u8]) -> Result<(TorrentMetadata, InfoHash), ParsingError> {
+        // Хеш считается от исходных байтов, а не от пересобранного словаря
+        let (_, node) = parse_node(bytes)?;
+        let info = get_info_bytes(&node)?;
+        let metadata = TorrentMetadata::try_deserialize_from_node(node)?;
+        let hash = InfoHash::from_info_bytes(info, metadata.info.version());
 
-            Ok((TorrentMetadata::try_deserialize_from_node(node)?, hash))
-        } else {
-            Err(ParsingError::InvalidFormat)
+        Ok((metadata, hash))
+    }
+
+    /// Все адреса трекеров: `announce` и затем уровни `announce-list`.
+    pub fn trackers(&self) -> Vec<&str> {
+        let mut trackers = vec![self.announce.as_str()];
+        for url in self.announce_list.iter().flatten().flatten() {
+            if !trackers.contains(&url.as_str()) {
+                trackers.push(url);
+            }
         }
+        trackers
+    }
+
+    /// Добавляет трекеры `other`, которых ещё нет, отдельными уровнями
+    /// в конец `announce-list`. Возвращает `true`, если что-то добавилось.
+    pub fn merge_trackers(&mut self, other: &TorrentMetadata) -> bool {
+        let known: Vec<String> = self.trackers().into_iter().map(String::from).collect();
+        let mut tiers: Vec<Vec<String>> = match &other.announce_list {
+            Some(list) => list.clone(),
+            None => vec![vec![other.announce.clone()]],
+        };
+        tiers
+            .iter_mut()
+            .for_each(|tier| tier.retain(|url| !known.contains(url)));
+        tiers.retain(|tier| !tier.is_empty());
+        if tiers.is_empty() {
+            return false;
+        }
+
+        // Без `announce-list` клиенты смотрят только на `announce`, поэтому
+        // он становится первым уровнем
+        let list = self
+            .announce_list
+            .get_or_insert_with(|| vec![vec![self.announce.clone()]]);
+        list.extend(tiers);
+        true
+    }
+
+    /// Слой кусков файла по его корню.
+    pub fn piece_layer(&self, pieces_root: &[u8; 32]) -> Option<Vec<merkle::Hash>> {
+        let layer = self.piece_layers.as_ref()?.get(pieces_root)?;
+        if layer.len() % 32 != 0 {
+            return None;
+        }
+        Some(layer.chunks(32).map(|h| h.try_into().unwrap()).collect())
+    }
+
+    /// Проверяет, что у каждого файла длиннее куска есть слой и он сходится с корнем.
+    pub fn verify_piece_layers(&self) -> bool {
+        let piece_length = self.info.piece_length;
+
+        self.info
+            .tree_files()
+            .iter()
+            .filter(|f| f.length > piece_length)
+            .all(|f| {
+                let Some(root) = f.pieces_root else {
+                    return false;
+                };
+                match self.piece_layer(&root) {
+                    Some(layer) if layer.len() as u64 == f.length.div_ceil(piece_length) => {
+                        MerkleTree::from_piece_layer(layer, piece_length).root() == root
+                    }
+                    _ => false,
+                }
+            })
     }
 }
 
 impl Serialize for TorrentMetadata {
-    fn serialize(&self) -> Vec<u8> {
-        BencodeDictBuilder::new()
-            .required(INFO, self.info.clone())
-            .required(ANNOUNCE, self.announce.clone())
-            .optional(ENCODING, self.encoding.clone())
-            .optional(HTTPSEEDS, self.httpseeds.clone())
-            .optional(ANNOUNCE_LIST, self.announce_list.clone())
-            .optional(CREATION_DATE, self.creation_date.clone())
-            .optional(COMMENT, self.comment.clone())
-            .optional(CREATED_BY, self.created_by.clone())
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        encoder
+            .dict()?
+            .required(ANNOUNCE, &self.announce)?
+            .optional(ANNOUNCE_LIST, self.announce_list.as_ref())?
+            .optional(COMMENT, self.comment.as_ref())?
+            .optional(CREATED_BY, self.created_by.as_ref())?
+            .optional(CREATION_DATE, self.creation_date.as_ref())?
+            .optional(ENCODING, self.encoding.as_ref())?
+            .optional(HTTPSEEDS, self.httpseeds.as_ref())?
+            .required(INFO, &self.info)?
+            .optional(PIECE_LAYERS, self.piece_layers.as_ref())?
+            .optional(URL_LIST, self.url_list.as_ref())?
             .fin()
     }
 }
@@ -172,60 +688,193 @@ impl Serialize for TorrentMetadata {
 impl<'a> TryDeserialize<'a> for TorrentMetadata {
     fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
         let dp = DataProvider::try_from(node)?;
+        let encoding: Option<String> = dp.optional(ENCODING)?;
+        let mut info: Info = dp.required(INFO)?;
+
+        // Без поля `encoding` имена считаются UTF-8
+        if let Some(encoding) = encoding
+            .as_deref()
+            .and_then(|e| Encoding::for_label(e.as_bytes()))
+        {
+            info.decode_names(encoding);
+        }
         Ok(TorrentMetadata {
-            info: dp.required(INFO)?,
+            info,
             announce: dp.required(ANNOUNCE)?,
-            encoding: dp.optional(ENCODING)?,
+            encoding,
             httpseeds: dp.optional(HTTPSEEDS)?,
             announce_list: dp.optional(ANNOUNCE_LIST)?,
             creation_date: dp.optional(CREATION_DATE)?,
             comment: dp.optional(COMMENT)?,
             created_by: dp.optional(CREATED_BY)?,
+            url_list: one_or_many(&dp, URL_LIST)?,
+            piece_layers: dp.optional(PIECE_LAYERS)?,
         })
     }
 }
 
+/// Читает поле, которое может быть одной строкой или списком строк.
+fn one_or_many(dp: &DataProvider, key: &[u8]) -> Result<Option<Vec<String>>, ParsingError> {
+    match dp.optional::<Vec<String>>(key) {
+        Err(ParsingError::TypeMismatch) => Ok(dp.optional::<String>(key)?.map(|s| vec![s])),
+        other => other,
+    }
+}
+
+/// Состояние торрента для фильтрации списка.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Paused,
+    /// Ждёт места в очереди.
+    Queued,
+    Downloading,
+    Seeding,
+}
+
+impl Status {
+    /// Обменивается ли торрент данными.
+    pub fn is_active(self) -> bool {
+        matches!(self, Status::Downloading | Status::Seeding)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Torrent {
     pub metadata: TorrentMetadata,
-    pub hash: [u8; 20],
+    pub hash: InfoHash,
     pub downloaded_pieces: Vec<u8>, // 1 бит - 1 скачанный кусок
     pub downloaded: u64,
+    /// Приоритеты файлов в порядке `Storage::files`. Недостающие — `Normal`.
+    /// От них зависит, где лежат данные, поэтому меняются они через
+    /// `TorrentRepo::set_file_priorities`.
+    pub priorities: Vec<Priority>,
+    pub resume: Option<ResumeData>,
+    pub paused: bool,
+    pub labels: Vec<String>,
+    /// Ограничения скорости торрента, байт в секунду.
+    pub download_limit: Option<u64>,
+    pub upload_limit: Option<u64>,
+    pub queue: QueueState,
 }
 
 impl Torrent {
-    pub fn new(metadata: TorrentMetadata, hash: [u8; 20]) -> Torrent {
-        let len = metadata.info.pieces.len() / (20 * 8) + 1;
+    pub fn new(metadata: TorrentMetadata, hash: InfoHash) -> Torrent {
+        let len = metadata.info.piece_count() / 8 + 1;
         Torrent {
             metadata,
             hash,
             downloaded_pieces: vec![0; len],
             downloaded: 0,
+            priorities: vec![],
+            resume: None,
+            paused: false,
+            labels: vec![],
+            download_limit: None,
+            upload_limit: None,
+            queue: QueueState::default(),
+        }
+    }
+
+    /// Скачаны ли все куски.
+    pub fn is_complete(&self) -> bool {
+        (0..self.metadata.info.piece_count()).all(|i| get_bit(&self.downloaded_pieces, i))
+    }
+
+    pub fn status(&self) -> Status {
+        if self.paused {
+            Status::Paused
+        } else if self.queue.queued {
+            Status::Queued
+        } else if self.is_complete() {
+            Status::Seeding
+        } else {
+            Status::Downloading
         }
     }
+
+    pub fn has_label(&self, label: &str) -> bool {
+        self.labels.iter().any(|l| l == label)
+    }
+
+    pub fn priority(&self, file: usize) -> Priority {
+        self.priorities.get(file).copied().unwrap_or_default()
+    }
 }
 
-impl Serialize for Torrent {
-    fn serialize(&self) -> Vec<u8> {
-        BencodeDictBuilder::new()
-            .required(DATA, self.metadata.clone())
-            .required(HASH, self.hash.to_vec())
-            .required(DOWNLOADED_PIECES, self.downloaded_pieces.clone())
-            .required(DOWNLOADED, self.downloaded)
+impl Torrent {
+    /// Пишет торрент. Без метаданных остаётся только состояние загрузки,
+    /// которое каталожное хранилище держит отдельно от `.torrent`-файла.
+    pub(crate) fn encode_with<W: Write>(
+        &self,
+        encoder: &mut Encoder<W>,
+        with_metadata: bool,
+    ) -> io::Result<()> {
+        encoder
+            .dict()?
+            .optional(DATA, Some(&self.metadata).filter(|_| with_metadata))?
+            .optional(DOWNLOAD_LIMIT, self.download_limit.as_ref())?
+            .required(DOWNLOADED, &self.downloaded)?
+            .required(DOWNLOADED_PIECES, &self.downloaded_pieces)?
+            .optional(HASH, self.hash.v1().as_ref())?
+            .optional(HASH_V2, self.hash.v2().as_ref())?
+            .optional(LABELS, Some(&self.labels).filter(|l| !l.is_empty()))?
+            .optional(PAUSED, Some(&1u64).filter(|_| self.paused))?
+            .optional(PRIORITIES, Some(&self.priorities).filter(|p| !p.is_empty()))?
+            .optional(
+                QUEUE,
+                Some(&self.queue).filter(|q| **q != QueueState::default()),
+            )?
+            .optional(RESUME, self.resume.as_ref())?
+            .optional(UPLOAD_LIMIT, self.upload_limit.as_ref())?
             .fin()
     }
-}
 
-impl<'a> TryDeserialize<'a> for Torrent {
-    fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
+    /// Разбирает торрент. Если метаданные уже известны, ключ `data` не нужен.
+    pub(crate) fn from_node(
+        node: Node<'_>,
+        metadata: Option<TorrentMetadata>,
+    ) -> Result<Torrent, ParsingError> {
         let dp = DataProvider::try_from(node)?;
 
-        let hash: Vec<u8> = dp.required(HASH)?;
+        // Старые репозитории хранят только хеш v1
+        let hash = match (dp.optional(HASH)?, dp.optional(HASH_V2)?) {
+            (Some(v1), Some(v2)) => InfoHash::Hybrid(v1, v2),
+            (Some(v1), None) => InfoHash::V1(v1),
+            (None, Some(v2)) => InfoHash::V2(v2),
+            (None, None) => {
+                return Err(ParsingError::MissingField(
+                    String::from_utf8_lossy(HASH).into_owned(),
+                ))
+            }
+        };
+        let metadata = match metadata {
+            Some(metadata) => metadata,
+            None => dp.required(DATA)?,
+        };
         Ok(Torrent {
-            metadata: dp.required(DATA)?,
+            metadata,
             downloaded_pieces: dp.required(DOWNLOADED_PIECES)?,
             downloaded: dp.required(DOWNLOADED)?,
-            hash: hash.try_into().unwrap(),
+            hash,
+            priorities: dp.optional(PRIORITIES)?.unwrap_or_default(),
+            resume: dp.optional(RESUME)?,
+            paused: dp.optional::<u64>(PAUSED)?.is_some_and(|p| p != 0),
+            labels: dp.optional(LABELS)?.unwrap_or_default(),
+            download_limit: dp.optional(DOWNLOAD_LIMIT)?,
+            upload_limit: dp.optional(UPLOAD_LIMIT)?,
+            queue: dp.optional(QUEUE)?.unwrap_or_default(),
         })
     }
 }
+
+impl Serialize for Torrent {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        self.encode_with(encoder, true)
+    }
+}
+
+impl<'a> TryDeserialize<'a> for Torrent {
+    fn try_deserialize_from_node(node: Node<'a>) -> Result<Self, ParsingError> {
+        Torrent::from_node(node, None)
+    }
+}
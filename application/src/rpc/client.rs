@@ -0,0 +1,70 @@
+/// Модуль с клиентом управляющего протокола.
+use std::io;
+
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, BufReader},
+    net::TcpStream,
+};
+
+use crate::error::AsyncErr;
+
+use super::{read_message, write_message, Endpoint, Request, RpcError};
+
+/// Соединение с демоном любого вида.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+pub struct RpcClient {
+    stream: BufReader<Box<dyn Connection>>,
+    next_id: u64,
+}
+
+impl RpcClient {
+    /// Подключается и, если дан токен, сразу проходит проверку.
+    pub async fn connect(endpoint: &Endpoint, token: Option<&str>) -> io::Result<RpcClient> {
+        let stream: Box<dyn Connection> = match endpoint {
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Box::new(tokio::net::UnixStream::connect(path).await?),
+            Endpoint::Tcp(addr) => Box::new(TcpStream::connect(addr).await?),
+        };
+        let mut client = RpcClient {
+            stream: BufReader::new(stream),
+            next_id: 1,
+        };
+        if let Some(token) = token {
+            client
+                .call("auth", json!({ "token": token }))
+                .await
+                .map_err(io::Error::other)?;
+        }
+        Ok(client)
+    }
+
+    pub async fn call(&mut self, method: &str, params: Value) -> Result<Value, AsyncErr> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = Request {
+            id: Some(id.into()),
+            method: method.to_string(),
+            params,
+        };
+        write_message(&mut self.stream, &request.to_json()).await?;
+
+        let line = read_message(&mut self.stream)
+            .await?
+            .ok_or("the daemon closed the connection")?;
+        let mut response: Value = serde_json::from_str(&line)?;
+        if response["id"] != id {
+            return Err(format!("unexpected response {line}").into());
+        }
+        if let Some(error) = response.get("error") {
+            return Err(Box::new(RpcError::new(
+                error["code"].as_i64().unwrap_or_default(),
+                error["message"].as_str().unwrap_or_default(),
+            )));
+        }
+        Ok(response["result"].take())
+    }
+}
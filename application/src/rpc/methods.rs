@@ -0,0 +1,477 @@
+/// Модуль с методами управления репозиторием.
+///
+/// Методы не знают, откуда пришёл вызов: их выполняет и демон, и командная
+/// строка, когда демон не запущен.
+use std::{path::PathBuf, time::SystemTime};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Value};
+
+use crate::{
+    repository::{
+        queue::{ratio, Goal, QueueMove},
+        resume::{Recheck, ResumeData},
+        types::{Priority, Status, Torrent, TorrentMetadata},
+        Added, Id, TorrentRepo, WithId,
+    },
+    settings::{format_ratio, parse_minutes, parse_ratio},
+    storage::Storage,
+    tools::to_hex,
+};
+
+use super::{RpcError, METHOD_NOT_FOUND};
+
+/// Выполняет метод. Вместе с результатом возвращает `true`, если
+/// репозиторий изменился и его нужно сохранить.
+pub async fn call(
+    repo: &mut TorrentRepo,
+    method: &str,
+    params: &Value,
+) -> Result<(Value, bool), RpcError> {
+    match method {
+        "torrent.add" => add(repo, params),
+        "torrent.list" => list(repo, params).map(|v| (v, false)),
+        "torrent.get" => {
+            let id = find(repo, required_str(params, "id")?)?;
+            Ok((details(repo.get_by_id(id).unwrap()), false))
+        }
+        "torrent.remove" => remove(repo, params).await,
+        "torrent.start" => set_paused(repo, params, false),
+        "torrent.stop" => set_paused(repo, params, true),
+        "torrent.recheck" => recheck(repo, params).await,
+        "torrent.set" => set(repo, params).await,
+        "torrent.queue" => queue(repo, params),
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method {method}"),
+        )),
+    }
+}
+
+fn required_str<'a>(params: &'a Value, key: &str) -> Result<&'a str, RpcError> {
+    params[key]
+        .as_str()
+        .ok_or_else(|| RpcError::invalid_params(format!("{key} must be a string")))
+}
+
+fn optional_str<'a>(params: &'a Value, key: &str) -> Result<Option<&'a str>, RpcError> {
+    match &params[key] {
+        Value::Null => Ok(None),
+        _ => required_str(params, key).map(Some),
+    }
+}
+
+fn flag(params: &Value, key: &str) -> Result<bool, RpcError> {
+    match &params[key] {
+        Value::Null => Ok(false),
+        Value::Bool(b) => Ok(*b),
+        _ => Err(RpcError::invalid_params(format!("{key} must be a boolean"))),
+    }
+}
+
+fn strings(params: &Value, key: &str) -> Result<Option<Vec<String>>, RpcError> {
+    match &params[key] {
+        Value::Null => Ok(None),
+        Value::Array(items) => items
+            .iter()
+            .map(|v| v.as_str().map(String::from))
+            .collect::<Option<_>>()
+            .map(Some)
+            .ok_or_else(|| RpcError::invalid_params(format!("{key} must be a list of strings"))),
+        _ => Err(RpcError::invalid_params(format!(
+            "{key} must be a list of strings"
+        ))),
+    }
+}
+
+/// Ограничение скорости в байтах в секунду, 0 снимает ограничение.
+/// Внешний `None` — ключа нет, менять нечего.
+fn limit(params: &Value, key: &str) -> Result<Option<Option<u64>>, RpcError> {
+    match &params[key] {
+        Value::Null => Ok(None),
+        value => value
+            .as_u64()
+            .map(|limit| Some(Some(limit).filter(|&l| l > 0)))
+            .ok_or_else(|| RpcError::invalid_params(format!("{key} must be a number of bytes"))),
+    }
+}
+
+/// Цель раздачи: `"global"`, `"unlimited"` или число, отношение либо
+/// минуты. Ноль тоже снимает цель.
+fn goal(params: &Value, key: &str, is_ratio: bool) -> Result<Option<Goal>, RpcError> {
+    let limit = match &params[key] {
+        Value::Null => return Ok(None),
+        Value::String(s) if s == "global" => return Ok(Some(Goal::Global)),
+        Value::String(s) if s == "unlimited" => return Ok(Some(Goal::Unlimited)),
+        Value::Number(n) if is_ratio => parse_ratio(key, &n.to_string()),
+        Value::Number(n) => parse_minutes(key, &n.to_string()),
+        _ => {
+            return Err(RpcError::invalid_params(format!(
+                "{key} must be global, unlimited or a number"
+            )))
+        }
+    };
+    let limit = limit.map_err(|e| RpcError::invalid_params(e.to_string()))?;
+    Ok(Some(limit.map_or(Goal::Unlimited, Goal::Limit)))
+}
+
+pub fn goal_json(goal: Goal, is_ratio: bool) -> Value {
+    match goal {
+        Goal::Global => "global".into(),
+        Goal::Unlimited => "unlimited".into(),
+        Goal::Limit(ratio) if is_ratio => format_ratio(ratio).into(),
+        Goal::Limit(minutes) => minutes.into(),
+    }
+}
+
+/// Находит торрент по id, уникальному началу id или хешу в hex.
+pub fn find(repo: &TorrentRepo, query: &str) -> Result<Id, RpcError> {
+    if let Ok(id) = query.parse::<Id>() {
+        if repo.get_by_id(id).is_some() {
+            return Ok(id);
+        }
+    }
+
+    let query = query.to_lowercase();
+    let matches: Vec<Id> = repo
+        .iter()
+        .filter(|t| {
+            t.id.to_string().starts_with(&query)
+                || t.value.hash.v1().is_some_and(|h| to_hex(&h) == query)
+                || t.value.hash.v2().is_some_and(|h| to_hex(&h) == query)
+        })
+        .map(|t| t.id)
+        .collect();
+    match matches[..] {
+        [id] => Ok(id),
+        [] => Err(RpcError::failed(format!("no torrent matches {query}"))),
+        _ => Err(RpcError::failed(format!(
+            "{query} matches several torrents, give more of the id"
+        ))),
+    }
+}
+
+pub fn status_name(status: Status) -> &'static str {
+    match status {
+        Status::Paused => "paused",
+        Status::Queued => "queued",
+        Status::Downloading => "downloading",
+        Status::Seeding => "seeding",
+    }
+}
+
+fn status_from_name(name: &str) -> Option<Status> {
+    [
+        Status::Paused,
+        Status::Queued,
+        Status::Downloading,
+        Status::Seeding,
+    ]
+    .into_iter()
+    .find(|s| status_name(*s) == name)
+}
+
+pub fn priority_name(priority: Priority) -> &'static str {
+    match priority {
+        Priority::Skip => "skip",
+        Priority::Low => "low",
+        Priority::Normal => "normal",
+        Priority::High => "high",
+    }
+}
+
+fn priority_from_name(name: &str) -> Option<Priority> {
+    [
+        Priority::Skip,
+        Priority::Low,
+        Priority::Normal,
+        Priority::High,
+    ]
+    .into_iter()
+    .find(|p| priority_name(*p) == name)
+}
+
+fn progress(torrent: &Torrent) -> f64 {
+    match torrent.metadata.info.total_length() {
+        0 => 1.0,
+        size => torrent.downloaded as f64 / size as f64,
+    }
+}
+
+/// Строка списка торрентов. Скорости и число пиров знает только демон,
+/// он их и подставляет.
+pub fn summary(torrent: &WithId<Torrent>) -> Value {
+    let value = &torrent.value;
+    json!({
+        "id": torrent.id.to_string(),
+        "name": value.metadata.info.name(),
+        "status": status_name(value.status()),
+        "progress": progress(value),
+        "downloaded": value.downloaded,
+        "size": value.metadata.info.total_length(),
+        "queue_position": value.queue.position,
+        "download_rate": 0,
+        "upload_rate": 0,
+        "connected_peers": 0,
+    })
+}
+
+/// Всё, что известно о торренте.
+pub fn details(torrent: &WithId<Torrent>) -> Value {
+    let value = &torrent.value;
+    let metadata = &value.metadata;
+    let storage = value.storage();
+    let done = storage.file_progress(&value.downloaded_pieces);
+    let files: Vec<Value> = storage
+        .files()
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| !f.is_padding())
+        .map(|(i, f)| {
+            json!({
+                "index": i,
+                "path": f.path.join("/"),
+                "size": f.length,
+                "done": done[i],
+                "priority": priority_name(value.priority(i)),
+            })
+        })
+        .collect();
+    let peers: Vec<String> = value
+        .resume
+        .iter()
+        .flat_map(|r| &r.peers)
+        .map(ToString::to_string)
+        .collect();
+    let web_seeds: Vec<&String> = metadata
+        .url_list
+        .iter()
+        .chain(metadata.httpseeds.iter())
+        .flatten()
+        .collect();
+
+    json!({
+        "id": torrent.id.to_string(),
+        "name": metadata.info.name(),
+        "hash_v1": value.hash.v1().map(|h| to_hex(&h)),
+        "hash_v2": value.hash.v2().map(|h| to_hex(&h)),
+        "status": status_name(value.status()),
+        "progress": progress(value),
+        "downloaded": value.downloaded,
+        "size": metadata.info.total_length(),
+        "save_path": value.save_path(),
+        "labels": value.labels,
+        "download_limit": value.download_limit,
+        "upload_limit": value.upload_limit,
+        "queue_position": value.queue.position,
+        "finished": value.queue.finished,
+        "ratio": ratio(value).map(format_ratio),
+        "seeding_time": value.queue.seeding_time,
+        "idle_time": value.queue.idle_time,
+        "ratio_goal": goal_json(value.queue.ratio_goal, true),
+        "seed_time_goal": goal_json(value.queue.seed_time_goal, false),
+        "idle_goal": goal_json(value.queue.idle_goal, false),
+        "pieces": metadata.info.piece_count(),
+        // Старший бит первого байта — кусок 0
+        "bitfield": STANDARD.encode(&value.downloaded_pieces),
+        "piece_length": metadata.info.piece_length,
+        "private": metadata.info.private == Some(1),
+        "comment": metadata.comment,
+        "created_by": metadata.created_by,
+        "creation_date": metadata.creation_date,
+        "trackers": metadata.trackers(),
+        "web_seeds": web_seeds,
+        "files": files,
+        "peers": peers,
+        "connected_peers": 0,
+    })
+}
+
+/// `metainfo` — содержимое `.torrent`-файла в base64.
+fn add(repo: &mut TorrentRepo, params: &Value) -> Result<(Value, bool), RpcError> {
+    let metainfo = STANDARD
+        .decode(required_str(params, "metainfo")?)
+        .map_err(|e| RpcError::invalid_params(format!("metainfo is not base64: {e}")))?;
+    let (metadata, hash) = TorrentMetadata::new(&metainfo)?;
+    let mut torrent = Torrent::new(metadata, hash);
+    let save_path = match optional_str(params, "save_path")? {
+        Some(path) => PathBuf::from(path),
+        None => std::env::current_dir()?,
+    };
+    let storage = Storage::new(&torrent.metadata.info, save_path);
+    torrent.resume = Some(ResumeData::new(&storage, SystemTime::now()));
+    torrent.paused = flag(params, "paused")?;
+    torrent.labels = strings(params, "labels")?.unwrap_or_default();
+    let name = torrent.metadata.info.name().to_string();
+
+    let added = repo.add_new_torrent(torrent);
+    let result = json!({
+        "id": added.id().to_string(),
+        "name": name,
+        "merged": matches!(added, Added::Merged(_)),
+    });
+    Ok((result, true))
+}
+
+fn list(repo: &TorrentRepo, params: &Value) -> Result<Value, RpcError> {
+    let status = optional_str(params, "status")?
+        .map(|s| status_from_name(s).ok_or_else(|| RpcError::invalid_params("unknown status")))
+        .transpose()?;
+    let label = optional_str(params, "label")?;
+    let torrents: Vec<Value> = repo
+        .iter()
+        .filter(|t| status.is_none_or(|s| t.value.status() == s))
+        .filter(|t| label.is_none_or(|l| t.value.has_label(l)))
+        .map(summary)
+        .collect();
+    Ok(torrents.into())
+}
+
+async fn remove(repo: &mut TorrentRepo, params: &Value) -> Result<(Value, bool), RpcError> {
+    let id = find(repo, required_str(params, "id")?)?;
+    let torrent = &repo.get_by_id(id).unwrap().value;
+    if flag(params, "delete_data")? {
+        torrent.storage().remove_data().await?;
+    }
+    let result = json!({
+        "id": id.to_string(),
+        "name": torrent.metadata.info.name(),
+        "removed": true,
+    });
+    repo.remove_torrent_by_id(id);
+    Ok((result, true))
+}
+
+fn set_paused(
+    repo: &mut TorrentRepo,
+    params: &Value,
+    paused: bool,
+) -> Result<(Value, bool), RpcError> {
+    let id = find(repo, required_str(params, "id")?)?;
+    let mut torrent = repo.get_by_id_mut(id).unwrap();
+    let changed = torrent.paused != paused;
+    torrent.paused = paused;
+    let result = json!({
+        "id": id.to_string(),
+        "name": torrent.metadata.info.name(),
+        "paused": paused,
+    });
+    Ok((result, changed))
+}
+
+async fn recheck(repo: &mut TorrentRepo, params: &Value) -> Result<(Value, bool), RpcError> {
+    let (id, metadata, storage) = recheck_target(repo, params)?;
+    let check = Recheck::run(&metadata, &storage).await?;
+    finish_recheck(repo, id, check, &storage)
+}
+
+/// Что нужно для проверки торрента из `params`. Сама проверка идёт без
+/// репозитория, поэтому демон может не держать его всё это время.
+pub fn recheck_target(
+    repo: &TorrentRepo,
+    params: &Value,
+) -> Result<(Id, TorrentMetadata, Storage), RpcError> {
+    let id = find(repo, required_str(params, "id")?)?;
+    let torrent = &repo.get_by_id(id).unwrap().value;
+    Ok((id, torrent.metadata.clone(), torrent.storage()))
+}
+
+/// Применяет проверку к торренту, если его не удалили за это время.
+pub fn finish_recheck(
+    repo: &mut TorrentRepo,
+    id: Id,
+    check: Recheck,
+    storage: &Storage,
+) -> Result<(Value, bool), RpcError> {
+    let mut torrent = repo
+        .get_by_id_mut(id)
+        .ok_or_else(|| RpcError::failed(format!("torrent {id} was removed during the check")))?;
+    torrent.apply_recheck(check, storage);
+    let result = json!({
+        "id": id.to_string(),
+        "downloaded": torrent.downloaded,
+        "size": torrent.metadata.info.total_length(),
+    });
+    Ok((result, true))
+}
+
+/// Меняет метки, приоритеты файлов, ограничения скорости и цели раздачи.
+/// `priorities` — объект из номера файла (`index` в `torrent.get`) в имя
+/// приоритета.
+async fn set(repo: &mut TorrentRepo, params: &Value) -> Result<(Value, bool), RpcError> {
+    let id = find(repo, required_str(params, "id")?)?;
+    let labels = strings(params, "labels")?;
+    let download_limit = limit(params, "download_limit")?;
+    let upload_limit = limit(params, "upload_limit")?;
+    let ratio_goal = goal(params, "ratio_goal", true)?;
+    let seed_time_goal = goal(params, "seed_time_goal", false)?;
+    let idle_goal = goal(params, "idle_goal", false)?;
+    let priorities = match &params["priorities"] {
+        Value::Null => vec![],
+        Value::Object(map) => map
+            .iter()
+            .map(|(file, priority)| {
+                let file = file.parse::<usize>().ok();
+                let priority = priority.as_str().and_then(priority_from_name);
+                file.zip(priority)
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| {
+                RpcError::invalid_params("priorities must map file indices to priority names")
+            })?,
+        _ => return Err(RpcError::invalid_params("priorities must be an object")),
+    };
+
+    let files = repo.get_by_id(id).unwrap().value.storage().files().len();
+    if let Some(&(file, _)) = priorities.iter().find(|(file, _)| *file >= files) {
+        return Err(RpcError::invalid_params(format!(
+            "the torrent has no file {file}"
+        )));
+    }
+    // Данные переносятся первыми: если перенос не удался, запись не меняется
+    if !priorities.is_empty() {
+        repo.set_file_priorities(id, &priorities).await?;
+    }
+
+    let mut torrent = repo.get_by_id_mut(id).unwrap();
+    if let Some(labels) = labels {
+        torrent.labels = labels;
+    }
+    if let Some(limit) = download_limit {
+        torrent.download_limit = limit;
+    }
+    if let Some(limit) = upload_limit {
+        torrent.upload_limit = limit;
+    }
+    let state = &mut torrent.queue;
+    state.ratio_goal = ratio_goal.unwrap_or(state.ratio_goal);
+    state.seed_time_goal = seed_time_goal.unwrap_or(state.seed_time_goal);
+    state.idle_goal = idle_goal.unwrap_or(state.idle_goal);
+    drop(torrent);
+    Ok((details(repo.get_by_id(id).unwrap()), true))
+}
+
+/// Переставляет торрент в очереди: `move` — `top`, `up`, `down` или
+/// `bottom`, либо `position` — место с нуля.
+fn queue(repo: &mut TorrentRepo, params: &Value) -> Result<(Value, bool), RpcError> {
+    let id = find(repo, required_str(params, "id")?)?;
+    let to = match (optional_str(params, "move")?, &params["position"]) {
+        (Some("top"), Value::Null) => QueueMove::Top,
+        (Some("up"), Value::Null) => QueueMove::Up,
+        (Some("down"), Value::Null) => QueueMove::Down,
+        (Some("bottom"), Value::Null) => QueueMove::Bottom,
+        (None, position) if position.is_u64() => QueueMove::To(position.as_u64().unwrap() as usize),
+        _ => {
+            return Err(RpcError::invalid_params(
+                "expected move as top, up, down or bottom, or a position",
+            ))
+        }
+    };
+    repo.move_in_queue(id, to);
+    let order = repo.queue_order();
+    let result = json!({
+        "id": id.to_string(),
+        "queue": order.iter().map(ToString::to_string).collect::<Vec<_>>(),
+    });
+    Ok((result, true))
+}
@@ -0,0 +1,191 @@
+pub mod client;
+pub mod methods;
+
+/// Модуль с протоколом управления демоном.
+///
+/// Это JSON-RPC 2.0, по одному сообщению в строке, поверх Unix-сокета или
+/// TCP на localhost. Если у демона задан токен, первым вызовом на каждом
+/// соединении должен быть `auth`.
+use std::{fmt::Display, io, net::SocketAddr, path::PathBuf, str::FromStr};
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::io::deserialization::ParsingError;
+
+/// Самое длинное сообщение. Метаинформация передаётся в base64, поэтому
+/// запас нужен на крупные торренты.
+pub const MAX_MESSAGE: u64 = 16 * 1024 * 1024;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// Вызов понят, но не выполнен: торрента нет, файл не читается и т. п.
+pub const FAILED: i64 = -32000;
+pub const UNAUTHORIZED: i64 = -32001;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> RpcError {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> RpcError {
+        RpcError::new(INVALID_PARAMS, message)
+    }
+
+    pub fn failed(message: impl Into<String>) -> RpcError {
+        RpcError::new(FAILED, message)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({ "code": self.code, "message": self.message })
+    }
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl From<io::Error> for RpcError {
+    fn from(e: io::Error) -> Self {
+        RpcError::failed(e.to_string())
+    }
+}
+
+impl From<ParsingError> for RpcError {
+    fn from(e: ParsingError) -> Self {
+        RpcError::failed(format!("invalid torrent: {e}"))
+    }
+}
+
+/// Вызов метода. `id` отсутствует у уведомлений, на них не отвечают.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub id: Option<Value>,
+    pub method: String,
+    pub params: Value,
+}
+
+impl Request {
+    pub fn to_json(&self) -> Value {
+        let mut value = json!({
+            "jsonrpc": "2.0",
+            "method": self.method,
+            "params": self.params,
+        });
+        if let Some(id) = &self.id {
+            value["id"] = id.clone();
+        }
+        value
+    }
+
+    /// Разбирает строку запроса. При ошибке возвращает и `id`, если его
+    /// удалось достать, чтобы ответить на правильный запрос.
+    pub fn parse(line: &str) -> Result<Request, (Value, RpcError)> {
+        let value: Value = serde_json::from_str(line)
+            .map_err(|e| (Value::Null, RpcError::new(PARSE_ERROR, e.to_string())))?;
+        let id = value.get("id").cloned();
+        let invalid = |message: &str| {
+            (
+                id.clone().unwrap_or(Value::Null),
+                RpcError::new(INVALID_REQUEST, message),
+            )
+        };
+        if value.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+            return Err(invalid("expected a JSON-RPC 2.0 request"));
+        }
+        let method = value
+            .get("method")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("missing method"))?;
+        let params = match value.get("params") {
+            None => json!({}),
+            Some(params @ Value::Object(_)) => params.clone(),
+            Some(_) => return Err(invalid("params must be an object")),
+        };
+        Ok(Request {
+            id,
+            method: method.to_string(),
+            params,
+        })
+    }
+}
+
+pub fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error.to_json() }),
+    }
+}
+
+/// Читает одно сообщение. `None`, если собеседник закрыл соединение.
+pub async fn read_message<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    let read = reader.take(MAX_MESSAGE).read_line(&mut line).await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') && read as u64 == MAX_MESSAGE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message is too long",
+        ));
+    }
+    Ok(Some(line))
+}
+
+pub async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Value,
+) -> io::Result<()> {
+    let mut line = message.to_string();
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    writer.flush().await
+}
+
+/// Куда подключаться к демону: `unix:<path>` или `<ip>:<port>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    #[cfg(unix)]
+    Unix(PathBuf),
+    Tcp(SocketAddr),
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        #[cfg(unix)]
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(Endpoint::Unix(PathBuf::from(path)));
+        }
+        s.parse()
+            .map(Endpoint::Tcp)
+            .map_err(|_| format!("expected unix:<path> or <ip>:<port>, got {s}"))
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(unix)]
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+            Endpoint::Tcp(addr) => write!(f, "{addr}"),
+        }
+    }
+}
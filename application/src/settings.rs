@@ -0,0 +1,801 @@
+/// Модуль с настройками клиента.
+///
+/// Настройки собираются по слоям: значения по умолчанию, файл TOML,
+/// переменные окружения `BITTORRENT_<КЛЮЧ>` и переопределения из командной
+/// строки. Каждый слой задаёт значения по ключам вида `limits.upload_rate`,
+/// поэтому все источники разбираются и проверяются одинаково.
+use std::{
+    error::Error,
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{Client, Proxy, Url};
+use serde_json::{json, Value};
+use toml_edit::DocumentMut;
+
+use crate::{client::Identity, network::bandwidth::LIMITED_TRAFFIC, tools::from_hex};
+
+pub const DEFAULT_PORT: u16 = 6881;
+
+/// Префикс переменных окружения с настройками.
+const ENV_PREFIX: &str = "BITTORRENT_";
+
+/// Все известные ключи в порядке описания.
+pub const KEYS: [&str; 31] = [
+    "listen_port",
+    "download_dir",
+    "user_agent",
+    "encryption",
+    "proxy",
+    "limits.connections",
+    "limits.connections_per_torrent",
+    "limits.download_rate",
+    "limits.upload_rate",
+    "protocols.dht",
+    "protocols.pex",
+    "protocols.lsd",
+    "protocols.utp",
+    "alt_speed.enabled",
+    "alt_speed.download_rate",
+    "alt_speed.upload_rate",
+    "alt_speed.scheduled",
+    "alt_speed.begin",
+    "alt_speed.end",
+    "alt_speed.days",
+    "alt_speed.utc_offset",
+    "queue.downloads",
+    "queue.seeds",
+    "seeding.ratio",
+    "seeding.time",
+    "seeding.idle",
+    "disk.write_cache",
+    "disk.read_cache",
+    "disk.read_ahead",
+    "identity.peer_id_prefix",
+    "identity.tracker_key",
+];
+
+/// Дни недели в порядке битов расписания, как в Transmission: воскресенье
+/// — младший бит.
+const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+pub const ALL_DAYS: u8 = 0b111_1111;
+const WEEKDAYS: u8 = 0b011_1110;
+const WEEKEND: u8 = 0b100_0001;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Encryption {
+    /// Только открытые соединения. Шифрования пока нет, поэтому
+    /// настройка принимает только это значение.
+    #[default]
+    Disabled,
+    /// Шифровать, если пир умеет, иначе открыто.
+    Preferred,
+    /// Только шифрованные соединения.
+    Required,
+}
+
+impl Encryption {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encryption::Disabled => "disabled",
+            Encryption::Preferred => "preferred",
+            Encryption::Required => "required",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Encryption> {
+        [
+            Encryption::Disabled,
+            Encryption::Preferred,
+            Encryption::Required,
+        ]
+        .into_iter()
+        .find(|e| e.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// Соединений с пирами на все торренты.
+    pub connections: usize,
+    pub connections_per_torrent: usize,
+    /// Байт в секунду, `None` — без ограничения. Ограничивает только
+    /// трафик из [`LIMITED_TRAFFIC`]: обмена с пирами пока нет.
+    pub download_rate: Option<u64>,
+    pub upload_rate: Option<u64>,
+}
+
+/// Дополнительные протоколы. Ни один ещё не реализован, так что
+/// включить их нельзя.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Protocols {
+    pub dht: bool,
+    pub pex: bool,
+    /// Поиск пиров в локальной сети.
+    pub lsd: bool,
+    pub utp: bool,
+}
+
+/// Сколько торрентов одновременно качается и раздаётся. Остальные ждут
+/// в очереди, `None` — без ограничения.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueLimits {
+    pub downloads: Option<usize>,
+    pub seeds: Option<usize>,
+}
+
+/// Когда раздача останавливается, если у торрента нет своих целей.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeedGoals {
+    /// Отношение отданного к скачанному в тысячных.
+    pub ratio: Option<u64>,
+    /// Минуты раздачи.
+    pub time: Option<u64>,
+    /// Минуты раздачи без обмена данными.
+    pub idle: Option<u64>,
+}
+
+/// Размеры кешей дисковой подсистемы.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskCache {
+    /// Байт на блоки недописанных кусков. Когда места нет, новые куски
+    /// ждут, пока диск не догонит.
+    pub write_cache: u64,
+    /// Байт на прочитанные для раздачи куски, `0` выключает кеш.
+    pub read_cache: u64,
+    /// Сколько следующих кусков читается вместе с запрошенным.
+    pub read_ahead: usize,
+}
+
+/// Запасные, обычно более строгие, ограничения скорости. Включаются вручную
+/// или по расписанию и, как и основные, касаются только [`LIMITED_TRAFFIC`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AltSpeed {
+    pub enabled: bool,
+    pub download_rate: Option<u64>,
+    pub upload_rate: Option<u64>,
+    pub scheduled: bool,
+    /// Минуты от полуночи. Если `begin` позже `end`, окно идёт через полночь.
+    pub begin: u16,
+    pub end: u16,
+    /// Биты дней недели из [`DAYS`].
+    pub days: u8,
+    /// Часовой пояс расписания в минутах от UTC.
+    pub utc_offset: i32,
+}
+
+impl AltSpeed {
+    /// Действуют ли запасные ограничения в момент `now`. День недели
+    /// берётся на этот же момент.
+    pub fn active(&self, now: SystemTime) -> bool {
+        if self.enabled {
+            return true;
+        }
+        if !self.scheduled {
+            return false;
+        }
+        let seconds = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64
+            + i64::from(self.utc_offset) * 60;
+        let minute = seconds.div_euclid(60).rem_euclid(24 * 60) as u16;
+        // 1 января 1970 года — четверг
+        let day = (seconds.div_euclid(24 * 60 * 60) + 4).rem_euclid(7);
+        if self.days & (1 << day) == 0 {
+            return false;
+        }
+        if self.begin <= self.end {
+            (self.begin..self.end).contains(&minute)
+        } else {
+            minute >= self.begin || minute < self.end
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// Порт для входящих соединений, его же получает трекер.
+    pub listen_port: u16,
+    /// Куда качаются торренты, добавленные без каталога. После загрузки
+    /// всегда абсолютный.
+    pub download_dir: PathBuf,
+    /// `User-Agent` запросов к трекерам.
+    pub user_agent: String,
+    pub encryption: Encryption,
+    /// HTTP-прокси для трекеров, `http://[user:password@]host:port`.
+    pub proxy: Option<String>,
+    pub limits: Limits,
+    pub protocols: Protocols,
+    pub alt_speed: AltSpeed,
+    pub queue: QueueLimits,
+    pub seeding: SeedGoals,
+    pub disk: DiskCache,
+    /// Префикс peer ID и ключ трекеров берутся из настроек, случайная
+    /// часть создаётся при запуске. Всё вместе переживает перечитывание
+    /// настроек и меняется только с перезапуском.
+    pub identity: Identity,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            listen_port: DEFAULT_PORT,
+            download_dir: PathBuf::from("."),
+            user_agent: format!("bittorrent_client/{}", env!("CARGO_PKG_VERSION")),
+            encryption: Encryption::default(),
+            proxy: None,
+            limits: Limits {
+                connections: 200,
+                connections_per_torrent: 50,
+                download_rate: None,
+                upload_rate: None,
+            },
+            protocols: Protocols {
+                dht: false,
+                pex: false,
+                lsd: false,
+                utp: false,
+            },
+            alt_speed: AltSpeed {
+                enabled: false,
+                download_rate: Some(50 * 1024),
+                upload_rate: Some(50 * 1024),
+                scheduled: false,
+                begin: 9 * 60,
+                end: 17 * 60,
+                days: ALL_DAYS,
+                utc_offset: 0,
+            },
+            queue: QueueLimits {
+                downloads: Some(5),
+                seeds: None,
+            },
+            seeding: SeedGoals {
+                ratio: None,
+                time: None,
+                idle: None,
+            },
+            disk: DiskCache {
+                write_cache: 64 << 20,
+                read_cache: 32 << 20,
+                read_ahead: 4,
+            },
+            identity: Identity::generate(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(PathBuf, io::Error),
+    Syntax(PathBuf, String),
+    UnknownKey(String),
+    InvalidValue { key: String, message: String },
+}
+
+impl SettingsError {
+    fn invalid(key: &str, message: impl Into<String>) -> SettingsError {
+        SettingsError::InvalidValue {
+            key: key.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsError::Io(path, e) => write!(f, "{}: {e}", path.display()),
+            SettingsError::Syntax(path, e) => write!(f, "{}: {e}", path.display()),
+            SettingsError::UnknownKey(key) => write!(f, "unknown setting {key}"),
+            SettingsError::InvalidValue { key, message } => write!(f, "{key}: {message}"),
+        }
+    }
+}
+
+impl Error for SettingsError {}
+
+impl Settings {
+    /// Меняет одну настройку. Значение — текст, как в переменной окружения.
+    /// Согласованность настроек между собой проверяет [`Settings::validate`].
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), SettingsError> {
+        let value = value.trim();
+        match key {
+            "listen_port" => {
+                self.listen_port = number(key, value)?;
+                if self.listen_port == 0 {
+                    return Err(SettingsError::invalid(key, "must be between 1 and 65535"));
+                }
+            }
+            "download_dir" => {
+                if value.is_empty() {
+                    return Err(SettingsError::invalid(key, "must not be empty"));
+                }
+                // Относительный путь считается от рабочего каталога
+                self.download_dir = std::path::absolute(value)
+                    .map_err(|e| SettingsError::invalid(key, e.to_string()))?;
+            }
+            "user_agent" => {
+                if value.is_empty() || value.chars().any(char::is_control) {
+                    return Err(SettingsError::invalid(
+                        key,
+                        "must be non-empty and printable",
+                    ));
+                }
+                self.user_agent = value.to_string();
+            }
+            "encryption" => {
+                let encryption = Encryption::from_name(value).ok_or_else(|| {
+                    SettingsError::invalid(key, "expected disabled, preferred or required")
+                })?;
+                if encryption != Encryption::Disabled {
+                    return Err(SettingsError::invalid(
+                        key,
+                        "encrypted connections are not supported yet",
+                    ));
+                }
+                self.encryption = encryption;
+            }
+            "proxy" => {
+                self.proxy = match value {
+                    "" | "none" => None,
+                    url => Some(proxy(key, url)?),
+                };
+            }
+            "limits.connections" => self.limits.connections = count(key, value)?,
+            "limits.connections_per_torrent" => {
+                self.limits.connections_per_torrent = count(key, value)?
+            }
+            "limits.download_rate" => self.limits.download_rate = parse_rate(key, value)?,
+            "limits.upload_rate" => self.limits.upload_rate = parse_rate(key, value)?,
+            "protocols.dht" => self.protocols.dht = unsupported(key, value)?,
+            "protocols.pex" => self.protocols.pex = unsupported(key, value)?,
+            "protocols.lsd" => self.protocols.lsd = unsupported(key, value)?,
+            "protocols.utp" => self.protocols.utp = unsupported(key, value)?,
+            "alt_speed.enabled" => self.alt_speed.enabled = flag(key, value)?,
+            "alt_speed.download_rate" => self.alt_speed.download_rate = parse_rate(key, value)?,
+            "alt_speed.upload_rate" => self.alt_speed.upload_rate = parse_rate(key, value)?,
+            "alt_speed.scheduled" => self.alt_speed.scheduled = flag(key, value)?,
+            "alt_speed.begin" => self.alt_speed.begin = time_of_day(key, value)?,
+            "alt_speed.end" => self.alt_speed.end = time_of_day(key, value)?,
+            "alt_speed.days" => self.alt_speed.days = days(key, value)?,
+            "alt_speed.utc_offset" => self.alt_speed.utc_offset = utc_offset(key, value)?,
+            "queue.downloads" => self.queue.downloads = optional_count(key, value)?,
+            "queue.seeds" => self.queue.seeds = optional_count(key, value)?,
+            "seeding.ratio" => self.seeding.ratio = parse_ratio(key, value)?,
+            "seeding.time" => self.seeding.time = parse_minutes(key, value)?,
+            "seeding.idle" => self.seeding.idle = parse_minutes(key, value)?,
+            "disk.write_cache" => {
+                self.disk.write_cache = parse_size(key, value)?;
+                if self.disk.write_cache == 0 {
+                    return Err(SettingsError::invalid(key, "must not be zero"));
+                }
+            }
+            "disk.read_cache" => self.disk.read_cache = parse_size(key, value)?,
+            "disk.read_ahead" => self.disk.read_ahead = number(key, value)?,
+            "identity.peer_id_prefix" => {
+                // Хотя бы восемь байт peer ID остаются случайными
+                if value.is_empty()
+                    || value.len() > 12
+                    || !value.bytes().all(|b| b.is_ascii_graphic())
+                {
+                    return Err(SettingsError::invalid(
+                        key,
+                        "must be 1 to 12 printable ASCII characters",
+                    ));
+                }
+                self.identity.set_prefix(value.as_bytes());
+            }
+            "identity.tracker_key" => {
+                let tracker_key = match value {
+                    "" | "none" => None,
+                    hex => Some(
+                        from_hex(hex)
+                            .and_then(|bytes| <[u8; 4]>::try_from(bytes).ok())
+                            .map(u32::from_be_bytes)
+                            .ok_or_else(|| {
+                                SettingsError::invalid(key, "expected 8 hex digits or none")
+                            })?,
+                    ),
+                };
+                self.identity.set_key(tracker_key);
+            }
+            _ => return Err(SettingsError::UnknownKey(key.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Проверки, в которых участвует несколько настроек.
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        if self.limits.connections_per_torrent > self.limits.connections {
+            return Err(SettingsError::invalid(
+                "limits.connections_per_torrent",
+                format!(
+                    "must not exceed limits.connections ({})",
+                    self.limits.connections
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "listen_port": self.listen_port,
+            "download_dir": self.download_dir,
+            "user_agent": self.user_agent,
+            "encryption": self.encryption.name(),
+            "proxy": self.proxy,
+            "limits": {
+                "connections": self.limits.connections,
+                "connections_per_torrent": self.limits.connections_per_torrent,
+                "download_rate": self.limits.download_rate,
+                "upload_rate": self.limits.upload_rate,
+                "applies_to": LIMITED_TRAFFIC,
+            },
+            "protocols": {
+                "dht": self.protocols.dht,
+                "pex": self.protocols.pex,
+                "lsd": self.protocols.lsd,
+                "utp": self.protocols.utp,
+            },
+            "alt_speed": {
+                "enabled": self.alt_speed.enabled,
+                "download_rate": self.alt_speed.download_rate,
+                "upload_rate": self.alt_speed.upload_rate,
+                "applies_to": LIMITED_TRAFFIC,
+                "scheduled": self.alt_speed.scheduled,
+                "begin": format_time_of_day(self.alt_speed.begin),
+                "end": format_time_of_day(self.alt_speed.end),
+                "days": format_days(self.alt_speed.days),
+                "utc_offset": format_utc_offset(self.alt_speed.utc_offset),
+            },
+            "queue": {
+                "downloads": self.queue.downloads,
+                "seeds": self.queue.seeds,
+            },
+            "seeding": {
+                "ratio": self.seeding.ratio.map(format_ratio),
+                "time": self.seeding.time,
+                "idle": self.seeding.idle,
+            },
+            "disk": {
+                "write_cache": self.disk.write_cache,
+                "read_cache": self.disk.read_cache,
+                "read_ahead": self.disk.read_ahead,
+            },
+            "identity": {
+                "peer_id_prefix": String::from_utf8_lossy(self.identity.prefix()),
+                "tracker_key": self.identity.key().map(|key| format!("{key:08X}")),
+            },
+            "peer_id": String::from_utf8_lossy(&self.identity.peer_id),
+        })
+    }
+
+    /// Ограничения скорости на все торренты в момент `now`: обычные или
+    /// запасные.
+    pub fn rate_limits(&self, now: SystemTime) -> (Option<u64>, Option<u64>) {
+        if self.alt_speed.active(now) {
+            (self.alt_speed.download_rate, self.alt_speed.upload_rate)
+        } else {
+            (self.limits.download_rate, self.limits.upload_rate)
+        }
+    }
+
+    /// HTTP-клиент для трекеров с `User-Agent` и прокси из настроек.
+    pub fn tracker_client(&self) -> reqwest::Result<Client> {
+        let mut builder = Client::builder()
+            .user_agent(&self.user_agent)
+            .timeout(Duration::from_secs(30));
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+        builder.build()
+    }
+}
+
+fn number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, SettingsError> {
+    value
+        .parse()
+        .map_err(|_| SettingsError::invalid(key, format!("expected a number, got {value:?}")))
+}
+
+fn count(key: &str, value: &str) -> Result<usize, SettingsError> {
+    match number(key, value)? {
+        0 => Err(SettingsError::invalid(key, "must be at least 1")),
+        n => Ok(n),
+    }
+}
+
+/// Число больше нуля или `none` и `unlimited` без ограничения.
+fn optional_count(key: &str, value: &str) -> Result<Option<usize>, SettingsError> {
+    match value {
+        "none" | "unlimited" => Ok(None),
+        _ => count(key, value).map(Some),
+    }
+}
+
+/// Минуты. `0`, `none` и `unlimited` — без ограничения.
+pub fn parse_minutes(key: &str, value: &str) -> Result<Option<u64>, SettingsError> {
+    match value {
+        "none" | "unlimited" => Ok(None),
+        _ => Ok(Some(number(key, value)?).filter(|&m| m > 0)),
+    }
+}
+
+/// Отношение вроде `1.5` в тысячные. `0`, `none` и `unlimited` — без
+/// ограничения.
+pub fn parse_ratio(key: &str, value: &str) -> Result<Option<u64>, SettingsError> {
+    if value == "none" || value == "unlimited" {
+        return Ok(None);
+    }
+    let ratio: f64 = number(key, value)?;
+    if !ratio.is_finite() || ratio < 0.0 {
+        return Err(SettingsError::invalid(
+            key,
+            format!("expected a ratio like 1.5, got {value:?}"),
+        ));
+    }
+    Ok(Some((ratio * 1000.0).round() as u64).filter(|&r| r > 0))
+}
+
+/// Тысячные обратно в отношение.
+pub fn format_ratio(ratio: u64) -> f64 {
+    ratio as f64 / 1000.0
+}
+
+/// Скорость в байтах в секунду с необязательной двоичной единицей:
+/// `524288`, `512KiB`, `1.5 MiB`. `0`, `none` и `unlimited` снимают
+/// ограничение.
+pub fn parse_rate(key: &str, value: &str) -> Result<Option<u64>, SettingsError> {
+    if value == "none" || value == "unlimited" {
+        return Ok(None);
+    }
+    match bytes(key, value, "bytes per second like 512KiB")? {
+        0 => Ok(None),
+        bytes => Ok(Some(bytes)),
+    }
+}
+
+/// Размер в байтах с необязательной двоичной единицей, как у скорости.
+pub fn parse_size(key: &str, value: &str) -> Result<u64, SettingsError> {
+    bytes(key, value, "a size like 64MiB")
+}
+
+fn bytes(key: &str, value: &str, expected: &str) -> Result<u64, SettingsError> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let multiplier: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        _ => {
+            return Err(SettingsError::invalid(
+                key,
+                format!("expected {expected}, got {value:?}"),
+            ))
+        }
+    };
+    let amount: f64 = number(key, amount)?;
+    Ok((amount * multiplier as f64) as u64)
+}
+
+fn flag(key: &str, value: &str) -> Result<bool, SettingsError> {
+    match value {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(SettingsError::invalid(
+            key,
+            format!("expected true or false, got {value:?}"),
+        )),
+    }
+}
+
+/// Флаг ещё не реализованной возможности: выключить её можно, включить нет.
+fn unsupported(key: &str, value: &str) -> Result<bool, SettingsError> {
+    match flag(key, value)? {
+        true => Err(SettingsError::invalid(key, "is not supported yet")),
+        false => Ok(false),
+    }
+}
+
+/// `HH:MM` в минуты от полуночи.
+fn time_of_day(key: &str, value: &str) -> Result<u16, SettingsError> {
+    let invalid = || SettingsError::invalid(key, format!("expected HH:MM, got {value:?}"));
+    let (hours, minutes) = value.split_once(':').ok_or_else(invalid)?;
+    let hours: u16 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u16 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+fn format_time_of_day(minutes: u16) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// `all`, `weekdays`, `weekend` или дни через запятую: `mon,wed,fri`.
+fn days(key: &str, value: &str) -> Result<u8, SettingsError> {
+    match value {
+        "all" => return Ok(ALL_DAYS),
+        "weekdays" => return Ok(WEEKDAYS),
+        "weekend" => return Ok(WEEKEND),
+        _ => {}
+    }
+    let mut days = 0;
+    for day in value.split(',').map(str::trim) {
+        let bit = DAYS.iter().position(|d| *d == day).ok_or_else(|| {
+            SettingsError::invalid(key, format!("expected days like mon,tue, got {day:?}"))
+        })?;
+        days |= 1 << bit;
+    }
+    Ok(days)
+}
+
+/// Дни недели строкой, как их принимает `alt_speed.days`.
+pub fn format_days(days: u8) -> String {
+    match days {
+        ALL_DAYS => "all".to_string(),
+        WEEKDAYS => "weekdays".to_string(),
+        WEEKEND => "weekend".to_string(),
+        _ => DAYS
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| days & (1 << bit) != 0)
+            .map(|(_, day)| *day)
+            .collect::<Vec<_>>()
+            .join(","),
+    }
+}
+
+/// `UTC`, `+03:00` или `-05:30`.
+fn utc_offset(key: &str, value: &str) -> Result<i32, SettingsError> {
+    if value == "UTC" {
+        return Ok(0);
+    }
+    let invalid = || SettingsError::invalid(key, format!("expected +HH:MM, got {value:?}"));
+    let (sign, rest) = match value.split_at_checked(1) {
+        Some(("+", rest)) => (1, rest),
+        Some(("-", rest)) => (-1, rest),
+        _ => return Err(invalid()),
+    };
+    let minutes = time_of_day(key, rest).map_err(|_| invalid())?;
+    if minutes > 14 * 60 {
+        return Err(invalid());
+    }
+    Ok(sign * i32::from(minutes))
+}
+
+fn format_utc_offset(minutes: i32) -> String {
+    let sign = if minutes < 0 { '-' } else { '+' };
+    let minutes = minutes.unsigned_abs();
+    format!("{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+fn proxy(key: &str, value: &str) -> Result<String, SettingsError> {
+    let url = Url::parse(value).map_err(|e| SettingsError::invalid(key, e.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(SettingsError::invalid(
+            key,
+            format!("{} proxies are not supported", url.scheme()),
+        ));
+    }
+    if url.host().is_none() {
+        return Err(SettingsError::invalid(key, "the proxy has no host"));
+    }
+    Ok(value.to_string())
+}
+
+/// Откуда собираются настройки. Демон хранит источник, чтобы перечитать
+/// настройки на ходу.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SettingsSource {
+    /// Файл TOML. Если его нет, используются значения по умолчанию.
+    pub file: Option<PathBuf>,
+    /// Отсутствие файла — ошибка: он указан явно.
+    pub required: bool,
+    /// Переопределения в порядке применения.
+    pub overrides: Vec<(String, String)>,
+}
+
+impl SettingsSource {
+    pub fn new() -> SettingsSource {
+        SettingsSource::default()
+    }
+
+    /// Файл, который должен существовать.
+    pub fn file(self, path: impl Into<PathBuf>) -> SettingsSource {
+        SettingsSource {
+            file: Some(path.into()),
+            required: true,
+            ..self
+        }
+    }
+
+    /// Файл по умолчанию, которого может и не быть.
+    pub fn default_file(self, path: impl Into<PathBuf>) -> SettingsSource {
+        SettingsSource {
+            file: Some(path.into()),
+            required: false,
+            ..self
+        }
+    }
+
+    /// Переменные окружения `BITTORRENT_<КЛЮЧ>`, где точки в ключе заменены
+    /// на `_`. Остальные переменные пропускаются.
+    pub fn env(mut self, vars: impl IntoIterator<Item = (String, String)>) -> SettingsSource {
+        for (name, value) in vars {
+            let key = KEYS.iter().find(|key| {
+                name.strip_prefix(ENV_PREFIX)
+                    .is_some_and(|n| n == key.to_uppercase().replace('.', "_"))
+            });
+            if let Some(key) = key {
+                self.overrides.push((key.to_string(), value));
+            }
+        }
+        self
+    }
+
+    pub fn set(mut self, key: impl Into<String>, value: impl Into<String>) -> SettingsSource {
+        self.overrides.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn load(&self) -> Result<Settings, SettingsError> {
+        let mut settings = Settings::default();
+        if let Some(path) = &self.file {
+            match std::fs::read_to_string(path) {
+                Ok(text) => apply_toml(&mut settings, path, &text)?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound && !self.required => {}
+                Err(e) => return Err(SettingsError::Io(path.clone(), e)),
+            }
+        }
+        for (key, value) in &self.overrides {
+            settings.set(key, value)?;
+        }
+        if settings.download_dir.is_relative() {
+            settings.set("download_dir", ".")?;
+        }
+        settings.validate()?;
+        Ok(settings)
+    }
+}
+
+/// Ключи верхнего уровня и таблицы `[limits]`, `[protocols]`.
+fn apply_toml(settings: &mut Settings, path: &Path, text: &str) -> Result<(), SettingsError> {
+    let document: DocumentMut = text.parse().map_err(|e: toml_edit::TomlError| {
+        SettingsError::Syntax(path.to_path_buf(), e.to_string())
+    })?;
+    for (name, item) in document.iter() {
+        let entries: Vec<(String, &toml_edit::Item)> = match item.as_table_like() {
+            Some(table) => table
+                .iter()
+                .map(|(key, item)| (format!("{name}.{key}"), item))
+                .collect(),
+            None => vec![(name.to_string(), item)],
+        };
+        for (key, item) in entries {
+            let value = if let Some(s) = item.as_str() {
+                s.to_string()
+            } else if let Some(n) = item.as_integer() {
+                n.to_string()
+            } else if let Some(b) = item.as_bool() {
+                b.to_string()
+            } else if let Some(f) = item.as_float() {
+                f.to_string()
+            } else {
+                return Err(SettingsError::invalid(
+                    &key,
+                    format!("unsupported value of type {}", item.type_name()),
+                ));
+            };
+            settings.set(&key, &value)?;
+        }
+    }
+    Ok(())
+}
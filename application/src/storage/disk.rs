@@ -0,0 +1,617 @@
+/// Модуль с дисковой подсистемой: чтение, запись и проверка кусков в
+/// отдельных потоках, чтобы не останавливать асинхронный рантайм.
+///
+/// Блоки копятся в кеше записи и уходят на диск целым куском после
+/// проверки хеша. Когда кеш полон, новые куски ждут места, а самый давний
+/// недокачанный кусок вытесняется на диск. Для раздачи куски читаются с
+/// упреждением в кеш чтения.
+///
+/// Соединений с пирами в клиенте пока нет, так что блоки сюда никто не
+/// пишет: демон только заводит подсистему, меняет размеры кешей и
+/// показывает счётчики, а признак `congested` никто не слушает.
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, MutexGuard,
+    },
+    thread,
+};
+
+use tokio::sync::{oneshot, Notify};
+
+use crate::{
+    repository::{merkle::BLOCK_SIZE, types::TorrentMetadata, Id},
+    settings::DiskCache,
+    storage::Storage,
+};
+
+const BLOCK: usize = BLOCK_SIZE as usize;
+/// Сколько заданий на поток может ждать, пока диск не считается отстающим.
+const QUEUE_PER_THREAD: usize = 8;
+
+type Job = Box<dyn FnOnce() + Send>;
+type Key = (Id, usize);
+
+/// Торрент, с которым работают потоки.
+#[derive(Clone)]
+struct Target {
+    storage: Arc<Storage>,
+    metadata: Arc<TorrentMetadata>,
+}
+
+/// Недокачанный кусок в кеше записи.
+struct Pending {
+    /// `None`, если уже полученные блоки вытеснены на диск.
+    data: Option<Vec<u8>>,
+    blocks: Vec<bool>,
+    left: usize,
+    /// Блоки сейчас пишутся на диск.
+    spilling: bool,
+    used: u64,
+}
+
+struct Cached {
+    data: Vec<u8>,
+    used: u64,
+}
+
+/// Счётчики работы с диском.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskStats {
+    /// Байт занято в кешах.
+    pub write_cache: u64,
+    pub read_cache: u64,
+    /// Недокачанных кусков в кеше записи.
+    pub pending_pieces: usize,
+    /// Заданий в очереди и в работе.
+    pub queue_depth: usize,
+    pub read_hits: u64,
+    pub read_misses: u64,
+    /// Кусков, прочитанных с упреждением.
+    pub read_ahead: u64,
+    pub pieces_written: u64,
+    pub hash_failures: u64,
+    /// Кусков, вытесненных на диск недокачанными.
+    pub spilled: u64,
+    /// Диск не успевает, и новые блоки лучше не запрашивать.
+    pub congested: bool,
+}
+
+struct Inner {
+    torrents: HashMap<Id, Target>,
+    pending: HashMap<Key, Pending>,
+    read: HashMap<Key, Cached>,
+    cache: DiskCache,
+    write_used: u64,
+    read_used: u64,
+    /// Часы для вытеснения давно не нужных кусков.
+    clock: u64,
+    stats: DiskStats,
+}
+
+impl Inner {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Кладёт кусок в кеш чтения, вытесняя самые давние.
+    fn cache_read(&mut self, key: Key, data: Vec<u8>) {
+        self.uncache_read(&key);
+        if data.len() as u64 > self.cache.read_cache {
+            return;
+        }
+        self.read_used += data.len() as u64;
+        let used = self.tick();
+        self.read.insert(key, Cached { data, used });
+        self.shrink_read();
+    }
+
+    fn uncache_read(&mut self, key: &Key) {
+        if let Some(old) = self.read.remove(key) {
+            self.read_used -= old.data.len() as u64;
+        }
+    }
+
+    fn shrink_read(&mut self) {
+        while self.read_used > self.cache.read_cache {
+            let Some(oldest) = self
+                .read
+                .iter()
+                .min_by_key(|(_, c)| c.used)
+                .map(|(k, _)| *k)
+            else {
+                break;
+            };
+            self.uncache_read(&oldest);
+        }
+    }
+}
+
+struct Shared {
+    inner: Mutex<Inner>,
+    queued: AtomicUsize,
+    threads: usize,
+    /// Будит ждущих места в кеше и очереди.
+    room: Notify,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn congested(&self, inner: &Inner) -> bool {
+        inner.write_used >= inner.cache.write_cache
+            || self.queued.load(Ordering::Acquire) >= self.threads * QUEUE_PER_THREAD
+    }
+}
+
+/// Что делать с пришедшим блоком.
+enum Step {
+    Done(Option<bool>),
+    Wait,
+    Flush(Vec<u8>),
+    Direct,
+    Spill(Key, Vec<u8>, Vec<bool>),
+}
+
+/// Дисковая подсистема клиента. Демон держит её через `Arc`.
+pub struct DiskIo {
+    shared: Arc<Shared>,
+    jobs: mpsc::Sender<Job>,
+}
+
+impl DiskIo {
+    /// Запускает `threads` потоков. Они завершаются вместе с `DiskIo`,
+    /// доделав начатое.
+    pub fn new(threads: usize, cache: &DiskCache) -> io::Result<DiskIo> {
+        let threads = threads.max(1);
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..threads {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("disk-{i}"))
+                .spawn(move || worker(&receiver))?;
+        }
+        Ok(DiskIo {
+            shared: Arc::new(Shared {
+                inner: Mutex::new(Inner {
+                    torrents: HashMap::new(),
+                    pending: HashMap::new(),
+                    read: HashMap::new(),
+                    cache: cache.clone(),
+                    write_used: 0,
+                    read_used: 0,
+                    clock: 0,
+                    stats: DiskStats::default(),
+                }),
+                queued: AtomicUsize::new(0),
+                threads,
+                room: Notify::new(),
+            }),
+            jobs,
+        })
+    }
+
+    /// Меняет размеры кешей на ходу.
+    pub fn set_cache(&self, cache: &DiskCache) {
+        let mut inner = self.shared.lock();
+        inner.cache = cache.clone();
+        inner.shrink_read();
+        drop(inner);
+        self.shared.room.notify_waiters();
+    }
+
+    /// Регистрирует торрент или обновляет его раскладку по файлам.
+    pub fn add_torrent(&self, id: Id, storage: Storage, metadata: TorrentMetadata) {
+        let target = Target {
+            storage: Arc::new(storage),
+            metadata: Arc::new(metadata),
+        };
+        let mut inner = self.shared.lock();
+        inner.torrents.insert(id, target);
+        // Файлы могли переехать
+        let stale: Vec<Key> = inner.read.keys().filter(|k| k.0 == id).copied().collect();
+        for key in stale {
+            inner.uncache_read(&key);
+        }
+    }
+
+    /// Забывает торрент. Недокачанные куски в памяти пропадают.
+    pub fn remove_torrent(&self, id: Id) {
+        let mut inner = self.shared.lock();
+        inner.torrents.remove(&id);
+        let keys: Vec<Key> = inner
+            .pending
+            .keys()
+            .filter(|k| k.0 == id)
+            .copied()
+            .collect();
+        for key in keys {
+            let piece = inner.pending.remove(&key).unwrap();
+            // Память вытесняемого куска освободит задание вытеснения
+            if let Some(data) = piece.data.filter(|_| !piece.spilling) {
+                inner.write_used -= data.len() as u64;
+            }
+        }
+        let keys: Vec<Key> = inner.read.keys().filter(|k| k.0 == id).copied().collect();
+        for key in keys {
+            inner.uncache_read(&key);
+        }
+        drop(inner);
+        self.shared.room.notify_waiters();
+    }
+
+    /// Принимает блок куска. Когда кусок собран, он проверяется и
+    /// пишется на диск: возвращается `Some` с результатом проверки. Если
+    /// в кеше нет места для нового куска, ждёт, пока диск не догонит.
+    pub async fn write_block(
+        &self,
+        torrent: Id,
+        index: usize,
+        begin: usize,
+        block: Vec<u8>,
+    ) -> io::Result<Option<bool>> {
+        let target = self.target(torrent)?;
+        let size = target.storage.piece_size(index) as usize;
+        if index >= target.storage.piece_count()
+            || !begin.is_multiple_of(BLOCK)
+            || begin >= size
+            || block.len() != BLOCK.min(size - begin)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "block does not fit the piece",
+            ));
+        }
+        let key = (torrent, index);
+        let slot = begin / BLOCK;
+
+        loop {
+            let notified = self.shared.room.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let step = self.place(key, size, slot, &block);
+            match step {
+                Step::Done(result) => return Ok(result),
+                Step::Wait => notified.await,
+                Step::Flush(data) => return self.flush(key, target, data).await.map(Some),
+                Step::Direct => {
+                    let written = target.clone();
+                    self.run(move |_| written.storage.write_blocking(index, begin, &block))
+                        .await??;
+                    return self.mark_on_disk(key, slot, target).await;
+                }
+                Step::Spill(victim, data, blocks) => self.spill(victim, data, blocks).await?,
+            }
+        }
+    }
+
+    /// Кладёт блок в кеш, если можно, и решает, что делать дальше.
+    fn place(&self, key: Key, size: usize, slot: usize, block: &[u8]) -> Step {
+        let mut inner = self.shared.lock();
+        let used = inner.tick();
+        if let Some(piece) = inner.pending.get_mut(&key) {
+            piece.used = used;
+            if piece.blocks[slot] {
+                return Step::Done(None);
+            }
+            if piece.spilling {
+                return Step::Wait;
+            }
+            let Some(data) = &mut piece.data else {
+                return Step::Direct;
+            };
+            let begin = slot * BLOCK;
+            data[begin..begin + block.len()].copy_from_slice(block);
+            piece.blocks[slot] = true;
+            piece.left -= 1;
+            if piece.left > 0 {
+                return Step::Done(None);
+            }
+            let piece = inner.pending.remove(&key).unwrap();
+            return Step::Flush(piece.data.unwrap());
+        }
+
+        if inner.write_used == 0 || inner.write_used + size as u64 <= inner.cache.write_cache {
+            let blocks = size.div_ceil(BLOCK);
+            inner.write_used += size as u64;
+            inner.uncache_read(&key);
+            inner.pending.insert(
+                key,
+                Pending {
+                    data: Some(vec![0; size]),
+                    blocks: vec![false; blocks],
+                    left: blocks,
+                    spilling: false,
+                    used,
+                },
+            );
+            drop(inner);
+            return self.place(key, size, slot, block);
+        }
+
+        // Места нет: на диск уходит самый давний недокачанный кусок
+        let victim = inner
+            .pending
+            .iter()
+            .filter(|(_, p)| p.data.is_some() && !p.spilling)
+            .min_by_key(|(_, p)| p.used)
+            .map(|(k, _)| *k);
+        let Some(victim) = victim else {
+            return Step::Wait;
+        };
+        let piece = inner.pending.get_mut(&victim).unwrap();
+        piece.spilling = true;
+        Step::Spill(victim, piece.data.take().unwrap(), piece.blocks.clone())
+    }
+
+    /// Проверяет собранный кусок и пишет его на диск.
+    async fn flush(&self, key: Key, target: Target, data: Vec<u8>) -> io::Result<bool> {
+        let (torrent, index) = key;
+        self.run(move |shared| {
+            let size = data.len() as u64;
+            let valid = target.storage.piece_matches(&target.metadata, index, &data);
+            let written = match valid {
+                true => target.storage.write_blocking(index, 0, &data),
+                false => Ok(()),
+            };
+            let mut inner = shared.lock();
+            inner.write_used -= size;
+            match &written {
+                Ok(()) if valid => {
+                    inner.stats.pieces_written += 1;
+                    if inner.torrents.contains_key(&torrent) {
+                        inner.cache_read(key, data);
+                    }
+                }
+                Ok(()) => inner.stats.hash_failures += 1,
+                Err(_) => {}
+            }
+            written.map(|()| valid)
+        })
+        .await?
+    }
+
+    /// Отмечает блок, записанный мимо кеша. Если это последний блок,
+    /// проверяет кусок по данным с диска.
+    async fn mark_on_disk(
+        &self,
+        key: Key,
+        slot: usize,
+        target: Target,
+    ) -> io::Result<Option<bool>> {
+        {
+            let mut inner = self.shared.lock();
+            let Some(piece) = inner.pending.get_mut(&key) else {
+                return Ok(None);
+            };
+            if piece.blocks[slot] {
+                return Ok(None);
+            }
+            piece.blocks[slot] = true;
+            piece.left -= 1;
+            if piece.left > 0 {
+                return Ok(None);
+            }
+            inner.pending.remove(&key);
+        }
+        let (torrent, index) = key;
+        self.run(move |shared| {
+            let mut data = vec![0; target.storage.piece_size(index) as usize];
+            target.storage.read_blocking(index, 0, &mut data)?;
+            let valid = target.storage.piece_matches(&target.metadata, index, &data);
+            let mut inner = shared.lock();
+            if !valid {
+                inner.stats.hash_failures += 1;
+            } else if inner.torrents.contains_key(&torrent) {
+                inner.stats.pieces_written += 1;
+                inner.cache_read(key, data);
+            }
+            Ok(Some(valid))
+        })
+        .await?
+    }
+
+    /// Пишет полученные блоки недокачанного куска на диск, освобождая
+    /// кеш. Если записать не вышло, кусок придётся качать заново.
+    async fn spill(&self, key: Key, data: Vec<u8>, blocks: Vec<bool>) -> io::Result<()> {
+        let target = self.target(key.0);
+        self.run(move |shared| {
+            let written = target.and_then(|target| {
+                blocks
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, have)| **have)
+                    .try_for_each(|(slot, _)| {
+                        let begin = slot * BLOCK;
+                        let end = (begin + BLOCK).min(data.len());
+                        target
+                            .storage
+                            .write_blocking(key.1, begin, &data[begin..end])
+                    })
+            });
+            let mut inner = shared.lock();
+            inner.write_used -= data.len() as u64;
+            inner.stats.spilled += 1;
+            match &written {
+                Ok(()) => {
+                    if let Some(piece) = inner.pending.get_mut(&key) {
+                        piece.spilling = false;
+                    }
+                }
+                Err(_) => {
+                    inner.pending.remove(&key);
+                }
+            }
+            written
+        })
+        .await?
+    }
+
+    /// Читает `len` байт куска с `begin`: из кеша или с диска вместе со
+    /// следующими кусками.
+    pub async fn read_block(
+        &self,
+        torrent: Id,
+        index: usize,
+        begin: usize,
+        len: usize,
+    ) -> io::Result<Vec<u8>> {
+        let target = self.target(torrent)?;
+        let count = target.storage.piece_count();
+        if index >= count || begin + len > target.storage.piece_size(index) as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "range is out of the piece",
+            ));
+        }
+
+        let pieces: Vec<usize> = {
+            let mut inner = self.shared.lock();
+            let used = inner.tick();
+            if let Some(cached) = inner.read.get_mut(&(torrent, index)) {
+                cached.used = used;
+                let block = cached.data[begin..begin + len].to_vec();
+                inner.stats.read_hits += 1;
+                return Ok(block);
+            }
+            inner.stats.read_misses += 1;
+            if inner.cache.read_cache == 0 {
+                vec![]
+            } else {
+                let ahead = (index + 1..count).take(inner.cache.read_ahead);
+                std::iter::once(index)
+                    .chain(ahead.filter(|&i| {
+                        let key = (torrent, i);
+                        !inner.read.contains_key(&key) && !inner.pending.contains_key(&key)
+                    }))
+                    .collect()
+            }
+        };
+
+        if pieces.is_empty() {
+            return self
+                .run(move |_| {
+                    let mut block = vec![0; len];
+                    target
+                        .storage
+                        .read_blocking(index, begin, &mut block)
+                        .map(|()| block)
+                })
+                .await?;
+        }
+        self.run(move |shared| {
+            let mut read = vec![];
+            for i in pieces {
+                let mut data = vec![0; target.storage.piece_size(i) as usize];
+                match target.storage.read_blocking(i, 0, &mut data) {
+                    Ok(()) => read.push((i, data)),
+                    Err(e) if i == index => return Err(e),
+                    // Дальше может не быть скачанных данных
+                    Err(_) => break,
+                }
+            }
+            let block = read[0].1[begin..begin + len].to_vec();
+            let mut inner = shared.lock();
+            if inner.torrents.contains_key(&torrent) {
+                inner.stats.read_ahead += read.len() as u64 - 1;
+                for (i, data) in read {
+                    inner.cache_read((torrent, i), data);
+                }
+            }
+            Ok(block)
+        })
+        .await?
+    }
+
+    /// Сверяет кусок на диске с хешами.
+    pub async fn verify_piece(&self, torrent: Id, index: usize) -> io::Result<bool> {
+        let target = self.target(torrent)?;
+        self.run(move |_| {
+            let mut data = vec![0; target.storage.piece_size(index) as usize];
+            target.storage.read_blocking(index, 0, &mut data)?;
+            Ok(target.storage.piece_matches(&target.metadata, index, &data))
+        })
+        .await?
+    }
+
+    /// Не успевает ли диск. Пока да, загрузке не стоит запрашивать новые
+    /// блоки.
+    pub fn congested(&self) -> bool {
+        self.shared.congested(&self.shared.lock())
+    }
+
+    /// Ждёт, пока диск не догонит.
+    pub async fn writable(&self) {
+        loop {
+            let notified = self.shared.room.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if !self.congested() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    pub fn stats(&self) -> DiskStats {
+        let inner = self.shared.lock();
+        DiskStats {
+            write_cache: inner.write_used,
+            read_cache: inner.read_used,
+            pending_pieces: inner.pending.len(),
+            queue_depth: self.shared.queued.load(Ordering::Acquire),
+            congested: self.shared.congested(&inner),
+            ..inner.stats
+        }
+    }
+
+    fn target(&self, torrent: Id) -> io::Result<Target> {
+        self.shared
+            .lock()
+            .torrents
+            .get(&torrent)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "torrent is not registered"))
+    }
+
+    /// Выполняет `work` в дисковом потоке. Задание доводится до конца,
+    /// даже если результат больше не ждут.
+    async fn run<T: Send + 'static>(
+        &self,
+        work: impl FnOnce(&Shared) -> T + Send + 'static,
+    ) -> io::Result<T> {
+        let (sender, receiver) = oneshot::channel();
+        let shared = self.shared.clone();
+        shared.queued.fetch_add(1, Ordering::AcqRel);
+        let job: Job = Box::new(move || {
+            let result = work(&shared);
+            shared.queued.fetch_sub(1, Ordering::AcqRel);
+            shared.room.notify_waiters();
+            let _ = sender.send(result);
+        });
+        // Потоки живут, пока жив отправитель, но на всякий случай
+        if let Err(mpsc::SendError(job)) = self.jobs.send(job) {
+            job();
+        }
+        receiver
+            .await
+            .map_err(|_| io::Error::other("disk job failed"))
+    }
+}
+
+fn worker(jobs: &Mutex<mpsc::Receiver<Job>>) {
+    loop {
+        let job = jobs.lock().unwrap_or_else(|e| e.into_inner()).recv();
+        match job {
+            Ok(job) => job(),
+            Err(_) => return,
+        }
+    }
+}
@@ -0,0 +1,697 @@
+pub mod disk;
+pub mod sanitize;
+
+/// Модуль, раскладывающий куски торрента по файлам на диске.
+///
+/// Файлы выравнивания (BEP 47) на диск не пишутся и при чтении дают нули.
+/// Ссылки создаются как ссылки, а у исполняемых файлов выставляются права.
+use std::{
+    collections::HashMap,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use sha1::{Digest, Sha1};
+use tokio::{
+    fs::{self, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+use crate::{
+    repository::{
+        merkle,
+        resume::{unix_time, FileState},
+        types::{FileAttributes, FileTreeNode, FilesMetadata, Info, Priority, TorrentMetadata},
+    },
+    tools::get_bit,
+};
+
+use self::sanitize::PathChange;
+
+/// Файл в общем потоке байтов торрента.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageFile {
+    /// Путь от каталога загрузки, первым идёт имя торрента.
+    pub path: Vec<String>,
+    pub offset: u64,
+    pub length: u64,
+    pub attr: Option<FileAttributes>,
+    pub symlink_path: Option<Vec<String>>,
+    pub pieces_root: Option<[u8; 32]>,
+}
+
+impl StorageFile {
+    pub fn is_padding(&self) -> bool {
+        self.attr.as_ref().is_some_and(FileAttributes::is_padding)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.attr.as_ref().is_some_and(FileAttributes::is_symlink) && self.symlink_path.is_some()
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.attr
+            .as_ref()
+            .is_some_and(FileAttributes::is_executable)
+    }
+
+    /// Есть ли у файла данные на диске.
+    fn has_data(&self) -> bool {
+        !self.is_padding() && !self.is_symlink()
+    }
+
+    fn end(&self) -> u64 {
+        self.offset + self.length
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Storage {
+    root: PathBuf,
+    files: Vec<StorageFile>,
+    piece_length: u64,
+    total_length: u64,
+    // В торрентах только v2 кусок никогда не захватывает два файла
+    aligned: bool,
+    path_changes: Vec<PathChange>,
+    priorities: Vec<Priority>,
+    /// Куски пропущенных файлов, попавшие в нужные куски. Лежат по своим
+    /// смещениям в общем потоке торрента.
+    parts: PathBuf,
+}
+
+impl Storage {
+    /// Раскладывает файлы торрента в `root`. Пути из торрента приводятся
+    /// к безопасному виду, изменения доступны через `path_changes`.
+    pub fn new(info: &Info, root: impl Into<PathBuf>) -> Storage {
+        let mut files = vec![];
+        let mut offset = 0;
+
+        match &info.files {
+            FilesMetadata::Single { name, length, .. } => files.push(StorageFile {
+                path: vec![name.clone()],
+                offset,
+                length: *length,
+                attr: None,
+                symlink_path: None,
+                pieces_root: None,
+            }),
+            FilesMetadata::Multiple { files: list, .. } => {
+                for f in list {
+                    files.push(StorageFile {
+                        path: f.path.clone(),
+                        offset,
+                        length: f.length,
+                        attr: f.attr.clone(),
+                        symlink_path: f.symlink_path.clone(),
+                        pieces_root: None,
+                    });
+                    offset += f.length;
+                }
+            }
+            FilesMetadata::Tree { name } => {
+                for f in info.tree_files() {
+                    // Торрент из одного файла хранит в дереве только его
+                    let path = if single_file_tree(info) {
+                        vec![name.clone()]
+                    } else {
+                        f.path
+                    };
+                    files.push(StorageFile {
+                        path,
+                        offset,
+                        length: f.length,
+                        attr: f.attr,
+                        symlink_path: None,
+                        pieces_root: f.pieces_root,
+                    });
+                    offset += f.length.div_ceil(info.piece_length) * info.piece_length;
+                }
+            }
+        }
+
+        let dir = match &info.files {
+            FilesMetadata::Multiple { base_name, .. } => Some(base_name.as_str()),
+            FilesMetadata::Tree { name } if !single_file_tree(info) => Some(name.as_str()),
+            _ => None,
+        };
+        let paths: Vec<_> = files
+            .iter()
+            .map(|f| (f.path.clone(), f.is_padding()))
+            .collect();
+        let sanitized = sanitize::sanitize_paths(dir, &paths);
+
+        // Цели ссылок указывают на файлы торрента и переименовываются вместе с ними
+        let renamed: HashMap<Vec<String>, Vec<String>> = paths
+            .into_iter()
+            .map(|(path, _)| path)
+            .zip(
+                sanitized
+                    .paths
+                    .iter()
+                    .map(|p| p[dir.map_or(0, |_| 1)..].to_vec()),
+            )
+            .collect();
+        for (file, path) in files.iter_mut().zip(sanitized.paths) {
+            file.path = path;
+            file.symlink_path = file.symlink_path.take().map(|target| {
+                renamed
+                    .get(&target)
+                    .cloned()
+                    .unwrap_or_else(|| sanitize::sanitize_path(&target, &mut vec![]))
+            });
+        }
+
+        let total_length = files.last().map_or(0, StorageFile::end);
+        let root = root.into();
+        let parts = root.join(format!(
+            ".{}.parts",
+            files.first().map_or("torrent", |f| f.path[0].as_str())
+        ));
+        Storage {
+            root,
+            files,
+            piece_length: info.piece_length,
+            total_length,
+            aligned: matches!(info.files, FilesMetadata::Tree { .. }),
+            path_changes: sanitized.changes,
+            priorities: vec![],
+            parts,
+        }
+    }
+
+    /// Приоритеты, с которыми данные уже разложены на диске, например
+    /// сохранённые в репозитории. Данные не переносятся: чтобы сменить
+    /// приоритеты, нужен `set_priorities`.
+    pub fn with_priorities(self, priorities: Vec<Priority>) -> Storage {
+        Storage { priorities, ..self }
+    }
+
+    pub fn priority(&self, file: usize) -> Priority {
+        self.priorities.get(file).copied().unwrap_or_default()
+    }
+
+    fn skipped(&self, file: usize) -> bool {
+        self.priority(file) == Priority::Skip
+    }
+
+    /// Меняет приоритеты и переносит края файлов между файлом и файлом
+    /// частей: данные пропущенного файла из соседних кусков живут там.
+    pub async fn set_priorities(&mut self, priorities: Vec<Priority>) -> io::Result<()> {
+        let old = std::mem::replace(&mut self.priorities, priorities);
+        let was_skipped = |i: usize| old.get(i) == Some(&Priority::Skip);
+
+        for i in 0..self.files.len() {
+            let file = &self.files[i];
+            if !file.has_data() || file.length == 0 || was_skipped(i) == self.skipped(i) {
+                continue;
+            }
+            let path = self.file_path(file);
+            let (from, to) = if self.skipped(i) {
+                (path, self.parts.clone())
+            } else {
+                self.materialize_file(file).await?;
+                (self.parts.clone(), path)
+            };
+
+            for (start, end) in self.edges(file) {
+                let mut buf = vec![0; (end - start) as usize];
+                // В файле смещения от его начала, в файле частей — от начала потока
+                let (from_at, to_at) = if self.skipped(i) {
+                    (start - file.offset, start)
+                } else {
+                    (start, start - file.offset)
+                };
+                read_at(&from, from_at, &mut buf).await?;
+                write_at(&to, to_at, &buf).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Части файла в его первом и последнем кусках, в координатах потока.
+    fn edges(&self, file: &StorageFile) -> Vec<(u64, u64)> {
+        let first = file.offset / self.piece_length;
+        let last = (file.end() - 1) / self.piece_length;
+        let mut edges: Vec<_> = [first, last]
+            .iter()
+            .map(|p| {
+                let start = (p * self.piece_length).max(file.offset);
+                let end = ((p + 1) * self.piece_length).min(file.end());
+                (start, end)
+            })
+            .collect();
+        edges.dedup();
+        edges
+    }
+
+    /// Где лежит часть файла `file` из куска `index`: края пропущенного
+    /// файла — в файле частей по смещению в потоке, остальное — в самом
+    /// файле, даже если он пропущен.
+    fn location(&self, file: usize, index: usize, within: u64) -> (PathBuf, u64) {
+        let f = &self.files[file];
+        let index = index as u64;
+        let edge =
+            index == f.offset / self.piece_length || index == (f.end() - 1) / self.piece_length;
+        if self.skipped(file) && edge {
+            (self.parts.clone(), f.offset + within)
+        } else {
+            (self.file_path(f), within)
+        }
+    }
+
+    /// Приоритет куска: наибольший из приоритетов файлов с данными в нём.
+    pub fn piece_priorities(&self) -> Vec<Priority> {
+        (0..self.piece_count())
+            .map(|index| {
+                self.spans(index)
+                    .iter()
+                    .filter(|(file, ..)| self.files[*file].has_data())
+                    .map(|(file, ..)| self.priority(*file))
+                    .max()
+                    .unwrap_or(Priority::Skip)
+            })
+            .collect()
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.total_length.div_ceil(self.piece_length) as usize
+    }
+
+    /// Сколько байтов нужных файлов ещё не скачано: это `left` для трекера.
+    pub fn left(&self, have: &[u8]) -> u64 {
+        (0..self.piece_count())
+            .filter(|&index| !get_bit(have, index))
+            .flat_map(|index| self.spans(index))
+            .filter(|(file, ..)| self.files[*file].has_data() && !self.skipped(*file))
+            .map(|(.., len)| len as u64)
+            .sum()
+    }
+
+    /// Сколько байтов каждого файла уже скачано, в порядке `files`.
+    pub fn file_progress(&self, have: &[u8]) -> Vec<u64> {
+        let mut done = vec![0; self.files.len()];
+        for index in (0..self.piece_count()).filter(|&index| get_bit(have, index)) {
+            for (file, .., len) in self.spans(index) {
+                done[file] += len as u64;
+            }
+        }
+        done
+    }
+
+    /// Пути, которые пришлось исправить при создании хранилища.
+    pub fn path_changes(&self) -> &[PathChange] {
+        &self.path_changes
+    }
+
+    pub fn files(&self) -> &[StorageFile] {
+        &self.files
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Размеры и времена изменения файлов для проверки данных возобновления.
+    pub async fn file_states(&self) -> io::Result<Vec<FileState>> {
+        let mut states = vec![];
+        for file in &self.files {
+            let state = match fs::symlink_metadata(self.file_path(file)).await {
+                Ok(_) if file.is_padding() => FileState::default(),
+                Ok(meta) => FileState {
+                    size: meta.len(),
+                    mtime: meta.modified().map_or(0, unix_time),
+                },
+                Err(e) if e.kind() == io::ErrorKind::NotFound => FileState::default(),
+                Err(e) => return Err(e),
+            };
+            states.push(state);
+        }
+        Ok(states)
+    }
+
+    pub fn file_path(&self, file: &StorageFile) -> PathBuf {
+        let mut path = self.root.clone();
+        path.extend(&file.path);
+        path
+    }
+
+    /// Удаляет скачанные файлы, файл частей и опустевшие каталоги торрента.
+    /// Корень загрузки остаётся на месте.
+    pub async fn remove_data(&self) -> io::Result<()> {
+        let mut dirs = vec![];
+        for file in self.files.iter().filter(|f| !f.is_padding()) {
+            let path = self.file_path(file);
+            remove_if_exists(&path).await?;
+            let mut dir = path.parent();
+            while let Some(d) = dir.filter(|d| *d != self.root && d.starts_with(&self.root)) {
+                dirs.push(d.to_path_buf());
+                dir = d.parent();
+            }
+        }
+        remove_if_exists(&self.parts).await?;
+
+        // Сначала самые глубокие; непустые каталоги не трогаются
+        dirs.sort();
+        dirs.dedup();
+        dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+        for dir in dirs {
+            let _ = fs::remove_dir(&dir).await;
+        }
+        Ok(())
+    }
+
+    /// Создаёт каталоги, файлы нужной длины и ссылки. Пропущенные файлы
+    /// не создаются.
+    pub async fn materialize(&self) -> io::Result<()> {
+        for (i, file) in self.files.iter().enumerate() {
+            if !file.is_padding() && !self.skipped(i) {
+                self.materialize_file(file).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn materialize_file(&self, file: &StorageFile) -> io::Result<()> {
+        let path = self.file_path(file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        if let Some(target) = file.symlink_path.as_ref().filter(|_| file.is_symlink()) {
+            if fs::symlink_metadata(&path).await.is_err() {
+                create_symlink(&file.path, target, &path).await?;
+            }
+            return Ok(());
+        }
+
+        let handle = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .await?;
+        if handle.metadata().await?.len() != file.length {
+            handle.set_len(file.length).await?;
+        }
+        if file.is_executable() {
+            set_executable(&path).await?;
+        }
+        Ok(())
+    }
+
+    /// Длина куска с учётом последнего куска и выравнивания v2.
+    pub fn piece_size(&self, index: usize) -> u64 {
+        let start = index as u64 * self.piece_length;
+        let end = if self.aligned {
+            self.files
+                .iter()
+                .find(|f| f.offset <= start && start < f.end())
+                .map_or(start, StorageFile::end)
+        } else {
+            self.total_length
+        };
+        self.piece_length.min(end.saturating_sub(start))
+    }
+
+    /// Части куска, попадающие в файлы: номер файла, смещение в нём,
+    /// смещение в куске и длина.
+    fn spans(&self, index: usize) -> Vec<(usize, u64, usize, usize)> {
+        self.range_spans(index, 0, self.piece_size(index) as usize)
+    }
+
+    /// То же для `len` байт куска с `begin`; смещения — от `begin`.
+    fn range_spans(
+        &self,
+        index: usize,
+        begin: usize,
+        len: usize,
+    ) -> Vec<(usize, u64, usize, usize)> {
+        let start = index as u64 * self.piece_length + begin as u64;
+        let end = start + len as u64;
+        let first = self.files.partition_point(|f| f.end() <= start);
+
+        self.files[first..]
+            .iter()
+            .enumerate()
+            .take_while(|(_, f)| f.offset < end)
+            .filter(|(_, f)| f.length > 0)
+            .map(|(i, f)| {
+                let from = start.max(f.offset);
+                let to = end.min(f.end());
+                (
+                    first + i,
+                    from - f.offset,
+                    (from - start) as usize,
+                    (to - from) as usize,
+                )
+            })
+            .collect()
+    }
+
+    pub async fn read_piece(&self, index: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; self.piece_size(index) as usize];
+
+        for (i, within, at, len) in self.spans(index) {
+            if !self.files[i].has_data() {
+                continue;
+            }
+            let (path, within) = self.location(i, index, within);
+            // Пропущенного файла может не быть на диске
+            if self.skipped(i) {
+                read_at(&path, within, &mut buf[at..at + len]).await?;
+                continue;
+            }
+            let mut handle = fs::File::open(path).await?;
+            handle.seek(SeekFrom::Start(within)).await?;
+            handle.read_exact(&mut buf[at..at + len]).await?;
+        }
+        Ok(buf)
+    }
+
+    pub async fn write_piece(&self, index: usize, data: &[u8]) -> io::Result<()> {
+        if data.len() as u64 != self.piece_size(index) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "piece has a wrong length",
+            ));
+        }
+
+        for (i, within, at, len) in self.spans(index) {
+            if !self.files[i].has_data() {
+                continue;
+            }
+            let (path, within) = self.location(i, index, within);
+            write_at(&path, within, &data[at..at + len]).await?;
+        }
+        Ok(())
+    }
+
+    /// Сверяет кусок на диске с хешами v1, а в торрентах только v2 — с деревом.
+    pub async fn verify_piece(&self, metadata: &TorrentMetadata, index: usize) -> io::Result<bool> {
+        let data = self.read_piece(index).await?;
+        Ok(self.piece_matches(metadata, index, &data))
+    }
+
+    /// Сверяет данные куска с хешами, не трогая диск.
+    pub fn piece_matches(&self, metadata: &TorrentMetadata, index: usize, data: &[u8]) -> bool {
+        if let Some(pieces) = &metadata.info.pieces {
+            let expected = pieces.get(index * 20..index * 20 + 20);
+            return expected == Some(&Sha1::digest(data)[..]);
+        }
+
+        let start = index as u64 * self.piece_length;
+        let Some(file) = self
+            .files
+            .iter()
+            .find(|f| f.offset <= start && start < f.end())
+        else {
+            return false;
+        };
+        let Some(root) = file.pieces_root else {
+            return false;
+        };
+        if file.length <= self.piece_length {
+            return merkle::file_root(data) == Some(root);
+        }
+
+        let in_file = ((start - file.offset) / self.piece_length) as usize;
+        let expected = metadata
+            .piece_layer(&root)
+            .and_then(|layer| layer.get(in_file).copied());
+        expected.is_some()
+            && expected
+                == merkle::piece_layer(data, self.piece_length)
+                    .first()
+                    .copied()
+    }
+
+    /// Читает `buf.len()` байт куска с `begin`. Блокирует поток, поэтому
+    /// вызывается из потоков `disk`, а не из асинхронного кода.
+    pub fn read_blocking(&self, index: usize, begin: usize, buf: &mut [u8]) -> io::Result<()> {
+        self.check_range(index, begin, buf.len())?;
+        for (i, within, at, len) in self.range_spans(index, begin, buf.len()) {
+            let part = &mut buf[at..at + len];
+            if !self.files[i].has_data() {
+                part.fill(0);
+                continue;
+            }
+            let (path, within) = self.location(i, index, within);
+            if self.skipped(i) {
+                blocking::read_at(&path, within, part)?;
+            } else {
+                let mut handle = std::fs::File::open(path)?;
+                handle.seek(SeekFrom::Start(within))?;
+                handle.read_exact(part)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Пишет `data` в кусок с `begin`. Блокирует поток, как `read_blocking`.
+    pub fn write_blocking(&self, index: usize, begin: usize, data: &[u8]) -> io::Result<()> {
+        self.check_range(index, begin, data.len())?;
+        for (i, within, at, len) in self.range_spans(index, begin, data.len()) {
+            if !self.files[i].has_data() {
+                continue;
+            }
+            let (path, within) = self.location(i, index, within);
+            blocking::write_at(&path, within, &data[at..at + len])?;
+        }
+        Ok(())
+    }
+
+    fn check_range(&self, index: usize, begin: usize, len: usize) -> io::Result<()> {
+        if index >= self.piece_count() || (begin + len) as u64 > self.piece_size(index) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "range is out of the piece",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Те же `write_at` и `read_at`, но на `std::fs`.
+mod blocking {
+    use std::{
+        fs::{File, OpenOptions},
+        io::{self, Read, Seek, SeekFrom, Write},
+        path::Path,
+    };
+
+    pub fn write_at(path: &Path, offset: u64, data: &[u8]) -> io::Result<()> {
+        let mut handle = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        handle.seek(SeekFrom::Start(offset))?;
+        handle.write_all(data)
+    }
+
+    pub fn read_at(path: &Path, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        buf.fill(0);
+        let mut handle = match File::open(path) {
+            Ok(handle) => handle,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        handle.seek(SeekFrom::Start(offset))?;
+        let mut read = 0;
+        while read < buf.len() {
+            match handle.read(&mut buf[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn single_file_tree(info: &Info) -> bool {
+    info.file_tree.as_ref().is_some_and(|tree| {
+        tree.len() == 1
+            && tree
+                .values()
+                .all(|node| matches!(node, FileTreeNode::File { .. }))
+    })
+}
+
+async fn write_at(path: &Path, offset: u64, data: &[u8]) -> io::Result<()> {
+    let mut handle = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .await?;
+    handle.seek(SeekFrom::Start(offset)).await?;
+    handle.write_all(data).await?;
+    // Иначе tokio может не дописать данные до закрытия файла
+    handle.flush().await
+}
+
+/// Читает, сколько есть. Отсутствующий файл и хвост за его концом — нули.
+async fn read_at(path: &Path, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+    buf.fill(0);
+    let mut handle = match fs::File::open(path).await {
+        Ok(handle) => handle,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    handle.seek(SeekFrom::Start(offset)).await?;
+    let mut read = 0;
+    while read < buf.len() {
+        match handle.read(&mut buf[read..]).await? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(())
+}
+
+/// Ссылка создаётся относительной, чтобы каталог можно было перенести.
+async fn create_symlink(link: &[String], target: &[String], path: &Path) -> io::Result<()> {
+    // Цель задана от корня торрента, а ссылка лежит на глубине `link.len() - 2`
+    let mut relative = PathBuf::new();
+    for _ in 2..link.len() {
+        relative.push("..");
+    }
+    relative.extend(target);
+
+    #[cfg(unix)]
+    {
+        fs::symlink(relative, path).await
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (relative, path);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "symlinks are not supported on this platform",
+        ))
+    }
+}
+
+async fn set_executable(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut permissions = fs::metadata(path).await?.permissions();
+        permissions.set_mode(permissions.mode() | 0o111);
+        fs::set_permissions(path, permissions).await
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(())
+    }
+}
+
+async fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
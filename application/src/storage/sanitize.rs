@@ -0,0 +1,305 @@
+/// Модуль, приводящий пути из торрента к безопасному виду.
+///
+/// Имена из .torrent не заслуживают доверия: `..`, абсолютные пути, NUL
+/// и зарезервированные в Windows имена могли бы вывести запись за пределы
+/// каталога загрузки. Опасные части переписываются, а каждое изменение
+/// попадает в отчёт.
+use std::{collections::HashSet, fmt::Display};
+
+/// Самая длинная часть пути в байтах, которую примет большинство ФС.
+pub const MAX_COMPONENT_LENGTH: usize = 255;
+
+/// Подставляется вместо части пути, от которой ничего не осталось.
+const PLACEHOLDER: &str = "_";
+
+const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Что было не так с путём.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Issue {
+    /// Пустая часть или `.`, она выброшена.
+    Empty,
+    /// Часть `..`, она выброшена.
+    ParentDir,
+    /// Часть начиналась с корня или буквы диска.
+    Absolute,
+    /// Разделители, управляющие или запрещённые в Windows символы.
+    InvalidChar,
+    /// Зарезервированное имя вроде `CON` или точка/пробел в конце.
+    Reserved,
+    /// Часть длиннее `MAX_COMPONENT_LENGTH`, она обрезана.
+    TooLong,
+    /// Путь совпал с другим файлом, к нему добавлен номер.
+    Duplicate,
+}
+
+impl Issue {
+    /// Мог ли путь без исправления оказаться вне каталога загрузки.
+    pub fn is_escape(&self) -> bool {
+        matches!(self, Issue::ParentDir | Issue::Absolute)
+    }
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Issue::Empty => "empty component",
+            Issue::ParentDir => "parent directory reference",
+            Issue::Absolute => "absolute path",
+            Issue::InvalidChar => "invalid character",
+            Issue::Reserved => "reserved name",
+            Issue::TooLong => "component is too long",
+            Issue::Duplicate => "duplicate path",
+        };
+        write!(f, "{text}")
+    }
+}
+
+/// Запись отчёта: путь до и после исправления.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathChange {
+    pub original: Vec<String>,
+    pub sanitized: Vec<String>,
+    pub issues: Vec<Issue>,
+}
+
+impl PathChange {
+    pub fn is_escape(&self) -> bool {
+        self.issues.iter().any(Issue::is_escape)
+    }
+}
+
+impl Display for PathChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let issues: Vec<String> = self.issues.iter().map(Issue::to_string).collect();
+        write!(
+            f,
+            "{:?} -> {:?} ({})",
+            self.original.join("/"),
+            self.sanitized.join("/"),
+            issues.join(", ")
+        )
+    }
+}
+
+/// Приводит одну часть пути к безопасному виду. `None` — часть выброшена.
+pub fn sanitize_component(component: &str, issues: &mut Vec<Issue>) -> Option<String> {
+    match component {
+        "" | "." => {
+            issues.push(Issue::Empty);
+            return None;
+        }
+        ".." => {
+            issues.push(Issue::ParentDir);
+            return None;
+        }
+        _ => {}
+    }
+
+    let mut rest = component;
+    let drive =
+        rest.len() >= 2 && rest.as_bytes()[0].is_ascii_alphabetic() && rest.as_bytes()[1] == b':';
+    if drive {
+        rest = &rest[2..];
+    }
+    let trimmed = rest.trim_start_matches(['/', '\\']);
+    if drive || trimmed.len() != rest.len() {
+        issues.push(Issue::Absolute);
+    }
+
+    let mut clean: String = trimmed
+        .chars()
+        .map(|c| {
+            if c.is_control() || RESERVED_CHARS.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    if clean != trimmed {
+        issues.push(Issue::InvalidChar);
+    }
+
+    // Windows молча отрезает точки и пробелы в конце имени
+    let end = clean.trim_end_matches(['.', ' ']).len();
+    if end != clean.len() {
+        issues.push(Issue::Reserved);
+        clean.truncate(end);
+    }
+    let stem = clean.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        issues.push(Issue::Reserved);
+        clean.insert(0, '_');
+    }
+
+    if clean.len() > MAX_COMPONENT_LENGTH {
+        issues.push(Issue::TooLong);
+        clean = truncate(&clean, MAX_COMPONENT_LENGTH);
+    }
+
+    match clean.as_str() {
+        "" => {
+            issues.push(Issue::Empty);
+            None
+        }
+        ".." => {
+            issues.push(Issue::ParentDir);
+            None
+        }
+        _ => Some(clean),
+    }
+}
+
+/// Обрезает имя до `max` байт, по возможности сохраняя расширение.
+fn truncate(name: &str, max: usize) -> String {
+    let ext = name
+        .rfind('.')
+        .map(|i| &name[i..])
+        .filter(|ext| ext.len() <= 16 && ext.len() < name.len());
+    let ext = ext.unwrap_or_default();
+
+    let mut end = max - ext.len();
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{ext}", &name[..end])
+}
+
+/// Приводит к безопасному виду путь целиком. Пустой путь становится `_`.
+pub fn sanitize_path(path: &[String], issues: &mut Vec<Issue>) -> Vec<String> {
+    let mut clean: Vec<String> = path
+        .iter()
+        .filter_map(|c| sanitize_component(c, issues))
+        .collect();
+    if clean.is_empty() {
+        clean.push(PLACEHOLDER.to_string());
+    }
+    clean
+}
+
+/// Ключ для сравнения путей: на части ФС регистр не различается.
+fn key(path: &[String]) -> Vec<String> {
+    path.iter().map(|c| c.to_lowercase()).collect()
+}
+
+/// Добавляет номер к последней части пути, сохраняя расширение.
+fn numbered(path: &[String], n: usize) -> Vec<String> {
+    let mut path = path.to_vec();
+    let last = path.last_mut().unwrap();
+    let suffix = format!("_{n}");
+    *last = match last.rfind('.').filter(|&i| i > 0) {
+        Some(i) => format!("{}{suffix}{}", &last[..i], &last[i..]),
+        None => format!("{last}{suffix}"),
+    };
+    if last.len() > MAX_COMPONENT_LENGTH {
+        *last = truncate(last, MAX_COMPONENT_LENGTH - suffix.len()) + &suffix;
+    }
+    path
+}
+
+/// Переименовывает каталоги на месте уже занятых файлов, а затем сам файл,
+/// если его путь уже занят. Возвращает `true`, если что-то изменилось.
+fn resolve_collisions(
+    path: &mut Vec<String>,
+    files: &HashSet<Vec<String>>,
+    dirs: &HashSet<Vec<String>>,
+) -> bool {
+    let mut changed = false;
+
+    // Один и тот же каталог у разных файлов получает один и тот же номер
+    for i in 0..path.len() - 1 {
+        if files.contains(&key(&path[..=i])) {
+            let prefix = (1..)
+                .map(|n| numbered(&path[..=i], n))
+                .find(|p| !files.contains(&key(p)))
+                .unwrap();
+            path.splice(..=i, prefix);
+            changed = true;
+        }
+    }
+
+    let taken = |p: &[String]| files.contains(&key(p)) || dirs.contains(&key(p));
+    if taken(path) {
+        *path = (1..)
+            .map(|n| numbered(path, n))
+            .find(|p| !taken(p))
+            .unwrap();
+        changed = true;
+    }
+    changed
+}
+
+/// Результат проверки путей всех файлов торрента.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sanitized {
+    pub paths: Vec<Vec<String>>,
+    pub changes: Vec<PathChange>,
+}
+
+/// Приводит к безопасному виду пути файлов и общий каталог `name`, если он есть.
+/// Совпадающие пути, а также файлы на месте чужих каталогов, получают номера.
+/// Пути с флагом `true` (файлы выравнивания) на совпадения не проверяются.
+pub fn sanitize_paths(name: Option<&str>, paths: &[(Vec<String>, bool)]) -> Sanitized {
+    let mut result = Sanitized::default();
+    let mut files: HashSet<Vec<String>> = HashSet::new();
+    let mut dirs: HashSet<Vec<String>> = HashSet::new();
+
+    let dir = name.map(|name| {
+        let original = vec![name.to_string()];
+        let mut issues = vec![];
+        let clean = sanitize_path(&original, &mut issues);
+        if !issues.is_empty() {
+            issues.dedup();
+            result.changes.push(PathChange {
+                original,
+                sanitized: clean.clone(),
+                issues,
+            });
+        }
+        clean
+    });
+
+    for (original, skip_duplicates) in paths {
+        let mut issues = vec![];
+        let mut path = sanitize_path(original, &mut issues);
+
+        if !skip_duplicates {
+            if resolve_collisions(&mut path, &files, &dirs) {
+                issues.push(Issue::Duplicate);
+            }
+            let k = key(&path);
+            (1..k.len()).for_each(|i| {
+                dirs.insert(k[..i].to_vec());
+            });
+            files.insert(k);
+        }
+
+        let path = dir
+            .iter()
+            .flatten()
+            .cloned()
+            .chain(path)
+            .collect::<Vec<_>>();
+        if !issues.is_empty() {
+            issues.sort();
+            issues.dedup();
+            result.changes.push(PathChange {
+                original: name
+                    .iter()
+                    .map(|n| n.to_string())
+                    .chain(original.clone())
+                    .collect(),
+                sanitized: path.clone(),
+                issues,
+            });
+        }
+        result.paths.push(path);
+    }
+    result
+}
@@ -0,0 +1,203 @@
+use crate::{
+    io::serialization::Serialize,
+    repository::{
+        backend::{migrate, Directory, RepoBackend, SingleFile},
+        format::FormatError,
+        resume::ResumeData,
+        types::{Torrent, TorrentMetadata},
+        TorrentRepo,
+    },
+};
+
+use super::{metadata, TempDir};
+
+/// Торрент с настоящим инфо-хешем, чтобы `.torrent`-файл открывался
+/// другими клиентами под тем же хешем.
+fn torrent(name: &str) -> Torrent {
+    let (metadata, hash) = TorrentMetadata::new(&metadata(name).serialize()).unwrap();
+    Torrent::new(metadata, hash)
+}
+
+fn repo() -> TorrentRepo {
+    let mut repo = TorrentRepo::empty();
+    let mut first = torrent("first");
+    first.labels = vec!["linux".to_string()];
+    first.resume = Some(ResumeData {
+        save_path: "/downloads".to_string(),
+        ..ResumeData::default()
+    });
+    repo.add_new_torrent(first);
+    let mut second = torrent("second");
+    second.paused = true;
+    repo.add_new_torrent(second);
+    repo
+}
+
+fn same(a: &TorrentRepo, b: &TorrentRepo) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .all(|t| b.get_by_id(t.id).is_some_and(|o| o.value == t.value))
+}
+
+fn names(dir: &TempDir) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(&dir.0)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn directory_keeps_a_file_pair_per_torrent() {
+    let dir = TempDir::new();
+    let repo = repo();
+
+    Directory::new(&dir.0).save(&repo).await.unwrap();
+
+    let mut expected = vec![];
+    for torrent in repo.iter() {
+        let stem = Directory::stem(&torrent.value);
+        let metainfo = std::fs::read(dir.0.join(format!("{stem}.torrent"))).unwrap();
+        let (metadata, hash) = TorrentMetadata::new(&metainfo).unwrap();
+        assert_eq!(torrent.value.metadata, metadata);
+        assert_eq!(torrent.value.hash, hash);
+        expected.extend([format!("{stem}.resume"), format!("{stem}.torrent")]);
+    }
+    expected.sort();
+    assert_eq!(expected, names(&dir));
+
+    let loaded = Directory::new(&dir.0).load().await.unwrap();
+    assert!(same(&repo, &loaded));
+}
+
+#[tokio::test]
+async fn directory_rewrites_only_changed_torrents() {
+    let dir = TempDir::new();
+    let mut repo = repo();
+    let mut backend = Directory::new(&dir.0);
+    backend.save(&repo).await.unwrap();
+
+    let ids: Vec<_> = repo.iter().map(|t| t.id).collect();
+    let stems: Vec<_> = repo.iter().map(|t| Directory::stem(&t.value)).collect();
+    // Если бы хранилище переписало первый торрент, файл появился бы снова
+    std::fs::remove_file(dir.0.join(format!("{}.resume", stems[0]))).unwrap();
+    std::fs::remove_file(dir.0.join(format!("{}.resume", stems[1]))).unwrap();
+    repo.get_by_id_mut(ids[1]).unwrap().downloaded = 1000;
+    backend.save(&repo).await.unwrap();
+
+    assert!(!dir.0.join(format!("{}.resume", stems[0])).exists());
+    assert!(dir.0.join(format!("{}.resume", stems[1])).exists());
+
+    // Файлы удалённого торрента убираются
+    repo.remove_torrent_by_id(ids[1]);
+    backend.save(&repo).await.unwrap();
+    assert_eq!(vec![format!("{}.torrent", stems[0])], names(&dir));
+}
+
+#[tokio::test]
+async fn directory_keeps_files_whose_name_passed_to_a_new_torrent() {
+    let dir = TempDir::new();
+    let mut repo = repo();
+    let mut backend = Directory::new(&dir.0);
+    backend.save(&repo).await.unwrap();
+    let first = repo.iter().next().unwrap().id;
+    let stem = Directory::stem(&repo.get_by_id(first).unwrap().value);
+
+    // Торрент удалён и добавлен заново между сохранениями
+    repo.remove_torrent_by_id(first);
+    let again = repo.add_new_torrent(torrent("first")).id();
+    backend.save(&repo).await.unwrap();
+
+    assert!(dir.0.join(format!("{stem}.resume")).exists());
+    let loaded = Directory::new(&dir.0).load().await.unwrap();
+    assert!(same(&repo, &loaded));
+    assert!(loaded.get_by_id(again).is_some());
+}
+
+#[tokio::test]
+async fn repo_migrates_between_backends() {
+    let dir = TempDir::new();
+    let file = dir.0.join("torrents.repo");
+    let torrents = dir.0.join("torrents");
+    let back = dir.0.join("back.repo");
+    let repo = repo();
+    repo.save_to(&file, &mut vec![]).await.unwrap();
+
+    let count = migrate(&mut SingleFile::new(&file), &mut Directory::new(&torrents))
+        .await
+        .unwrap();
+    assert_eq!(2, count);
+    assert!(same(
+        &repo,
+        &Directory::new(&torrents).load().await.unwrap()
+    ));
+
+    migrate(&mut Directory::new(&torrents), &mut SingleFile::new(&back))
+        .await
+        .unwrap();
+    assert!(same(&repo, &TorrentRepo::load_from(&back).await.unwrap()));
+    // Источник остаётся на месте
+    assert!(same(&repo, &TorrentRepo::load_from(&file).await.unwrap()));
+}
+
+#[tokio::test]
+async fn missing_state_loads_empty() {
+    let dir = TempDir::new();
+
+    assert!(Directory::new(&dir.0).load().await.unwrap().is_empty());
+    assert!(SingleFile::new(dir.0.join("repo"))
+        .load()
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn newer_resume_files_are_refused() {
+    let dir = TempDir::new();
+    Directory::new(&dir.0).save(&repo()).await.unwrap();
+    let resume = dir.0.join(
+        names(&dir)
+            .into_iter()
+            .find(|n| n.ends_with(".resume"))
+            .unwrap(),
+    );
+    let mut bytes = std::fs::read(&resume).unwrap();
+    let at = bytes.len() - b"i1ee".len();
+    bytes.splice(at.., b"i9ee".to_vec());
+    std::fs::write(&resume, bytes).unwrap();
+
+    let err = Directory::new(&dir.0).load().await.unwrap_err();
+
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(FormatError::UnsupportedVersion { found: 9, .. })
+    ));
+}
+
+#[tokio::test]
+async fn broken_entries_are_set_aside() {
+    let dir = TempDir::new();
+    let repo = repo();
+    Directory::new(&dir.0).save(&repo).await.unwrap();
+    let broken = repo.iter().next().unwrap().clone();
+    let stem = Directory::stem(&broken.value);
+    std::fs::write(dir.0.join(format!("{stem}.resume")), b"d2:id").unwrap();
+
+    // Остальные торренты загружаются, а битая запись откладывается
+    let mut backend = Directory::new(&dir.0);
+    let loaded = backend.load().await.unwrap();
+    assert_eq!(1, loaded.len());
+    assert!(loaded.get_by_id(broken.id).is_none());
+    assert_eq!(1, backend.corrupt().len());
+    assert_eq!(dir.0.join(format!("{stem}.resume")), backend.corrupt()[0].0);
+
+    // Сохранение не удаляет отложенные файлы
+    backend.save(&loaded).await.unwrap();
+    let names = names(&dir);
+    assert!(names.contains(&format!("{stem}.resume.corrupt")));
+    assert!(names.contains(&format!("{stem}.torrent.corrupt")));
+    assert!(!names.contains(&format!("{stem}.resume")));
+    assert!(Directory::new(&dir.0).load().await.is_ok());
+}
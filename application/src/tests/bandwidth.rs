@@ -0,0 +1,175 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::{
+    network::{
+        bandwidth::{Direction, Limited, Limiter, TokenBucket},
+        get_start, get_web_seed,
+        message::Message,
+        TorrentState,
+    },
+    repository::types::{InfoHash, Torrent},
+    settings::Settings,
+    storage::Storage,
+};
+
+use super::{data, http_stub, metadata};
+
+#[test]
+fn bucket_refills_at_its_rate() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(Some(1000), start);
+    // Полное ведро — одна секунда трафика
+    assert_eq!(1000, bucket.available(start));
+    bucket.consume(1000);
+    assert_eq!(0, bucket.available(start));
+    assert_eq!(Duration::from_millis(500), bucket.wait(500));
+    // Больше ёмкости ждать бессмысленно
+    assert_eq!(Duration::from_secs(1), bucket.wait(5000));
+
+    assert_eq!(250, bucket.available(start + Duration::from_millis(250)));
+    assert_eq!(1000, bucket.available(start + Duration::from_secs(10)));
+
+    bucket.set_rate(Some(100), start + Duration::from_secs(10));
+    assert_eq!(100, bucket.available(start + Duration::from_secs(10)));
+    bucket.set_rate(None, start + Duration::from_secs(10));
+    assert_eq!(u64::MAX, bucket.available(start));
+    assert_eq!(Duration::ZERO, bucket.wait(u64::MAX));
+}
+
+#[test]
+fn torrent_and_global_limits_both_apply() {
+    let limiter = Limiter::new();
+    let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+    let now = Instant::now();
+    limiter.set_global(Some(1000), None);
+    limiter.set_torrent(first, Some(300), None);
+
+    let request = |id, want| limiter.request_at(Some(id), Direction::Download, want, now);
+    assert_eq!(Ok(300), request(first, 500));
+    assert!(request(first, 500).is_err());
+    // Второму торренту остаётся только общий остаток
+    assert_eq!(Ok(700), request(second, 5000));
+    assert!(request(second, 1).is_err());
+
+    limiter.refund(Some(second), Direction::Download, 200);
+    assert_eq!(Ok(200), request(second, 5000));
+    // Отдача не ограничена
+    assert_eq!(
+        Ok(1 << 20),
+        limiter.request_at(Some(first), Direction::Upload, 1 << 20, now)
+    );
+
+    limiter.remove_torrent(first);
+    assert_eq!((Some(1000), None), limiter.global_rates());
+}
+
+#[test]
+fn overhead_is_counted_apart_from_payload() {
+    let limiter = Limiter::new();
+    let id = Uuid::new_v4();
+    let piece = Message::Piece {
+        index: 0,
+        begin: 0,
+        block: vec![0; 16384],
+    };
+    let mut wire = vec![];
+    piece.encode(&mut wire);
+    limiter.record(
+        Some(id),
+        Direction::Download,
+        wire.len(),
+        piece.payload_len(),
+    );
+    limiter.record(
+        Some(id),
+        Direction::Download,
+        17,
+        Message::Interested.payload_len(),
+    );
+
+    let stats = limiter.stats(Some(id));
+    assert_eq!(16384, stats.download.payload);
+    assert_eq!(13 + 17, stats.download.overhead);
+    assert_eq!(0, stats.upload.payload);
+    assert_eq!(stats, limiter.stats(None));
+    assert_eq!(0, limiter.stats(Some(Uuid::new_v4())).download.payload);
+}
+
+#[tokio::test]
+async fn limited_stream_is_slowed_down() {
+    let limiter = Arc::new(Limiter::new());
+    let id = Uuid::new_v4();
+    limiter.set_torrent(id, None, Some(10_000));
+    let (writer, reader) = tokio::io::duplex(64 * 1024);
+    let mut writer = Limited::new(writer, limiter.clone(), Some(id));
+    let mut reader = Limited::new(reader, limiter.clone(), None);
+
+    let started = Instant::now();
+    let data = vec![7; 15_000];
+    let write = async {
+        writer.write_all(&data).await.unwrap();
+        writer.shutdown().await.unwrap();
+    };
+    let mut received = vec![];
+    let read = reader.read_to_end(&mut received);
+    let ((), read) = tokio::join!(write, read);
+
+    assert_eq!(15_000, read.unwrap());
+    assert_eq!(data, received);
+    // Первая секунда уходит сразу, остаток ждёт пополнения ведра
+    assert!(started.elapsed() >= Duration::from_millis(400));
+    let stats = limiter.stats(Some(id));
+    assert_eq!(15_000, stats.upload.overhead);
+    assert_eq!(15_000, limiter.stats(None).download.overhead);
+
+    limiter.record_payload(Some(id), Direction::Upload, 14_000);
+    assert_eq!(14_000, limiter.stats(Some(id)).upload.payload);
+    assert_eq!(1000, limiter.stats(Some(id)).upload.overhead);
+}
+
+#[tokio::test]
+async fn web_seed_ranges_are_payload() {
+    let limiter = Limiter::new();
+    let id = Uuid::new_v4();
+    let body = data(5000, 9);
+    let (addr, seed) = http_stub(body.clone()).await;
+
+    let client = Settings::default().tracker_client().unwrap();
+    let url = format!("http://{addr}/pkg/a");
+    let got = get_web_seed(&client, &url, 1000, 5000, &limiter, id)
+        .await
+        .unwrap();
+    assert_eq!(body, got);
+    assert!(seed.await.unwrap().contains("range: bytes=1000-5999\r\n"));
+    let stats = limiter.stats(Some(id));
+    assert_eq!(5000, stats.download.payload);
+    assert_eq!(0, stats.download.overhead);
+}
+
+#[tokio::test]
+async fn tracker_traffic_is_overhead() {
+    let limiter = Limiter::new();
+    let id = Uuid::new_v4();
+    let (addr, _) = http_stub(b"d8:intervali60ee".to_vec()).await;
+    let mut announced = metadata("announced");
+    announced.announce = format!("http://{addr}/announce");
+    let storage = Storage::new(&announced.info, ".");
+    let state = TorrentState::new(Torrent::new(announced, InfoHash::V1([1; 20])), &storage);
+
+    let settings = Settings::default();
+    let client = settings.tracker_client().unwrap();
+    get_start(&client, &state, &settings, &limiter, id)
+        .await
+        .unwrap();
+    let stats = limiter.stats(Some(id));
+    assert_eq!(0, stats.download.payload);
+    assert_eq!(16, stats.download.overhead);
+    assert!(stats.upload.overhead > 0);
+    assert_eq!(0, stats.upload.payload);
+}
@@ -0,0 +1,218 @@
+use std::path::Path;
+
+use clap::Parser;
+use serde_json::Value;
+
+use crate::{
+    cli::{run, Cli},
+    error::AsyncErr,
+};
+
+use super::TempDir;
+
+async fn cli(state: &Path, args: &[&str]) -> Result<String, AsyncErr> {
+    let state = state.join("state");
+    let args = ["bittorrent", "--state-dir", state.to_str().unwrap()]
+        .into_iter()
+        .chain(args.iter().copied());
+    let mut out = vec![];
+    run(Cli::try_parse_from(args)?, &mut out).await?;
+    Ok(String::from_utf8(out).unwrap())
+}
+
+async fn json(state: &Path, args: &[&str]) -> Value {
+    let mut args = args.to_vec();
+    args.push("--json");
+    serde_json::from_str(&cli(state, &args).await.unwrap()).unwrap()
+}
+
+/// Раздача `pkg` из двух файлов и торрент для неё в `pkg.torrent`.
+async fn seed(dir: &TempDir) -> String {
+    let data = dir.0.join("data/pkg");
+    std::fs::create_dir_all(data.join("sub")).unwrap();
+    std::fs::write(data.join("readme"), vec![1; 1000]).unwrap();
+    std::fs::write(data.join("sub/tool"), vec![2; 40_000]).unwrap();
+    let torrent = dir.0.join("pkg.torrent");
+    cli(
+        &dir.0,
+        &[
+            "create",
+            data.to_str().unwrap(),
+            torrent.to_str().unwrap(),
+            "--announce",
+            "http://tracker/announce",
+            "--piece-length",
+            "16384",
+        ],
+    )
+    .await
+    .unwrap();
+    torrent.to_str().unwrap().to_string()
+}
+
+async fn add(dir: &TempDir, torrent: &str, extra: &[&str]) -> String {
+    let save_path = dir.0.join("data");
+    let mut args = vec!["add", torrent, "--save-path", save_path.to_str().unwrap()];
+    args.extend(extra);
+    let added = json(&dir.0, &args).await;
+    added["id"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn added_torrents_are_listed() {
+    let dir = TempDir::new();
+    let torrent = seed(&dir).await;
+
+    let id = add(&dir, &torrent, &["--paused", "--label", "linux"]).await;
+    let list = json(&dir.0, &["list"]).await;
+
+    assert_eq!(1, list.as_array().unwrap().len());
+    assert_eq!(id, list[0]["id"]);
+    assert_eq!("pkg", list[0]["name"]);
+    assert_eq!("paused", list[0]["status"]);
+    assert_eq!(41_000, list[0]["size"]);
+    assert_eq!(
+        1,
+        json(&dir.0, &["list", "--label", "linux"])
+            .await
+            .as_array()
+            .unwrap()
+            .len()
+    );
+    assert!(json(&dir.0, &["list", "--status", "seeding"])
+        .await
+        .as_array()
+        .unwrap()
+        .is_empty());
+
+    // Повторное добавление не создаёт второй записи
+    let again = json(&dir.0, &["add", &torrent]).await;
+    assert_eq!(id, again["id"]);
+    assert_eq!(true, again["merged"]);
+
+    let info = json(&dir.0, &["info", &id]).await;
+    assert_eq!(
+        vec!["http://tracker/announce"],
+        info["trackers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t.as_str().unwrap())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(2, info["files"].as_array().unwrap().len());
+    let text = cli(&dir.0, &["info", &id]).await.unwrap();
+    assert!(text.contains("name:\t\tpkg"));
+    assert!(text.contains("sub/tool"));
+}
+
+#[tokio::test]
+async fn torrents_are_started_stopped_and_rechecked() {
+    let dir = TempDir::new();
+    let torrent = seed(&dir).await;
+    let id = add(&dir, &torrent, &["--paused"]).await;
+
+    // Достаточно начала id
+    cli(&dir.0, &["start", &id[..6]]).await.unwrap();
+    assert_eq!("downloading", json(&dir.0, &["list"]).await[0]["status"]);
+
+    let checked = json(&dir.0, &["recheck", &id]).await;
+    assert_eq!(41_000, checked["downloaded"]);
+    let list = json(&dir.0, &["list"]).await;
+    assert_eq!("seeding", list[0]["status"]);
+    assert_eq!(1.0, list[0]["progress"]);
+
+    cli(&dir.0, &["stop", &id]).await.unwrap();
+    assert_eq!("paused", json(&dir.0, &["list"]).await[0]["status"]);
+}
+
+#[tokio::test]
+async fn removal_optionally_deletes_data() {
+    let dir = TempDir::new();
+    let torrent = seed(&dir).await;
+    let id = add(&dir, &torrent, &[]).await;
+
+    cli(&dir.0, &["remove", &id]).await.unwrap();
+    assert!(json(&dir.0, &["list"]).await.as_array().unwrap().is_empty());
+    assert!(dir.0.join("data/pkg/sub/tool").exists());
+
+    let id = add(&dir, &torrent, &[]).await;
+    cli(&dir.0, &["remove", &id, "--delete-data"])
+        .await
+        .unwrap();
+    assert!(!dir.0.join("data/pkg").exists());
+    assert!(dir.0.join("data").exists());
+}
+
+#[tokio::test]
+async fn bad_input_is_reported() {
+    let dir = TempDir::new();
+    let torrent = seed(&dir).await;
+
+    let err = cli(&dir.0, &["add", "magnet:?xt=urn:btih:0123"])
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("magnet links are not supported"));
+    assert!(cli(&dir.0, &["info", "0"])
+        .await
+        .unwrap_err()
+        .to_string()
+        .contains("no torrent"));
+
+    add(&dir, &torrent, &[]).await;
+    std::fs::write(dir.0.join("data/other"), vec![3; 100]).unwrap();
+    let other = dir.0.join("other.torrent");
+    cli(
+        &dir.0,
+        &[
+            "create",
+            dir.0.join("data/other").to_str().unwrap(),
+            other.to_str().unwrap(),
+            "--announce",
+            "http://tracker/announce",
+        ],
+    )
+    .await
+    .unwrap();
+    add(&dir, other.to_str().unwrap(), &[]).await;
+    // Пустое начало подходит к обоим торрентам
+    let err = cli(&dir.0, &["start", ""]).await.unwrap_err();
+    assert!(err.to_string().contains("several torrents"));
+}
+
+#[tokio::test]
+async fn settings_come_from_the_file_and_flags() {
+    let dir = TempDir::new();
+    let config = dir.0.join("config.toml");
+    std::fs::write(
+        &config,
+        "listen_port = 51413\n[limits]\nconnections = 120\n",
+    )
+    .unwrap();
+    let config = config.to_str().unwrap();
+
+    let text = cli(
+        &dir.0,
+        &[
+            "--config",
+            config,
+            "--set",
+            "limits.connections=80",
+            "settings",
+        ],
+    )
+    .await
+    .unwrap();
+    assert!(text.contains("listen_port = 51413\n"));
+    assert!(text.contains("limits.connections = 80\n"));
+    assert!(text.contains("limits.download_rate = none\n"));
+
+    let err = cli(
+        &dir.0,
+        &["--config", config, "--set", "colour=blue", "settings"],
+    )
+    .await
+    .unwrap_err();
+    assert_eq!("unknown setting colour", err.to_string());
+    assert!(cli(&dir.0, &["settings", "--reload"]).await.is_err());
+}
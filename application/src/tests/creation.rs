@@ -0,0 +1,157 @@
+use std::{
+    fs,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    creation::{auto_piece_length, CreateError, TorrentBuilder, MIN_PIECE_LENGTH},
+    io::{deserialization::Decoder, serialization::Serialize},
+    repository::types::{FilesMetadata, TorrentMetadata},
+};
+
+use super::{data, TempDir, SMALL_PIECE};
+
+impl TempDir {
+    fn file(&self, rel: &str, len: usize) -> Vec<u8> {
+        let path = self.0.join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let data = data(len, 0);
+        fs::write(path, &data).unwrap();
+        data
+    }
+}
+
+fn expected_pieces(data: &[u8]) -> Vec<u8> {
+    data.chunks(SMALL_PIECE as usize)
+        .flat_map(|c| <[u8; 20]>::from(Sha1::digest(c)))
+        .collect()
+}
+
+#[test]
+fn create_from_directory() {
+    let dir = TempDir::new();
+    // Порядок создания отличается от порядка путей
+    let mut data = dir.file("b.bin", 20_000);
+    let mut a = dir.file("a/z.bin", 10_000);
+    dir.file("a/empty", 0);
+    a.append(&mut data);
+    let data = a;
+
+    let (metadata, hash) = TorrentBuilder::new(&dir.0)
+        .announce("http://tracker/announce")
+        .announce_tier(vec!["http://backup/announce".to_string()])
+        .comment("dataset")
+        .web_seed("http://seed/")
+        .private(true)
+        .piece_length(SMALL_PIECE)
+        .threads(3)
+        .build()
+        .unwrap();
+
+    let FilesMetadata::Multiple { files, .. } = &metadata.info.files else {
+        panic!("expected a multi-file torrent");
+    };
+    let paths: Vec<_> = files.iter().map(|f| f.path.join("/")).collect();
+    assert_eq!(vec!["a/empty", "a/z.bin", "b.bin"], paths);
+    assert_eq!(Some(expected_pieces(&data)), metadata.info.pieces);
+    assert_eq!(Some(1), metadata.info.private);
+
+    let bytes = metadata.serialize();
+    assert!(Decoder::new().strict(true).decode(&bytes).is_ok());
+    assert_eq!((metadata, hash), TorrentMetadata::new(&bytes).unwrap());
+}
+
+#[test]
+fn create_from_single_file() {
+    let dir = TempDir::new();
+    let data = dir.file("movie.mkv", 3 * SMALL_PIECE as usize + 5);
+
+    let (metadata, _) = TorrentBuilder::new(dir.0.join("movie.mkv"))
+        .announce("http://tracker/announce")
+        .creation_date(None)
+        .piece_length(SMALL_PIECE)
+        .build()
+        .unwrap();
+
+    assert_eq!(
+        FilesMetadata::Single {
+            name: "movie.mkv".to_string(),
+            length: data.len() as u64,
+            md5sum: None,
+        },
+        metadata.info.files
+    );
+    assert_eq!(Some(expected_pieces(&data)), metadata.info.pieces);
+    assert_eq!(None, metadata.creation_date);
+}
+
+#[test]
+fn progress_reaches_total() {
+    let dir = TempDir::new();
+    dir.file("data", 5 * SMALL_PIECE as usize);
+    let calls = Arc::new(AtomicUsize::new(0));
+    let last = Arc::new(AtomicUsize::new(0));
+
+    let (c, l) = (calls.clone(), last.clone());
+    TorrentBuilder::new(&dir.0)
+        .announce("http://tracker/announce")
+        .piece_length(SMALL_PIECE)
+        .on_progress(move |p| {
+            assert_eq!(5, p.total_pieces);
+            c.fetch_add(1, Ordering::SeqCst);
+            l.fetch_max(p.hashed_pieces, Ordering::SeqCst);
+        })
+        .build()
+        .unwrap();
+
+    assert_eq!(5, calls.load(Ordering::SeqCst));
+    assert_eq!(5, last.load(Ordering::SeqCst));
+}
+
+#[test]
+fn cancelled_creation() {
+    let dir = TempDir::new();
+    dir.file("data", 4 * SMALL_PIECE as usize);
+
+    let builder = TorrentBuilder::new(&dir.0)
+        .announce("http://tracker/announce")
+        .piece_length(SMALL_PIECE);
+    builder.cancel_handle().store(true, Ordering::SeqCst);
+
+    assert!(matches!(builder.build(), Err(CreateError::Cancelled)));
+}
+
+#[test]
+fn invalid_inputs() {
+    let dir = TempDir::new();
+    fs::create_dir(dir.0.join("empty")).unwrap();
+    dir.file("data", 10);
+
+    let no_tracker = TorrentBuilder::new(&dir.0).build();
+    let bad_length = TorrentBuilder::new(&dir.0)
+        .announce("http://tracker/announce")
+        .piece_length(SMALL_PIECE + 1)
+        .build();
+    let empty = TorrentBuilder::new(dir.0.join("empty"))
+        .announce("http://tracker/announce")
+        .build();
+
+    assert!(matches!(no_tracker, Err(CreateError::NoTracker)));
+    assert!(matches!(
+        bad_length,
+        Err(CreateError::InvalidPieceLength(_))
+    ));
+    assert!(matches!(empty, Err(CreateError::NothingToShare(_))));
+}
+
+#[test]
+fn piece_length_selection() {
+    assert_eq!(MIN_PIECE_LENGTH, auto_piece_length(0));
+    assert_eq!(1 << 20, auto_piece_length(1500 << 20));
+    assert_eq!(16 << 20, auto_piece_length(u64::MAX / 2));
+}
@@ -0,0 +1,225 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::Parser;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    task::JoinHandle,
+};
+
+use crate::{
+    cli::{run, Cli},
+    daemon::{socket_path, Daemon, DaemonConfig},
+    error::AsyncErr,
+    io::serialization::Serialize,
+    rpc::{
+        client::RpcClient, Endpoint, RpcError, INVALID_PARAMS, METHOD_NOT_FOUND, PARSE_ERROR,
+        UNAUTHORIZED,
+    },
+};
+
+use super::{metadata, start_daemon, TempDir};
+
+fn metainfo(name: &str) -> Vec<u8> {
+    metadata(name).serialize()
+}
+
+async fn start(config: DaemonConfig) -> (Vec<Endpoint>, JoinHandle<Result<(), AsyncErr>>) {
+    start_daemon(config, Daemon::endpoints).await
+}
+
+async fn cli(dir: &TempDir, args: &[&str]) -> Result<Value, AsyncErr> {
+    let state = dir.0.to_str().unwrap();
+    let args = ["bittorrent", "--state-dir", state, "--json"]
+        .into_iter()
+        .chain(args.iter().copied());
+    let mut out = vec![];
+    run(Cli::try_parse_from(args)?, &mut out).await?;
+    Ok(serde_json::from_slice(&out)?)
+}
+
+async fn exchange(stream: &mut BufReader<TcpStream>, request: &str) -> Value {
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    serde_json::from_str(&line).unwrap()
+}
+
+fn code(err: AsyncErr) -> i64 {
+    err.downcast_ref::<RpcError>().unwrap().code
+}
+
+#[tokio::test]
+async fn cli_talks_to_a_running_daemon() {
+    let dir = TempDir::new();
+    let torrent = dir.0.join("first.torrent");
+    let (endpoints, daemon) = start(DaemonConfig::new(&dir.0)).await;
+    std::fs::write(&torrent, metainfo("first")).unwrap();
+
+    // Без `--connect` находится сокет в каталоге состояния
+    let added = cli(
+        &dir,
+        &[
+            "add",
+            torrent.to_str().unwrap(),
+            "--save-path",
+            "/downloads",
+        ],
+    )
+    .await
+    .unwrap();
+    let id = added["id"].as_str().unwrap();
+    assert_eq!(
+        "downloading",
+        cli(&dir, &["list"]).await.unwrap()[0]["status"]
+    );
+
+    let mut client = RpcClient::connect(&endpoints[0], None).await.unwrap();
+    let stats = client.call("daemon.stats", json!({})).await.unwrap();
+    assert_eq!(1, stats["torrents"]);
+    assert_eq!(1, stats["active"]);
+    #[cfg(unix)]
+    {
+        // Сокет лежит в каталоге, закрытом для остальных
+        use std::os::unix::fs::PermissionsExt;
+        let run = socket_path(&dir.0).parent().unwrap().to_path_buf();
+        let mode = std::fs::metadata(run).unwrap().permissions().mode();
+        assert_eq!(0o700, mode & 0o777);
+        let secret = dir.0.join("secret");
+        let mode = std::fs::metadata(secret).unwrap().permissions().mode();
+        assert_eq!(0o600, mode & 0o777);
+    }
+
+    // Проверка отпускает состояние, но результат применяется к торренту
+    let checked = client
+        .call("torrent.recheck", json!({ "id": id }))
+        .await
+        .unwrap();
+    assert_eq!(0, checked["downloaded"]);
+
+    // Ключ трекера не меняется между запросами
+    let key = cli(&dir, &["info", id]).await.unwrap()["tracker_key"].clone();
+    assert_eq!(8, key.as_str().unwrap().len());
+    assert_eq!(key, cli(&dir, &["info", id]).await.unwrap()["tracker_key"]);
+
+    let set = cli(&dir, &["set", id, "--download-limit", "512KiB"])
+        .await
+        .unwrap();
+    assert_eq!(524288, set["download_limit"]);
+    assert_eq!(Value::Null, set["upload_limit"]);
+    assert!(cli(&dir, &["set", id, "--upload-limit", "fast"])
+        .await
+        .is_err());
+    let stats = client.call("daemon.stats", json!({})).await.unwrap();
+    assert_eq!(false, stats["alt_speed"]);
+    assert_eq!(false, stats["limits_enforced"]);
+    assert_eq!(json!(["tracker", "web_seed"]), stats["limited_traffic"]);
+    assert_eq!(Value::Null, stats["download_limit"]);
+    assert_eq!(0, stats["download"]["overhead"]);
+    assert_eq!(0, stats["disk"]["queue_depth"]);
+    assert_eq!(false, stats["disk"]["congested"]);
+
+    cli(&dir, &["stop", &id[..8]]).await.unwrap();
+    let stats = client.call("daemon.stats", json!({})).await.unwrap();
+    assert_eq!(0, stats["active"]);
+
+    client.call("daemon.shutdown", json!({})).await.unwrap();
+    daemon.await.unwrap().unwrap();
+    assert!(!socket_path(&dir.0).exists());
+
+    // Изменения сохранены, и без демона команды работают с репозиторием
+    let list = cli(&dir, &["list"]).await.unwrap();
+    assert_eq!(id, list[0]["id"]);
+    assert_eq!("paused", list[0]["status"]);
+    let info = cli(&dir, &["info", id]).await.unwrap();
+    assert_eq!(524288, info["download_limit"]);
+
+    // Секрет лежит в каталоге состояния, и ключ трекера переживает перезапуск
+    let (endpoints, daemon) = start(DaemonConfig::new(&dir.0)).await;
+    assert_eq!(key, cli(&dir, &["info", id]).await.unwrap()["tracker_key"]);
+    let mut client = RpcClient::connect(&endpoints[0], None).await.unwrap();
+    client.call("daemon.shutdown", json!({})).await.unwrap();
+    daemon.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn token_is_required_when_set() {
+    let dir = TempDir::new();
+    let config = DaemonConfig::new(&dir.0)
+        .listen("127.0.0.1:0".parse().unwrap())
+        .token("secret");
+    let (endpoints, daemon) = start(config).await;
+    let tcp = endpoints.last().unwrap();
+    assert!(matches!(tcp, Endpoint::Tcp(_)));
+
+    let mut anonymous = RpcClient::connect(tcp, None).await.unwrap();
+    let err = anonymous.call("torrent.list", json!({})).await.unwrap_err();
+    assert_eq!(UNAUTHORIZED, code(err));
+    assert!(RpcClient::connect(tcp, Some("guess")).await.is_err());
+
+    let mut client = RpcClient::connect(tcp, Some("secret")).await.unwrap();
+    let params = json!({ "metainfo": STANDARD.encode(metainfo("first")), "paused": true });
+    client.call("torrent.add", params).await.unwrap();
+    assert_eq!(
+        1,
+        client
+            .call("torrent.list", json!({}))
+            .await
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .len()
+    );
+
+    // Второй демон на том же каталоге не запускается
+    assert!(Daemon::bind(DaemonConfig::new(&dir.0)).await.is_err());
+    assert!(Daemon::bind(
+        DaemonConfig::new(dir.0.join("other")).listen("0.0.0.0:0".parse().unwrap())
+    )
+    .await
+    .is_err());
+
+    client.call("daemon.shutdown", json!({})).await.unwrap();
+    daemon.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn protocol_errors_are_reported() {
+    let dir = TempDir::new();
+    let config = DaemonConfig::new(&dir.0).listen("127.0.0.1:0".parse().unwrap());
+    let (endpoints, daemon) = start(config).await;
+    let Endpoint::Tcp(addr) = endpoints.last().unwrap() else {
+        panic!("expected a TCP endpoint");
+    };
+
+    let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+    let response = exchange(&mut stream, "{not json\n").await;
+    assert_eq!(Value::Null, response["id"]);
+    assert_eq!(PARSE_ERROR, response["error"]["code"]);
+
+    // На уведомление без id ответа нет, следующий ответ — на запрос
+    let response = exchange(
+        &mut stream,
+        "{\"jsonrpc\":\"2.0\",\"method\":\"torrent.list\"}\n\
+         {\"jsonrpc\":\"2.0\",\"id\":7,\"method\":\"torrent.pause\"}\n",
+    )
+    .await;
+    assert_eq!(7, response["id"]);
+    assert_eq!(METHOD_NOT_FOUND, response["error"]["code"]);
+
+    let response = exchange(
+        &mut stream,
+        "{\"jsonrpc\":\"2.0\",\"id\":8,\"method\":\"torrent.get\",\"params\":{}}\n",
+    )
+    .await;
+    assert_eq!(INVALID_PARAMS, response["error"]["code"]);
+
+    let response = exchange(
+        &mut stream,
+        "{\"jsonrpc\":\"2.0\",\"id\":\"x\",\"method\":\"daemon.shutdown\"}\n",
+    )
+    .await;
+    assert_eq!("x", response["id"]);
+    assert!(response["result"].is_object());
+    daemon.await.unwrap().unwrap();
+}
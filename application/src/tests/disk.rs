@@ -0,0 +1,243 @@
+use std::{io, time::Duration};
+
+use uuid::Uuid;
+
+use crate::{
+    settings::{DiskCache, Settings},
+    storage::{disk::DiskIo, Storage},
+};
+
+use super::{single_file, TempDir, PIECE};
+
+const BLOCK: usize = 16 * 1024;
+
+/// Три куска, последний короче.
+fn content() -> Vec<u8> {
+    (0..2 * PIECE as usize + 20_000)
+        .map(|i| (i % 251) as u8)
+        .collect()
+}
+
+fn cache(write_cache: u64, read_cache: u64, read_ahead: usize) -> DiskCache {
+    DiskCache {
+        write_cache,
+        read_cache,
+        read_ahead,
+    }
+}
+
+/// Подсистема с одним зарегистрированным торрентом.
+fn disk(dir: &TempDir, cache: DiskCache) -> (DiskIo, Uuid, Storage) {
+    let metadata = single_file("data.bin", PIECE, &content());
+    let storage = Storage::new(&metadata.info, &dir.0);
+    let disk = DiskIo::new(2, &cache).unwrap();
+    let id = Uuid::new_v4();
+    disk.add_torrent(id, storage.clone(), metadata);
+    (disk, id, storage)
+}
+
+fn block(content: &[u8], index: usize, begin: usize) -> Vec<u8> {
+    let start = index * PIECE as usize + begin;
+    content[start..(start + BLOCK).min(content.len())].to_vec()
+}
+
+#[tokio::test]
+async fn pieces_reach_disk_whole_and_verified() {
+    let dir = TempDir::new();
+    let (disk, id, storage) = disk(&dir, cache(1 << 20, 0, 0));
+    let content = content();
+    let file = dir.0.join("data.bin");
+
+    // Блоки приходят не по порядку и ждут в памяти
+    assert_eq!(
+        None,
+        disk.write_block(id, 0, BLOCK, block(&content, 0, BLOCK))
+            .await
+            .unwrap()
+    );
+    assert!(!file.exists());
+    assert_eq!(1, disk.stats().pending_pieces);
+    assert_eq!(
+        Some(true),
+        disk.write_block(id, 0, 0, block(&content, 0, 0))
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        &content[..PIECE as usize],
+        storage.read_piece(0).await.unwrap()
+    );
+
+    // Короткий последний кусок, но с чужими данными
+    assert_eq!(
+        None,
+        disk.write_block(id, 2, 0, vec![0; BLOCK]).await.unwrap()
+    );
+    assert_eq!(
+        Some(false),
+        disk.write_block(id, 2, BLOCK, vec![0; 20_000 - BLOCK])
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        None,
+        disk.write_block(id, 1, 0, block(&content, 1, 0))
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        None,
+        disk.write_block(id, 1, 0, block(&content, 1, 0))
+            .await
+            .unwrap()
+    );
+
+    let stats = disk.stats();
+    assert_eq!(1, stats.pieces_written);
+    assert_eq!(1, stats.hash_failures);
+    assert_eq!(1, stats.pending_pieces);
+    assert_eq!(PIECE, stats.write_cache);
+    assert!(disk.verify_piece(id, 0).await.unwrap());
+    storage.materialize().await.unwrap();
+    assert!(!disk.verify_piece(id, 2).await.unwrap());
+
+    let error = disk
+        .write_block(id, 1, 100, vec![0; BLOCK])
+        .await
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+    let error = disk
+        .write_block(id, 3, 0, vec![0; BLOCK])
+        .await
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+
+    disk.remove_torrent(id);
+    assert_eq!(0, disk.stats().write_cache);
+    let error = disk
+        .write_block(id, 1, 0, vec![0; BLOCK])
+        .await
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::NotFound, error.kind());
+}
+
+#[tokio::test]
+async fn seeding_reads_ahead_into_the_cache() {
+    let dir = TempDir::new();
+    let (disk, id, storage) = disk(&dir, cache(1 << 20, 1 << 20, 1));
+    let content = content();
+    for (index, piece) in content.chunks(PIECE as usize).enumerate() {
+        storage.write_piece(index, piece).await.unwrap();
+    }
+
+    assert_eq!(
+        block(&content, 0, BLOCK),
+        disk.read_block(id, 0, BLOCK, BLOCK).await.unwrap()
+    );
+    assert_eq!(
+        &content[..100],
+        disk.read_block(id, 0, 0, 100).await.unwrap()
+    );
+    // Следующий кусок уже прочитан с упреждением
+    assert_eq!(
+        block(&content, 1, 0),
+        disk.read_block(id, 1, 0, BLOCK).await.unwrap()
+    );
+    let stats = disk.stats();
+    assert_eq!(
+        (2, 1, 1),
+        (stats.read_hits, stats.read_misses, stats.read_ahead)
+    );
+    assert_eq!(2 * PIECE, stats.read_cache);
+
+    // Кеш на один кусок вытесняет самый давний
+    disk.set_cache(&cache(1 << 20, PIECE, 1));
+    assert_eq!(PIECE, disk.stats().read_cache);
+    disk.read_block(id, 1, BLOCK, BLOCK).await.unwrap();
+    assert_eq!(3, disk.stats().read_hits);
+
+    let error = disk.read_block(id, 2, 0, 20_001).await.unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+
+    // Без кеша каждое чтение идёт на диск
+    disk.set_cache(&cache(1 << 20, 0, 1));
+    assert_eq!(0, disk.stats().read_cache);
+    assert_eq!(
+        &content[2 * PIECE as usize..],
+        disk.read_block(id, 2, 0, 20_000).await.unwrap()
+    );
+    assert_eq!(2, disk.stats().read_misses);
+}
+
+#[tokio::test]
+async fn full_cache_spills_and_holds_back_writers() {
+    let dir = TempDir::new();
+    let (disk, id, storage) = disk(&dir, cache(PIECE, 0, 0));
+    let content = content();
+
+    disk.write_block(id, 0, 0, block(&content, 0, 0))
+        .await
+        .unwrap();
+    assert!(disk.congested());
+    let writable = disk.writable();
+    tokio::pin!(writable);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), writable.as_mut())
+            .await
+            .is_err()
+    );
+
+    // Новому куску места нет, и недокачанный первый уходит на диск
+    disk.write_block(id, 1, 0, block(&content, 1, 0))
+        .await
+        .unwrap();
+    assert_eq!(1, disk.stats().spilled);
+    assert_eq!(2, disk.stats().pending_pieces);
+    assert_eq!(
+        Some(true),
+        disk.write_block(id, 0, BLOCK, block(&content, 0, BLOCK))
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        Some(true),
+        disk.write_block(id, 1, BLOCK, block(&content, 1, BLOCK))
+            .await
+            .unwrap()
+    );
+    writable.await;
+
+    assert_eq!(
+        &content[..PIECE as usize],
+        storage.read_piece(0).await.unwrap()
+    );
+    assert_eq!(
+        &content[PIECE as usize..2 * PIECE as usize],
+        storage.read_piece(1).await.unwrap()
+    );
+    let stats = disk.stats();
+    assert_eq!(
+        (2, 0, 0, 0),
+        (
+            stats.pieces_written,
+            stats.pending_pieces,
+            stats.write_cache,
+            stats.queue_depth
+        )
+    );
+    assert!(!stats.congested);
+}
+
+#[test]
+fn disk_cache_sizes_are_parsed() {
+    let mut settings = Settings::default();
+    settings.set("disk.write_cache", "16MiB").unwrap();
+    settings.set("disk.read_cache", "0").unwrap();
+    settings.set("disk.read_ahead", "8").unwrap();
+    assert_eq!(cache(16 << 20, 0, 8), settings.disk);
+    assert_eq!(16 << 20, settings.to_json()["disk"]["write_cache"]);
+
+    assert!(settings.set("disk.write_cache", "0").is_err());
+    assert!(settings.set("disk.read_cache", "lots").is_err());
+    assert!(settings.set("disk.read_ahead", "-1").is_err());
+}
@@ -0,0 +1,114 @@
+use encoding_rs::{SHIFT_JIS, WINDOWS_1251};
+
+use crate::{
+    io::serialization::Serialize,
+    repository::types::{FilesMetadata, InfoHash, TorrentMetadata},
+};
+
+fn string(bytes: &[u8]) -> Vec<u8> {
+    let mut out = format!("{}:", bytes.len()).into_bytes();
+    out.extend_from_slice(bytes);
+    out
+}
+
+/// Торрент из одного файла `path` в каталоге `name`. `utf8` — значения
+/// ключей `name.utf-8` и `path.utf-8`.
+fn torrent(
+    encoding: Option<&str>,
+    name: &[u8],
+    path: &[&[u8]],
+    utf8: Option<(&str, &str)>,
+) -> Vec<u8> {
+    let mut out = b"d8:announce15:http://tracker/".to_vec();
+    if let Some(encoding) = encoding {
+        out.extend(b"8:encoding");
+        out.extend(string(encoding.as_bytes()));
+    }
+    out.extend(b"4:infod5:filesld6:lengthi1e4:pathl");
+    path.iter().for_each(|c| out.extend(string(c)));
+    out.push(b'e');
+    if let Some((_, path)) = utf8 {
+        out.extend(b"10:path.utf-8l");
+        out.extend(string(path.as_bytes()));
+        out.push(b'e');
+    }
+    out.extend(b"ee4:name");
+    out.extend(string(name));
+    if let Some((name, _)) = utf8 {
+        out.extend(b"10:name.utf-8");
+        out.extend(string(name.as_bytes()));
+    }
+    out.extend(b"12:piece lengthi16384e6:pieces20:");
+    out.extend([0; 20]);
+    out.extend(b"ee");
+    out
+}
+
+fn names(metadata: &TorrentMetadata) -> (String, Vec<String>) {
+    let FilesMetadata::Multiple { base_name, files } = &metadata.info.files else {
+        panic!("expected a multi-file torrent");
+    };
+    (base_name.clone(), files[0].path.clone())
+}
+
+#[test]
+fn names_are_decoded_with_encoding_field() {
+    let name = WINDOWS_1251.encode("Фильмы").0;
+    let file = WINDOWS_1251.encode("Сериал.avi").0;
+    let bytes = torrent(Some("windows-1251"), &name, &[&file], None);
+
+    let (metadata, hash) = TorrentMetadata::new(&bytes).unwrap();
+
+    assert_eq!(
+        ("Фильмы".to_string(), vec!["Сериал.avi".to_string()]),
+        names(&metadata)
+    );
+    assert_eq!(Some(name.to_vec()), metadata.info.name_raw);
+    assert_eq!(bytes, metadata.serialize());
+    let (_, again) = TorrentMetadata::new(&metadata.serialize()).unwrap();
+    assert_eq!(hash, again);
+}
+
+#[test]
+fn utf8_keys_take_precedence() {
+    let name = SHIFT_JIS.encode("音楽").0;
+    let file = SHIFT_JIS.encode("曲.mp3").0;
+    let bytes = torrent(None, &name, &[&file], Some(("音楽", "曲.mp3")));
+
+    let (metadata, _) = TorrentMetadata::new(&bytes).unwrap();
+
+    assert_eq!(
+        ("音楽".to_string(), vec!["曲.mp3".to_string()]),
+        names(&metadata)
+    );
+    assert_eq!(Some("音楽".to_string()), metadata.info.name_utf8);
+    assert_eq!(bytes, metadata.serialize());
+}
+
+#[test]
+fn unknown_bytes_load_losslessly() {
+    let name = [b'a', 0xff, b'b'];
+    let bytes = torrent(Some("no-such-encoding"), &name, &[&[0xc0, 0x80]], None);
+
+    let (metadata, hash) = TorrentMetadata::new(&bytes).unwrap();
+    let (display, path) = names(&metadata);
+
+    assert_eq!("a\u{fffd}b", display);
+    assert!(path[0].contains('\u{fffd}'));
+    assert_eq!(bytes, metadata.serialize());
+    assert!(matches!(hash, InfoHash::V1(_)));
+}
+
+#[test]
+fn utf8_names_keep_no_raw_copy() {
+    let bytes = torrent(Some("UTF-8"), "имя".as_bytes(), &[b"file"], None);
+
+    let (metadata, _) = TorrentMetadata::new(&bytes).unwrap();
+
+    assert_eq!(None, metadata.info.name_raw);
+    let FilesMetadata::Multiple { files, .. } = &metadata.info.files else {
+        panic!("expected a multi-file torrent");
+    };
+    assert_eq!(None, files[0].path_raw);
+    assert_eq!(bytes, metadata.serialize());
+}
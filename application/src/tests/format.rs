@@ -0,0 +1,111 @@
+use std::borrow::Cow;
+
+use uuid::Uuid;
+
+use crate::{
+    io::{
+        deserialization::{Decoder, TryDeserialize},
+        serialization::Serialize,
+    },
+    repository::{
+        format::{upgrade, FormatError, FORMAT_VERSION},
+        types::{FilesMetadata, InfoHash, Priority},
+        TorrentRepo,
+    },
+};
+
+/// Репозиторий, записанный самой первой версией клиента: ключи не
+/// отсортированы, у торрента есть только хеш v1.
+const V0: &[u8] = include_bytes!("fixtures/repo_v0.bencode");
+/// Последняя раскладка без номера версии: гибридный хеш, приоритеты
+/// и данные возобновления.
+const V0_RESUME: &[u8] = include_bytes!("fixtures/repo_v0_resume.bencode");
+/// Тот же репозиторий в первой версии формата.
+const V1: &[u8] = include_bytes!("fixtures/repo_v1.bencode");
+
+#[test]
+fn original_layout_is_migrated() {
+    let repo = TorrentRepo::from_bytes(V0).unwrap();
+
+    assert_eq!(2, repo.len());
+    let first = &repo.get_torrent_list()[0];
+    assert_eq!(Uuid::from_bytes(*b"0123456789abcdef"), first.id);
+    assert_eq!(InfoHash::V1(*b"12345678901234567890"), first.value.hash);
+    assert_eq!(vec![6, 4, 5], first.value.downloaded_pieces);
+    assert_eq!(None, first.value.resume);
+
+    let second = &repo.get_torrent_list()[1].value;
+    assert_eq!(256, second.downloaded);
+    let FilesMetadata::Multiple { base_name, files } = &second.metadata.info.files else {
+        panic!("expected a multi-file torrent");
+    };
+    assert_eq!("dir", base_name);
+    assert_eq!(vec!["sub".to_string(), "file".to_string()], files[0].path);
+}
+
+#[test]
+fn unversioned_resume_layout_is_migrated() {
+    let repo = TorrentRepo::from_bytes(V0_RESUME).unwrap();
+    let torrent = &repo.get_torrent_list()[0].value;
+
+    assert_eq!(
+        InfoHash::Hybrid(*b"12345678901234567890", [7; 32]),
+        torrent.hash
+    );
+    assert_eq!(vec![Priority::High], torrent.priorities);
+    let resume = torrent.resume.as_ref().unwrap();
+    assert_eq!("/downloads", resume.save_path);
+    assert_eq!(2, resume.peers.len());
+    assert!(resume.unfinished[0].has_block(0));
+    assert_eq!(repo, TorrentRepo::from_bytes(V1).unwrap());
+}
+
+#[test]
+fn current_version_is_stable() {
+    let repo = TorrentRepo::from_bytes(V1).unwrap();
+
+    assert!(matches!(upgrade(V1).unwrap(), Cow::Borrowed(_)));
+    assert_eq!(V1, &repo.serialize()[..]);
+    assert!(Decoder::new().strict(true).decode(V1).is_ok());
+
+    // Миграция даёт канонический документ, который уже не нужно трогать
+    let migrated = upgrade(V0).unwrap();
+    assert!(Decoder::new().strict(true).decode(&migrated).is_ok());
+    assert!(matches!(upgrade(&migrated).unwrap(), Cow::Borrowed(_)));
+}
+
+fn newer_version() -> Vec<u8> {
+    let mut bytes = V1.to_vec();
+    let at = bytes.len() - b"i1ee".len();
+    bytes.splice(at.., format!("i{}ee", FORMAT_VERSION + 1).into_bytes());
+    bytes
+}
+
+#[test]
+fn newer_versions_are_refused() {
+    let err = TorrentRepo::from_bytes(&newer_version()).unwrap_err();
+
+    assert!(matches!(
+        err,
+        FormatError::UnsupportedVersion { found, supported }
+            if found == FORMAT_VERSION + 1 && supported == FORMAT_VERSION
+    ));
+    assert!(err.to_string().contains("update the client"));
+    // Без миграций старый разбор файл всё ещё понимает
+    assert!(TorrentRepo::try_deserialize(V1).is_ok());
+}
+
+#[tokio::test]
+async fn newer_repo_is_not_replaced_by_backup() {
+    let dir = std::env::temp_dir().join(format!("format-{}", Uuid::new_v4()));
+    std::fs::create_dir(&dir).unwrap();
+    let path = dir.join("torrents.repo");
+    std::fs::write(dir.join("torrents.repo.bak"), V1).unwrap();
+    std::fs::write(&path, newer_version()).unwrap();
+
+    let err = TorrentRepo::load_from(&path).await.unwrap_err();
+
+    assert!(err.downcast_ref::<FormatError>().is_some());
+    assert_eq!(newer_version(), std::fs::read(&path).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,106 @@
+use uuid::Uuid;
+
+use crate::{
+    client::{peer_id_prefix, Identity},
+    network::{bandwidth::Limiter, get_start, TorrentState},
+    repository::types::{InfoHash, Torrent},
+    settings::Settings,
+    storage::Storage,
+    tools::url_encode,
+};
+
+use super::{http_stub, metadata};
+
+#[test]
+fn peer_id_is_azureus_style() {
+    let prefix = format!(
+        "-RC{}{}{}0-",
+        env!("CARGO_PKG_VERSION_MAJOR"),
+        env!("CARGO_PKG_VERSION_MINOR"),
+        env!("CARGO_PKG_VERSION_PATCH")
+    );
+    assert_eq!(prefix.as_bytes(), peer_id_prefix());
+
+    let identity = Identity::generate();
+    assert_eq!(prefix.as_bytes(), &identity.peer_id[..8]);
+    assert!(identity.peer_id[8..].iter().all(u8::is_ascii_alphanumeric));
+
+    // Случайная часть своя у каждой сессии
+    let other = Identity::generate();
+    assert_ne!(identity.peer_id, other.peer_id);
+}
+
+#[test]
+fn tracker_key_is_stable_per_torrent() {
+    let identity = Identity::generate();
+    let first = InfoHash::V1([1; 20]);
+    let second = InfoHash::Hybrid([2; 20], [3; 32]);
+
+    assert_eq!(identity.tracker_key(&first), identity.tracker_key(&first));
+    assert_ne!(identity.tracker_key(&first), identity.tracker_key(&second));
+    assert_ne!(
+        identity.tracker_key(&first),
+        Identity::generate().tracker_key(&first)
+    );
+}
+
+#[test]
+fn prefix_and_key_can_be_replaced() {
+    let mut identity = Identity::generate();
+    let hash = InfoHash::V1([1; 20]);
+    let derived = identity.tracker_key(&hash);
+
+    identity.set_prefix(b"-XX");
+    assert_eq!(b"-XX", identity.prefix());
+    assert_eq!(b"-XX", &identity.peer_id[..3]);
+    assert!(identity.peer_id[3..].iter().all(u8::is_ascii_alphanumeric));
+
+    identity.set_key(Some(7));
+    assert_eq!(7, identity.tracker_key(&hash));
+    identity.set_key(None);
+    assert_eq!(derived, identity.tracker_key(&hash));
+
+    // Ключ следует за секретом, который демон хранит между запусками
+    let mut other = Identity::generate();
+    other.set_secret(identity.secret());
+    assert_eq!(derived, other.tracker_key(&hash));
+}
+
+#[test]
+fn raw_bytes_are_percent_encoded() {
+    assert_eq!("%00%12%FF", url_encode(&[0x00, 0x12, 0xff]));
+    assert_eq!("-RC0100-aZ~._%20%2B", url_encode(b"-RC0100-aZ~._ +"));
+}
+
+#[tokio::test]
+async fn announce_sends_every_field_in_the_query() {
+    let (addr, tracker) = http_stub(b"d8:intervali1800ee".to_vec()).await;
+    let mut announced = metadata("announced");
+    announced.announce = format!("http://{addr}/announce?passkey=abc");
+    let storage = Storage::new(&announced.info, ".");
+    let mut torrent = Torrent::new(announced, InfoHash::V1([0xab; 20]));
+    torrent.downloaded = 300;
+    let mut settings = Settings::default();
+    settings.identity.set_prefix(b"-XX0001-");
+    settings.identity.set_key(Some(0x1f));
+    let state = TorrentState {
+        uploaded: 100,
+        left: 700,
+        ..TorrentState::new(torrent, &storage)
+    };
+    let peer_id = url_encode(&settings.identity.peer_id);
+
+    let client = settings.tracker_client().unwrap();
+    let limiter = Limiter::new();
+    let body = get_start(&client, &state, &settings, &limiter, Uuid::new_v4())
+        .await
+        .unwrap();
+    assert_eq!(b"d8:intervali1800ee", &body[..]);
+    let target = format!(
+        "GET /announce?passkey=abc&info_hash={}&peer_id={peer_id}&port={}\
+         &uploaded=100&downloaded=300&left=700&compact=0&event=started&key=0000001F ",
+        "%AB".repeat(20),
+        settings.listen_port,
+    );
+    assert!(tracker.await.unwrap().starts_with(&target));
+}
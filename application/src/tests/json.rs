@@ -0,0 +1,71 @@
+use serde_json::json;
+
+use crate::io::{
+    deserialization::parse_node,
+    json::{from_json_str, json_to_bencode, node_to_json, to_json_string, BinaryEncoding},
+    pretty::{pretty, PrettyOptions},
+};
+
+#[test]
+fn convert_to_json() {
+    let data: &[u8] = b"d4:infod6:pieces3:\x00\xff\x014:name4:spame4:listli42e4:$eggee";
+
+    let (_, node) = parse_node(data).unwrap();
+
+    assert_eq!(
+        json!({
+            "info": {"name": "spam", "pieces": "$hex:00ff01"},
+            "list": [42, "$hex:24656767"],
+        }),
+        node_to_json(node, BinaryEncoding::Hex)
+    );
+}
+
+#[test]
+fn json_roundtrip() {
+    let data: &[u8] = b"d3:$ab1:\xfe4:infod6:pieces3:\x00\xff\x01e4:listli42e0:ee";
+
+    for encoding in [BinaryEncoding::Hex, BinaryEncoding::Base64] {
+        let json = to_json_string(data, encoding, true).unwrap();
+
+        assert_eq!(data, &from_json_str(&json).unwrap()[..]);
+    }
+}
+
+#[test]
+fn json_sorts_keys_by_raw_bytes() {
+    let value = json!({"b": 1, "$hex:61": 2});
+
+    assert_eq!(b"d1:ai2e1:bi1ee", &json_to_bencode(&value).unwrap()[..]);
+}
+
+#[test]
+fn json_rejects_unsupported_values() {
+    let cases = [
+        json!(-1),
+        json!(1.5),
+        json!(true),
+        json!(null),
+        json!("$unknown"),
+        json!("$hex:0"),
+        json!("$hex:+f"),
+        json!("$hex:0x"),
+        json!({"a": 1, "$hex:61": 2}),
+    ];
+
+    for case in cases {
+        assert!(json_to_bencode(&case).is_err(), "{case}");
+    }
+}
+
+#[test]
+fn pretty_print() {
+    let data: &[u8] = b"d4:infod6:pieces3:\x00\xff\x01e4:listli42eleee";
+
+    let (_, node) = parse_node(data).unwrap();
+
+    assert_eq!(
+        "{\n  \"info\": {\n    \"pieces\": <3 bytes> 00ff01\n  }\n  \"list\": [\n    42\n    []\n  ]\n}\n",
+        pretty(node, PrettyOptions::default())
+    );
+}
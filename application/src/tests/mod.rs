@@ -1,2 +1,159 @@
+mod backend;
+mod bandwidth;
+mod cli;
+mod creation;
+mod daemon;
+mod disk;
+mod encoding;
+mod format;
+mod identity;
+mod json;
 mod parsing;
+mod priorities;
+mod properties;
+mod queue;
+mod repo;
+mod resume;
+mod sanitize;
+#[cfg(feature = "serde")]
+mod serde;
 mod serialization;
+mod settings;
+mod storage;
+mod transmission;
+mod tui;
+mod v2;
+
+use std::{net::SocketAddr, path::PathBuf};
+
+use sha1::{Digest, Sha1};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    task::JoinHandle,
+};
+use uuid::Uuid;
+
+use crate::{
+    daemon::{Daemon, DaemonConfig},
+    error::AsyncErr,
+    repository::{
+        merkle::BLOCK_SIZE,
+        types::{FileAttributes, FileMetadata, FilesMetadata, Info, TorrentMetadata},
+    },
+};
+
+/// Кусок тестовых торрентов из двух блоков, чтобы было что делить.
+const PIECE: u64 = 2 * BLOCK_SIZE;
+/// Кусок в один блок, самый короткий из допустимых.
+const SMALL_PIECE: u64 = BLOCK_SIZE;
+
+/// Каталог во временной папке, удаляется вместе со значением.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> TempDir {
+        let dir = std::env::temp_dir().join(format!("bittorrent-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Данные длины `len`, разные для разных `seed`.
+fn data(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i as u8).wrapping_mul(7) ^ seed).collect()
+}
+
+/// Файл многофайлового торрента с атрибутами `attr`.
+fn entry(path: &[&str], length: u64, attr: Option<&str>) -> FileMetadata {
+    FileMetadata {
+        path: path.iter().map(|s| s.to_string()).collect(),
+        path_raw: None,
+        path_utf8: None,
+        length,
+        md5sum: None,
+        attr: attr.map(|a| FileAttributes(a.to_string())),
+        symlink_path: None,
+        sha1: None,
+    }
+}
+
+/// Торрент из одного файла `name` с содержимым `data`, настоящими хешами
+/// кусков и трекером `http://tracker/announce`.
+pub(super) fn single_file(name: &str, piece_length: u64, data: &[u8]) -> TorrentMetadata {
+    TorrentMetadata {
+        info: Info {
+            piece_length,
+            pieces: Some(
+                data.chunks(piece_length as usize)
+                    .flat_map(Sha1::digest)
+                    .collect(),
+            ),
+            private: None,
+            files: FilesMetadata::Single {
+                name: name.to_string(),
+                length: data.len() as u64,
+                md5sum: None,
+            },
+            meta_version: None,
+            file_tree: None,
+            name_raw: None,
+            name_utf8: None,
+        },
+        announce: "http://tracker/announce".to_string(),
+        encoding: None,
+        httpseeds: None,
+        announce_list: None,
+        creation_date: None,
+        comment: None,
+        created_by: None,
+        url_list: None,
+        piece_layers: None,
+    }
+}
+
+/// Торрент из одного файла в 1000 байт.
+fn metadata(name: &str) -> TorrentMetadata {
+    single_file(name, SMALL_PIECE, &data(1000, 0))
+}
+
+/// Запускает демон. `inspect` узнаёт о нём нужное до запуска, например
+/// адреса.
+async fn start_daemon<T>(
+    config: DaemonConfig,
+    inspect: impl FnOnce(&Daemon) -> T,
+) -> (T, JoinHandle<Result<(), AsyncErr>>) {
+    let daemon = Daemon::bind(config).await.unwrap();
+    let seen = inspect(&daemon);
+    (seen, tokio::spawn(daemon.run(std::future::pending())))
+}
+
+/// HTTP-сервер на один запрос: отвечает `body` и возвращает заголовок
+/// запроса.
+async fn http_stub(body: Vec<u8>) -> (SocketAddr, JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let served = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = vec![];
+        let mut buf = [0; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+        }
+        let head = format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(&body).await.unwrap();
+        String::from_utf8(request).unwrap()
+    });
+    (addr, served)
+}
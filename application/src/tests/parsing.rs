@@ -1,4 +1,6 @@
-use crate::io::deserialization::{parse_node, Node};
+#![allow(clippy::assertions_on_constants)]
+
+use crate::io::deserialization::{parse_node, Decoder, Node, ParsingError};
 
 #[test]
 fn parse_a_pos_num() {
@@ -80,8 +82,8 @@ fn parse_list() {
     assert!(res.is_ok());
     let (next, list) = res.unwrap();
     if let Node::List(list) = list {
-        assert_eq!(Node::String(b"spam"), list[0]);
-        assert_eq!(Node::UnsignedNum(42), list[1]);
+        assert_eq!(Some(Node::String(b"spam")), list.get(0));
+        assert_eq!(Some(Node::UnsignedNum(42)), list.get(1));
     } else {
         assert!(false)
     }
@@ -112,11 +114,11 @@ fn parse_dict() {
 
     assert!(res.is_ok());
     let (next, dict) = res.unwrap();
-    if let Node::Dict(dict, raw) = dict {
-        assert_eq!(b"d4:spami42e5:hello3:lole", raw);
+    if let Node::Dict(dict) = dict {
+        assert_eq!(b"d4:spami42e5:hello3:lole", dict.raw());
         assert_eq!(2, dict.len());
-        assert_eq!(Node::UnsignedNum(42), dict[b"spam" as &[u8]]);
-        assert_eq!(Node::String(b"lol"), dict[b"hello" as &[u8]]);
+        assert_eq!(Some(Node::UnsignedNum(42)), dict.get(b"spam"));
+        assert_eq!(Some(Node::String(b"lol")), dict.get(b"hello"));
     } else {
         assert!(false)
     }
@@ -131,11 +133,83 @@ fn parse_empty_dict() {
 
     assert!(res.is_ok());
     let (next, dict) = res.unwrap();
-    if let Node::Dict(dict, raw) = dict {
-        assert_eq!(b"de", raw);
+    if let Node::Dict(dict) = dict {
+        assert_eq!(b"de", dict.raw());
         assert_eq!(0, dict.len());
     } else {
         assert!(false)
     }
     assert_eq!(b"lol", next);
 }
+
+#[test]
+fn parse_nested_dict() {
+    let inp = b"d1:ad1:bi1ee1:cl1:dee";
+
+    let (next, node) = parse_node(inp).unwrap();
+
+    assert_eq!(b"", next);
+    if let Node::Dict(dict) = node {
+        assert_eq!(
+            Some(Node::UnsignedNum(1)),
+            match dict.get(b"a") {
+                Some(Node::Dict(inner)) => inner.get(b"b"),
+                _ => None,
+            }
+        );
+        assert!(matches!(dict.get(b"c"), Some(Node::List(list)) if list.len() == 1));
+        assert_eq!(None, dict.get(b"b"));
+    } else {
+        panic!("unexpected node")
+    }
+}
+
+#[test]
+fn parse_malformed() {
+    let cases: &[&[u8]] = &[
+        b"",
+        b"i42",
+        b"i4x2e",
+        b"5:spam",
+        b"l4:spam",
+        b"di42ei1ee",
+        b"d4:spame",
+        b"x",
+        b"99999999999999999999999:a",
+        b"i99999999999999999999999e",
+    ];
+
+    for case in cases {
+        assert!(parse_node(case).is_err(), "{:?}", case);
+    }
+}
+
+#[test]
+fn parse_too_deep() {
+    let mut inp = vec![b'l'; 100_000];
+    inp.extend(vec![b'e'; 100_000]);
+
+    assert!(matches!(parse_node(&inp), Err(ParsingError::TooDeep)));
+    assert!(Decoder::new().max_depth(100_000).decode(&inp).is_ok());
+    assert!(matches!(
+        Decoder::new().max_depth(2).decode(b"llleee"),
+        Err(ParsingError::TooDeep)
+    ));
+}
+
+#[test]
+fn parse_strict_keys() {
+    let unsorted = b"d1:bi1e1:ai2ee";
+    let duplicated = b"d1:ai1e1:ai2ee";
+
+    assert!(parse_node(unsorted).is_ok());
+    assert!(matches!(
+        Decoder::new().strict(true).decode(unsorted),
+        Err(ParsingError::UnsortedKeys(_))
+    ));
+    assert!(Decoder::new().strict(true).decode(duplicated).is_err());
+    assert!(Decoder::new()
+        .strict(true)
+        .decode(b"d1:ad1:bi1ee1:bi2ee")
+        .is_ok());
+}
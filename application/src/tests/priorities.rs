@@ -0,0 +1,282 @@
+use std::time::SystemTime;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    daemon::{Daemon, DaemonConfig},
+    io::{deserialization::TryDeserialize, serialization::Serialize},
+    network::picker::PiecePicker,
+    repository::{
+        resume::ResumeData,
+        types::{FilesMetadata, InfoHash, Priority, Torrent, TorrentMetadata},
+        TorrentRepo,
+    },
+    rpc::client::RpcClient,
+    storage::Storage,
+    tools::set_bit,
+};
+
+use super::{data, entry, single_file, start_daemon, TempDir, SMALL_PIECE};
+
+/// `a` и `c` делят куски с `b`, а кусок 2 целиком лежит в `b`:
+/// a = [0, 20000), b = [20000, 60000), c = [60000, 70000).
+fn torrent() -> (TorrentMetadata, Vec<u8>) {
+    let mut stream = data(20_000, 1);
+    stream.extend(data(40_000, 2));
+    stream.extend(data(10_000, 3));
+
+    let mut metadata = single_file("pkg", SMALL_PIECE, &stream);
+    metadata.info.files = FilesMetadata::Multiple {
+        base_name: "pkg".to_string(),
+        files: vec![
+            entry(&["a"], 20_000, None),
+            entry(&["b"], 40_000, None),
+            entry(&["c"], 10_000, None),
+        ],
+    };
+    (metadata, stream)
+}
+
+fn piece(stream: &[u8], index: usize) -> &[u8] {
+    stream.chunks(SMALL_PIECE as usize).nth(index).unwrap()
+}
+
+#[test]
+fn skipped_pieces_are_never_picked() {
+    let (metadata, _) = torrent();
+    let storage = Storage::new(&metadata.info, "/downloads").with_priorities(vec![
+        Priority::Normal,
+        Priority::Skip,
+        Priority::High,
+    ]);
+    let all = [0xff];
+
+    let priorities = storage.piece_priorities();
+    assert_eq!(
+        vec![
+            Priority::Normal,
+            Priority::Normal,
+            Priority::Skip,
+            Priority::High,
+            Priority::High
+        ],
+        priorities
+    );
+
+    let mut picker = PiecePicker::new(priorities, &[0]);
+    assert_eq!(Some(3), picker.pick(&all));
+    picker.set_have(3);
+    assert_eq!(Some(4), picker.pick(&all));
+    picker.set_have(4);
+
+    // Кусок 0 есть у двух пиров, кусок 1 — ни у кого: он реже
+    picker.add_peer(&[0b1000_0000]);
+    picker.add_peer(&[0b1000_0000]);
+    assert_eq!(Some(1), picker.pick(&all));
+    assert_eq!(Some(0), picker.pick(&[0b1010_0000]));
+    assert_eq!(None, picker.pick(&[0b0010_0000]));
+
+    picker.set_have(0);
+    picker.set_have(1);
+    assert!(picker.is_finished());
+}
+
+#[test]
+fn left_counts_only_wanted_bytes() {
+    let (metadata, _) = torrent();
+    let storage = Storage::new(&metadata.info, "/downloads").with_priorities(vec![
+        Priority::Low,
+        Priority::Skip,
+        Priority::Normal,
+    ]);
+    let mut have = vec![0];
+
+    assert_eq!(30_000, storage.left(&have));
+    // Кусок 1: 16384..20000 из `a`, остальное в пропущенном `b`
+    set_bit(&mut have, 1);
+    assert_eq!(30_000 - 3616, storage.left(&have));
+    [0, 3, 4].iter().for_each(|&i| set_bit(&mut have, i));
+    assert_eq!(0, storage.left(&have));
+
+    let all = Storage::new(&metadata.info, "/downloads");
+    assert_eq!(70_000, all.left(&[0]));
+}
+
+#[tokio::test]
+async fn boundary_pieces_of_skipped_files_go_to_part_file() {
+    let dir = TempDir::new();
+    let (metadata, stream) = torrent();
+    let mut storage = Storage::new(&metadata.info, &dir.0).with_priorities(vec![
+        Priority::Normal,
+        Priority::Skip,
+        Priority::Normal,
+    ]);
+
+    storage.materialize().await.unwrap();
+    for index in [0, 1, 3, 4] {
+        storage
+            .write_piece(index, piece(&stream, index))
+            .await
+            .unwrap();
+    }
+
+    assert!(!dir.0.join("pkg/b").exists());
+    assert!(dir.0.join(".pkg.parts").exists());
+    assert_eq!(
+        stream[..20_000],
+        std::fs::read(dir.0.join("pkg/a")).unwrap()
+    );
+    assert_eq!(
+        stream[60_000..],
+        std::fs::read(dir.0.join("pkg/c")).unwrap()
+    );
+    for index in [0, 1, 3, 4] {
+        assert!(storage.verify_piece(&metadata, index).await.unwrap());
+    }
+
+    // Края `b` переезжают из файла частей в сам файл
+    storage.set_priorities(vec![]).await.unwrap();
+    storage.write_piece(2, piece(&stream, 2)).await.unwrap();
+    for index in 0..5 {
+        assert!(storage.verify_piece(&metadata, index).await.unwrap());
+    }
+    assert_eq!(
+        stream[20_000..60_000],
+        std::fs::read(dir.0.join("pkg/b")).unwrap()
+    );
+
+    // И обратно: после пропуска куски на краях всё ещё сходятся
+    storage
+        .set_priorities(vec![Priority::Normal, Priority::Skip])
+        .await
+        .unwrap();
+    assert!(storage.verify_piece(&metadata, 1).await.unwrap());
+    assert!(storage.verify_piece(&metadata, 3).await.unwrap());
+}
+
+#[tokio::test]
+async fn middle_pieces_stay_in_skipped_file() {
+    let dir = TempDir::new();
+    let (metadata, stream) = torrent();
+    let mut storage = Storage::new(&metadata.info, &dir.0);
+    storage.materialize().await.unwrap();
+    for (index, piece) in stream.chunks(SMALL_PIECE as usize).enumerate() {
+        storage.write_piece(index, piece).await.unwrap();
+    }
+
+    // В файл частей переезжают только края `b`, кусок 2 читается из `b`
+    storage
+        .set_priorities(vec![Priority::Normal, Priority::Skip])
+        .await
+        .unwrap();
+    assert_eq!(piece(&stream, 2), storage.read_piece(2).await.unwrap());
+    let mut buf = vec![0; 100];
+    storage.read_blocking(2, 1000, &mut buf).unwrap();
+    assert_eq!(piece(&stream, 2)[1000..1100], buf);
+    for index in 0..5 {
+        assert!(storage.verify_piece(&metadata, index).await.unwrap());
+    }
+
+    // Без самого файла середина читается нулями, а края остаются целы
+    std::fs::remove_file(dir.0.join("pkg/b")).unwrap();
+    assert!(storage.verify_piece(&metadata, 1).await.unwrap());
+    assert_eq!(
+        vec![0; SMALL_PIECE as usize],
+        storage.read_piece(2).await.unwrap()
+    );
+}
+
+#[test]
+fn priorities_are_stored_in_repo() {
+    let (metadata, _) = torrent();
+    let mut torrent = Torrent::new(metadata, InfoHash::V1([1; 20]));
+
+    let plain = torrent.serialize();
+    torrent.priorities = vec![Priority::Normal, Priority::Skip, Priority::High];
+    let restored = Torrent::try_deserialize(&torrent.serialize()).unwrap();
+
+    assert_eq!(torrent, restored);
+    assert_eq!(
+        vec![Priority::Normal, Priority::Skip, Priority::High],
+        restored.priorities
+    );
+    assert_eq!(Priority::Normal, restored.priority(7));
+    // Без приоритетов запись репозитория не меняется
+    assert!(!plain.windows(10).any(|w| w == b"priorities"));
+}
+
+#[tokio::test]
+async fn repo_moves_data_with_file_priority() {
+    let dir = TempDir::new();
+    let (metadata, stream) = torrent();
+    let mut torrent = Torrent::new(metadata.clone(), InfoHash::V1([1; 20]));
+    torrent.resume = Some(ResumeData::new(
+        &Storage::new(&metadata.info, &dir.0),
+        SystemTime::now(),
+    ));
+    let storage = torrent.storage();
+    storage.materialize().await.unwrap();
+    for (index, piece) in stream.chunks(SMALL_PIECE as usize).enumerate() {
+        storage.write_piece(index, piece).await.unwrap();
+    }
+    let mut repo = TorrentRepo::empty();
+    let id = repo.add_new_torrent(torrent).id();
+
+    assert!(repo
+        .set_file_priorities(id, &[(1, Priority::Skip), (2, Priority::High)])
+        .await
+        .unwrap());
+    assert!(!repo
+        .set_file_priorities(Uuid::new_v4(), &[(0, Priority::Skip)])
+        .await
+        .unwrap());
+    let torrent = &repo.get_by_id(id).unwrap().value;
+    assert_eq!(
+        vec![Priority::Normal, Priority::Skip, Priority::High],
+        torrent.priorities
+    );
+    // Края `b` уже в файле частей, и куски на них по-прежнему сходятся
+    std::fs::remove_file(dir.0.join("pkg/b")).unwrap();
+    let storage = torrent.storage();
+    for index in [0, 1, 3, 4] {
+        assert!(storage.verify_piece(&metadata, index).await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn daemon_moves_data_when_a_file_is_skipped_and_unskipped() {
+    let dir = TempDir::new();
+    let (metadata, stream) = torrent();
+    let downloads = dir.0.join("downloads");
+    let (endpoints, daemon) = start_daemon(DaemonConfig::new(&dir.0), Daemon::endpoints).await;
+    let mut client = RpcClient::connect(&endpoints[0], None).await.unwrap();
+    let params = json!({
+        "metainfo": STANDARD.encode(metadata.serialize()),
+        "save_path": downloads,
+        "paused": true,
+    });
+    let id = client.call("torrent.add", params).await.unwrap()["id"].clone();
+
+    let storage = Storage::new(&metadata.info, &downloads);
+    storage.materialize().await.unwrap();
+    for (index, piece) in stream.chunks(SMALL_PIECE as usize).enumerate() {
+        storage.write_piece(index, piece).await.unwrap();
+    }
+
+    // `b` делит куски 1 и 3 с соседями: их части переезжают и обратно
+    for priority in ["skip", "normal"] {
+        let params = json!({ "id": id, "priorities": { "1": priority } });
+        let set = client.call("torrent.set", params).await.unwrap();
+        assert_eq!(priority, set["files"][1]["priority"]);
+        let checked = client
+            .call("torrent.recheck", json!({ "id": id }))
+            .await
+            .unwrap();
+        assert_eq!(70_000, checked["downloaded"]);
+    }
+
+    client.call("daemon.shutdown", json!({})).await.unwrap();
+    daemon.await.unwrap().unwrap();
+}
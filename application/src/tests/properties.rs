@@ -0,0 +1,151 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use proptest::prelude::*;
+
+use crate::{
+    io::{
+        deserialization::{parse_node, Node, TryDeserialize},
+        json::{from_json_str, to_json_string, BinaryEncoding},
+        serialization::{Encoder, Serialize},
+    },
+    repository::{types::FileMetadata, TorrentRepo},
+};
+
+/// Каноническое значение bencode: ключи словарей отсортированы и не повторяются.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Num(u64),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Serialize for Value {
+    fn encode<W: Write>(&self, encoder: &mut Encoder<W>) -> io::Result<()> {
+        match self {
+            Value::Num(num) => encoder.write_unsigned(*num),
+            Value::Bytes(bytes) => encoder.write_bytes(bytes),
+            Value::List(list) => encoder.write_list(list),
+            Value::Dict(dict) => dict
+                .iter()
+                .try_fold(encoder.dict()?, |d, (k, v)| d.required(k, v))?
+                .fin(),
+        }
+    }
+}
+
+impl From<Node<'_>> for Value {
+    fn from(node: Node) -> Self {
+        match node {
+            Node::UnsignedNum(num) => Value::Num(num),
+            Node::String(s) => Value::Bytes(s.to_vec()),
+            Node::List(list) => Value::List(list.iter().map(Value::from).collect()),
+            Node::Dict(dict) => Value::Dict(
+                dict.iter()
+                    .map(|(k, v)| (k.to_vec(), Value::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+fn value() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        any::<u64>().prop_map(Value::Num),
+        prop::collection::vec(any::<u8>(), 0..16).prop_map(Value::Bytes),
+    ];
+
+    leaf.prop_recursive(6, 64, 8, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..8).prop_map(Value::List),
+            prop::collection::btree_map(prop::collection::vec(any::<u8>(), 0..8), inner, 0..8)
+                .prop_map(Value::Dict),
+        ]
+    })
+}
+
+fn file_metadata() -> impl Strategy<Value = FileMetadata> {
+    (
+        prop::collection::vec(".{0,8}", 1..4),
+        any::<u64>(),
+        prop::option::of("[0-9a-f]{32}"),
+    )
+        .prop_map(|(path, length, md5sum)| FileMetadata {
+            path,
+            path_raw: None,
+            path_utf8: None,
+            length,
+            md5sum,
+            attr: None,
+            symlink_path: None,
+            sha1: None,
+        })
+}
+
+proptest! {
+    #[test]
+    fn parse_after_serialize_is_identity(value in value()) {
+        let bytes = value.serialize();
+
+        let (rest, node) = parse_node(&bytes).unwrap();
+
+        prop_assert!(rest.is_empty());
+        prop_assert_eq!(&value, &Value::from(node));
+    }
+
+    #[test]
+    fn serialize_after_parse_is_identity(value in value()) {
+        let bytes = value.serialize();
+
+        let (_, node) = parse_node(&bytes).unwrap();
+
+        prop_assert_eq!(&bytes, &Value::from(node).serialize());
+    }
+
+    #[test]
+    fn json_roundtrip(value in value(), base64 in any::<bool>()) {
+        let bytes = value.serialize();
+        let encoding = if base64 { BinaryEncoding::Base64 } else { BinaryEncoding::Hex };
+
+        let json = to_json_string(&bytes, encoding, false).unwrap();
+
+        prop_assert_eq!(bytes, from_json_str(&json).unwrap());
+    }
+
+    #[test]
+    fn file_metadata_roundtrip(file in file_metadata()) {
+        let bytes = file.serialize();
+
+        prop_assert_eq!(file, FileMetadata::try_deserialize(&bytes).unwrap());
+    }
+
+    #[test]
+    fn arbitrary_bytes_dont_panic(bytes in prop::collection::vec(any::<u8>(), 0..256)) {
+        if let Ok((_, node)) = parse_node(&bytes) {
+            let _ = Value::from(node);
+        }
+        let _ = crate::repository::types::TorrentMetadata::new(&bytes);
+        let _ = TorrentRepo::try_deserialize(&bytes);
+    }
+
+    #[test]
+    fn mutated_values_dont_panic(value in value(), index in any::<prop::sample::Index>(), byte in any::<u8>()) {
+        let mut bytes = value.serialize();
+        let i = index.index(bytes.len());
+        bytes[i] = byte;
+
+        if let Ok((_, node)) = parse_node(&bytes) {
+            let _ = Value::from(node);
+        }
+    }
+}
+
+#[test]
+fn short_hash_in_repo_is_an_error() {
+    let data: &[u8] = b"d8:torrentsld2:id16:0123456789abcdef5:valued4:datad4:infod6:lengthi1e4:name1:a12:piece lengthi1e6:pieces0:e8:announce0:e4:hash3:abc17:downloaded_pieces0:10:downloadedi0eeeee";
+
+    assert!(TorrentRepo::try_deserialize(data).is_err());
+}
@@ -0,0 +1,252 @@
+use std::{path::PathBuf, time::UNIX_EPOCH};
+
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    daemon::DaemonConfig,
+    io::serialization::Serialize,
+    repository::{
+        queue::{Goal, QueueManager, QueueMove},
+        resume::ResumeData,
+        types::{InfoHash, Status, Torrent},
+        Id, TorrentRepo,
+    },
+    rpc::client::RpcClient,
+    settings::{Settings, SettingsSource},
+    storage::Storage,
+};
+
+use super::{metadata, start_daemon, TempDir};
+
+/// Торренты с номерами `0..count`; `seeding` из них уже скачаны.
+fn repo(count: u8, seeding: u8) -> (TorrentRepo, Vec<Id>) {
+    let mut repo = TorrentRepo::empty();
+    let ids = (0..count)
+        .map(|i| {
+            let mut torrent = Torrent::new(metadata(&i.to_string()), InfoHash::V1([i; 20]));
+            if i < seeding {
+                torrent.downloaded_pieces = vec![0x80];
+                torrent.downloaded = 1000;
+            }
+            repo.add_new_torrent(torrent).id()
+        })
+        .collect();
+    (repo, ids)
+}
+
+fn settings(pairs: &[(&str, &str)]) -> Settings {
+    pairs
+        .iter()
+        .fold(SettingsSource::new(), |source, (key, value)| {
+            source.set(*key, *value)
+        })
+        .load()
+        .unwrap()
+}
+
+fn status(repo: &TorrentRepo, id: Id) -> Status {
+    repo.get_by_id(id).unwrap().value.status()
+}
+
+#[test]
+fn queue_positions_follow_moves_and_removals() {
+    let (mut repo, ids) = repo(4, 0);
+    assert_eq!(ids, repo.queue_order());
+
+    assert!(repo.move_in_queue(ids[3], QueueMove::Top));
+    assert!(repo.move_in_queue(ids[0], QueueMove::Down));
+    assert!(repo.move_in_queue(ids[2], QueueMove::To(10)));
+    assert_eq!(vec![ids[3], ids[1], ids[0], ids[2]], repo.queue_order());
+    assert!(repo.move_in_queue(ids[3], QueueMove::Up));
+    assert!(!repo.move_in_queue(Uuid::new_v4(), QueueMove::Bottom));
+
+    // Места идут подряд и после удаления
+    repo.remove_torrent_by_id(ids[1]);
+    let positions: Vec<u64> = repo
+        .queue_order()
+        .iter()
+        .map(|id| repo.get_by_id(*id).unwrap().value.queue.position)
+        .collect();
+    assert_eq!(vec![0, 1, 2], positions);
+    let id = repo
+        .add_new_torrent(Torrent::new(metadata("new"), InfoHash::V1([9; 20])))
+        .id();
+    assert_eq!(Some(&id), repo.queue_order().last());
+}
+
+#[test]
+fn free_slots_are_given_in_queue_order() {
+    let (mut repo, ids) = repo(5, 2);
+    let queue = QueueManager::new(&settings(&[("queue.downloads", "2"), ("queue.seeds", "1")]));
+    assert!(queue.schedule(&mut repo));
+    let statuses: Vec<Status> = ids.iter().map(|id| status(&repo, *id)).collect();
+    assert_eq!(
+        vec![
+            Status::Seeding,
+            Status::Queued,
+            Status::Downloading,
+            Status::Downloading,
+            Status::Queued,
+        ],
+        statuses
+    );
+    assert!(!queue.schedule(&mut repo));
+
+    // Остановленный торрент освобождает место следующему
+    repo.get_by_id_mut(ids[2]).unwrap().paused = true;
+    repo.move_in_queue(ids[1], QueueMove::Top);
+    assert!(queue.schedule(&mut repo));
+    assert_eq!(Status::Downloading, status(&repo, ids[4]));
+    assert_eq!(Status::Seeding, status(&repo, ids[1]));
+    assert_eq!(Status::Queued, status(&repo, ids[0]));
+
+    let unlimited = QueueManager::new(&settings(&[("queue.downloads", "none")]));
+    unlimited.schedule(&mut repo);
+    assert_eq!(0, repo.iter_by_status(Status::Queued).count());
+}
+
+#[test]
+fn seeding_stops_at_its_goals() {
+    let (mut repo, ids) = repo(3, 3);
+    let queue = QueueManager::new(&settings(&[
+        ("queue.seeds", "2"),
+        ("seeding.time", "2"),
+        ("seeding.idle", "1"),
+    ]));
+    queue.schedule(&mut repo);
+    assert_eq!(Status::Queued, status(&repo, ids[2]));
+
+    // Первый отдаёт данные и копит только время раздачи
+    let busy = ids[0];
+    assert!(!queue.tick(&mut repo, 30, |id| id == busy));
+    assert!(queue.tick(&mut repo, 30, |id| id == busy));
+    // Второй простоял минуту
+    assert_eq!(Status::Paused, status(&repo, ids[1]));
+    assert!(repo.get_by_id(ids[1]).unwrap().value.queue.finished);
+    assert_eq!(Status::Seeding, status(&repo, ids[2]));
+    assert_eq!(60, repo.get_by_id(busy).unwrap().value.queue.seeding_time);
+    assert_eq!(0, repo.get_by_id(busy).unwrap().value.queue.idle_time);
+
+    // Собственная цель торрента важнее общей
+    let mut torrent = repo.get_by_id_mut(ids[2]).unwrap();
+    torrent.queue.idle_goal = Goal::Unlimited;
+    torrent.queue.seed_time_goal = Goal::Limit(10);
+    drop(torrent);
+    assert!(queue.tick(&mut repo, 60, |id| id == busy));
+    assert_eq!(Status::Paused, status(&repo, busy));
+    assert!(!queue.tick(&mut repo, 300, |_| false));
+    assert_eq!(Status::Seeding, status(&repo, ids[2]));
+
+    // Огромная цель не переполняется при переводе в секунды
+    repo.get_by_id_mut(ids[2]).unwrap().queue.seed_time_goal = Goal::Limit(u64::MAX);
+    assert!(!queue.tick(&mut repo, 300, |_| false));
+    assert_eq!(Status::Seeding, status(&repo, ids[2]));
+}
+
+#[test]
+fn ratio_goal_is_lifted_on_manual_start() {
+    let (mut repo, ids) = repo(1, 1);
+    let queue = QueueManager::new(&settings(&[("seeding.ratio", "1.5")]));
+    let mut torrent = repo.get_by_id_mut(ids[0]).unwrap();
+    let storage = Storage::new(&torrent.metadata.info, PathBuf::from("/tmp"));
+    torrent.resume = Some(ResumeData {
+        total_uploaded: 1499,
+        ..ResumeData::new(&storage, UNIX_EPOCH)
+    });
+    drop(torrent);
+    assert!(!queue.tick(&mut repo, 1, |_| true));
+
+    repo.get_by_id_mut(ids[0])
+        .unwrap()
+        .resume
+        .as_mut()
+        .unwrap()
+        .total_uploaded = 1500;
+    assert!(queue.tick(&mut repo, 1, |_| true));
+    assert_eq!(Status::Paused, status(&repo, ids[0]));
+
+    let mut torrent = repo.get_by_id_mut(ids[0]).unwrap();
+    queue.release(&mut torrent);
+    torrent.paused = false;
+    assert_eq!(Goal::Unlimited, torrent.queue.ratio_goal);
+    assert!(!torrent.queue.finished);
+    drop(torrent);
+    assert!(!queue.tick(&mut repo, 1, |_| true));
+    assert_eq!(Status::Seeding, status(&repo, ids[0]));
+}
+
+#[tokio::test]
+async fn daemon_keeps_extra_torrents_queued() {
+    let dir = TempDir::new();
+    let config = DaemonConfig::new(dir.0.join("state"))
+        .settings(SettingsSource::new().set("queue.downloads", "1"));
+    let (endpoint, handle) = start_daemon(config, |d| d.endpoints().remove(0)).await;
+    let mut client = RpcClient::connect(&endpoint, None).await.unwrap();
+
+    let mut ids = vec![];
+    for name in ["first", "second", "third"] {
+        let metainfo = base64::Engine::encode(
+            &base64::engine::general_purpose::STANDARD,
+            metadata(name).serialize(),
+        );
+        let added = client
+            .call("torrent.add", json!({ "metainfo": metainfo }))
+            .await
+            .unwrap();
+        ids.push(added["id"].as_str().unwrap().to_string());
+    }
+    let statuses = |list: &serde_json::Value| -> Vec<String> {
+        let list = list.as_array().unwrap();
+        ids.iter()
+            .map(|id| {
+                let torrent = list.iter().find(|t| t["id"] == id.as_str()).unwrap();
+                torrent["status"].as_str().unwrap().to_string()
+            })
+            .collect()
+    };
+    let list = client.call("torrent.list", json!({})).await.unwrap();
+    assert_eq!(vec!["downloading", "queued", "queued"], statuses(&list));
+    let stats = client.call("daemon.stats", json!({})).await.unwrap();
+    assert_eq!(1, stats["active"]);
+
+    let moved = client
+        .call("torrent.queue", json!({ "id": ids[2], "move": "top" }))
+        .await
+        .unwrap();
+    assert_eq!(ids[2], moved["queue"][0]);
+    let list = client.call("torrent.list", json!({})).await.unwrap();
+    assert_eq!(vec!["queued", "queued", "downloading"], statuses(&list));
+
+    client
+        .call("settings.set", json!({ "queue.downloads": "none" }))
+        .await
+        .unwrap();
+    let list = client
+        .call("torrent.list", json!({ "status": "queued" }))
+        .await
+        .unwrap();
+    assert_eq!(json!([]), list);
+
+    let set = client
+        .call(
+            "torrent.set",
+            json!({ "id": ids[0], "ratio_goal": 2.5, "idle_goal": "unlimited" }),
+        )
+        .await
+        .unwrap();
+    assert_eq!(2.5, set["ratio_goal"]);
+    assert_eq!("unlimited", set["idle_goal"]);
+    assert_eq!("global", set["seed_time_goal"]);
+    assert!(client
+        .call("torrent.set", json!({ "id": ids[0], "seed_time_goal": -1 }))
+        .await
+        .is_err());
+    assert!(client
+        .call("torrent.queue", json!({ "id": ids[0], "move": "sideways" }))
+        .await
+        .is_err());
+
+    client.call("daemon.shutdown", json!({})).await.unwrap();
+    handle.await.unwrap().unwrap();
+}
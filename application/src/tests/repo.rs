@@ -0,0 +1,254 @@
+use uuid::Uuid;
+
+use crate::{
+    io::{deserialization::TryDeserialize, serialization::Serialize},
+    repository::{
+        types::{InfoHash, Status, Torrent, TorrentMetadata},
+        Added, TorrentRepo, WithId,
+    },
+};
+
+use super::single_file;
+
+/// Торрент на два куска с трекерами `announce` и `tiers`.
+fn torrent(hash: InfoHash, announce: &str, tiers: Option<Vec<Vec<&str>>>) -> Torrent {
+    let metadata = TorrentMetadata {
+        announce: announce.to_string(),
+        announce_list: tiers.map(|tiers| {
+            tiers
+                .into_iter()
+                .map(|tier| tier.into_iter().map(String::from).collect())
+                .collect()
+        }),
+        ..single_file("file", 16384, &[0; 20_000])
+    };
+    Torrent::new(metadata, hash)
+}
+
+#[test]
+fn torrents_are_found_by_id_and_hash() {
+    let mut repo = TorrentRepo::empty();
+    let v1 = repo
+        .add_new_torrent(torrent(InfoHash::V1([1; 20]), "http://a", None))
+        .id();
+    let hybrid = repo
+        .add_new_torrent(torrent(
+            InfoHash::Hybrid([2; 20], [2; 32]),
+            "http://a",
+            None,
+        ))
+        .id();
+    let v2 = repo
+        .add_new_torrent(torrent(InfoHash::V2([3; 32]), "http://a", None))
+        .id();
+
+    assert_eq!(3, repo.len());
+    assert_eq!(v1, repo.get_by_hash(&InfoHash::V1([1; 20])).unwrap().id);
+    // Гибридный торрент находится по любому из хешей
+    assert_eq!(hybrid, repo.get_by_hash(&InfoHash::V1([2; 20])).unwrap().id);
+    assert_eq!(hybrid, repo.get_by_hash(&InfoHash::V2([2; 32])).unwrap().id);
+    assert_eq!(v2, repo.get_by_hash(&InfoHash::V2([3; 32])).unwrap().id);
+    assert!(repo.get_by_hash(&InfoHash::V1([3; 20])).is_none());
+
+    // После удаления индексы остальных торрентов сдвигаются
+    assert!(repo.remove_torrent_by_id(v1));
+    assert!(!repo.remove_torrent_by_id(v1));
+    assert!(repo.get_by_id(v1).is_none());
+    assert!(repo.get_by_hash(&InfoHash::V1([1; 20])).is_none());
+    assert_eq!(
+        InfoHash::V2([3; 32]),
+        repo.get_by_id(v2).unwrap().value.hash
+    );
+    assert_eq!(hybrid, repo.get_by_hash(&InfoHash::V2([2; 32])).unwrap().id);
+}
+
+#[test]
+fn duplicates_merge_trackers() {
+    let mut repo = TorrentRepo::empty();
+    let hash = InfoHash::V1([1; 20]);
+    let id = repo.add_new_torrent(torrent(hash, "http://a", None)).id();
+
+    let added = repo.add_new_torrent(torrent(
+        hash,
+        "http://b",
+        Some(vec![vec!["http://b", "http://a"], vec!["http://c"]]),
+    ));
+
+    assert_eq!(Added::Merged(id), added);
+    assert_eq!(1, repo.len());
+    let metadata = &repo.get_by_id(id).unwrap().value.metadata;
+    assert_eq!(
+        vec!["http://a", "http://b", "http://c"],
+        metadata.trackers()
+    );
+    assert_eq!(
+        Some(vec![
+            vec!["http://a".to_string()],
+            vec!["http://b".to_string()],
+            vec!["http://c".to_string()]
+        ]),
+        metadata.announce_list
+    );
+
+    // Копия без новых трекеров ничего не меняет
+    let before = repo.clone();
+    assert_eq!(
+        Added::Merged(id),
+        repo.add_new_torrent(torrent(hash, "http://c", None))
+    );
+    assert_eq!(before, repo);
+}
+
+#[test]
+fn edit_does_not_take_another_torrents_hash() {
+    let mut repo = TorrentRepo::empty();
+    let first = repo
+        .add_new_torrent(torrent(InfoHash::V1([1; 20]), "http://a", None))
+        .id();
+    let hybrid = repo
+        .add_new_torrent(torrent(
+            InfoHash::Hybrid([2; 20], [2; 32]),
+            "http://a",
+            None,
+        ))
+        .id();
+
+    // Совпадение хотя бы по одному из хешей гибрида тоже отклоняется
+    let before = repo.clone();
+    for hash in [InfoHash::V1([2; 20]), InfoHash::Hybrid([1; 20], [9; 32])] {
+        let edit = WithId {
+            id: if hash.v2().is_some() { hybrid } else { first },
+            value: torrent(hash, "http://b", None),
+        };
+        assert!(!repo.edit_torrent(edit));
+    }
+    assert_eq!(before, repo);
+
+    // Свой хеш и новый свободный хеш можно оставить
+    let edit = WithId {
+        id: hybrid,
+        value: torrent(InfoHash::Hybrid([2; 20], [3; 32]), "http://b", None),
+    };
+    assert!(repo.edit_torrent(edit));
+    assert_eq!(hybrid, repo.get_by_hash(&InfoHash::V2([3; 32])).unwrap().id);
+    assert!(repo.get_by_hash(&InfoHash::V2([2; 32])).is_none());
+    assert_eq!(2, repo.len());
+}
+
+#[test]
+fn hash_changed_in_place_is_reindexed() {
+    let mut repo = TorrentRepo::empty();
+    let first = repo
+        .add_new_torrent(torrent(InfoHash::V1([1; 20]), "http://a", None))
+        .id();
+    let second = repo
+        .add_new_torrent(torrent(InfoHash::V1([2; 20]), "http://a", None))
+        .id();
+
+    repo.get_by_id_mut(first).unwrap().hash = InfoHash::Hybrid([1; 20], [5; 32]);
+    assert_eq!(first, repo.get_by_hash(&InfoHash::V2([5; 32])).unwrap().id);
+
+    // Чужой хеш откатывается, и индекс по-прежнему ведёт к своим торрентам
+    repo.get_by_id_mut(first).unwrap().hash = InfoHash::V1([2; 20]);
+    assert_eq!(
+        InfoHash::Hybrid([1; 20], [5; 32]),
+        repo.get_by_id(first).unwrap().value.hash
+    );
+    assert_eq!(second, repo.get_by_hash(&InfoHash::V1([2; 20])).unwrap().id);
+    assert_eq!(first, repo.get_by_hash(&InfoHash::V1([1; 20])).unwrap().id);
+}
+
+#[test]
+fn only_written_torrents_get_a_new_revision() {
+    let mut repo = TorrentRepo::empty();
+    let first = repo
+        .add_new_torrent(torrent(InfoHash::V1([1; 20]), "http://a", None))
+        .id();
+    let second = repo
+        .add_new_torrent(torrent(InfoHash::V1([2; 20]), "http://a", None))
+        .id();
+    let (before, other) = (repo.revision(first), repo.revision(second));
+
+    // Чтение через guard правкой не считается
+    assert!(!repo.get_by_id_mut(first).unwrap().paused);
+    assert_eq!(before, repo.revision(first));
+
+    repo.get_by_id_mut(first).unwrap().paused = true;
+    assert_ne!(before, repo.revision(first));
+    assert_eq!(other, repo.revision(second));
+
+    // Копия правится независимо, и номера правок не совпадают
+    let mut copy = repo.clone();
+    copy.get_by_id_mut(second).unwrap().paused = true;
+    repo.get_by_id_mut(second).unwrap().paused = true;
+    assert_ne!(copy.revision(second), repo.revision(second));
+}
+
+#[test]
+fn duplicates_in_stored_repo_are_merged() {
+    let hash = InfoHash::V1([1; 20]);
+    let first = WithId {
+        id: Uuid::new_v4(),
+        value: torrent(hash, "http://a", None),
+    };
+    let second = WithId {
+        id: Uuid::new_v4(),
+        value: torrent(hash, "http://b", None),
+    };
+
+    let repo = TorrentRepo::from_torrents(vec![first.clone(), second]);
+    let restored = TorrentRepo::try_deserialize(&repo.serialize()).unwrap();
+
+    assert_eq!(1, repo.len());
+    assert_eq!(
+        vec!["http://a", "http://b"],
+        repo.get_by_id(first.id).unwrap().value.metadata.trackers()
+    );
+    assert_eq!(repo, restored);
+}
+
+#[test]
+fn torrents_are_filtered_by_status_and_label() {
+    let mut repo = TorrentRepo::empty();
+    let downloading = repo
+        .add_new_torrent(torrent(InfoHash::V1([1; 20]), "http://a", None))
+        .id();
+    let seeding = repo
+        .add_new_torrent(torrent(InfoHash::V1([2; 20]), "http://a", None))
+        .id();
+    let paused = repo
+        .add_new_torrent(torrent(InfoHash::V1([3; 20]), "http://a", None))
+        .id();
+
+    let mut torrent = repo.get_by_id_mut(seeding).unwrap();
+    torrent.downloaded_pieces = vec![0b1100_0000];
+    torrent.labels = vec!["linux".to_string(), "iso".to_string()];
+    drop(torrent);
+    let mut torrent = repo.get_by_id_mut(paused).unwrap();
+    torrent.paused = true;
+    torrent.labels = vec!["linux".to_string()];
+    drop(torrent);
+
+    let ids = |iter: &mut dyn Iterator<Item = &WithId<Torrent>>| -> Vec<Uuid> {
+        iter.map(|t| t.id).collect()
+    };
+    assert_eq!(
+        vec![downloading],
+        ids(&mut repo.iter_by_status(Status::Downloading))
+    );
+    assert_eq!(
+        vec![seeding],
+        ids(&mut repo.iter_by_status(Status::Seeding))
+    );
+    assert_eq!(vec![paused], ids(&mut repo.iter_by_status(Status::Paused)));
+    assert_eq!(vec![seeding, paused], ids(&mut repo.iter_by_label("linux")));
+    assert_eq!(vec![seeding], ids(&mut repo.iter_by_label("iso")));
+    assert!(repo.iter_by_label("movies").next().is_none());
+
+    let restored = TorrentRepo::try_deserialize(&repo.serialize()).unwrap();
+    assert_eq!(repo, restored);
+    assert_eq!(
+        Status::Paused,
+        restored.get_by_id(paused).unwrap().value.status()
+    );
+}
@@ -0,0 +1,232 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde_json::json;
+
+use crate::{
+    daemon::{repo_backend, session::Session, Daemon, DaemonConfig},
+    io::{
+        deserialization::{Decoder, TryDeserialize},
+        serialization::Serialize,
+    },
+    network::bandwidth::{Direction, Limiter},
+    repository::{
+        backend::RepoBackend,
+        resume::{PartialPiece, ResumeData, ResumeOutcome, TrackerState},
+        types::{InfoHash, Torrent},
+        TorrentRepo,
+    },
+    rpc::client::RpcClient,
+    storage::Storage,
+    tools::get_bit,
+};
+
+use super::{data, single_file, start_daemon, TempDir, SMALL_PIECE};
+
+/// Торрент из одного файла `movie` на три куска.
+fn torrent() -> (Torrent, Vec<u8>) {
+    let data: Vec<u8> = (0..40_000).map(|i| (i % 251) as u8).collect();
+    let metadata = single_file("movie", SMALL_PIECE, &data);
+    (Torrent::new(metadata, InfoHash::V1([3; 20])), data)
+}
+
+async fn download(storage: &Storage, data: &[u8]) {
+    storage.materialize().await.unwrap();
+    for (index, piece) in data.chunks(SMALL_PIECE as usize).enumerate() {
+        storage.write_piece(index, piece).await.unwrap();
+    }
+}
+
+#[test]
+fn resume_data_round_trip() {
+    let (mut torrent, _) = torrent();
+    let storage = Storage::new(&torrent.metadata.info, "/downloads");
+    let mut resume = ResumeData::new(&storage, SystemTime::UNIX_EPOCH);
+    let mut partial = PartialPiece::new(2, 7232);
+    partial.set_block(0);
+    resume.unfinished.push(partial);
+    resume.total_uploaded = 10;
+    resume.total_downloaded = 20;
+    resume.peers = vec![
+        "10.0.0.1:6881".parse::<SocketAddr>().unwrap(),
+        "[::1]:51413".parse().unwrap(),
+    ];
+    resume.trackers.push(TrackerState {
+        url: "http://tracker/announce".to_string(),
+        last_announce: Some(1_700_000_000),
+        interval: Some(1800),
+        tracker_id: Some("abc".to_string()),
+        failures: 1,
+    });
+    resume.completed = Some(1_700_000_100);
+    torrent.resume = Some(resume);
+
+    let bytes = torrent.serialize();
+    let restored = Torrent::try_deserialize(&bytes).unwrap();
+
+    assert!(Decoder::new().strict(true).decode(&bytes).is_ok());
+    assert_eq!(torrent, restored);
+    let resume = restored.resume.unwrap();
+    assert_eq!("/downloads", resume.save_path);
+    assert!(resume.unfinished[0].has_block(0));
+    assert!(!resume.unfinished[0].has_block(1));
+}
+
+#[tokio::test]
+async fn unchanged_files_are_trusted() {
+    let dir = TempDir::new();
+    let (mut torrent, data) = torrent();
+    let storage = Storage::new(&torrent.metadata.info, &dir.0);
+    download(&storage, &data).await;
+
+    // Первый запуск без данных возобновления всегда перепроверяет
+    assert_eq!(
+        ResumeOutcome::Rechecked,
+        torrent.resume(&storage).await.unwrap()
+    );
+    assert!((0..3).all(|i| get_bit(&torrent.downloaded_pieces, i)));
+    assert_eq!(40_000, torrent.downloaded);
+
+    let resume = torrent.resume.as_mut().unwrap();
+    resume.unfinished.push(PartialPiece::new(1, SMALL_PIECE));
+    resume.capture(&storage).await.unwrap();
+    let mut restored = Torrent::try_deserialize(&torrent.serialize()).unwrap();
+
+    assert_eq!(
+        ResumeOutcome::Resumed,
+        restored.resume(&storage).await.unwrap()
+    );
+    assert_eq!(torrent, restored);
+}
+
+#[tokio::test]
+async fn changed_files_fall_back_to_recheck() {
+    let dir = TempDir::new();
+    let (mut torrent, data) = torrent();
+    let storage = Storage::new(&torrent.metadata.info, &dir.0);
+    download(&storage, &data).await;
+    torrent.resume(&storage).await.unwrap();
+    torrent
+        .resume
+        .as_mut()
+        .unwrap()
+        .unfinished
+        .push(PartialPiece::new(1, SMALL_PIECE));
+
+    // Файл обрезан за спиной клиента: куски 1 и 2 потеряны
+    std::fs::write(dir.0.join("movie"), &data[..30_000]).unwrap();
+
+    assert_eq!(
+        ResumeOutcome::Rechecked,
+        torrent.resume(&storage).await.unwrap()
+    );
+    assert!(get_bit(&torrent.downloaded_pieces, 0));
+    assert!(!get_bit(&torrent.downloaded_pieces, 1));
+    assert!(!get_bit(&torrent.downloaded_pieces, 2));
+    assert_eq!(SMALL_PIECE, torrent.downloaded);
+    assert!(torrent.resume.as_ref().unwrap().unfinished.is_empty());
+
+    std::fs::remove_file(dir.0.join("movie")).unwrap();
+    torrent.resume(&storage).await.unwrap();
+    assert_eq!(0, torrent.downloaded);
+}
+
+#[tokio::test]
+async fn recheck_rebuilds_an_empty_bitfield() {
+    let dir = TempDir::new();
+    let (mut torrent, data) = torrent();
+    let storage = Storage::new(&torrent.metadata.info, &dir.0);
+    download(&storage, &data).await;
+    // Повреждённые данные возобновления без битового поля
+    torrent.downloaded_pieces.clear();
+
+    torrent.recheck(&storage).await.unwrap();
+
+    assert_eq!(1, torrent.downloaded_pieces.len());
+    assert!((0..3).all(|i| get_bit(&torrent.downloaded_pieces, i)));
+    assert_eq!(40_000, torrent.downloaded);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn recheck_leaves_the_runtime_responsive() {
+    const BIG: u64 = 16 << 20;
+    let dir = TempDir::new();
+    let data = data(2 * BIG as usize, 5);
+    let mut torrent = Torrent::new(single_file("big", BIG, &data), InfoHash::V1([5; 20]));
+    let storage = Storage::new(&torrent.metadata.info, &dir.0);
+    storage.materialize().await.unwrap();
+    for (index, piece) in data.chunks(BIG as usize).enumerate() {
+        storage.write_piece(index, piece).await.unwrap();
+    }
+
+    let check = tokio::spawn(async move {
+        torrent.recheck(&storage).await.unwrap();
+        torrent
+    });
+    // Рантайм в одном потоке продолжает будить таймеры, пока идёт проверка
+    let mut slowest = Duration::ZERO;
+    while !check.is_finished() {
+        let start = Instant::now();
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        slowest = slowest.max(start.elapsed());
+    }
+    let torrent = check.await.unwrap();
+
+    assert_eq!(2 * BIG, torrent.downloaded);
+    assert!(slowest < Duration::from_millis(100), "{slowest:?}");
+}
+
+#[tokio::test]
+async fn daemon_resumes_torrents_on_start() {
+    let dir = TempDir::new();
+    let (mut torrent, data) = torrent();
+    let storage = Storage::new(&torrent.metadata.info, dir.0.join("downloads"));
+    torrent.resume = Some(ResumeData::new(&storage, SystemTime::now()));
+    torrent.paused = true;
+    download(&storage, &data).await;
+    let mut repo = TorrentRepo::empty();
+    let id = repo.add_new_torrent(torrent).id().to_string();
+    repo_backend(&dir.0).save(&repo).await.unwrap();
+
+    let restart = || async {
+        let (endpoints, daemon) = start_daemon(DaemonConfig::new(&dir.0), Daemon::endpoints).await;
+        let mut client = RpcClient::connect(&endpoints[0], None).await.unwrap();
+        let info = client
+            .call("torrent.get", json!({ "id": id }))
+            .await
+            .unwrap();
+        client.call("daemon.shutdown", json!({})).await.unwrap();
+        daemon.await.unwrap().unwrap();
+        info
+    };
+
+    // Битового поля нет, а файлы есть: демон их проверяет и запоминает
+    assert_eq!(40_000, restart().await["downloaded"]);
+    let saved = repo_backend(&dir.0).load().await.unwrap();
+    let resume = saved.iter().next().unwrap().value.resume.clone().unwrap();
+    assert_eq!(storage.file_states().await.unwrap(), resume.files);
+
+    // Файл обрезан, пока демон не работал
+    std::fs::write(dir.0.join("downloads/movie"), &data[..30_000]).unwrap();
+    assert_eq!(SMALL_PIECE, restart().await["downloaded"]);
+}
+
+#[test]
+fn session_counts_transferred_payload() {
+    let (torrent, _) = torrent();
+    let mut repo = TorrentRepo::empty();
+    let id = repo.add_new_torrent(torrent).id();
+    let limiter = Limiter::new();
+    let mut session = Session::new(repo.get_by_id(id).unwrap());
+
+    limiter.record(Some(id), Direction::Download, 1100, 1000);
+    limiter.record(Some(id), Direction::Upload, 60, 50);
+    assert_eq!((1000, 50), session.refresh(&limiter));
+    // Уже учтённое второй раз не считается
+    limiter.record(Some(id), Direction::Download, 20, 0);
+    assert_eq!((0, 0), session.refresh(&limiter));
+    limiter.record_payload(Some(id), Direction::Upload, 7);
+    assert_eq!((0, 7), session.refresh(&limiter));
+}
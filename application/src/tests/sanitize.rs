@@ -0,0 +1,212 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    repository::types::{FileMetadata, FilesMetadata, Info},
+    storage::{
+        sanitize::{sanitize_paths, Issue, MAX_COMPONENT_LENGTH},
+        Storage,
+    },
+};
+
+use super::{entry, TempDir, SMALL_PIECE};
+
+fn strings(path: &[&str]) -> Vec<String> {
+    path.iter().map(|s| s.to_string()).collect()
+}
+
+fn info(name: &str, files: Vec<FileMetadata>) -> Info {
+    Info {
+        piece_length: SMALL_PIECE,
+        pieces: Some(vec![0; 20]),
+        private: None,
+        files: FilesMetadata::Multiple {
+            base_name: name.to_string(),
+            files,
+        },
+        meta_version: None,
+        file_tree: None,
+        name_raw: None,
+        name_utf8: None,
+    }
+}
+
+/// Путь файла, ожидаемый результат и найденные проблемы.
+#[test]
+fn malicious_paths_are_rewritten() {
+    let long = format!("{}.txt", "a".repeat(300));
+    let table: Vec<(Vec<&str>, Vec<&str>, Vec<Issue>)> = vec![
+        (
+            vec!["..", "..", "etc", "passwd"],
+            vec!["etc", "passwd"],
+            vec![Issue::ParentDir],
+        ),
+        (
+            vec!["/etc/passwd"],
+            vec!["etc_passwd"],
+            vec![Issue::Absolute, Issue::InvalidChar],
+        ),
+        (
+            vec!["C:\\Windows", "system32"],
+            vec!["Windows", "system32"],
+            vec![Issue::Absolute],
+        ),
+        (vec!["a\0b"], vec!["a_b"], vec![Issue::InvalidChar]),
+        (
+            vec!["a/../../b"],
+            vec!["a_.._.._b"],
+            vec![Issue::InvalidChar],
+        ),
+        (vec!["", ".", "x"], vec!["x"], vec![Issue::Empty]),
+        (
+            vec!["..", "."],
+            vec!["_"],
+            vec![Issue::Empty, Issue::ParentDir],
+        ),
+        (vec!["..."], vec!["_"], vec![Issue::Empty, Issue::Reserved]),
+        (vec!["CON"], vec!["_CON"], vec![Issue::Reserved]),
+        (
+            vec!["dir", "aux.txt"],
+            vec!["dir", "_aux.txt"],
+            vec![Issue::Reserved],
+        ),
+        (vec!["name. "], vec!["name"], vec![Issue::Reserved]),
+        (vec!["ok", "plain.txt"], vec!["ok", "plain.txt"], vec![]),
+    ];
+
+    for (original, expected, issues) in table {
+        let sanitized = sanitize_paths(Some("pkg"), &[(strings(&original), false)]);
+
+        let mut full = vec!["pkg"];
+        full.extend(&expected);
+        assert_eq!(vec![strings(&full)], sanitized.paths, "{original:?}");
+        match sanitized.changes.first() {
+            Some(change) => assert_eq!(issues, change.issues, "{original:?}"),
+            None => assert!(issues.is_empty(), "{original:?}"),
+        }
+    }
+
+    let sanitized = sanitize_paths(None, &[(vec![long], false)]);
+    let name = &sanitized.paths[0][0];
+    assert_eq!(MAX_COMPONENT_LENGTH, name.len());
+    assert!(name.ends_with(".txt"));
+    assert_eq!(vec![Issue::TooLong], sanitized.changes[0].issues);
+
+    let cyrillic = "я".repeat(200);
+    let sanitized = sanitize_paths(None, &[(vec![cyrillic], false)]);
+    assert!(sanitized.paths[0][0].len() <= MAX_COMPONENT_LENGTH);
+}
+
+#[test]
+fn colliding_paths_are_numbered() {
+    let paths = [
+        (strings(&["x.txt"]), false),
+        (strings(&["X.TXT"]), false),
+        (strings(&["x.txt"]), false),
+        (strings(&["d"]), false),
+        (strings(&["d", "e"]), false),
+        (strings(&["d", "f"]), false),
+        (strings(&["g", "h"]), false),
+        (strings(&["g"]), false),
+        // Файлы выравнивания одной длины повторяются законно
+        (strings(&[".pad", "10"]), true),
+        (strings(&[".pad", "10"]), true),
+    ];
+
+    let sanitized = sanitize_paths(None, &paths);
+
+    let joined: Vec<String> = sanitized.paths.iter().map(|p| p.join("/")).collect();
+    assert_eq!(
+        vec![
+            "x.txt", "X_1.TXT", "x_2.txt", "d", "d_1/e", "d_1/f", "g/h", "g_1", ".pad/10",
+            ".pad/10"
+        ],
+        joined
+    );
+    assert_eq!(5, sanitized.changes.len());
+    assert!(sanitized
+        .changes
+        .iter()
+        .all(|c| c.issues == vec![Issue::Duplicate] && !c.is_escape()));
+}
+
+#[test]
+fn storage_reports_changes() {
+    let mut link = entry(&["link"], 0, Some("l"));
+    link.symlink_path = Some(strings(&["..", "..", "etc", "passwd"]));
+    let mut to_renamed = entry(&["good"], 0, Some("l"));
+    to_renamed.symlink_path = Some(strings(&["CON"]));
+    let info = info(
+        "..",
+        vec![
+            entry(&["CON"], 1, None),
+            entry(&["..", "evil"], 1, None),
+            link,
+            to_renamed,
+        ],
+    );
+
+    let storage = Storage::new(&info, "/downloads");
+
+    let paths: Vec<String> = storage.files().iter().map(|f| f.path.join("/")).collect();
+    assert_eq!(vec!["_/_CON", "_/evil", "_/link", "_/good"], paths);
+    assert_eq!(
+        Some(strings(&["etc", "passwd"])),
+        storage.files()[2].symlink_path
+    );
+    assert_eq!(Some(strings(&["_CON"])), storage.files()[3].symlink_path);
+
+    let changes = storage.path_changes();
+    assert_eq!(strings(&[".."]), changes[0].original);
+    assert_eq!(strings(&["..", "..", "evil"]), changes[2].original);
+    assert!(changes[2].is_escape());
+    assert_eq!(
+        r#""../../evil" -> "_/evil" (parent directory reference)"#,
+        changes[2].to_string()
+    );
+}
+
+fn walk(dir: &Path, out: &mut Vec<PathBuf>) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        out.push(path.clone());
+        if path.is_dir() && !path.is_symlink() {
+            walk(&path, out);
+        }
+    }
+}
+
+#[tokio::test]
+async fn malicious_torrent_stays_inside_root() {
+    let outer = TempDir::new();
+    let root = outer.0.join("downloads");
+    let mut link = entry(&["up"], 0, Some("l"));
+    link.symlink_path = Some(strings(&["..", "..", ".."]));
+    let info = info(
+        "../escape",
+        vec![
+            entry(&["..", "..", "outside"], 1, None),
+            entry(&["/abs"], 1, None),
+            entry(&["up"], 1, None),
+            link,
+            entry(&["up", "through"], 1, None),
+        ],
+    );
+
+    let storage = Storage::new(&info, &root);
+    storage.materialize().await.unwrap();
+    storage.write_piece(0, &[1, 2, 3, 4]).await.unwrap();
+
+    let mut created = vec![];
+    walk(&outer.0, &mut created);
+    assert!(created.iter().all(|p| p.starts_with(&root)), "{created:?}");
+    for path in created.iter().filter(|p| p.is_symlink()) {
+        let target = std::fs::canonicalize(path).unwrap_or(path.clone());
+        assert!(
+            target.starts_with(std::fs::canonicalize(&root).unwrap()),
+            "{target:?}"
+        );
+    }
+    for file in storage.files() {
+        assert!(storage.file_path(file).starts_with(&root));
+    }
+}
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+
+use crate::io::serde_bencode::{from_bytes, to_bytes};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct File<'a> {
+    #[serde(rename = "piece length")]
+    piece_length: u64,
+    #[serde(with = "serde_bytes")]
+    pieces: &'a [u8],
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(default)]
+    private: bool,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Event {
+    Started,
+    Stopped,
+    Peers(Vec<String>),
+}
+
+#[test]
+fn serialize_struct_with_sorted_keys() {
+    let file = File {
+        piece_length: 16,
+        pieces: b"abab",
+        name: "spam".to_string(),
+        comment: None,
+        private: true,
+    };
+
+    let bytes = to_bytes(&file).unwrap();
+
+    assert_eq!(
+        b"d4:name4:spam12:piece lengthi16e6:pieces4:abab7:privatei1ee" as &[u8],
+        &bytes[..]
+    );
+}
+
+#[test]
+fn deserialize_struct_with_missing_optional() {
+    let data: &[u8] = b"d4:name4:spam12:piece lengthi16e6:pieces4:abab5:extrai3ee";
+
+    let file: File = from_bytes(data).unwrap();
+
+    assert_eq!(
+        File {
+            piece_length: 16,
+            pieces: b"abab",
+            name: "spam".to_string(),
+            comment: None,
+            private: false,
+        },
+        file
+    );
+}
+
+#[test]
+fn roundtrip_struct() {
+    let file = File {
+        piece_length: 256,
+        pieces: &[0, 159, 146, 150],
+        name: "eggs".to_string(),
+        comment: Some("FOOBAR".to_string()),
+        private: false,
+    };
+
+    let bytes = to_bytes(&file).unwrap();
+    let new: File = from_bytes(&bytes).unwrap();
+
+    assert_eq!(file, new);
+}
+
+#[test]
+fn roundtrip_enum() {
+    let events = vec![
+        Event::Started,
+        Event::Peers(vec!["a".to_string()]),
+        Event::Stopped,
+    ];
+
+    let bytes = to_bytes(&events).unwrap();
+    let new: Vec<Event> = from_bytes(&bytes).unwrap();
+
+    assert_eq!(b"l7:startedd5:peersl1:aee7:stoppede" as &[u8], &bytes[..]);
+    assert_eq!(events, new);
+}
+
+#[test]
+fn reject_trailing_data() {
+    let data: &[u8] = b"i42ei42e";
+
+    assert!(from_bytes::<u64>(data).is_err());
+}
+
+#[test]
+fn reject_negative_number() {
+    assert!(to_bytes(&-1i64).is_err());
+}
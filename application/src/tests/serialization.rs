@@ -1,13 +1,23 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::Mutex;
 use uuid::Uuid;
 
 use crate::{
-    io::{deserialization::TryDeserialize, serialization::Serialize},
+    io::{
+        deserialization::{Decoder, TryDeserialize},
+        serialization::{write_async, BencodeDictBuilder, Encoder, Serialize},
+    },
     repository::{
-        types::{FileMetadata, FilesMetadata, Info, Torrent, TorrentMetadata},
+        backend::{autosave, RepoBackend, SingleFile},
+        queue::{Goal, QueueState},
+        types::{FileMetadata, FilesMetadata, Info, InfoHash, Torrent, TorrentMetadata},
         TorrentRepo, WithId,
     },
 };
 
+use super::TempDir;
+
 #[test]
 fn serialize_str() {
     let data: &[u8] = b"4:spam";
@@ -50,7 +60,7 @@ fn serialize_list() {
 
 #[test]
 fn serialize_file() {
-    let data: &[u8] = b"d4:pathl5:abobae6:lengthi42ee";
+    let data: &[u8] = b"d6:lengthi42e4:pathl5:abobaee";
 
     let file = FileMetadata::try_deserialize(data).unwrap();
     let new = &file.serialize();
@@ -64,35 +74,59 @@ fn serialize_file() {
 // }
 
 fn generate_repo_object() -> TorrentRepo {
-    TorrentRepo {
-        torrents: vec![WithId {
-            id: Uuid::new_v4(),
-            value: Torrent {
-                metadata: TorrentMetadata {
-                    info: Info {
-                        piece_length: 256,
-                        pieces: "QWERTYILKNAWKJN".to_string().as_bytes().to_vec(),
-                        private: Some(1),
-                        files: FilesMetadata::Single {
-                            name: "1".to_string(),
-                            length: 16,
-                            md5sum: None,
-                        },
+    TorrentRepo::from_torrents(vec![WithId {
+        id: Uuid::new_v4(),
+        value: Torrent {
+            metadata: TorrentMetadata {
+                info: Info {
+                    piece_length: 256,
+                    pieces: Some("QWERTYILKNAWKJN".to_string().as_bytes().to_vec()),
+                    private: Some(1),
+                    files: FilesMetadata::Single {
+                        name: "1".to_string(),
+                        length: 16,
+                        md5sum: None,
                     },
-                    announce: "TEST".to_string(),
-                    encoding: None,
-                    httpseeds: None,
-                    announce_list: Some(vec![vec!["TEST1".to_string(), "TEST2".to_string()]]),
-                    creation_date: Some(123),
-                    comment: Some("FOOBAR".to_string()),
-                    created_by: Some("Zalygin".to_string()),
+                    meta_version: None,
+                    file_tree: None,
+                    name_raw: None,
+                    name_utf8: None,
                 },
-                hash: *b"12345678901234567890",
-                downloaded_pieces: vec![6u8, 4u8, 5u8],
-                downloaded: 0,
+                announce: "TEST".to_string(),
+                encoding: None,
+                httpseeds: None,
+                announce_list: Some(vec![vec!["TEST1".to_string(), "TEST2".to_string()]]),
+                creation_date: Some(123),
+                comment: Some("FOOBAR".to_string()),
+                created_by: Some("Zalygin".to_string()),
+                url_list: Some(vec!["http://seed/".to_string()]),
+                piece_layers: None,
             },
-        }],
-    }
+            hash: InfoHash::V1(*b"12345678901234567890"),
+            downloaded_pieces: vec![6u8, 4u8, 5u8],
+            downloaded: 0,
+            priorities: vec![],
+            resume: None,
+            paused: false,
+            labels: vec![],
+            download_limit: Some(512 * 1024),
+            upload_limit: None,
+            queue: QueueState {
+                position: 3,
+                queued: true,
+                seeding_time: 7200,
+                ratio_goal: Goal::Limit(1500),
+                idle_goal: Goal::Unlimited,
+                ..QueueState::default()
+            },
+        },
+    }])
+}
+
+/// Меняет торрент в репозитории, чтобы было что сохранять.
+fn touch(repo: &mut TorrentRepo) {
+    let id = repo.get_torrent_list()[0].id;
+    repo.get_by_id_mut(id).unwrap().downloaded = 4242;
 }
 
 #[test]
@@ -109,3 +143,181 @@ fn serialize_repo() {
     let new_repo = new_repo.unwrap();
     assert_eq!(repo, new_repo);
 }
+
+#[test]
+fn repo_is_canonical() {
+    let bytes = generate_repo_object().serialize();
+
+    assert!(Decoder::new().strict(true).decode(&bytes).is_ok());
+}
+
+#[test]
+fn url_list_as_single_string() {
+    let data: &[u8] = b"d8:announce4:TEST4:infod6:lengthi1e4:name1:112:piece lengthi1e6:pieces0:e8:url-list12:http://seed/e";
+
+    let metadata = TorrentMetadata::try_deserialize(data).unwrap();
+
+    assert_eq!(Some(vec!["http://seed/".to_string()]), metadata.url_list);
+}
+
+#[test]
+fn encode_into_writer() {
+    let repo = generate_repo_object();
+
+    let mut encoder = Encoder::new(vec![]);
+    repo.encode(&mut encoder).unwrap();
+
+    assert_eq!(repo.serialize(), encoder.into_inner());
+}
+
+#[test]
+fn serialize_into_reused_buffer() {
+    let mut buf = b"i1e".to_vec();
+
+    42u64.serialize_into(&mut buf);
+    vec!["spam".to_string()].serialize_into(&mut buf);
+
+    assert_eq!(b"i1ei42el4:spame", &buf[..]);
+}
+
+#[test]
+fn dict_builder_still_works() {
+    let data = BencodeDictBuilder::new()
+        .required(b"length", 42u64)
+        .optional::<String>(b"md5sum", None)
+        .required(b"path", vec!["aboba".to_string()])
+        .fin();
+
+    let file = FileMetadata::try_deserialize(&data).unwrap();
+
+    assert_eq!(data, file.serialize());
+}
+
+#[tokio::test]
+async fn write_repo_async() {
+    let repo = generate_repo_object();
+    let mut out: Vec<u8> = vec![];
+    let mut buf = vec![];
+
+    write_async(&repo, &mut out, &mut buf).await.unwrap();
+
+    assert_eq!(repo, TorrentRepo::try_deserialize(&out).unwrap());
+}
+
+#[tokio::test]
+async fn large_repo_is_written_in_chunks() {
+    let template = generate_repo_object().get_torrent_list()[0].value.clone();
+    let repo = TorrentRepo::from_torrents(
+        (0..1000u32)
+            .map(|i| {
+                let mut hash = [0; 20];
+                hash[..4].copy_from_slice(&i.to_be_bytes());
+                WithId {
+                    id: Uuid::new_v4(),
+                    value: Torrent {
+                        hash: InfoHash::V1(hash),
+                        ..template.clone()
+                    },
+                }
+            })
+            .collect(),
+    );
+    let mut out: Vec<u8> = vec![];
+    let mut buf = vec![];
+
+    write_async(&repo, &mut out, &mut buf).await.unwrap();
+
+    // Буфер не разрастается до размера всего репозитория
+    assert_eq!(repo.serialize(), out);
+    assert!(buf.capacity() < out.len() / 2);
+}
+
+#[tokio::test]
+async fn save_and_load_repo() {
+    let repo = generate_repo_object();
+    let path = std::env::temp_dir().join(format!("repo-{}", Uuid::new_v4()));
+    let mut buf = vec![];
+
+    repo.save_to(&path, &mut buf).await.unwrap();
+    let loaded = TorrentRepo::load_from(&path).await.unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+
+    assert_eq!(repo, loaded);
+}
+
+#[tokio::test]
+async fn save_keeps_previous_generation() {
+    let dir = TempDir::new();
+    let path = dir.0.join("torrents.repo");
+    let old = generate_repo_object();
+    let mut new = old.clone();
+    touch(&mut new);
+    let mut buf = vec![];
+
+    old.save_to(&path, &mut buf).await.unwrap();
+    new.save_to(&path, &mut buf).await.unwrap();
+
+    assert_eq!(new, TorrentRepo::load_from(&path).await.unwrap());
+    let backup = std::fs::read(dir.0.join("torrents.repo.bak")).unwrap();
+    assert_eq!(old, TorrentRepo::try_deserialize(&backup).unwrap());
+    assert!(!dir.0.join("torrents.repo.tmp").exists());
+}
+
+#[tokio::test]
+async fn broken_repo_is_recovered_from_backup() {
+    let dir = TempDir::new();
+    let path = dir.0.join("torrents.repo");
+    let old = generate_repo_object();
+    let mut new = old.clone();
+    touch(&mut new);
+    let mut buf = vec![];
+    old.save_to(&path, &mut buf).await.unwrap();
+    new.save_to(&path, &mut buf).await.unwrap();
+
+    // Запись оборвалась на полпути
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+
+    assert_eq!(old, TorrentRepo::load_from(&path).await.unwrap());
+    assert!(dir.0.join("torrents.repo.corrupt").exists());
+    // Следующее сохранение не затирает резервную копию битым файлом
+    new.save_to(&path, &mut buf).await.unwrap();
+    let backup = std::fs::read(dir.0.join("torrents.repo.bak")).unwrap();
+    assert_eq!(old, TorrentRepo::try_deserialize(&backup).unwrap());
+
+    // Основной файл пропал
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(old, TorrentRepo::load_from(&path).await.unwrap());
+
+    std::fs::remove_file(dir.0.join("torrents.repo.bak")).unwrap();
+    assert!(TorrentRepo::load_from(&path).await.is_err());
+}
+
+#[tokio::test]
+async fn autosave_writes_only_changes() {
+    let dir = TempDir::new();
+    let path = dir.0.join("torrents.repo");
+    let repo = Arc::new(Mutex::new(generate_repo_object()));
+    let mut backend = SingleFile::new(&path);
+    backend.save(&*repo.lock().await).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // Хранилище помнит, что уже записало, и не трогает файл зря
+    let task = tokio::spawn(autosave(
+        repo.clone(),
+        backend,
+        Duration::from_millis(10),
+        |e| panic!("{e}"),
+    ));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!path.exists());
+
+    touch(&mut *repo.lock().await);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    task.abort();
+
+    assert_eq!(
+        *repo.lock().await,
+        TorrentRepo::load_from(&path).await.unwrap()
+    );
+}
@@ -0,0 +1,158 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use crate::{
+    io::{
+        deserialization::{Decoder, TryDeserialize},
+        serialization::Serialize,
+    },
+    repository::{
+        merkle,
+        types::{FileMetadata, FileTreeNode, FilesMetadata, TorrentMetadata},
+    },
+    storage::Storage,
+};
+
+use super::{data, entry, single_file, TempDir, PIECE};
+
+/// `tool` (исполняемый), выравнивание, `readme` и ссылка на `tool`.
+fn padded_torrent() -> (TorrentMetadata, Vec<u8>) {
+    let tool = data(20_000, 1);
+    let readme = data(100, 2);
+    let pad = PIECE - tool.len() as u64;
+
+    let mut stream = tool.clone();
+    stream.resize(PIECE as usize, 0);
+    stream.extend(&readme);
+
+    let mut link = entry(&["bin", "link"], 0, Some("l"));
+    link.symlink_path = Some(vec!["tool".to_string()]);
+    let mut metadata = single_file("pkg", PIECE, &stream);
+    metadata.info.files = FilesMetadata::Multiple {
+        base_name: "pkg".to_string(),
+        files: vec![
+            entry(&["tool"], tool.len() as u64, Some("x")),
+            entry(&[".pad", &pad.to_string()], pad, Some("p")),
+            entry(&["readme"], readme.len() as u64, None),
+            link,
+        ],
+    };
+    (metadata, stream)
+}
+
+#[test]
+fn attributes_round_trip() {
+    let mut file = entry(&["a"], 1, Some("xh"));
+    file.symlink_path = Some(vec!["b".to_string(), "c".to_string()]);
+    file.sha1 = Some([9; 20]);
+
+    let bytes = file.serialize();
+
+    assert!(Decoder::new().strict(true).decode(&bytes).is_ok());
+    assert_eq!(file, FileMetadata::try_deserialize(&bytes).unwrap());
+    let attr = file.attr.unwrap();
+    assert!(attr.is_executable() && attr.is_hidden());
+    assert!(!attr.is_padding() && !attr.is_symlink());
+}
+
+#[tokio::test]
+async fn padding_is_not_written() {
+    let dir = TempDir::new();
+    let (metadata, stream) = padded_torrent();
+    let storage = Storage::new(&metadata.info, &dir.0);
+
+    storage.materialize().await.unwrap();
+    for (index, piece) in stream.chunks(PIECE as usize).enumerate() {
+        storage.write_piece(index, piece).await.unwrap();
+    }
+
+    assert!(!dir.0.join("pkg/.pad").exists());
+    assert_eq!(
+        data(20_000, 1),
+        std::fs::read(dir.0.join("pkg/tool")).unwrap()
+    );
+    assert_eq!(
+        data(100, 2),
+        std::fs::read(dir.0.join("pkg/readme")).unwrap()
+    );
+    for index in 0..2 {
+        assert!(storage.verify_piece(&metadata, index).await.unwrap());
+        assert_eq!(
+            stream.chunks(PIECE as usize).nth(index).unwrap(),
+            &storage.read_piece(index).await.unwrap()[..]
+        );
+    }
+
+    std::fs::write(dir.0.join("pkg/tool"), vec![0; 20_000]).unwrap();
+    assert!(!storage.verify_piece(&metadata, 0).await.unwrap());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn executable_and_symlink_are_honoured() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new();
+    let (metadata, _) = padded_torrent();
+    let storage = Storage::new(&metadata.info, &dir.0);
+
+    storage.materialize().await.unwrap();
+
+    let mode = std::fs::metadata(dir.0.join("pkg/tool"))
+        .unwrap()
+        .permissions()
+        .mode();
+    let link = dir.0.join("pkg/bin/link");
+    assert_eq!(0o111, mode & 0o111);
+    assert_eq!(PathBuf::from("../tool"), std::fs::read_link(&link).unwrap());
+    assert_eq!(
+        std::fs::canonicalize(dir.0.join("pkg/tool")).unwrap(),
+        std::fs::canonicalize(link).unwrap()
+    );
+    // Повторное создание не падает на уже существующих файлах
+    storage.materialize().await.unwrap();
+}
+
+#[tokio::test]
+async fn v2_pieces_are_aligned_to_files() {
+    let dir = TempDir::new();
+    let a = data(70_000, 3);
+    let b = data(100, 4);
+
+    let node = |d: &[u8]| FileTreeNode::File {
+        length: d.len() as u64,
+        pieces_root: merkle::file_root(d),
+        attr: None,
+    };
+    let mut tree = BTreeMap::new();
+    tree.insert("a".to_string(), node(&a));
+    tree.insert("b".to_string(), node(&b));
+    let mut layers = BTreeMap::new();
+    layers.insert(
+        merkle::file_root(&a).unwrap(),
+        merkle::piece_layer(&a, PIECE).concat(),
+    );
+
+    let mut metadata = single_file("set", PIECE, &[]);
+    metadata.info.pieces = None;
+    metadata.info.files = FilesMetadata::Tree {
+        name: "set".to_string(),
+    };
+    metadata.info.meta_version = Some(2);
+    metadata.info.file_tree = Some(tree);
+    metadata.piece_layers = Some(layers);
+
+    let storage = Storage::new(&metadata.info, &dir.0);
+    storage.materialize().await.unwrap();
+
+    let mut pieces: Vec<&[u8]> = a.chunks(PIECE as usize).collect();
+    pieces.push(&b);
+    assert_eq!(4, metadata.info.piece_count());
+    for (index, piece) in pieces.iter().enumerate() {
+        assert_eq!(piece.len() as u64, storage.piece_size(index));
+        storage.write_piece(index, piece).await.unwrap();
+    }
+    for index in 0..pieces.len() {
+        assert!(storage.verify_piece(&metadata, index).await.unwrap());
+    }
+    assert_eq!(b, std::fs::read(dir.0.join("set/b")).unwrap());
+}
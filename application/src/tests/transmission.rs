@@ -0,0 +1,385 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::{Client, StatusCode};
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+
+use crate::{
+    daemon::{
+        transmission::{RPC_PATH, SESSION_HEADER},
+        Daemon, DaemonConfig,
+    },
+    error::AsyncErr,
+    io::serialization::Serialize,
+    repository::types::TorrentMetadata,
+};
+
+use super::{metadata, start_daemon, TempDir};
+
+/// Торрент с запасным трекером во втором уровне.
+fn metainfo(name: &str) -> Vec<u8> {
+    TorrentMetadata {
+        announce_list: Some(vec![
+            vec!["http://tracker/announce".to_string()],
+            vec!["http://backup/announce".to_string()],
+        ]),
+        ..metadata(name)
+    }
+    .serialize()
+}
+
+/// Клиент, который ведёт себя как скрипты для Transmission: получив 409,
+/// запоминает id сессии и повторяет запрос.
+struct Rpc {
+    client: Client,
+    url: String,
+    session: Option<String>,
+    password: Option<&'static str>,
+}
+
+impl Rpc {
+    fn new(daemon: &Daemon) -> Rpc {
+        Rpc {
+            client: Client::new(),
+            url: format!("http://{}{RPC_PATH}", daemon.transmission_addr().unwrap()),
+            session: None,
+            password: None,
+        }
+    }
+
+    async fn post(&self, body: &Value) -> reqwest::Response {
+        let mut request = self.client.post(&self.url).body(body.to_string());
+        if let Some(session) = &self.session {
+            request = request.header(SESSION_HEADER, session);
+        }
+        if let Some(password) = self.password {
+            request = request.basic_auth("admin", Some(password));
+        }
+        request.send().await.unwrap()
+    }
+
+    async fn call(&mut self, method: &str, arguments: Value) -> Value {
+        let body = json!({ "method": method, "arguments": arguments, "tag": 5 });
+        let mut response = self.post(&body).await;
+        if response.status() == StatusCode::CONFLICT {
+            let session = response.headers()[SESSION_HEADER].to_str().unwrap();
+            self.session = Some(session.to_string());
+            response = self.post(&body).await;
+        }
+        assert_eq!(StatusCode::OK, response.status());
+        let response: Value = response.json().await.unwrap();
+        assert_eq!(5, response["tag"]);
+        response
+    }
+
+    async fn ok(&mut self, method: &str, arguments: Value) -> Value {
+        let response = self.call(method, arguments).await;
+        assert_eq!("success", response["result"], "{response}");
+        response["arguments"].clone()
+    }
+}
+
+async fn start(config: DaemonConfig) -> (Rpc, JoinHandle<Result<(), AsyncErr>>) {
+    start_daemon(
+        config.transmission("127.0.0.1:0".parse().unwrap()),
+        Rpc::new,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn session_id_handshake_is_required() {
+    let dir = TempDir::new();
+    let (mut rpc, daemon) = start(DaemonConfig::new(&dir.0)).await;
+
+    let response = rpc.post(&json!({ "method": "session-get" })).await;
+    assert_eq!(StatusCode::CONFLICT, response.status());
+    let session = response.headers()[SESSION_HEADER]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let get = rpc.client.get(&rpc.url).header(SESSION_HEADER, &session);
+    assert_eq!(
+        StatusCode::METHOD_NOT_ALLOWED,
+        get.send().await.unwrap().status()
+    );
+    let other = rpc.url.replace(RPC_PATH, "/other");
+    assert_eq!(
+        StatusCode::NOT_FOUND,
+        rpc.client.post(other).send().await.unwrap().status()
+    );
+
+    let arguments = rpc
+        .ok(
+            "session-get",
+            json!({ "fields": ["rpc-version", "download-dir"] }),
+        )
+        .await;
+    assert_eq!(Some(session), rpc.session);
+    assert_eq!(17, arguments["rpc-version"]);
+    assert_eq!(2, arguments.as_object().unwrap().len());
+
+    let response = rpc.call("torrent-reannounce", json!({})).await;
+    assert_eq!("method name not recognized", response["result"]);
+    daemon.abort();
+}
+
+#[tokio::test]
+async fn token_is_checked_as_basic_auth_password() {
+    let dir = TempDir::new();
+    let (mut rpc, daemon) = start(DaemonConfig::new(&dir.0).token("secret")).await;
+
+    let response = rpc.post(&json!({ "method": "session-get" })).await;
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+    rpc.password = Some("guess");
+    let response = rpc.post(&json!({ "method": "session-get" })).await;
+    assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+
+    rpc.password = Some("secret");
+    rpc.ok("session-stats", json!({})).await;
+    daemon.abort();
+
+    // Снаружи localhost без токена сервер не поднимается
+    let config = DaemonConfig::new(dir.0.join("other")).transmission("0.0.0.0:0".parse().unwrap());
+    assert!(Daemon::bind(config).await.is_err());
+}
+
+#[tokio::test]
+async fn torrents_are_managed_through_transmission_methods() {
+    let dir = TempDir::new();
+    let (mut rpc, daemon) = start(DaemonConfig::new(&dir.0)).await;
+    let metainfo = STANDARD.encode(metainfo("first"));
+
+    let added = rpc
+        .ok(
+            "torrent-add",
+            json!({ "metainfo": metainfo, "paused": true, "download-dir": "/downloads" }),
+        )
+        .await;
+    let id = added["torrent-added"]["id"].clone();
+    let hash = added["torrent-added"]["hashString"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(1, id);
+    assert_eq!("first", added["torrent-added"]["name"]);
+    let again = rpc.ok("torrent-add", json!({ "metainfo": metainfo })).await;
+    assert_eq!(id, again["torrent-duplicate"]["id"]);
+
+    let fields = json!([
+        "id",
+        "name",
+        "status",
+        "downloadDir",
+        "files",
+        "fileStats",
+        "trackers",
+        "unknown"
+    ]);
+    let get = rpc
+        .ok("torrent-get", json!({ "ids": [1], "fields": fields }))
+        .await;
+    let torrent = &get["torrents"][0];
+    assert_eq!(7, torrent.as_object().unwrap().len());
+    assert_eq!(0, torrent["status"]);
+    assert_eq!("/downloads", torrent["downloadDir"]);
+    assert_eq!(
+        json!([{ "name": "first", "length": 1000, "bytesCompleted": 0 }]),
+        torrent["files"]
+    );
+    assert_eq!(true, torrent["fileStats"][0]["wanted"]);
+    assert_eq!(1, torrent["trackers"][1]["tier"]);
+    assert_eq!("http://backup/announce", torrent["trackers"][1]["announce"]);
+
+    // Торрент выбирается и по хешу
+    rpc.ok("torrent-start", json!({ "ids": [hash] })).await;
+    let get = rpc
+        .ok("torrent-get", json!({ "ids": 1, "fields": ["status"] }))
+        .await;
+    assert_eq!(4, get["torrents"][0]["status"]);
+    let stats = rpc.ok("session-stats", json!({})).await;
+    assert_eq!(1, stats["activeTorrentCount"]);
+    assert_eq!(1, stats["torrentCount"]);
+
+    rpc.ok("torrent-stop", json!({ "ids": [1] })).await;
+    let stats = rpc.ok("session-stats", json!({})).await;
+    assert_eq!(1, stats["pausedTorrentCount"]);
+
+    rpc.ok(
+        "torrent-remove",
+        json!({ "ids": [1], "delete-local-data": false }),
+    )
+    .await;
+    let get = rpc.ok("torrent-get", json!({ "fields": ["id"] })).await;
+    assert_eq!(json!([]), get["torrents"]);
+    daemon.abort();
+}
+
+#[tokio::test]
+async fn session_set_changes_the_download_dir() {
+    let dir = TempDir::new();
+    let (mut rpc, daemon) = start(DaemonConfig::new(&dir.0)).await;
+    let file = dir.0.join("second.torrent");
+    std::fs::write(&file, metainfo("second")).unwrap();
+
+    let response = rpc
+        .call("session-set", json!({ "download-dir": "relative" }))
+        .await;
+    assert_ne!("success", response["result"]);
+    rpc.ok("session-set", json!({ "download-dir": "/srv/torrents" }))
+        .await;
+    let session = rpc.ok("session-get", json!({})).await;
+    assert_eq!("/srv/torrents", session["download-dir"]);
+
+    rpc.ok("torrent-add", json!({ "filename": file })).await;
+    let get = rpc
+        .ok("torrent-get", json!({ "fields": ["downloadDir"] }))
+        .await;
+    assert_eq!("/srv/torrents", get["torrents"][0]["downloadDir"]);
+
+    let response = rpc
+        .call(
+            "torrent-add",
+            json!({ "filename": "magnet:?xt=urn:btih:00" }),
+        )
+        .await;
+    assert!(response["result"].as_str().unwrap().contains("magnet"));
+    daemon.abort();
+}
+
+#[tokio::test]
+async fn session_set_changes_limits_and_protocols() {
+    let dir = TempDir::new();
+    let (mut rpc, daemon) = start(DaemonConfig::new(&dir.0)).await;
+
+    let session = rpc.ok("session-get", json!({})).await;
+    assert_eq!(false, session["speed-limit-down-enabled"]);
+    assert_eq!(false, session["dht-enabled"]);
+    assert_eq!("disabled", session["encryption"]);
+
+    for args in [
+        json!({ "speed-limit-down-enabled": true }),
+        json!({ "dht-enabled": true }),
+        json!({ "encryption": "required" }),
+    ] {
+        let response = rpc.call("session-set", args).await;
+        assert_ne!("success", response["result"]);
+    }
+    // Шифрованные соединения не принимаются, так что это не "tolerated"
+    let response = rpc
+        .call("session-set", json!({ "encryption": "tolerated" }))
+        .await;
+    assert!(response["result"]
+        .as_str()
+        .unwrap()
+        .contains("always plain"));
+    rpc.ok(
+        "session-set",
+        json!({
+            "speed-limit-down": 500,
+            "speed-limit-down-enabled": true,
+            "peer-port": 51413,
+            "peer-limit-per-torrent": 30,
+            "dht-enabled": false,
+            "encryption": "disabled",
+        }),
+    )
+    .await;
+    let session = rpc.ok("session-get", json!({})).await;
+    assert_eq!(true, session["speed-limit-down-enabled"]);
+    assert_eq!(500, session["speed-limit-down"]);
+    assert_eq!(false, session["speed-limit-up-enabled"]);
+    assert_eq!(51413, session["peer-port"]);
+    assert_eq!(30, session["peer-limit-per-torrent"]);
+    assert_eq!(false, session["dht-enabled"]);
+    assert_eq!("disabled", session["encryption"]);
+
+    let response = rpc.call("session-set", json!({ "peer-port": 0 })).await;
+    assert_ne!("success", response["result"]);
+
+    rpc.ok("session-set", json!({ "speed-limit-down-enabled": false }))
+        .await;
+    let session = rpc.ok("session-get", json!({})).await;
+    assert_eq!(false, session["speed-limit-down-enabled"]);
+
+    daemon.abort();
+}
+
+#[tokio::test]
+async fn alt_speed_and_torrent_limits_are_set() {
+    let dir = TempDir::new();
+    let (mut rpc, daemon) = start(DaemonConfig::new(&dir.0)).await;
+    let metainfo = STANDARD.encode(metainfo("limited"));
+    rpc.ok(
+        "torrent-add",
+        json!({ "metainfo": metainfo, "paused": true }),
+    )
+    .await;
+
+    rpc.ok(
+        "session-set",
+        json!({
+            "alt-speed-enabled": true,
+            "alt-speed-down": 20,
+            "alt-speed-time-enabled": true,
+            "alt-speed-time-begin": 22 * 60,
+            "alt-speed-time-end": 6 * 60 + 30,
+            "alt-speed-time-day": 62,
+        }),
+    )
+    .await;
+    let session = rpc.ok("session-get", json!({})).await;
+    assert_eq!(true, session["alt-speed-enabled"]);
+    assert_eq!(20, session["alt-speed-down"]);
+    assert_eq!(true, session["alt-speed-time-enabled"]);
+    assert_eq!(1320, session["alt-speed-time-begin"]);
+    assert_eq!(390, session["alt-speed-time-end"]);
+    assert_eq!(62, session["alt-speed-time-day"]);
+    for args in [
+        json!({ "alt-speed-time-begin": 1440 }),
+        json!({ "alt-speed-time-day": 0 }),
+    ] {
+        let response = rpc.call("session-set", args).await;
+        assert_ne!("success", response["result"]);
+    }
+    // Скорости в КБ/с не переполняются при переводе в байты
+    for name in ["alt-speed-down", "speed-limit-down"] {
+        let response = rpc.call("session-set", json!({ name: u64::MAX })).await;
+        assert_eq!(format!("{name} is too large"), response["result"]);
+    }
+    let response = rpc
+        .call(
+            "torrent-set",
+            json!({ "ids": [1], "downloadLimit": u64::MAX, "downloadLimited": true }),
+        )
+        .await;
+    assert_eq!("downloadLimit is too large", response["result"]);
+
+    let fields = json!(["downloadLimit", "downloadLimited", "uploadLimited"]);
+    rpc.ok(
+        "torrent-set",
+        json!({ "ids": [1], "downloadLimit": 100, "downloadLimited": true }),
+    )
+    .await;
+    let get = rpc
+        .ok("torrent-get", json!({ "ids": [1], "fields": fields }))
+        .await;
+    assert_eq!(100, get["torrents"][0]["downloadLimit"]);
+    assert_eq!(true, get["torrents"][0]["downloadLimited"]);
+    assert_eq!(false, get["torrents"][0]["uploadLimited"]);
+
+    let response = rpc
+        .call("torrent-set", json!({ "ids": [1], "uploadLimited": true }))
+        .await;
+    assert_ne!("success", response["result"]);
+    rpc.ok(
+        "torrent-set",
+        json!({ "ids": [1], "downloadLimited": false }),
+    )
+    .await;
+    let get = rpc
+        .ok("torrent-get", json!({ "ids": [1], "fields": fields }))
+        .await;
+    assert_eq!(false, get["torrents"][0]["downloadLimited"]);
+    daemon.abort();
+}
@@ -0,0 +1,185 @@
+use ratatui::{
+    backend::TestBackend,
+    crossterm::event::{KeyCode, KeyEvent},
+    Terminal,
+};
+use serde_json::{json, Value};
+
+use crate::tui::{
+    app::{Action, App, Focus, Tab},
+    ui::{draw, format_eta, piece_map, progress_bar},
+};
+
+fn torrent(id: &str, name: &str, status: &str) -> Value {
+    json!({
+        "id": id,
+        "name": name,
+        "status": status,
+        "progress": 0.5,
+        "downloaded": 512,
+        "size": 1024,
+        "download_rate": 256,
+        "upload_rate": 0,
+        "connected_peers": 3,
+    })
+}
+
+fn app() -> App {
+    let mut app = App::default();
+    app.set_torrents(vec![
+        torrent("aaaa", "first", "downloading"),
+        torrent("bbbb", "second", "paused"),
+    ]);
+    app.set_detail(json!({
+        "id": "aaaa",
+        "name": "first",
+        "pieces": 4,
+        "bitfield": "oA==",
+        "trackers": ["http://tracker/announce"],
+        "web_seeds": [],
+        "peers": ["10.0.0.1:6881"],
+        "connected_peers": 3,
+        "files": [
+            { "index": 0, "path": "first/a", "size": 1000, "done": 1000, "priority": "normal" },
+            { "index": 2, "path": "first/b", "size": 24, "done": 0, "priority": "high" },
+        ],
+    }));
+    app
+}
+
+fn press(app: &mut App, code: KeyCode) -> Option<Action> {
+    app.handle_key(KeyEvent::from(code))
+}
+
+#[test]
+fn selection_follows_the_torrent_across_refreshes() {
+    let mut app = app();
+    press(&mut app, KeyCode::Down);
+    assert_eq!(app.selected_id(), Some("bbbb"));
+    assert!(app.detail_stale());
+
+    app.set_torrents(vec![
+        torrent("cccc", "third", "seeding"),
+        torrent("aaaa", "first", "downloading"),
+        torrent("bbbb", "second", "paused"),
+    ]);
+    assert_eq!(app.selected_id(), Some("bbbb"));
+
+    app.set_torrents(vec![torrent("aaaa", "first", "downloading")]);
+    assert_eq!(app.selected_id(), Some("aaaa"));
+}
+
+#[test]
+fn keys_map_to_calls() {
+    let mut app = app();
+    assert_eq!(
+        press(&mut app, KeyCode::Char('p')),
+        Some(Action::Call("torrent.stop", json!({ "id": "aaaa" })))
+    );
+    press(&mut app, KeyCode::Char('j'));
+    assert_eq!(
+        press(&mut app, KeyCode::Char(' ')),
+        Some(Action::Call("torrent.start", json!({ "id": "bbbb" })))
+    );
+
+    // Удаление только после подтверждения
+    assert_eq!(press(&mut app, KeyCode::Char('d')), None);
+    assert!(app.message.is_some());
+    assert_eq!(press(&mut app, KeyCode::Char('n')), None);
+    assert_eq!(press(&mut app, KeyCode::Char('d')), None);
+    assert_eq!(
+        press(&mut app, KeyCode::Char('D')),
+        Some(Action::Call(
+            "torrent.remove",
+            json!({ "id": "bbbb", "delete_data": true })
+        ))
+    );
+
+    assert_eq!(press(&mut app, KeyCode::Char('q')), Some(Action::Quit));
+}
+
+#[test]
+fn priorities_change_for_the_selected_file() {
+    let mut app = app();
+    press(&mut app, KeyCode::Enter);
+    assert_eq!(app.focus, Focus::Detail);
+    assert_eq!(
+        press(&mut app, KeyCode::Char('-')),
+        Some(Action::Call(
+            "torrent.set",
+            json!({ "id": "aaaa", "priorities": { "0": "low" } })
+        ))
+    );
+
+    // Номер файла берётся из `index`, а не из позиции в списке
+    press(&mut app, KeyCode::Down);
+    assert_eq!(app.file, 1);
+    assert_eq!(press(&mut app, KeyCode::Char('+')), None);
+    assert_eq!(
+        press(&mut app, KeyCode::Char('-')),
+        Some(Action::Call(
+            "torrent.set",
+            json!({ "id": "aaaa", "priorities": { "2": "normal" } })
+        ))
+    );
+
+    // На других вкладках приоритеты не меняются
+    press(&mut app, KeyCode::Tab);
+    assert_eq!(app.tab, Tab::Trackers);
+    assert_eq!(press(&mut app, KeyCode::Char('-')), None);
+
+    press(&mut app, KeyCode::Esc);
+    assert_eq!(app.focus, Focus::List);
+    assert_eq!(press(&mut app, KeyCode::Esc), Some(Action::Quit));
+}
+
+#[test]
+fn piece_map_aggregates_pieces() {
+    // Куски 0, 2 и 3 из 8
+    let bitfield = [0b1011_0000];
+    assert_eq!(piece_map(&bitfield, 8, 4), vec![0.5, 1.0, 0.0, 0.0]);
+    assert_eq!(piece_map(&bitfield, 8, 100).len(), 8);
+    assert_eq!(piece_map(&bitfield, 3, 2), vec![1.0, 0.5]);
+    assert!(piece_map(&[], 0, 10).is_empty());
+}
+
+#[test]
+fn eta_and_progress_are_formatted() {
+    assert_eq!(format_eta(0, 0), "-");
+    assert_eq!(format_eta(100, 0), "∞");
+    assert_eq!(format_eta(100, 30), "4s");
+    assert_eq!(format_eta(3_700, 1), "1h 01m");
+    assert_eq!(format_eta(200_000, 1), "2d 7h");
+    assert_eq!(progress_bar(0.5, 4), "██░░  50.0%");
+}
+
+#[test]
+fn screen_shows_torrents_and_details() {
+    let mut app = app();
+    let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+    let screen = |terminal: &mut Terminal<TestBackend>, app: &App| {
+        terminal.draw(|frame| draw(frame, app)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+
+    let text = screen(&mut terminal, &app);
+    assert!(text.contains("second"));
+    assert!(text.contains("█████░░░░░  50.0%"));
+    assert!(text.contains("first/b"));
+    assert!(text.contains("100.0%"));
+
+    press(&mut app, KeyCode::Right);
+    press(&mut app, KeyCode::Right);
+    assert!(screen(&mut terminal, &app).contains("10.0.0.1:6881"));
+
+    press(&mut app, KeyCode::Right);
+    assert!(screen(&mut terminal, &app).contains("█ █"));
+}
@@ -0,0 +1,333 @@
+use std::collections::BTreeMap;
+
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    io::{
+        deserialization::{Decoder, ParsingError, TryDeserialize},
+        serialization::Serialize,
+    },
+    network::message::{answer_hash_request, verify_hashes, HashRequest, Message, MessageError},
+    repository::{
+        merkle::{self, MerkleTree},
+        types::{
+            FileTreeNode, FilesMetadata, Info, InfoHash, MetaVersion, Torrent, TorrentMetadata,
+        },
+    },
+};
+
+use super::{data, entry, PIECE};
+
+// Два блока на кусок, чтобы слой кусков не совпадал с листьями
+
+fn file(data: &[u8]) -> FileTreeNode {
+    FileTreeNode::File {
+        length: data.len() as u64,
+        pieces_root: merkle::file_root(data),
+        attr: None,
+    }
+}
+
+/// Торрент из каталога `dir/a` (три куска), `b` (меньше куска) и пустого `c`.
+fn v2_torrent(hybrid: bool) -> TorrentMetadata {
+    let a = data(70_000, 1);
+    let b = data(100, 2);
+
+    let mut dir = BTreeMap::new();
+    dir.insert("a".to_string(), file(&a));
+    let mut tree = BTreeMap::new();
+    tree.insert("dir".to_string(), FileTreeNode::Dir(dir));
+    tree.insert("b".to_string(), file(&b));
+    tree.insert("c".to_string(), file(&[]));
+
+    let root_a = merkle::file_root(&a).unwrap();
+    let layer: Vec<u8> = merkle::piece_layer(&a, PIECE).concat();
+    let mut piece_layers = BTreeMap::new();
+    piece_layers.insert(root_a, layer);
+
+    let (pieces, files) = if hybrid {
+        // В v1 каждый файл v2 выравнивается до куска файлом-заглушкой
+        let pad = PIECE as usize - b.len();
+        let mut stream = b.clone();
+        stream.resize(PIECE as usize, 0);
+        stream.extend(&a);
+        let pieces = stream
+            .chunks(PIECE as usize)
+            .flat_map(|c| <[u8; 20]>::from(Sha1::digest(c)))
+            .collect();
+        let files = FilesMetadata::Multiple {
+            base_name: "root".to_string(),
+            files: vec![
+                entry(&["b"], b.len() as u64, None),
+                entry(&[".pad", &pad.to_string()], pad as u64, Some("p")),
+                entry(&["c"], 0, None),
+                entry(&["dir", "a"], a.len() as u64, None),
+            ],
+        };
+        (Some(pieces), files)
+    } else {
+        (
+            None,
+            FilesMetadata::Tree {
+                name: "root".to_string(),
+            },
+        )
+    };
+
+    TorrentMetadata {
+        info: Info {
+            piece_length: PIECE,
+            pieces,
+            private: None,
+            files,
+            meta_version: Some(2),
+            file_tree: Some(tree),
+            name_raw: None,
+            name_utf8: None,
+        },
+        announce: "http://tracker/announce".to_string(),
+        encoding: None,
+        httpseeds: None,
+        announce_list: None,
+        creation_date: None,
+        comment: None,
+        created_by: None,
+        url_list: None,
+        piece_layers: Some(piece_layers),
+    }
+}
+
+fn root_of_a(metadata: &TorrentMetadata) -> [u8; 32] {
+    *metadata
+        .piece_layers
+        .as_ref()
+        .unwrap()
+        .keys()
+        .next()
+        .unwrap()
+}
+
+#[test]
+fn merkle_roots_agree() {
+    let small = data(1000, 3);
+    let large = data(5 * PIECE as usize + 17, 4);
+
+    assert_eq!(
+        <[u8; 32]>::from(Sha256::digest(&small)),
+        merkle::file_root(&small).unwrap()
+    );
+    let layer = merkle::piece_layer(&large, PIECE);
+    assert_eq!(6, layer.len());
+    assert_eq!(
+        merkle::file_root(&large).unwrap(),
+        MerkleTree::from_piece_layer(layer, PIECE).root()
+    );
+    assert_eq!(None, merkle::file_root(&[]));
+}
+
+#[test]
+fn parse_v2_torrent() {
+    let metadata = v2_torrent(false);
+    let bytes = metadata.serialize();
+    assert!(Decoder::new().strict(true).decode(&bytes).is_ok());
+
+    let (parsed, hash) = TorrentMetadata::new(&bytes).unwrap();
+    let info = parsed.info.serialize();
+
+    assert_eq!(metadata, parsed);
+    assert_eq!(MetaVersion::V2, parsed.info.version());
+    assert_eq!(InfoHash::V2(Sha256::digest(&info).into()), hash);
+    assert_eq!(hash.truncated_v2(), Some(hash.short()));
+    assert_eq!(None, hash.v1());
+    assert_eq!(3 + 1, parsed.info.piece_count());
+    assert_eq!(70_100, parsed.info.total_length());
+    let paths: Vec<_> = parsed
+        .info
+        .tree_files()
+        .into_iter()
+        .map(|f| f.path.join("/"))
+        .collect();
+    assert_eq!(vec!["b", "c", "dir/a"], paths);
+    assert!(parsed.verify_piece_layers());
+}
+
+#[test]
+fn parse_hybrid_torrent() {
+    let metadata = v2_torrent(true);
+    let bytes = metadata.serialize();
+
+    let (parsed, hash) = TorrentMetadata::new(&bytes).unwrap();
+    let info = parsed.info.serialize();
+
+    assert_eq!(metadata, parsed);
+    assert_eq!(MetaVersion::Hybrid, parsed.info.version());
+    assert_eq!(
+        InfoHash::Hybrid(Sha1::digest(&info).into(), Sha256::digest(&info).into()),
+        hash
+    );
+    assert_eq!(hash.v1(), Some(hash.short()));
+    assert!(parsed.info.hybrid_files_match());
+    assert!(parsed.verify_piece_layers());
+}
+
+#[test]
+fn broken_piece_layers() {
+    let mut metadata = v2_torrent(false);
+    let root = root_of_a(&metadata);
+
+    metadata
+        .piece_layers
+        .as_mut()
+        .unwrap()
+        .get_mut(&root)
+        .unwrap()[0] ^= 1;
+    assert!(!metadata.verify_piece_layers());
+
+    metadata.piece_layers = None;
+    assert!(!metadata.verify_piece_layers());
+}
+
+#[test]
+fn invalid_v2_info() {
+    let info = |extra: &str| {
+        format!("d9:file treed1:ad0:d6:lengthi1e11:pieces root32:{}eee{extra}4:name1:x12:piece lengthi16384ee", "r".repeat(32))
+    };
+
+    let unknown_version = info("12:meta versioni3e");
+    let small_piece = info("12:meta versioni2e").replace("i16384e", "i1000e");
+    let no_root =
+        "d9:file treed1:ad0:d6:lengthi1eeee12:meta versioni2e4:name1:x12:piece lengthi16384ee";
+
+    assert!(matches!(
+        Info::try_deserialize(unknown_version.as_bytes()),
+        Err(ParsingError::InvalidFormat)
+    ));
+    assert!(matches!(
+        Info::try_deserialize(small_piece.as_bytes()),
+        Err(ParsingError::InvalidFormat)
+    ));
+    assert!(matches!(
+        Info::try_deserialize(no_root.as_bytes()),
+        Err(ParsingError::MissingField(f)) if f == "pieces root"
+    ));
+    assert!(Info::try_deserialize(info("12:meta versioni2e").as_bytes()).is_ok());
+}
+
+#[test]
+fn v2_hash_in_repo() {
+    let metadata = v2_torrent(false);
+    let (_, hash) = TorrentMetadata::new(&metadata.serialize()).unwrap();
+    let torrent = Torrent::new(metadata, hash);
+
+    let restored = Torrent::try_deserialize(&torrent.serialize()).unwrap();
+
+    assert_eq!(torrent, restored);
+}
+
+#[test]
+fn wire_messages_round_trip() {
+    let request = HashRequest {
+        pieces_root: [7; 32],
+        base_layer: 1,
+        index: 2,
+        length: 2,
+        proof_layers: 1,
+    };
+    let messages = vec![
+        Message::KeepAlive,
+        Message::Unchoke,
+        Message::Have(42),
+        Message::Bitfield(vec![0b1010_0000]),
+        Message::Request {
+            index: 1,
+            begin: 16384,
+            length: 16384,
+        },
+        Message::Piece {
+            index: 1,
+            begin: 0,
+            block: vec![1, 2, 3],
+        },
+        Message::HashRequest(request),
+        Message::Hashes {
+            request,
+            hashes: vec![[1; 32], [2; 32], [3; 32]],
+        },
+        Message::HashReject(request),
+    ];
+
+    let mut buf = vec![];
+    messages.iter().for_each(|m| m.encode(&mut buf));
+
+    let mut decoded = vec![];
+    let mut rest = &buf[..];
+    while let Some((message, used)) = Message::decode(rest).unwrap() {
+        decoded.push(message);
+        rest = &rest[used..];
+    }
+    assert_eq!(messages, decoded);
+    assert!(rest.is_empty());
+
+    assert_eq!(Ok(None), Message::decode(&[0, 0, 0, 5, 4]));
+    assert_eq!(
+        Err(MessageError::UnknownId(99)),
+        Message::decode(&[0, 0, 0, 1, 99])
+    );
+    assert_eq!(
+        Err(MessageError::InvalidLength(4)),
+        Message::decode(&[0, 0, 0, 2, 4, 0])
+    );
+    assert_eq!(
+        Err(MessageError::TooLong(u32::MAX)),
+        Message::decode(&[0xff; 8])
+    );
+}
+
+#[test]
+fn hash_request_answer_verifies() {
+    let metadata = v2_torrent(false);
+    let root = root_of_a(&metadata);
+    // Слой кусков — первый над листьями, дерево файла высотой 3
+    let request = HashRequest {
+        pieces_root: root,
+        base_layer: 1,
+        index: 2,
+        length: 2,
+        proof_layers: 8,
+    };
+
+    let Message::Hashes { hashes, .. } = answer_hash_request(&metadata, request) else {
+        panic!("expected hashes");
+    };
+    assert_eq!(3, hashes.len());
+    assert!(verify_hashes(&metadata, &request, &hashes));
+
+    let mut forged = hashes.clone();
+    forged[0][0] ^= 1;
+    assert!(!verify_hashes(&metadata, &request, &forged));
+    assert!(!verify_hashes(&metadata, &request, &hashes[..2]));
+    // Слой вне всякой высоты не переполняет счёт
+    let overflow = HashRequest {
+        base_layer: u32::MAX,
+        ..request
+    };
+    assert!(!verify_hashes(&metadata, &overflow, &hashes));
+
+    let leaves = HashRequest {
+        base_layer: 0,
+        ..request
+    };
+    let unknown = HashRequest {
+        pieces_root: [0; 32],
+        ..request
+    };
+    assert_eq!(
+        Message::HashReject(leaves),
+        answer_hash_request(&metadata, leaves)
+    );
+    assert_eq!(
+        Message::HashReject(unknown),
+        answer_hash_request(&metadata, unknown)
+    );
+}
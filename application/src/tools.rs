@@ -11,3 +11,44 @@ pub fn assert_eq<T: PartialEq + std::fmt::Debug>(l: T, r: T) -> Result<()> {
         ))
     }
 }
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+    // `from_str_radix` пропускает ведущий `+`, поэтому цифры проверяются заранее
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Процентное кодирование для строки запроса. Как есть остаются только
+/// незарезервированные символы, так что байты не обязаны быть UTF-8.
+pub fn url_encode(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Бит куска `index` в битовом поле: старший бит первого байта — кусок 0.
+pub fn get_bit(bits: &[u8], index: usize) -> bool {
+    bits.get(index / 8)
+        .is_some_and(|byte| byte & (0x80 >> (index % 8)) != 0)
+}
+
+pub fn set_bit(bits: &mut [u8], index: usize) {
+    if let Some(byte) = bits.get_mut(index / 8) {
+        *byte |= 0x80 >> (index % 8);
+    }
+}
@@ -0,0 +1,209 @@
+/// Модуль с состоянием интерфейса и разбором клавиш.
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde_json::{json, Value};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Tab {
+    #[default]
+    Files,
+    Trackers,
+    Peers,
+    Pieces,
+}
+
+impl Tab {
+    pub const ALL: [Tab; 4] = [Tab::Files, Tab::Trackers, Tab::Peers, Tab::Pieces];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Tab::Files => "Files",
+            Tab::Trackers => "Trackers",
+            Tab::Peers => "Peers",
+            Tab::Pieces => "Pieces",
+        }
+    }
+
+    fn index(self) -> usize {
+        Tab::ALL.iter().position(|t| *t == self).unwrap()
+    }
+
+    fn next(self) -> Tab {
+        Tab::ALL[(self.index() + 1) % Tab::ALL.len()]
+    }
+
+    fn prev(self) -> Tab {
+        Tab::ALL[(self.index() + Tab::ALL.len() - 1) % Tab::ALL.len()]
+    }
+}
+
+/// Куда идут стрелки: по торрентам или по файлам выбранного торрента.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Focus {
+    #[default]
+    List,
+    Detail,
+}
+
+/// Что нужно сделать в ответ на клавишу.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Quit,
+    Call(&'static str, Value),
+}
+
+const PRIORITIES: [&str; 4] = ["skip", "low", "normal", "high"];
+
+/// Данные берутся из тех же вызовов, что и у командной строки:
+/// `torrent.list` для списка и `torrent.get` для выбранного торрента.
+#[derive(Debug, Default)]
+pub struct App {
+    pub torrents: Vec<Value>,
+    pub selected: usize,
+    pub detail: Option<Value>,
+    pub tab: Tab,
+    pub focus: Focus,
+    /// Выбранная строка на вкладке файлов.
+    pub file: usize,
+    /// Ждём подтверждения удаления.
+    pub confirm_remove: bool,
+    /// Ошибка последнего вызова или подсказка.
+    pub message: Option<String>,
+}
+
+impl App {
+    /// Обновляет список, оставляя выбранным тот же торрент.
+    pub fn set_torrents(&mut self, torrents: Vec<Value>) {
+        let selected = self.selected_id().map(String::from);
+        self.torrents = torrents;
+        if let Some(position) = selected.and_then(|id| {
+            self.torrents
+                .iter()
+                .position(|t| t["id"].as_str() == Some(&id))
+        }) {
+            self.selected = position;
+        }
+        self.selected = self.selected.min(self.torrents.len().saturating_sub(1));
+        if self.torrents.is_empty() {
+            self.detail = None;
+        }
+    }
+
+    pub fn set_detail(&mut self, detail: Value) {
+        self.detail = Some(detail);
+        self.file = self.file.min(self.files().len().saturating_sub(1));
+    }
+
+    pub fn selected_id(&self) -> Option<&str> {
+        self.torrents.get(self.selected)?["id"].as_str()
+    }
+
+    /// Детали устарели: выбран другой торрент.
+    pub fn detail_stale(&self) -> bool {
+        self.selected_id() != self.detail.as_ref().and_then(|d| d["id"].as_str())
+    }
+
+    pub fn files(&self) -> &[Value] {
+        self.detail
+            .as_ref()
+            .and_then(|d| d["files"].as_array())
+            .map_or(&[], Vec::as_slice)
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Option<Action> {
+        self.message = None;
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(Action::Quit);
+        }
+        if self.confirm_remove {
+            self.confirm_remove = false;
+            let id = self.selected_id()?;
+            return match key.code {
+                KeyCode::Char('y') => Some(Action::Call(
+                    "torrent.remove",
+                    json!({ "id": id, "delete_data": false }),
+                )),
+                KeyCode::Char('D') => Some(Action::Call(
+                    "torrent.remove",
+                    json!({ "id": id, "delete_data": true }),
+                )),
+                _ => None,
+            };
+        }
+
+        match key.code {
+            KeyCode::Char('q') => return Some(Action::Quit),
+            KeyCode::Esc if self.focus == Focus::Detail => self.focus = Focus::List,
+            KeyCode::Esc => return Some(Action::Quit),
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+            KeyCode::Tab | KeyCode::Right | KeyCode::Char('l') => self.tab = self.tab.next(),
+            KeyCode::BackTab | KeyCode::Left | KeyCode::Char('h') => self.tab = self.tab.prev(),
+            KeyCode::Enter => {
+                self.focus = match self.focus {
+                    Focus::List => Focus::Detail,
+                    Focus::Detail => Focus::List,
+                }
+            }
+            KeyCode::Char('p') | KeyCode::Char(' ') => {
+                let torrent = self.torrents.get(self.selected)?;
+                let method = if torrent["status"] == "paused" {
+                    "torrent.start"
+                } else {
+                    "torrent.stop"
+                };
+                return Some(Action::Call(method, json!({ "id": torrent["id"] })));
+            }
+            KeyCode::Char('d') | KeyCode::Delete => {
+                let name = self.torrents.get(self.selected)?["name"].as_str()?;
+                self.message = Some(format!(
+                    "remove {name}? y: keep data, D: delete data, any other key: cancel"
+                ));
+                self.confirm_remove = true;
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') => return self.shift_priority(1),
+            KeyCode::Char('-') => return self.shift_priority(-1),
+            _ => {}
+        }
+        None
+    }
+
+    fn move_by(&mut self, step: isize) {
+        let len = match self.focus {
+            Focus::List => self.torrents.len(),
+            Focus::Detail => self.files().len(),
+        };
+        let position = match self.focus {
+            Focus::List => &mut self.selected,
+            Focus::Detail => &mut self.file,
+        };
+        if len > 0 {
+            *position = position.saturating_add_signed(step).min(len - 1);
+        }
+        if self.focus == Focus::List {
+            self.file = 0;
+        }
+    }
+
+    /// Приоритет выбранного файла на ступень выше или ниже.
+    fn shift_priority(&self, step: isize) -> Option<Action> {
+        if self.tab != Tab::Files || self.detail_stale() {
+            return None;
+        }
+        let file = self.files().get(self.file)?;
+        let current = PRIORITIES
+            .iter()
+            .position(|p| file["priority"] == *p)
+            .unwrap_or(2);
+        let next = current
+            .saturating_add_signed(step)
+            .min(PRIORITIES.len() - 1);
+        if next == current {
+            return None;
+        }
+        let index = file["index"].to_string();
+        Some(Action::Call(
+            "torrent.set",
+            json!({ "id": self.selected_id()?, "priorities": { index: PRIORITIES[next] } }),
+        ))
+    }
+}
@@ -0,0 +1,87 @@
+pub mod app;
+pub mod ui;
+
+/// Модуль с интерактивным интерфейсом в терминале.
+///
+/// Интерфейс — ещё один клиент того же API, что и командная строка: он
+/// работает с демоном или, если тот не запущен, прямо с репозиторием.
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use serde_json::json;
+use tokio::sync::mpsc;
+
+use crate::{cli::Target, error::AsyncErr};
+
+use self::app::{Action, App};
+
+/// Как часто перечитывать список торрентов.
+const REFRESH: Duration = Duration::from_secs(1);
+
+/// Возвращает терминал в обычный режим и при ошибке, и при панике.
+struct Restore;
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+pub async fn run(mut target: Target) -> Result<(), AsyncErr> {
+    let mut terminal = ratatui::try_init()?;
+    let _restore = Restore;
+
+    // Чтение событий блокирует, поэтому живёт в своём потоке
+    let (sender, mut events) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut app = App::default();
+    let mut refresh = tokio::time::interval(REFRESH);
+    loop {
+        terminal.draw(|frame| ui::draw(frame, &app))?;
+        tokio::select! {
+            _ = refresh.tick() => update(&mut target, &mut app).await,
+            Some(event) = events.recv() => {
+                let Event::Key(key) = event else { continue };
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                match app.handle_key(key) {
+                    Some(Action::Quit) => return Ok(()),
+                    Some(Action::Call(method, params)) => {
+                        match target.call(method, params).await {
+                            Ok(_) => update(&mut target, &mut app).await,
+                            Err(e) => app.message = Some(e.to_string()),
+                        }
+                    }
+                    None if app.detail_stale() => update(&mut target, &mut app).await,
+                    None => {}
+                }
+            }
+        }
+    }
+}
+
+/// Перечитывает список и детали выбранного торрента. Ошибки показываются
+/// в строке состояния, интерфейс продолжает работать.
+async fn update(target: &mut Target, app: &mut App) {
+    if let Err(e) = try_update(target, app).await {
+        app.message = Some(e.to_string());
+    }
+}
+
+async fn try_update(target: &mut Target, app: &mut App) -> Result<(), AsyncErr> {
+    let torrents = target.call("torrent.list", json!({})).await?;
+    app.set_torrents(torrents.as_array().cloned().unwrap_or_default());
+    if let Some(id) = app.selected_id() {
+        let detail = target.call("torrent.get", json!({ "id": id })).await?;
+        app.set_detail(detail);
+    }
+    Ok(())
+}
@@ -0,0 +1,242 @@
+/// Модуль с отрисовкой интерфейса.
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, Cell, Paragraph, Row, Table, TableState, Tabs, Wrap},
+    Frame,
+};
+use serde_json::Value;
+
+use crate::cli::output::format_size;
+
+use super::app::{App, Focus, Tab};
+
+const HELP: &str = "q quit  j/k move  tab switch pane  enter files  p pause/resume  \
+                    d remove  +/- priority";
+
+/// Оттенки карты кусков от пустой ячейки к полностью скачанной.
+const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [list, detail, status] = Layout::vertical([
+        Constraint::Percentage(45),
+        Constraint::Fill(1),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    draw_list(frame, app, list);
+    draw_detail(frame, app, detail);
+    let status_line = app.message.as_deref().unwrap_or(HELP);
+    frame.render_widget(Paragraph::new(status_line), status);
+}
+
+fn draw_list(frame: &mut Frame, app: &App, area: Rect) {
+    let header = Row::new([
+        "Name", "Status", "Progress", "Size", "Down", "Up", "ETA", "Peers",
+    ])
+    .style(Style::new().add_modifier(Modifier::BOLD));
+    let rows = app.torrents.iter().map(|t| {
+        let number = |key: &str| t[key].as_u64().unwrap_or_default();
+        let progress = t["progress"].as_f64().unwrap_or_default();
+        let left = number("size").saturating_sub(number("downloaded"));
+        Row::new([
+            Cell::from(t["name"].as_str().unwrap_or_default().to_string()),
+            Cell::from(t["status"].as_str().unwrap_or_default().to_string()),
+            Cell::from(progress_bar(progress, 10)),
+            Cell::from(format_size(number("size"))),
+            Cell::from(format!("{}/s", format_size(number("download_rate")))),
+            Cell::from(format!("{}/s", format_size(number("upload_rate")))),
+            Cell::from(format_eta(left, number("download_rate"))),
+            Cell::from(number("connected_peers").to_string()),
+        ])
+    });
+    let widths = [
+        Constraint::Fill(1),
+        Constraint::Length(11),
+        Constraint::Length(17),
+        Constraint::Length(10),
+        Constraint::Length(12),
+        Constraint::Length(12),
+        Constraint::Length(8),
+        Constraint::Length(5),
+    ];
+    let table = Table::new(rows, widths)
+        .header(header)
+        .block(pane("Torrents", app.focus == Focus::List))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    let mut state = TableState::default().with_selected(Some(app.selected));
+    frame.render_stateful_widget(table, area, &mut state);
+}
+
+fn draw_detail(frame: &mut Frame, app: &App, area: Rect) {
+    let block = pane(
+        app.detail
+            .as_ref()
+            .and_then(|d| d["name"].as_str())
+            .unwrap_or("No torrent selected"),
+        app.focus == Focus::Detail,
+    );
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let Some(detail) = &app.detail else {
+        return;
+    };
+
+    let [tabs, content] =
+        Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]).areas(inner);
+    let titles = Tab::ALL.iter().map(|t| t.title());
+    let selected = Tab::ALL.iter().position(|t| *t == app.tab);
+    frame.render_widget(
+        Tabs::new(titles)
+            .select(selected)
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+        tabs,
+    );
+
+    match app.tab {
+        Tab::Files => draw_files(frame, app, content),
+        Tab::Trackers => {
+            let mut lines = strings(&detail["trackers"]);
+            lines.extend(
+                strings(&detail["web_seeds"])
+                    .into_iter()
+                    .map(|s| format!("{s} (web seed)")),
+            );
+            frame.render_widget(Paragraph::new(lines.join("\n")), content);
+        }
+        Tab::Peers => {
+            let mut lines = vec![format!(
+                "connected: {}",
+                detail["connected_peers"].as_u64().unwrap_or_default()
+            )];
+            lines.extend(strings(&detail["peers"]));
+            frame.render_widget(Paragraph::new(lines.join("\n")), content);
+        }
+        Tab::Pieces => {
+            let bitfield = detail["bitfield"]
+                .as_str()
+                .and_then(|b| STANDARD.decode(b).ok())
+                .unwrap_or_default();
+            let pieces = detail["pieces"].as_u64().unwrap_or_default() as usize;
+            let cells = content.width as usize * content.height as usize;
+            let map: String = piece_map(&bitfield, pieces, cells)
+                .into_iter()
+                .map(shade)
+                .collect();
+            frame.render_widget(Paragraph::new(map).wrap(Wrap { trim: false }), content);
+        }
+    }
+}
+
+fn draw_files(frame: &mut Frame, app: &App, area: Rect) {
+    let rows = app.files().iter().map(|f| {
+        let size = f["size"].as_u64().unwrap_or_default();
+        let done = f["done"].as_u64().unwrap_or_default();
+        let percent = if size == 0 {
+            100.0
+        } else {
+            done as f64 * 100.0 / size as f64
+        };
+        Row::new([
+            f["path"].as_str().unwrap_or_default().to_string(),
+            format_size(size),
+            format!("{percent:.1}%"),
+            f["priority"].as_str().unwrap_or_default().to_string(),
+        ])
+    });
+    let widths = [
+        Constraint::Fill(1),
+        Constraint::Length(10),
+        Constraint::Length(7),
+        Constraint::Length(8),
+    ];
+    let mut table = Table::new(rows, widths)
+        .header(Row::new(["Path", "Size", "Done", "Priority"]))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    if app.focus == Focus::List {
+        table = table.row_highlight_style(Style::new());
+    }
+    let mut state = TableState::default().with_selected(Some(app.file));
+    frame.render_stateful_widget(table, area, &mut state);
+}
+
+fn pane(title: &str, focused: bool) -> Block<'_> {
+    let block = Block::new().borders(Borders::ALL).title(Line::from(title));
+    if focused {
+        block.border_style(Style::new().add_modifier(Modifier::BOLD))
+    } else {
+        block
+    }
+}
+
+fn strings(value: &Value) -> Vec<String> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v.as_str().map(String::from))
+        .collect()
+}
+
+/// `██████░░░░  60.0%`
+pub fn progress_bar(progress: f64, width: usize) -> String {
+    let filled = ((progress.clamp(0.0, 1.0) * width as f64).round() as usize).min(width);
+    format!(
+        "{}{} {:>5.1}%",
+        "█".repeat(filled),
+        "░".repeat(width - filled),
+        progress * 100.0
+    )
+}
+
+/// Сколько осталось качать при текущей скорости.
+pub fn format_eta(left: u64, rate: u64) -> String {
+    if left == 0 {
+        return "-".to_string();
+    }
+    if rate == 0 {
+        return "∞".to_string();
+    }
+    let seconds = left.div_ceil(rate);
+    match seconds {
+        0..60 => format!("{seconds}s"),
+        60..3600 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        3600..86400 => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+        _ => format!("{}d {}h", seconds / 86400, seconds % 86400 / 3600),
+    }
+}
+
+/// Раскладывает куски по `cells` ячейкам и возвращает долю скачанных
+/// кусков в каждой. Если ячеек больше, чем кусков, их столько же, сколько
+/// кусков.
+pub fn piece_map(bitfield: &[u8], pieces: usize, cells: usize) -> Vec<f64> {
+    let cells = cells.min(pieces);
+    let have = |piece: usize| {
+        bitfield
+            .get(piece / 8)
+            .is_some_and(|byte| byte & (0x80 >> (piece % 8)) != 0)
+    };
+    (0..cells)
+        .map(|cell| {
+            let start = cell * pieces / cells;
+            let end = (cell + 1) * pieces / cells;
+            let done = (start..end).filter(|p| have(*p)).count();
+            done as f64 / (end - start) as f64
+        })
+        .collect()
+}
+
+fn shade(fraction: f64) -> char {
+    if fraction >= 1.0 {
+        return SHADES[SHADES.len() - 1];
+    }
+    if fraction <= 0.0 {
+        return SHADES[0];
+    }
+    // Частично скачанные ячейки не должны выглядеть ни пустыми, ни полными
+    let middle = SHADES.len() - 2;
+    SHADES[1 + ((fraction * middle as f64) as usize).min(middle - 1)]
+}